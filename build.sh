@@ -0,0 +1,11 @@
+#!/bin/bash
+set -e
+
+# Build every contract into its res directory. The vaults are built first, as the factory embeds
+# their wasm. Paths of dependencies are remapped, so with the locked dependencies and the
+# toolchain of CI the wasm is the same wherever it is built, and CI fails if the committed wasm
+# differs
+cd "$(dirname "$0")"
+for contract in shares contract pool market mock-nft; do
+    (cd "$contract" && ./build.sh)
+done
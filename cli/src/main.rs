@@ -0,0 +1,397 @@
+//! Command line client for the Fractose factory and its vaults
+mod units;
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use fractose_client::rpc::RpcTransport;
+use fractose_client::{Client, Outcome, PoolSeed, RedeemQuote, SecuritizeArgs, Signer, PORTFOLIO_STORAGE_DEPOSIT};
+use futures::executor::block_on;
+use near_sdk::json_types::{U128, U64};
+
+use units::{format_amount, format_near, format_share_price, format_timestamp, parse_amount, parse_near};
+
+const TGAS: u64 = 1_000_000_000_000;
+
+/// Deposit for the NFT contract to store the factory's approval. The unused part is refunded
+const APPROVE_DEPOSIT: &str = "0.01";
+
+#[derive(Parser)]
+#[command(name = "fractose", version, about = "Securitize NFTs into vaults of fungible shares")]
+struct Cli {
+    /// JSON RPC endpoint, such as a local sandbox node
+    #[arg(long, env = "FRACTOSE_RPC_URL", default_value = "https://rpc.testnet.near.org", global = true)]
+    rpc_url: String,
+
+    /// Fractose factory contract
+    #[arg(long, env = "FRACTOSE_FACTORY", default_value = "fractose.monkeyis.testnet", global = true)]
+    factory: String,
+
+    /// Account sending transactions, and whose shares are quoted
+    #[arg(long, env = "NEAR_ACCOUNT_ID", global = true)]
+    account_id: Option<String>,
+
+    /// Key file of the account. Defaults to the near-cli credentials of the account on `--network`
+    #[arg(long, env = "NEAR_KEY_FILE", global = true)]
+    key_file: Option<PathBuf>,
+
+    /// Network whose near-cli credentials are used
+    #[arg(long, default_value = "testnet", global = true)]
+    network: String,
+
+    /// Gas attached to transactions, in TGas
+    #[arg(long, default_value_t = 300, global = true)]
+    gas: u64,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Approve the factory to take an NFT, then securitize it into a vault of shares
+    Securitize {
+        nft_contract: String,
+        token_id: String,
+        /// Number of shares, in whole shares
+        #[arg(long)]
+        shares: String,
+        /// Decimal places of the shares
+        #[arg(long, default_value_t = 4)]
+        decimals: u8,
+        /// Price in NEAR of every share together. The NFT can be redeemed by paying it
+        #[arg(long)]
+        exit_price: String,
+        /// Seconds for which the NFT can't be redeemed
+        #[arg(long)]
+        lock: Option<u64>,
+        /// Shares, in whole shares, seeding a shares/NEAR pool deployed with the vault
+        #[arg(long, requires = "pool_near")]
+        pool_shares: Option<String>,
+        /// NEAR seeding the pool, attached on top of the balances of the new accounts
+        #[arg(long, requires = "pool_shares")]
+        pool_near: Option<String>,
+        /// Don't approve the factory first, as it already is
+        #[arg(long)]
+        skip_approve: bool,
+    },
+    /// List vaults created by the factory
+    Vaults {
+        #[arg(long, default_value_t = 0)]
+        from_index: u64,
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+    },
+    /// Have the factory index the vault shares of the account, paying for the index storage
+    RegisterPortfolio,
+    /// List the vault shares the account holds and their value at each vault's share price
+    Portfolio {
+        #[arg(long, default_value_t = 0)]
+        from_index: u64,
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+    },
+    /// Show the NFT, shares and prices of a vault
+    VaultInfo {
+        vault: String,
+    },
+    /// Show what the account pays to redeem the NFT of a vault
+    RedeemQuote {
+        vault: String,
+    },
+    /// Redeem the NFT of a vault, burning the account's shares and paying for the rest
+    Redeem {
+        vault: String,
+    },
+    /// Exchange the account's shares for NEAR once the NFT of a vault was redeemed
+    Claim {
+        vault: String,
+    },
+    /// Send shares, registering the receiver with the vault if needed
+    TransferShares {
+        vault: String,
+        receiver_id: String,
+        /// Amount in whole shares
+        amount: String,
+    },
+}
+
+struct App {
+    rpc_url: String,
+    factory: String,
+    account_id: Option<String>,
+    key_file: Option<PathBuf>,
+    network: String,
+    gas: u64,
+}
+
+impl App {
+    fn account_id(&self) -> Result<&str> {
+        self.account_id.as_deref().context("--account-id is required")
+    }
+
+    fn signer(&self) -> Result<Signer> {
+        let path = match &self.key_file {
+            Some(path) => path.clone(),
+            None => {
+                let home = std::env::var_os("HOME").context("HOME is not set, pass --key-file")?;
+                PathBuf::from(home).join(".near-credentials").join(&self.network).join(format!("{}.json", self.account_id()?))
+            }
+        };
+        let signer = Signer::from_key_file(&path)?;
+        if let Some(account_id) = &self.account_id {
+            if *account_id != signer.account_id {
+                bail!("key file {} belongs to {}, not {}", path.display(), signer.account_id, account_id);
+            }
+        }
+        Ok(signer)
+    }
+
+    /// Client for views only
+    fn viewer(&self) -> Client<RpcTransport> {
+        Client::new(RpcTransport::new(self.rpc_url.clone()), self.factory.clone())
+    }
+
+    /// Client signing transactions as the account
+    fn sender(&self) -> Result<Client<RpcTransport>> {
+        let transport = RpcTransport::new(self.rpc_url.clone()).with_signer(self.signer()?);
+        Ok(Client::new(transport, self.factory.clone()).with_gas(self.gas * TGAS))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn securitize(
+        &self,
+        nft_contract: String,
+        token_id: String,
+        shares: String,
+        decimals: u8,
+        exit_price: String,
+        lock: Option<u64>,
+        pool: Option<(String, String)>,
+        skip_approve: bool,
+    ) -> Result<()> {
+        let shares_count = parse_amount(&shares, decimals)?;
+        let exit_price = parse_near(&exit_price)?;
+        if shares_count == 0 || exit_price == 0 {
+            bail!("shares and exit price must be positive");
+        }
+        if exit_price % shares_count != 0 {
+            bail!("exit price {} can't be split evenly into {} shares", format_near(exit_price), shares);
+        }
+        let pool_seed = pool.map(|(shares, near)| -> Result<PoolSeed> {
+            Ok(PoolSeed { shares: U128(parse_amount(&shares, decimals)?), near: U128(parse_near(&near)?) })
+        }).transpose()?;
+
+        println!(
+            "Securitizing {}/{} into {} shares at {}",
+            nft_contract, token_id, shares, format_share_price(exit_price / shares_count, decimals)
+        );
+
+        let client = self.sender()?;
+        if !skip_approve {
+            print_logs(&block_on(client.factory().approve(&nft_contract, &token_id, parse_near(APPROVE_DEPOSIT)?))?);
+        }
+        let (vault, outcome) = block_on(client.factory().securitize(&SecuritizeArgs {
+            nft_contract_address: nft_contract,
+            nft_token_id: token_id,
+            shares_count: U128(shares_count),
+            decimals,
+            exit_price: U128(exit_price),
+            min_lock_duration: lock.map(|seconds| U64(seconds * 1_000_000_000)),
+            pool_seed,
+        }))?;
+        print_logs(&outcome);
+        println!("Vault: {}", vault);
+        Ok(())
+    }
+
+    fn vaults(&self, from_index: u64, limit: u64) -> Result<()> {
+        let client = self.viewer();
+        let factory = client.factory();
+        for vault in block_on(factory.get_vaults(from_index, limit))? {
+            let nft_address = block_on(factory.nft_address_of(&vault))?;
+            println!("{}  {}", vault, nft_address.unwrap_or_else(|| "invalid".to_string()));
+        }
+        Ok(())
+    }
+
+    fn register_portfolio(&self) -> Result<()> {
+        let account_id = self.account_id()?;
+        if block_on(self.viewer().factory().is_portfolio_registered(account_id))? {
+            bail!("the portfolio of {} is already registered", account_id);
+        }
+
+        println!("Registering the portfolio of {} for {}", account_id, format_near(PORTFOLIO_STORAGE_DEPOSIT));
+        print_logs(&block_on(self.sender()?.factory().register_portfolio())?);
+        Ok(())
+    }
+
+    fn portfolio(&self, from_index: u64, limit: u64) -> Result<()> {
+        let account_id = self.account_id()?;
+        let client = self.viewer();
+        let mut total = 0;
+        let portfolio = block_on(client.factory().portfolio_of(account_id, from_index, limit))?;
+        for position in portfolio.positions {
+            let decimals = block_on(client.vault(&position.vault_id).ft_metadata())?.decimals;
+            println!("{}  {} shares  {}", position.vault_id, format_amount(position.balance.0, decimals), format_near(position.value.0));
+            total += position.value.0;
+        }
+        println!("Total value  {}", format_near(total));
+        if portfolio.truncated {
+            println!("The portfolio is full and left out positions, so the total is understated");
+        }
+        Ok(())
+    }
+
+    fn vault_info(&self, vault: &str) -> Result<()> {
+        let client = self.viewer();
+        let vault_client = client.vault(vault);
+        let metadata = block_on(vault_client.ft_metadata())?;
+        let supply = block_on(vault_client.ft_total_supply())?;
+        let is_backed = block_on(vault_client.is_backed())?;
+
+        println!("Vault        {}", vault);
+        println!("Shares       {} ({})", metadata.name, metadata.symbol);
+        println!("NFT          {}/{}", metadata.nft_contract_address, metadata.nft_token_id);
+        println!("Trust tier   {:?}", metadata.trust_tier);
+        println!("Custody      {}", if is_backed { "verified" } else { "not verified" });
+        println!("Supply       {}", format_amount(supply, metadata.decimals));
+        println!("Share price  {}", format_share_price(metadata.share_price.0, metadata.decimals));
+
+        if metadata.released {
+            println!("Status       redeemed, {} left to claim", format_near(block_on(vault_client.vault_balance())?));
+        } else {
+            println!("Exit price   {}", format_near(block_on(vault_client.exit_price())?));
+            match metadata.min_lock_until {
+                Some(until) => println!("Status       locked until {}", format_timestamp(until.0)),
+                None => println!("Status       redeemable"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Print and return the quote, or None if the NFT was redeemed
+    fn redeem_quote(&self, vault: &str) -> Result<Option<RedeemQuote>> {
+        let account_id = self.account_id()?;
+        let client = self.viewer();
+        let vault_client = client.vault(vault);
+        let metadata = block_on(vault_client.ft_metadata())?;
+
+        if metadata.released {
+            let shares = block_on(vault_client.ft_balance_of(account_id))?;
+            let claim = block_on(vault_client.vault_balance_of(account_id))?;
+            println!("Already redeemed. {} can claim {} for {} shares", account_id, format_near(claim), format_amount(shares, metadata.decimals));
+            return Ok(None);
+        }
+
+        let quote = block_on(vault_client.redeem_quote(account_id))?;
+        println!("Shares burnt  {}", format_amount(quote.shares_burnt.0, metadata.decimals));
+        println!("NEAR to pay   {}", format_near(quote.near_required.0));
+        println!("Paid by vault {} NFT transfer deposit, {} storage", format_near(quote.nft_transfer_deposit.0), format_near(quote.storage_cost.0));
+        if quote.refund.0 > 0 {
+            println!("Refund        {}", format_near(quote.refund.0));
+        }
+        if quote.redeemable_at.0 > 0 {
+            println!("Redeemable    from {}", format_timestamp(quote.redeemable_at.0));
+        }
+        if let Some(reason) = &quote.reason {
+            println!("Cannot redeem {}", reason);
+        }
+        Ok(Some(quote))
+    }
+
+    fn redeem(&self, vault: &str) -> Result<()> {
+        let quote = self.redeem_quote(vault)?.context("the NFT was already redeemed")?;
+        if let Some(reason) = quote.reason {
+            bail!("cannot redeem {}: {}", vault, reason);
+        }
+        print_logs(&block_on(self.sender()?.vault(vault).redeem(quote.near_required.0))?);
+        println!("Redeemed the NFT of {}", vault);
+        Ok(())
+    }
+
+    fn claim(&self, vault: &str) -> Result<()> {
+        let account_id = self.account_id()?;
+        let claim = block_on(self.viewer().vault(vault).vault_balance_of(account_id))?;
+        if claim == 0 {
+            bail!("{} has nothing to claim from {}", account_id, vault);
+        }
+
+        println!("Claiming {}", format_near(claim));
+        print_logs(&block_on(self.sender()?.vault(vault).claim())?);
+        Ok(())
+    }
+
+    fn transfer_shares(&self, vault: &str, receiver_id: &str, amount: &str) -> Result<()> {
+        let client = self.sender()?;
+        let vault_client = client.vault(vault);
+        let metadata = block_on(vault_client.ft_metadata())?;
+        let amount = parse_amount(amount, metadata.decimals)?;
+
+        if block_on(vault_client.storage_balance_of(receiver_id))?.is_none() {
+            let deposit = block_on(vault_client.storage_balance_bounds())?.min.0;
+            println!("Registering {} with {} for {}", receiver_id, vault, format_near(deposit));
+            print_logs(&block_on(vault_client.storage_deposit(receiver_id))?);
+        }
+
+        println!("Sending {} {} to {}", format_amount(amount, metadata.decimals), metadata.symbol, receiver_id);
+        print_logs(&block_on(vault_client.ft_transfer(receiver_id, amount, None))?);
+        Ok(())
+    }
+}
+
+/// Print the logs of a transaction, and the receipts which failed after it succeeded
+fn print_logs(outcome: &Outcome) {
+    for log in &outcome.logs {
+        println!("  log: {}", log.message);
+    }
+    for failure in &outcome.failures {
+        println!("  failed receipt: {}", failure);
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let app = App {
+        rpc_url: cli.rpc_url,
+        factory: cli.factory,
+        account_id: cli.account_id,
+        key_file: cli.key_file,
+        network: cli.network,
+        gas: cli.gas,
+    };
+
+    match cli.command {
+        Command::Securitize { nft_contract, token_id, shares, decimals, exit_price, lock, pool_shares, pool_near, skip_approve } => {
+            app.securitize(nft_contract, token_id, shares, decimals, exit_price, lock, pool_shares.zip(pool_near), skip_approve)
+        }
+        Command::Vaults { from_index, limit } => app.vaults(from_index, limit),
+        Command::RegisterPortfolio => app.register_portfolio(),
+        Command::Portfolio { from_index, limit } => app.portfolio(from_index, limit),
+        Command::VaultInfo { vault } => app.vault_info(&vault),
+        Command::RedeemQuote { vault } => app.redeem_quote(&vault).map(|_| ()),
+        Command::Redeem { vault } => app.redeem(&vault),
+        Command::Claim { vault } => app.claim(&vault),
+        Command::TransferShares { vault, receiver_id, amount } => app.transfer_shares(&vault, &receiver_id, &amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["fractose", "--rpc-url", "http://localhost:3030", "vault-info", "vault.testnet"]).unwrap();
+        assert_eq!(cli.rpc_url, "http://localhost:3030");
+        assert!(matches!(cli.command, Command::VaultInfo { vault } if vault == "vault.testnet"));
+
+        // A pool needs both its shares and NEAR
+        assert!(Cli::try_parse_from(["fractose", "securitize", "nft.testnet", "0", "--shares", "1", "--exit-price", "1", "--pool-near", "1"]).is_err());
+    }
+}
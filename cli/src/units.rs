@@ -0,0 +1,122 @@
+//! Conversions between human readable amounts and the integer amounts used by contracts
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+pub const NEAR_DECIMALS: u8 = 24;
+
+/// Format an integer amount with `decimals` decimal places, without trailing zeros
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    let digits = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+pub fn format_near(amount: u128) -> String {
+    format!("{} NEAR", format_amount(amount, NEAR_DECIMALS))
+}
+
+/// Price of a whole share, given the price of its smallest unit
+pub fn format_share_price(unit_price: u128, decimals: u8) -> String {
+    match 10u128.checked_pow(decimals as u32).and_then(|one_share| one_share.checked_mul(unit_price)) {
+        Some(price) => format!("{} per share", format_near(price)),
+        None => format!("{} per {} share", format_near(unit_price), format_amount(1, decimals)),
+    }
+}
+
+/// Parse a decimal amount such as `1.5` into an integer amount with `decimals` decimal places
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<u128> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() || !(whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())) {
+        bail!("invalid amount {:?}", amount);
+    }
+    if fraction.len() > decimals as usize {
+        bail!("{:?} has more than {} decimal places", amount, decimals);
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    digits.parse().with_context(|| format!("amount {:?} is too large", amount))
+}
+
+pub fn parse_near(amount: &str) -> Result<u128> {
+    parse_amount(amount, NEAR_DECIMALS)
+}
+
+/// Block timestamp in nanoseconds, relative to now
+pub fn format_timestamp(timestamp: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let at = timestamp / 1_000_000_000;
+
+    if at > now {
+        format!("{} (in {})", at, format_duration(at - now))
+    } else {
+        format!("{} ({} ago)", at, format_duration(now - at))
+    }
+}
+
+/// Duration in seconds, as days, hours and minutes
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86_400, seconds / 3_600 % 24, seconds / 60 % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(0, 0), "0");
+        assert_eq!(format_amount(1000, 0), "1000");
+        assert_eq!(format_amount(12_345, 4), "1.2345");
+        assert_eq!(format_amount(10_000, 4), "1");
+        assert_eq!(format_amount(5, 4), "0.0005");
+        assert_eq!(format_near(1_500_000_000_000_000_000_000_000), "1.5 NEAR");
+        assert_eq!(format_near(1), "0.000000000000000000000001 NEAR");
+        assert_eq!(format_amount(u128::MAX, 38), "3.40282366920938463463374607431768211455");
+        assert_eq!(format_share_price(10u128.pow(20), 4), "1 NEAR per share");
+        assert_eq!(format_share_price(1, 40), "0.000000000000000000000001 NEAR per 0.0000000000000000000000000000000000000001 share");
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("1000", 0).unwrap(), 1000);
+        assert_eq!(parse_amount("1.2345", 4).unwrap(), 12_345);
+        assert_eq!(parse_amount("1", 4).unwrap(), 10_000);
+        assert_eq!(parse_amount(".5", 1).unwrap(), 5);
+        assert_eq!(parse_amount("2.", 1).unwrap(), 20);
+        assert_eq!(parse_near("0.000000000000000000000001").unwrap(), 1);
+        assert_eq!(parse_near("10").unwrap(), 10u128.pow(25));
+
+        assert!(parse_amount("1.23", 1).is_err());
+        assert!(parse_amount("", 1).is_err());
+        assert!(parse_amount(".", 1).is_err());
+        assert!(parse_amount("-1", 1).is_err());
+        assert!(parse_amount("1e3", 1).is_err());
+        assert!(parse_near("1000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (amount, decimals) in &[(0, 0), (1, 24), (123_456_789, 3), (u128::MAX, 0)] {
+            assert_eq!(parse_amount(&format_amount(*amount, *decimals), *decimals).unwrap(), *amount);
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "0m");
+        assert_eq!(format_duration(3_660), "1h 1m");
+        assert_eq!(format_duration(90_000), "1d 1h");
+    }
+}
@@ -0,0 +1,100 @@
+//! Calls to the Fractose factory
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{AccountId, Balance};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{securitize_deposit, Client, Error, Outcome, PoolSeed, Portfolio, Result, TokenId, Transport, TrustTier, PORTFOLIO_STORAGE_DEPOSIT};
+
+/// Arguments of `securitize`
+#[derive(Serialize, Clone, Debug)]
+pub struct SecuritizeArgs {
+    pub nft_contract_address: AccountId,
+    pub nft_token_id: TokenId,
+    /// Shares minted, in the smallest unit
+    pub shares_count: U128,
+    pub decimals: u8,
+    /// Must be a multiple of `shares_count`
+    pub exit_price: U128,
+    /// Nanoseconds for which the NFT can't be redeemed
+    pub min_lock_duration: Option<U64>,
+    pub pool_seed: Option<PoolSeed>,
+}
+
+pub struct FactoryClient<'a, T> {
+    client: &'a Client<T>,
+    pub factory_id: AccountId,
+}
+
+impl<'a, T: Transport> FactoryClient<'a, T> {
+    pub(crate) fn new(client: &'a Client<T>, factory_id: AccountId) -> Self {
+        Self { client, factory_id }
+    }
+
+    /// Approve the factory to take an NFT of the signer, which `securitize` needs. `deposit` pays
+    /// the NFT contract for storing the approval
+    pub async fn approve(&self, nft_contract_address: &str, nft_token_id: &str, deposit: Balance) -> Result<Outcome> {
+        self.client.call(nft_contract_address, "nft_approve", json!({
+            "token_id": nft_token_id,
+            "account_id": self.factory_id,
+        }), deposit).await
+    }
+
+    /// Securitize an NFT the factory was approved for, attaching the deposit the factory requires.
+    /// Returns the new vault
+    pub async fn securitize(&self, args: &SecuritizeArgs) -> Result<(AccountId, Outcome)> {
+        let deposit = securitize_deposit(args.pool_seed.as_ref());
+        let outcome = self.client.call(&self.factory_id, "securitize", json!(args), deposit).await?;
+        let vault_id = self.shares_address_of(&args.nft_contract_address, &args.nft_token_id).await?
+            .ok_or_else(|| Error::Execution("the factory did not register the vault".to_string()))?;
+        Ok((vault_id, outcome))
+    }
+
+    /// Vaults created by the factory, in creation order
+    pub async fn get_vaults(&self, from_index: u64, limit: u64) -> Result<Vec<AccountId>> {
+        self.client.view(&self.factory_id, "get_vaults", json!({ "from_index": U64(from_index), "limit": limit })).await
+    }
+
+    /// NFT of a valid vault, as `nft_contract_address/nft_token_id`
+    pub async fn nft_address_of(&self, vault_id: &str) -> Result<Option<String>> {
+        self.client.view(&self.factory_id, "nft_address_of", json!({ "shares_contract": vault_id })).await
+    }
+
+    pub async fn shares_address_of(&self, nft_contract_address: &str, nft_token_id: &str) -> Result<Option<AccountId>> {
+        self.client.view(&self.factory_id, "shares_address_of", json!({
+            "nft_contract_address": nft_contract_address,
+            "nft_token_id": nft_token_id,
+        })).await
+    }
+
+    /// Have the factory index the vault shares of the signer, attaching the storage deposit it requires
+    pub async fn register_portfolio(&self) -> Result<Outcome> {
+        self.client.call(&self.factory_id, "register_portfolio", json!({}), PORTFOLIO_STORAGE_DEPOSIT).await
+    }
+
+    /// Shares an account holds in vaults reporting to the factory, valued at their share price
+    pub async fn portfolio_of(&self, account_id: &str, from_index: u64, limit: u64) -> Result<Portfolio> {
+        self.client.view(&self.factory_id, "portfolio_of", json!({
+            "account_id": account_id,
+            "from_index": U64(from_index),
+            "limit": limit,
+        })).await
+    }
+
+    pub async fn is_portfolio_registered(&self, account_id: &str) -> Result<bool> {
+        self.client.view(&self.factory_id, "is_portfolio_registered", json!({ "account_id": account_id })).await
+    }
+
+    pub async fn trust_tier_of(&self, vault_id: &str) -> Result<Option<TrustTier>> {
+        self.client.view(&self.factory_id, "trust_tier_of", json!({ "shares_contract": vault_id })).await
+    }
+
+    pub async fn is_invalid_vault(&self, vault_id: &str) -> Result<bool> {
+        self.client.view(&self.factory_id, "is_invalid_vault", json!({ "shares_contract": vault_id })).await
+    }
+
+    /// Check that a vault holds its NFT. Returns whether it does
+    pub async fn check_custody(&self, vault_id: &str) -> Result<bool> {
+        self.client.call(&self.factory_id, "check_custody", json!({ "shares_contract": vault_id }), 0).await?.json()
+    }
+}
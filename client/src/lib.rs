@@ -0,0 +1,149 @@
+//! Typed async client for the Fractose factory and its vaults.
+//!
+//! Requests go through a [`Transport`], which signs and sends them. The `rpc` feature provides
+//! one for NEAR JSON RPC nodes and the `sandbox` feature one for the in-process simulation runtime.
+use std::fmt;
+
+use async_trait::async_trait;
+use near_sdk::{AccountId, Balance, Gas};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+mod factory;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+#[cfg(feature = "rpc")]
+mod transaction;
+mod vault;
+
+pub use factory::{FactoryClient, SecuritizeArgs};
+pub use fractose_common::{securitize_deposit, Event, PoolSeed, Portfolio, Position, RedeemQuote, SharesMetadata, TokenId, TrustTier, PORTFOLIO_STORAGE_DEPOSIT};
+#[cfg(feature = "rpc")]
+pub use transaction::Signer;
+pub use vault::{StorageBalance, StorageBalanceBounds, VaultClient};
+
+/// Gas attached to transactions unless set with [`Client::with_gas`]
+pub const DEFAULT_GAS: Gas = 300_000_000_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The node could not be reached or answered with something else than a result
+    Transport(String),
+    /// A view or transaction failed, with the error of the contract or node
+    Execution(String),
+    /// A result could not be decoded
+    Decode(String),
+    /// The key of the signer could not be read
+    Signer(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(message) => write!(f, "transport error: {}", message),
+            Error::Execution(message) => write!(f, "execution failed: {}", message),
+            Error::Decode(message) => write!(f, "invalid result: {}", message),
+            Error::Signer(message) => write!(f, "invalid signer: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Log written by a receipt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    /// Account which executed the receipt
+    pub executor_id: AccountId,
+    pub message: String,
+}
+
+impl Log {
+    /// Events of the log. Structured logs may hold several
+    pub fn events(&self) -> Vec<Event> {
+        Event::parse_all(&self.message)
+    }
+}
+
+/// Successful transaction and the receipts it spawned
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// JSON returned by the transaction, empty for methods returning nothing
+    pub value: Vec<u8>,
+    pub logs: Vec<Log>,
+    /// Errors of receipts which failed without failing the transaction, such as callbacks
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.value).map_err(|err| Error::Decode(err.to_string()))
+    }
+
+    /// Events logged by the receipts, with the account which emitted them
+    pub fn events(&self) -> Vec<(AccountId, Event)> {
+        self.logs.iter()
+            .flat_map(|log| log.events().into_iter().map(move |event| (log.executor_id.clone(), event)))
+            .collect()
+    }
+}
+
+/// Signs and sends requests on behalf of an account
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Account signing transactions, if any
+    fn signer_id(&self) -> Option<AccountId>;
+
+    /// Call a view method and return its JSON result
+    async fn view(&self, contract_id: &str, method: &str, args: Value) -> Result<Vec<u8>>;
+
+    /// Sign a function call, send it and wait for every receipt it spawns. Fails if the transaction failed
+    async fn call(&self, receiver_id: &str, method: &str, args: Value, deposit: Balance, gas: Gas) -> Result<Outcome>;
+}
+
+pub struct Client<T> {
+    transport: T,
+    factory_id: AccountId,
+    gas: Gas,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T, factory_id: impl Into<AccountId>) -> Self {
+        Self { transport, factory_id: factory_id.into(), gas: DEFAULT_GAS }
+    }
+
+    /// Gas attached to every transaction
+    pub fn with_gas(mut self, gas: Gas) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn signer_id(&self) -> Result<AccountId> {
+        self.transport.signer_id().ok_or_else(|| Error::Transport("the transport has no signer".to_string()))
+    }
+
+    pub fn factory(&self) -> FactoryClient<'_, T> {
+        FactoryClient::new(self, self.factory_id.clone())
+    }
+
+    pub fn vault(&self, vault_id: impl Into<AccountId>) -> VaultClient<'_, T> {
+        VaultClient::new(self, vault_id.into())
+    }
+
+    pub(crate) async fn view<R: DeserializeOwned>(&self, contract_id: &str, method: &str, args: Value) -> Result<R> {
+        let value = self.transport.view(contract_id, method, args).await?;
+        serde_json::from_slice(&value).map_err(|err| Error::Decode(format!("{}.{}: {}", contract_id, method, err)))
+    }
+
+    pub(crate) async fn call(&self, contract_id: &str, method: &str, args: Value, deposit: Balance) -> Result<Outcome> {
+        self.transport.call(contract_id, method, args, deposit, self.gas).await
+    }
+}
@@ -0,0 +1,140 @@
+//! Transport for NEAR JSON RPC nodes, such as testnet or a local sandbox node.
+//!
+//! Requests are sent with a blocking HTTP client, so drive it from a thread which may block,
+//! for example with `futures::executor::block_on` or tokio's `spawn_blocking`.
+use async_trait::async_trait;
+use near_sdk::{AccountId, Balance, Gas};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::transaction::{parse_block_hash, FunctionCall, Signer};
+use crate::{Error, Log, Outcome, Result, Transport};
+
+pub struct RpcTransport {
+    url: String,
+    signer: Option<Signer>,
+}
+
+#[derive(Deserialize)]
+struct AccessKey {
+    nonce: u64,
+    block_hash: String,
+}
+
+impl RpcTransport {
+    /// Transport which can only call view methods
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into(), signer: None }
+    }
+
+    /// Sign transactions with `signer`
+    pub fn with_signer(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": "fractose", "method": method, "params": params });
+        let response: Value = match ureq::post(&self.url).send_json(body) {
+            Ok(response) => response.into_json(),
+            // Nodes answer failed requests with a JSON error and an error status
+            Err(ureq::Error::Status(_, response)) => response.into_json(),
+            Err(err) => return Err(Error::Transport(format!("requesting {}: {}", self.url, err))),
+        }.map_err(|err| Error::Transport(err.to_string()))?;
+
+        if let Some(error) = response.get("error") {
+            let message = error.get("data").or_else(|| error.get("message")).unwrap_or(error);
+            return Err(Error::Execution(format!("{}: {}", method, message)));
+        }
+        response.get("result").cloned().ok_or_else(|| Error::Transport(format!("{} returned no result", method)))
+    }
+}
+
+#[async_trait]
+impl Transport for RpcTransport {
+    fn signer_id(&self) -> Option<AccountId> {
+        self.signer.as_ref().map(|signer| signer.account_id.clone())
+    }
+
+    /// Call a view method at the latest final block
+    async fn view(&self, contract_id: &str, method: &str, args: Value) -> Result<Vec<u8>> {
+        let result = self.request("query", json!({
+            "request_type": "call_function",
+            "finality": "final",
+            "account_id": contract_id,
+            "method_name": method,
+            "args_base64": base64::encode(args.to_string()),
+        }))?;
+
+        // Contract panics are reported as a result holding an error
+        if let Some(error) = result.get("error") {
+            return Err(Error::Execution(format!("{}.{}: {}", contract_id, method, error)));
+        }
+        serde_json::from_value(result["result"].clone()).map_err(|err| Error::Decode(err.to_string()))
+    }
+
+    async fn call(&self, receiver_id: &str, method: &str, args: Value, deposit: Balance, gas: Gas) -> Result<Outcome> {
+        let signer = self.signer.as_ref().ok_or_else(|| Error::Signer("the transport has no signer".to_string()))?;
+        let access_key: AccessKey = serde_json::from_value(self.request("query", json!({
+            "request_type": "view_access_key",
+            "finality": "final",
+            "account_id": signer.account_id,
+            "public_key": signer.public_key(),
+        }))?).map_err(|err| Error::Decode(err.to_string()))?;
+
+        let transaction = signer.sign(receiver_id, access_key.nonce + 1, parse_block_hash(&access_key.block_hash)?, vec![FunctionCall {
+            method_name: method.to_string(),
+            args: args.to_string().into_bytes(),
+            gas,
+            deposit,
+        }]);
+        parse_outcome(&self.request("broadcast_tx_commit", json!([transaction.to_base64()]))?)
+    }
+}
+
+/// Outcome of `broadcast_tx_commit`. Fails if the transaction failed
+fn parse_outcome(result: &Value) -> Result<Outcome> {
+    let receipts = result["receipts_outcome"].as_array().cloned().unwrap_or_default();
+    let outcomes = std::iter::once(&result["transaction_outcome"]).chain(receipts.iter());
+
+    let mut logs = vec![];
+    let mut failures = vec![];
+    for outcome in outcomes {
+        let executor_id = outcome["outcome"]["executor_id"].as_str().unwrap_or_default();
+        logs.extend(outcome["outcome"]["logs"].as_array().into_iter().flatten()
+            .filter_map(|log| log.as_str())
+            .map(|message| Log { executor_id: executor_id.to_string(), message: message.to_string() }));
+        if let Some(failure) = outcome["outcome"]["status"].get("Failure") {
+            failures.push(failure.to_string());
+        }
+    }
+
+    match result["status"]["SuccessValue"].as_str() {
+        Some(value) => Ok(Outcome { value: base64::decode(value).map_err(|err| Error::Decode(err.to_string()))?, logs, failures }),
+        None => Err(Error::Execution(result["status"]["Failure"].to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_outcome() {
+        let outcome = parse_outcome(&json!({
+            "status": { "SuccessValue": base64::encode("true") },
+            "transaction_outcome": { "outcome": { "executor_id": "alice.testnet", "logs": [], "status": { "SuccessReceiptId": "abc" } } },
+            "receipts_outcome": [
+                { "outcome": { "executor_id": "vault.testnet", "logs": ["Redeem(alice.testnet)"], "status": { "SuccessValue": "" } } },
+                { "outcome": { "executor_id": "vault.testnet", "logs": [], "status": { "Failure": { "ActionError": { "index": 0 } } } } },
+            ],
+        })).unwrap();
+
+        assert_eq!(outcome.json::<bool>(), Ok(true));
+        assert_eq!(outcome.logs, vec![Log { executor_id: "vault.testnet".to_string(), message: "Redeem(alice.testnet)".to_string() }]);
+        assert_eq!(outcome.failures, vec![json!({ "ActionError": { "index": 0 } }).to_string()]);
+
+        let error = parse_outcome(&json!({ "status": { "Failure": { "ActionError": {} } }, "transaction_outcome": { "outcome": {} } }));
+        assert_eq!(error, Err(Error::Execution(json!({ "ActionError": {} }).to_string())));
+    }
+}
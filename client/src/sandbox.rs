@@ -0,0 +1,57 @@
+//! Transport running the contracts on the in-process simulation runtime of `fractose_sim`
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use fractose_sim::{ExecutionResult, Runtime};
+use near_sdk::{AccountId, Balance, Gas};
+use serde_json::Value;
+
+use crate::{Error, Log, Outcome, Result, Transport};
+
+/// Sends transactions signed by `signer_id` to a runtime shared with other sandbox transports
+#[derive(Clone)]
+pub struct Sandbox {
+    runtime: Arc<Mutex<Runtime>>,
+    signer_id: AccountId,
+}
+
+impl Sandbox {
+    pub fn new(runtime: Runtime, signer_id: impl Into<AccountId>) -> Self {
+        Self { runtime: Arc::new(Mutex::new(runtime)), signer_id: signer_id.into() }
+    }
+
+    /// Transport signing as another account of the same runtime
+    pub fn as_account(&self, signer_id: impl Into<AccountId>) -> Self {
+        Self { runtime: self.runtime.clone(), signer_id: signer_id.into() }
+    }
+
+    /// Runtime, to create accounts, move time or check balances
+    pub fn runtime(&self) -> MutexGuard<'_, Runtime> {
+        self.runtime.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Transport for Sandbox {
+    fn signer_id(&self) -> Option<AccountId> {
+        Some(self.signer_id.clone())
+    }
+
+    async fn view(&self, contract_id: &str, method: &str, args: Value) -> Result<Vec<u8>> {
+        self.runtime().try_view(contract_id, method, args).map_err(Error::Execution)
+    }
+
+    async fn call(&self, receiver_id: &str, method: &str, args: Value, deposit: Balance, gas: Gas) -> Result<Outcome> {
+        let result = self.runtime().call(&self.signer_id, receiver_id, method, args, deposit, gas);
+        outcome(&result)
+    }
+}
+
+fn outcome(result: &ExecutionResult) -> Result<Outcome> {
+    let value = result.result().map_err(|error| Error::Execution(error.to_string()))?.to_vec();
+    let logs = result.outcomes.iter()
+        .flat_map(|outcome| outcome.logs.iter().map(move |message| Log { executor_id: outcome.receiver_id.clone(), message: message.clone() }))
+        .collect();
+
+    Ok(Outcome { value, logs, failures: result.promise_errors().into_iter().map(String::from).collect() })
+}
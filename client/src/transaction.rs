@@ -0,0 +1,204 @@
+//! Signed function call transactions, serialized like the protocol's `SignedTransaction`
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Signer as _, SigningKey};
+use near_sdk::borsh::{self, BorshSerialize};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+/// Index of `FunctionCall` in the protocol's `Action` enum
+const FUNCTION_CALL_ACTION: u8 = 2;
+
+/// Index of ED25519 in the protocol's `KeyType` enum, for keys and signatures
+const ED25519: u8 = 0;
+
+pub struct FunctionCall {
+    pub method_name: String,
+    pub args: Vec<u8>,
+    pub gas: u64,
+    pub deposit: u128,
+}
+
+impl BorshSerialize for FunctionCall {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        FUNCTION_CALL_ACTION.serialize(writer)?;
+        self.method_name.serialize(writer)?;
+        self.args.serialize(writer)?;
+        self.gas.serialize(writer)?;
+        self.deposit.serialize(writer)
+    }
+}
+
+#[derive(BorshSerialize)]
+pub struct Transaction {
+    pub signer_id: String,
+    pub public_key: (u8, [u8; 32]),
+    pub nonce: u64,
+    pub receiver_id: String,
+    pub block_hash: [u8; 32],
+    pub actions: Vec<FunctionCall>,
+}
+
+#[derive(BorshSerialize)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    pub signature: (u8, [u8; 64]),
+}
+
+impl SignedTransaction {
+    /// Base64 encoding expected by `broadcast_tx_commit`
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.try_to_vec().unwrap())
+    }
+}
+
+/// Key file written by near-cli, or `validator_key.json` of a sandbox node
+#[derive(Deserialize)]
+struct KeyFile {
+    account_id: String,
+    #[serde(alias = "secret_key")]
+    private_key: String,
+}
+
+/// Full access key of the account sending transactions
+pub struct Signer {
+    pub account_id: String,
+    key: SigningKey,
+}
+
+impl Signer {
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|err| Error::Signer(format!("reading key file {}: {}", path.display(), err)))?;
+        let key_file: KeyFile = serde_json::from_str(&contents).map_err(|err| Error::Signer(format!("parsing key file {}: {}", path.display(), err)))?;
+
+        Ok(Self {
+            account_id: key_file.account_id,
+            key: parse_secret_key(&key_file.private_key)?,
+        })
+    }
+
+    /// Public key as `ed25519:<base58>`, the format used by RPC queries
+    pub fn public_key(&self) -> String {
+        format!("ed25519:{}", bs58::encode(self.key.verifying_key().as_bytes()).into_string())
+    }
+
+    /// Sign a transaction calling `receiver_id`, with the access key's next nonce and a recent block hash
+    pub fn sign(&self, receiver_id: &str, nonce: u64, block_hash: [u8; 32], actions: Vec<FunctionCall>) -> SignedTransaction {
+        let transaction = Transaction {
+            signer_id: self.account_id.clone(),
+            public_key: (ED25519, self.key.verifying_key().to_bytes()),
+            nonce,
+            receiver_id: receiver_id.to_string(),
+            block_hash,
+            actions,
+        };
+        let hash = Sha256::digest(transaction.try_to_vec().unwrap());
+        let signature = self.key.sign(&hash).to_bytes();
+
+        SignedTransaction { transaction, signature: (ED25519, signature) }
+    }
+}
+
+/// Parse `ed25519:<base58>` holding a 64 byte keypair or a 32 byte seed
+fn parse_secret_key(key: &str) -> Result<SigningKey> {
+    let encoded = key.strip_prefix("ed25519:").ok_or_else(|| Error::Signer("only ed25519 keys are supported".to_string()))?;
+    let bytes = bs58::decode(encoded).into_vec().map_err(|_| Error::Signer("invalid base58 in secret key".to_string()))?;
+
+    match bytes.len() {
+        32 => Ok(SigningKey::from_bytes(bytes[..].try_into().unwrap())),
+        64 => SigningKey::from_keypair_bytes(bytes[..].try_into().unwrap())
+            .map_err(|_| Error::Signer("secret key does not match its public key".to_string())),
+        len => Err(Error::Signer(format!("secret key has {} bytes, expected 32 or 64", len))),
+    }
+}
+
+/// Decode a base58 block hash returned by the RPC
+pub fn parse_block_hash(hash: &str) -> Result<[u8; 32]> {
+    let bytes = bs58::decode(hash).into_vec().map_err(|_| Error::Decode("invalid base58 in block hash".to_string()))?;
+    bytes[..].try_into().map_err(|_| Error::Decode("block hash is not 32 bytes".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier};
+
+    use super::*;
+
+    fn signer() -> Signer {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        Signer { account_id: "alice.testnet".to_string(), key }
+    }
+
+    #[test]
+    fn test_parse_secret_key() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let keypair = format!("ed25519:{}", bs58::encode(key.to_keypair_bytes()).into_string());
+        let seed = format!("ed25519:{}", bs58::encode(key.to_bytes()).into_string());
+
+        assert_eq!(parse_secret_key(&keypair).unwrap().to_bytes(), key.to_bytes());
+        assert_eq!(parse_secret_key(&seed).unwrap().to_bytes(), key.to_bytes());
+        assert!(parse_secret_key("secp256k1:abc").is_err());
+        assert!(parse_secret_key("ed25519:abc").is_err());
+
+        // The public half of a keypair must match its secret
+        let mut mismatched = key.to_keypair_bytes();
+        mismatched[63] ^= 1;
+        assert!(parse_secret_key(&format!("ed25519:{}", bs58::encode(mismatched).into_string())).is_err());
+    }
+
+    #[test]
+    fn test_transaction_layout() {
+        let signed = signer().sign("vault.testnet", 5, [1; 32], vec![FunctionCall {
+            method_name: "claim".to_string(),
+            args: b"{}".to_vec(),
+            gas: 30,
+            deposit: 1,
+        }]);
+        let bytes = signed.transaction.try_to_vec().unwrap();
+
+        let mut expected = vec![];
+        expected.extend_from_slice(&13u32.to_le_bytes());
+        expected.extend_from_slice(b"alice.testnet");
+        expected.push(ED25519);
+        expected.extend_from_slice(signer().key.verifying_key().as_bytes());
+        expected.extend_from_slice(&5u64.to_le_bytes());
+        expected.extend_from_slice(&13u32.to_le_bytes());
+        expected.extend_from_slice(b"vault.testnet");
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.push(FUNCTION_CALL_ACTION);
+        expected.extend_from_slice(&5u32.to_le_bytes());
+        expected.extend_from_slice(b"claim");
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.extend_from_slice(b"{}");
+        expected.extend_from_slice(&30u64.to_le_bytes());
+        expected.extend_from_slice(&1u128.to_le_bytes());
+        assert_eq!(bytes, expected);
+
+        // The signature covers the hash of the transaction, and follows it with its key type
+        let signature = Signature::from_bytes(&signed.signature.1);
+        signer().key.verifying_key().verify(&Sha256::digest(&bytes), &signature).unwrap();
+        let signed_bytes = signed.try_to_vec().unwrap();
+        assert_eq!(signed_bytes.len(), bytes.len() + 65);
+        assert_eq!(signed_bytes[bytes.len()], ED25519);
+    }
+
+    #[test]
+    fn test_key_file() {
+        let path = std::env::temp_dir().join("fractose-client-test-key.json");
+        let key = format!("ed25519:{}", bs58::encode(signer().key.to_keypair_bytes()).into_string());
+
+        // near-cli and sandbox node key files name the secret differently
+        for field in &["private_key", "secret_key"] {
+            fs::write(&path, serde_json::json!({ "account_id": "alice.testnet", "public_key": "", *field: key }).to_string()).unwrap();
+            let signer = Signer::from_key_file(&path).unwrap();
+            assert_eq!(signer.account_id, "alice.testnet");
+            assert_eq!(signer.public_key(), format!("ed25519:{}", bs58::encode(signer.key.verifying_key().as_bytes()).into_string()));
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
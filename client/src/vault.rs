@@ -0,0 +1,128 @@
+//! Calls to a vault, the shares contract of a securitized NFT
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{AccountId, Balance};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{Client, Outcome, RedeemQuote, Result, SharesMetadata, Transport};
+
+/// Storage deposit of an account registered with a vault
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+pub struct VaultClient<'a, T> {
+    client: &'a Client<T>,
+    pub vault_id: AccountId,
+}
+
+impl<'a, T: Transport> VaultClient<'a, T> {
+    pub(crate) fn new(client: &'a Client<T>, vault_id: AccountId) -> Self {
+        Self { client, vault_id }
+    }
+
+    async fn view<R: serde::de::DeserializeOwned>(&self, method: &str, args: serde_json::Value) -> Result<R> {
+        self.client.view(&self.vault_id, method, args).await
+    }
+
+    async fn call(&self, method: &str, args: serde_json::Value, deposit: Balance) -> Result<Outcome> {
+        self.client.call(&self.vault_id, method, args, deposit).await
+    }
+
+    pub async fn ft_metadata(&self) -> Result<SharesMetadata> {
+        self.view("ft_metadata", json!({})).await
+    }
+
+    pub async fn ft_total_supply(&self) -> Result<Balance> {
+        Ok(self.view::<U128>("ft_total_supply", json!({})).await?.0)
+    }
+
+    pub async fn ft_balance_of(&self, account_id: &str) -> Result<Balance> {
+        Ok(self.view::<U128>("ft_balance_of", json!({ "account_id": account_id })).await?.0)
+    }
+
+    /// NEAR needed to buy every share, and with them the NFT
+    pub async fn exit_price(&self) -> Result<Balance> {
+        Ok(self.view::<U128>("exit_price", json!({})).await?.0)
+    }
+
+    /// NEAR `account_id` pays on top of its shares to redeem the NFT. Fails once it is redeemed
+    pub async fn redeem_amount_of(&self, account_id: &str) -> Result<Balance> {
+        Ok(self.view::<U128>("redeem_amount_of", json!({ "from": account_id })).await?.0)
+    }
+
+    /// Shares burnt, NEAR to attach and costs of a redemption by `account_id`, and whether it can redeem now
+    pub async fn redeem_quote(&self, account_id: &str) -> Result<RedeemQuote> {
+        self.view("redeem_quote", json!({ "account_id": account_id })).await
+    }
+
+    /// NEAR left to claim once the NFT is redeemed
+    pub async fn vault_balance(&self) -> Result<Balance> {
+        Ok(self.view::<U128>("vault_balance", json!({})).await?.0)
+    }
+
+    /// NEAR `account_id` can claim once the NFT is redeemed
+    pub async fn vault_balance_of(&self, account_id: &str) -> Result<Balance> {
+        Ok(self.view::<U128>("vault_balance_of", json!({ "from": account_id })).await?.0)
+    }
+
+    /// Block timestamp in nanoseconds from which the NFT can be redeemed
+    pub async fn redeemable_at(&self) -> Result<u64> {
+        Ok(self.view::<U64>("redeemable_at", json!({})).await?.0)
+    }
+
+    pub async fn is_backed(&self) -> Result<bool> {
+        self.view("is_backed", json!({})).await
+    }
+
+    pub async fn custody_verified_at(&self) -> Result<Option<u64>> {
+        Ok(self.view::<Option<U64>>("custody_verified_at", json!({})).await?.map(|timestamp| timestamp.0))
+    }
+
+    pub async fn storage_balance_of(&self, account_id: &str) -> Result<Option<StorageBalance>> {
+        self.view("storage_balance_of", json!({ "account_id": account_id })).await
+    }
+
+    pub async fn storage_balance_bounds(&self) -> Result<StorageBalanceBounds> {
+        self.view("storage_balance_bounds", json!({})).await
+    }
+
+    /// Ask the NFT contract whether the vault holds the NFT. Returns whether it does
+    pub async fn verify_custody(&self) -> Result<bool> {
+        self.call("verify_custody", json!({}), 0).await?.json()
+    }
+
+    /// Redeem the NFT, burning the signer's shares and paying `deposit` for the rest. Change is refunded
+    pub async fn redeem(&self, deposit: Balance) -> Result<Outcome> {
+        self.call("redeem", json!({}), deposit).await
+    }
+
+    /// Exchange the signer's shares for NEAR once the NFT is redeemed
+    pub async fn claim(&self) -> Result<Outcome> {
+        self.call("claim", json!({}), 0).await
+    }
+
+    /// Give up the signer's deed. A redeemed vault is deleted once its deed is burnt and every share claimed
+    pub async fn burn_deed(&self) -> Result<Outcome> {
+        self.call("burn_deed", json!({}), 0).await
+    }
+
+    /// Register `account_id` with the vault, paying the minimum storage deposit
+    pub async fn storage_deposit(&self, account_id: &str) -> Result<Outcome> {
+        let deposit = self.storage_balance_bounds().await?.min.0;
+        self.call("storage_deposit", json!({ "account_id": account_id, "registration_only": true }), deposit).await
+    }
+
+    /// Send shares of the signer to a registered account
+    pub async fn ft_transfer(&self, receiver_id: &str, amount: Balance, memo: Option<String>) -> Result<Outcome> {
+        self.call("ft_transfer", json!({ "receiver_id": receiver_id, "amount": U128(amount), "memo": memo }), 1).await
+    }
+}
@@ -0,0 +1,154 @@
+use fractose_client::sandbox::Sandbox;
+use fractose_client::{Client, Error, Event, Portfolio, Position, SecuritizeArgs, TrustTier};
+use fractose_sim::*;
+use futures::executor::block_on;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::Balance;
+
+const FACTORY: &str = "fractose.near";
+const NFT: &str = "nft.near";
+const TOKEN_ID: &str = "0";
+const ALICE: &str = "alice.near";
+const BOB: &str = "bob.near";
+
+const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+const SHARES_COUNT: Balance = 100;
+const EXIT_PRICE: Balance = 10 * NEAR;
+
+/// Client signing as Alice, who owns token 0 of the NFT contract and approved the factory
+fn setup() -> Client<Sandbox> {
+    let mut runtime = runtime();
+    runtime.deploy(FACTORY, FRACTOSE_WASM, 100 * NEAR);
+    runtime.deploy(NFT, MOCK_NFT_WASM, 10 * NEAR);
+    for user in &[ALICE, BOB] {
+        runtime.create_account(user, 100 * NEAR);
+    }
+
+    runtime.call(NFT, NFT, "new", json!({ "owner_id": ALICE }), 0, MAX_GAS).assert_success();
+    runtime.call(ALICE, NFT, "nft_mint", json!({
+        "token_id": TOKEN_ID,
+        "receiver_id": ALICE,
+        "token_metadata": { "title": "Mock #0" },
+    }), NEAR, MAX_GAS).assert_success();
+    runtime.call(ALICE, NFT, "nft_approve", json!({ "token_id": TOKEN_ID, "account_id": FACTORY }), NEAR / 100, MAX_GAS)
+        .assert_success();
+
+    Client::new(Sandbox::new(runtime, ALICE), FACTORY)
+}
+
+fn securitize_args() -> SecuritizeArgs {
+    SecuritizeArgs {
+        nft_contract_address: NFT.to_string(),
+        nft_token_id: TOKEN_ID.to_string(),
+        shares_count: U128(SHARES_COUNT),
+        decimals: 0,
+        exit_price: U128(EXIT_PRICE),
+        min_lock_duration: None,
+        pool_seed: None,
+    }
+}
+
+#[test]
+fn test_securitize() {
+    let client = setup();
+    let (vault_id, outcome) = block_on(client.factory().securitize(&securitize_args())).unwrap();
+    assert!(outcome.failures.is_empty(), "{:?}", outcome.failures);
+
+    // The vault logged its creation
+    assert!(outcome.events().contains(&(vault_id.clone(), Event::Securitize {
+        owner_id: ALICE.to_string(),
+        nft_contract_id: NFT.to_string(),
+        token_id: TOKEN_ID.to_string(),
+        vault_id: vault_id.clone(),
+    })));
+
+    let factory = client.factory();
+    assert_eq!(block_on(factory.get_vaults(0, 10)).unwrap(), vec![vault_id.clone()]);
+    assert_eq!(block_on(factory.nft_address_of(&vault_id)).unwrap(), Some(format!("{}/{}", NFT, TOKEN_ID)));
+    assert_eq!(block_on(factory.trust_tier_of(&vault_id)).unwrap(), Some(TrustTier::Unverified));
+    assert!(block_on(factory.check_custody(&vault_id)).unwrap());
+
+    let vault = client.vault(vault_id);
+    let metadata = block_on(vault.ft_metadata()).unwrap();
+    assert_eq!((metadata.nft_contract_address.as_str(), metadata.share_price.0), (NFT, EXIT_PRICE / SHARES_COUNT));
+    assert_eq!(block_on(vault.ft_total_supply()).unwrap(), SHARES_COUNT);
+    assert_eq!(block_on(vault.ft_balance_of(ALICE)).unwrap(), SHARES_COUNT);
+    assert_eq!(block_on(vault.exit_price()).unwrap(), EXIT_PRICE);
+    assert_eq!(block_on(vault.redeem_amount_of(BOB)).unwrap(), EXIT_PRICE);
+    assert_eq!(block_on(vault.redeemable_at()).unwrap(), 0);
+}
+
+#[test]
+fn test_transfer_redeem_and_claim() {
+    let client = setup();
+    let bob = Client::new(client.transport().as_account(BOB), FACTORY);
+    block_on(bob.factory().register_portfolio()).unwrap();
+    assert!(block_on(client.factory().is_portfolio_registered(BOB)).unwrap());
+    let (vault_id, _) = block_on(client.factory().securitize(&securitize_args())).unwrap();
+    let vault = client.vault(vault_id.clone());
+    assert!(block_on(vault.verify_custody()).unwrap());
+
+    // Alice sends part of her shares to Bob
+    assert_eq!(block_on(vault.storage_balance_of(BOB)).unwrap(), None);
+    block_on(vault.storage_deposit(BOB)).unwrap();
+    let outcome = block_on(vault.ft_transfer(BOB, 40, None)).unwrap();
+    assert_eq!(outcome.events(), vec![(vault_id.clone(), Event::Transfer {
+        sender_id: ALICE.to_string(),
+        receiver_id: BOB.to_string(),
+        amount: 40,
+    })]);
+    assert_eq!(block_on(client.factory().portfolio_of(BOB, 0, 10)).unwrap(), Portfolio {
+        positions: vec![Position {
+            vault_id: vault_id.clone(),
+            balance: U128(40),
+            share_price: U128(EXIT_PRICE / SHARES_COUNT),
+            value: U128(EXIT_PRICE * 40 / 100),
+        }],
+        truncated: false,
+    });
+
+    // Alice redeems by paying for Bob's shares
+    let quote = block_on(vault.redeem_quote(ALICE)).unwrap();
+    assert!(quote.redeemable, "{:?}", quote.reason);
+    assert_eq!((quote.shares_burnt.0, quote.near_required.0), (60, EXIT_PRICE * 40 / 100));
+    let payment = quote.near_required.0;
+    let outcome = block_on(vault.redeem(payment)).unwrap();
+    assert!(outcome.events().iter().any(|(_, event)| matches!(event, Event::Redeem { redeemer_id, .. } if redeemer_id == ALICE)));
+    assert_eq!(block_on(vault.vault_balance()).unwrap(), payment);
+    assert!(block_on(vault.redeem_amount_of(ALICE)).is_err());
+    assert_eq!(block_on(vault.redeem_quote(ALICE)).unwrap().reason.as_deref(), Some("token already redeemed"));
+
+    // Bob claims the payment, which is logged as a claim
+    let bob = Client::new(client.transport().as_account(BOB), FACTORY);
+    let bob_vault = bob.vault(vault_id.clone());
+    assert_eq!(block_on(bob_vault.vault_balance_of(BOB)).unwrap(), payment);
+    let balance = client.transport().runtime().balance(BOB);
+    let outcome = block_on(bob_vault.claim()).unwrap();
+    assert!(outcome.events().iter().any(|(_, event)| matches!(event, Event::Claim { claimant_id, shares: 40, .. } if claimant_id == BOB)));
+
+    assert!(client.transport().runtime().balance(BOB) > balance);
+
+    // Bob burnt the last shares, and once Alice gives up her deed the vault deletes itself and leaves her its balance
+    assert!(client.transport().runtime().exists(&vault_id));
+    let balance = client.transport().runtime().balance(ALICE);
+    block_on(vault.burn_deed()).unwrap();
+    let runtime = client.transport().runtime();
+    assert!(!runtime.exists(&vault_id));
+    assert!(runtime.balance(ALICE) > balance);
+}
+
+#[test]
+fn test_errors() {
+    let client = setup();
+
+    // Failed transactions and views report the contract's error
+    let mut args = securitize_args();
+    args.exit_price = U128(EXIT_PRICE + 1);
+    match block_on(client.factory().securitize(&args)) {
+        Err(Error::Execution(message)) => assert!(message.contains("share price cannot be fractional"), "{}", message),
+        result => panic!("unexpected result {:?}", result.map(|(vault_id, _)| vault_id)),
+    }
+    assert!(matches!(block_on(client.vault("missing.near").exit_price()), Err(Error::Execution(_))));
+    assert!(matches!(block_on(client.factory().get_vaults(0, 10)), Ok(vaults) if vaults.is_empty()));
+}
@@ -0,0 +1,331 @@
+//! Events logged by the factory and vaults.
+//!
+//! Fractose events and mints are logged as NEP-297 structured events, `EVENT_JSON:` followed by a JSON
+//! object. `parse` also decodes NEP-141 structured transfers and burns, ad-hoc logs of the NEP-141
+//! implementation such as `Transfer ...`, and ad-hoc logs of older vaults such as `Redeem(...)`.
+use std::fmt;
+
+use near_sdk::serde_json::{self, json, Value};
+use near_sdk::{AccountId, Balance};
+
+use crate::TokenId;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A vault was created by `owner_id`
+    Securitize { owner_id: AccountId, nft_contract_id: AccountId, token_id: TokenId, vault_id: AccountId },
+    /// The NFT was redeemed by `redeemer_id`
+    Redeem { redeemer_id: AccountId, nft_contract_id: AccountId, token_id: TokenId, vault_id: AccountId },
+    /// `claimant_id` exchanged `shares` for NEAR after the NFT was redeemed
+    Claim { claimant_id: AccountId, nft_contract_id: AccountId, token_id: TokenId, vault_id: AccountId, shares: Balance },
+    /// Shares were created for `owner_id`, when the vault was created
+    Mint { owner_id: AccountId, amount: Balance },
+    /// Shares moved between accounts
+    Transfer { sender_id: AccountId, receiver_id: AccountId, amount: Balance },
+    /// The receiver of `ft_transfer_call` returned unused shares
+    Refund { sender_id: AccountId, receiver_id: AccountId, amount: Balance },
+    /// Shares of `account_id` were burnt
+    Burn { account_id: AccountId, amount: Balance },
+    /// `account_id` unregistered from the vault, burning `balance` shares
+    AccountClosed { account_id: AccountId, balance: Balance },
+    /// The factory found that a vault doesn't hold its NFT
+    VaultInvalidated { vault_id: AccountId },
+    CustodyVerified { timestamp: u64, vault_id: AccountId },
+    CustodyMissing { owner_id: AccountId, vault_id: AccountId },
+    /// Price of a share in the pool of the vault
+    PoolPrice { share_price: Balance, vault_id: AccountId },
+    /// The NFT was sold on a marketplace to `buyer_id`, for `proceeds` paid to the vault
+    NftSold { buyer_id: AccountId, proceeds: Balance, vault_id: AccountId },
+    /// The price of a share changed, by a proposal or to the claim price of a buyout or market sale
+    SharePrice { share_price: Balance, vault_id: AccountId },
+}
+
+impl Event {
+    /// Decode a log. Logs which are not events give None, and structured logs holding several
+    /// events their first one
+    pub fn parse(log: &str) -> Option<Event> {
+        Self::parse_all(log).into_iter().next()
+    }
+
+    /// Decode every event of a log
+    pub fn parse_all(log: &str) -> Vec<Event> {
+        match log.strip_prefix(EVENT_JSON_PREFIX) {
+            Some(json) => parse_structured(json),
+            None => Self::parse_adhoc(log).into_iter().collect(),
+        }
+    }
+
+    /// Decode logs of the NEP-141 implementation, and events of vaults deployed before they were structured
+    fn parse_adhoc(log: &str) -> Option<Event> {
+        if let Some(rest) = log.strip_prefix("Transfer ") {
+            let (amount, rest) = rest.split_once(" from ")?;
+            let (sender_id, receiver_id) = rest.split_once(" to ")?;
+            return Some(Event::Transfer { sender_id: sender_id.into(), receiver_id: receiver_id.into(), amount: amount.parse().ok()? });
+        }
+        if let Some(rest) = log.strip_prefix("Refund ") {
+            let (amount, rest) = rest.split_once(" from ")?;
+            let (sender_id, receiver_id) = rest.split_once(" to ")?;
+            return Some(Event::Refund { sender_id: sender_id.into(), receiver_id: receiver_id.into(), amount: amount.parse().ok()? });
+        }
+        if let Some(rest) = log.strip_prefix("Account @") {
+            let (account_id, amount) = rest.split_once(" burned ")?;
+            return Some(Event::Burn { account_id: account_id.into(), amount: amount.parse().ok()? });
+        }
+        if let Some(rest) = log.strip_prefix("Closed @") {
+            let (account_id, balance) = rest.split_once(" with ")?;
+            return Some(Event::AccountClosed { account_id: account_id.into(), balance: balance.parse().ok()? });
+        }
+
+        let (name, args) = log.strip_suffix(')')?.split_once('(')?;
+        let args: Vec<&str> = args.split(", ").collect();
+        let event = match (name, args.as_slice()) {
+            ("Securitize", [owner_id, nft_contract_id, token_id, vault_id]) => Event::Securitize {
+                owner_id: owner_id.to_string(),
+                nft_contract_id: nft_contract_id.to_string(),
+                token_id: token_id.to_string(),
+                vault_id: vault_id.to_string(),
+            },
+            ("Redeem", [redeemer_id, nft_contract_id, token_id, vault_id]) => Event::Redeem {
+                redeemer_id: redeemer_id.to_string(),
+                nft_contract_id: nft_contract_id.to_string(),
+                token_id: token_id.to_string(),
+                vault_id: vault_id.to_string(),
+            },
+            ("Claim", [claimant_id, nft_contract_id, token_id, vault_id, shares]) => Event::Claim {
+                claimant_id: claimant_id.to_string(),
+                nft_contract_id: nft_contract_id.to_string(),
+                token_id: token_id.to_string(),
+                vault_id: vault_id.to_string(),
+                shares: shares.parse().ok()?,
+            },
+            ("VaultInvalidated", [vault_id]) => Event::VaultInvalidated { vault_id: vault_id.to_string() },
+            ("CustodyVerified", [timestamp, vault_id]) => Event::CustodyVerified { timestamp: timestamp.parse().ok()?, vault_id: vault_id.to_string() },
+            ("CustodyMissing", [owner_id, vault_id]) => Event::CustodyMissing { owner_id: owner_id.to_string(), vault_id: vault_id.to_string() },
+            ("PoolPrice", [share_price, vault_id]) => Event::PoolPrice { share_price: share_price.parse().ok()?, vault_id: vault_id.to_string() },
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+/// NEP-297 standard of the events of vaults and the factory
+const EVENT_STANDARD: &str = "fractose";
+
+/// Decode a NEP-297 event, which has one event per item of `data`. Only NEP-141 and Fractose events are known
+fn parse_structured(json: &str) -> Vec<Event> {
+    let event: Value = match serde_json::from_str(json) {
+        Ok(event) => event,
+        Err(_) => return vec![],
+    };
+    let parse_item = match event["standard"].as_str() {
+        Some("nep141") => parse_nep141,
+        Some(EVENT_STANDARD) => parse_fractose,
+        _ => return vec![],
+    };
+    let name = event["event"].as_str().unwrap_or_default();
+
+    event["data"].as_array().into_iter().flatten().filter_map(|item| parse_item(name, item)).collect()
+}
+
+fn string(item: &Value, key: &str) -> Option<String> {
+    item[key].as_str().map(str::to_string)
+}
+
+/// Balances and timestamps are strings, as they may not fit JSON numbers
+fn number<T: std::str::FromStr>(item: &Value, key: &str) -> Option<T> {
+    item[key].as_str()?.parse().ok()
+}
+
+fn parse_nep141(name: &str, item: &Value) -> Option<Event> {
+    match name {
+        "ft_mint" => Some(Event::Mint { owner_id: string(item, "owner_id")?, amount: number(item, "amount")? }),
+        "ft_burn" => Some(Event::Burn { account_id: string(item, "owner_id")?, amount: number(item, "amount")? }),
+        "ft_transfer" => Some(Event::Transfer {
+            sender_id: string(item, "old_owner_id")?,
+            receiver_id: string(item, "new_owner_id")?,
+            amount: number(item, "amount")?,
+        }),
+        _ => None,
+    }
+}
+
+fn parse_fractose(name: &str, item: &Value) -> Option<Event> {
+    let event = match name {
+        "securitize" => Event::Securitize {
+            owner_id: string(item, "owner_id")?,
+            nft_contract_id: string(item, "nft_contract_id")?,
+            token_id: string(item, "token_id")?,
+            vault_id: string(item, "vault_id")?,
+        },
+        "redeem" => Event::Redeem {
+            redeemer_id: string(item, "redeemer_id")?,
+            nft_contract_id: string(item, "nft_contract_id")?,
+            token_id: string(item, "token_id")?,
+            vault_id: string(item, "vault_id")?,
+        },
+        "claim" => Event::Claim {
+            claimant_id: string(item, "claimant_id")?,
+            nft_contract_id: string(item, "nft_contract_id")?,
+            token_id: string(item, "token_id")?,
+            vault_id: string(item, "vault_id")?,
+            shares: number(item, "shares")?,
+        },
+        "vault_invalidated" => Event::VaultInvalidated { vault_id: string(item, "vault_id")? },
+        "custody_verified" => Event::CustodyVerified { timestamp: number(item, "timestamp")?, vault_id: string(item, "vault_id")? },
+        "custody_missing" => Event::CustodyMissing { owner_id: string(item, "owner_id")?, vault_id: string(item, "vault_id")? },
+        "pool_price" => Event::PoolPrice { share_price: number(item, "share_price")?, vault_id: string(item, "vault_id")? },
+        "nft_sold" => Event::NftSold {
+            buyer_id: string(item, "buyer_id")?,
+            proceeds: number(item, "proceeds")?,
+            vault_id: string(item, "vault_id")?,
+        },
+        "share_price" => Event::SharePrice { share_price: number(item, "share_price")?, vault_id: string(item, "vault_id")? },
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Write a NEP-297 event with a single item
+fn write_structured(f: &mut fmt::Formatter, standard: &str, event: &str, item: Value) -> fmt::Result {
+    write!(f, "{}{}", EVENT_JSON_PREFIX, json!({
+        "standard": standard,
+        "version": "1.0.0",
+        "event": event,
+        "data": [item],
+    }))
+}
+
+/// Log written for the event, which `parse` decodes
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fractose = |f: &mut fmt::Formatter, event: &str, item: Value| write_structured(f, EVENT_STANDARD, event, item);
+        match self {
+            Event::Securitize { owner_id, nft_contract_id, token_id, vault_id } => fractose(f, "securitize", json!({
+                "owner_id": owner_id, "nft_contract_id": nft_contract_id, "token_id": token_id, "vault_id": vault_id,
+            })),
+            Event::Redeem { redeemer_id, nft_contract_id, token_id, vault_id } => fractose(f, "redeem", json!({
+                "redeemer_id": redeemer_id, "nft_contract_id": nft_contract_id, "token_id": token_id, "vault_id": vault_id,
+            })),
+            Event::Claim { claimant_id, nft_contract_id, token_id, vault_id, shares } => fractose(f, "claim", json!({
+                "claimant_id": claimant_id,
+                "nft_contract_id": nft_contract_id,
+                "token_id": token_id,
+                "vault_id": vault_id,
+                "shares": shares.to_string(),
+            })),
+            Event::Mint { owner_id, amount } => {
+                write_structured(f, "nep141", "ft_mint", json!({ "owner_id": owner_id, "amount": amount.to_string() }))
+            }
+            Event::Transfer { sender_id, receiver_id, amount } => write!(f, "Transfer {} from {} to {}", amount, sender_id, receiver_id),
+            Event::Refund { sender_id, receiver_id, amount } => write!(f, "Refund {} from {} to {}", amount, sender_id, receiver_id),
+            Event::Burn { account_id, amount } => write!(f, "Account @{} burned {}", account_id, amount),
+            Event::AccountClosed { account_id, balance } => write!(f, "Closed @{} with {}", account_id, balance),
+            Event::VaultInvalidated { vault_id } => fractose(f, "vault_invalidated", json!({ "vault_id": vault_id })),
+            Event::CustodyVerified { timestamp, vault_id } => {
+                fractose(f, "custody_verified", json!({ "timestamp": timestamp.to_string(), "vault_id": vault_id }))
+            }
+            Event::CustodyMissing { owner_id, vault_id } => fractose(f, "custody_missing", json!({ "owner_id": owner_id, "vault_id": vault_id })),
+            Event::PoolPrice { share_price, vault_id } => {
+                fractose(f, "pool_price", json!({ "share_price": share_price.to_string(), "vault_id": vault_id }))
+            }
+            Event::NftSold { buyer_id, proceeds, vault_id } => {
+                fractose(f, "nft_sold", json!({ "buyer_id": buyer_id, "proceeds": proceeds.to_string(), "vault_id": vault_id }))
+            }
+            Event::SharePrice { share_price, vault_id } => {
+                fractose(f, "share_price", json!({ "share_price": share_price.to_string(), "vault_id": vault_id }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Event::parse("Securitize(alice.near, nft.near, 0, nft-near-0.fractose.near)"), Some(Event::Securitize {
+            owner_id: "alice.near".into(),
+            nft_contract_id: "nft.near".into(),
+            token_id: "0".into(),
+            vault_id: "nft-near-0.fractose.near".into(),
+        }));
+        assert_eq!(Event::parse("Claim(bob.near, nft.near, 0, vault.near, 25)"), Some(Event::Claim {
+            claimant_id: "bob.near".into(),
+            nft_contract_id: "nft.near".into(),
+            token_id: "0".into(),
+            vault_id: "vault.near".into(),
+            shares: 25,
+        }));
+        assert!(matches!(Event::parse("Redeem(bob.near, nft.near, 0, vault.near)"), Some(Event::Redeem { .. })));
+        assert_eq!(Event::parse("Transfer 10 from alice.near to bob.near"), Some(Event::Transfer {
+            sender_id: "alice.near".into(),
+            receiver_id: "bob.near".into(),
+            amount: 10,
+        }));
+        assert_eq!(Event::parse("Account @bob.near burned 10"), Some(Event::Burn { account_id: "bob.near".into(), amount: 10 }));
+        assert_eq!(Event::parse("Closed @bob.near with 0"), Some(Event::AccountClosed { account_id: "bob.near".into(), balance: 0 }));
+        assert_eq!(Event::parse("VaultInvalidated(vault.near)"), Some(Event::VaultInvalidated { vault_id: "vault.near".into() }));
+        assert_eq!(Event::parse("CustodyVerified(5, vault.near)"), Some(Event::CustodyVerified { timestamp: 5, vault_id: "vault.near".into() }));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let events = vec![
+            Event::Securitize { owner_id: "a.near".into(), nft_contract_id: "nft.near".into(), token_id: "0".into(), vault_id: "v.near".into() },
+            Event::Redeem { redeemer_id: "a.near".into(), nft_contract_id: "nft.near".into(), token_id: "0".into(), vault_id: "v.near".into() },
+            Event::Claim { claimant_id: "a.near".into(), nft_contract_id: "nft.near".into(), token_id: "0".into(), vault_id: "v.near".into(), shares: 3 },
+            Event::Mint { owner_id: "a.near".into(), amount: 100 },
+            Event::Transfer { sender_id: "a.near".into(), receiver_id: "b.near".into(), amount: 1 },
+            Event::Refund { sender_id: "b.near".into(), receiver_id: "a.near".into(), amount: 1 },
+            Event::Burn { account_id: "a.near".into(), amount: 2 },
+            Event::AccountClosed { account_id: "a.near".into(), balance: 0 },
+            Event::VaultInvalidated { vault_id: "v.near".into() },
+            Event::CustodyVerified { timestamp: 7, vault_id: "v.near".into() },
+            Event::CustodyMissing { owner_id: "b.near".into(), vault_id: "v.near".into() },
+            Event::PoolPrice { share_price: 9, vault_id: "v.near".into() },
+            Event::NftSold { buyer_id: "b.near".into(), proceeds: 1_000_000_000_000_000_000_000_000, vault_id: "v.near".into() },
+            Event::SharePrice { share_price: 11, vault_id: "v.near".into() },
+            // Values which can't be split on ", " or don't fit JSON numbers
+            Event::Redeem { redeemer_id: "a.near".into(), nft_contract_id: "nft.near".into(), token_id: "a, b)".into(), vault_id: "v.near".into() },
+            Event::CustodyVerified { timestamp: u64::MAX, vault_id: "v.near".into() },
+        ];
+        for event in events {
+            assert_eq!(Event::parse(&event.to_string()), Some(event));
+        }
+    }
+
+    #[test]
+    fn test_parse_structured() {
+        let log = r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[
+            {"old_owner_id":"a.near","new_owner_id":"b.near","amount":"5"},
+            {"old_owner_id":"a.near","new_owner_id":"c.near","amount":"7","memo":"hi"}
+        ]}"#;
+        assert_eq!(Event::parse_all(log), vec![
+            Event::Transfer { sender_id: "a.near".into(), receiver_id: "b.near".into(), amount: 5 },
+            Event::Transfer { sender_id: "a.near".into(), receiver_id: "c.near".into(), amount: 7 },
+        ]);
+        assert_eq!(Event::parse(log), Some(Event::Transfer { sender_id: "a.near".into(), receiver_id: "b.near".into(), amount: 5 }));
+        assert_eq!(
+            Event::parse(r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_burn","data":[{"owner_id":"a.near","amount":"3"}]}"#),
+            Some(Event::Burn { account_id: "a.near".into(), amount: 3 })
+        );
+
+        // Other standards, unknown events and malformed items are skipped
+        assert!(Event::parse_all(r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"a.near","token_ids":["0"]}]}"#).is_empty());
+        assert!(Event::parse_all(r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_mint","data":[{"owner_id":"a.near","amount":3}]}"#).is_empty());
+        assert!(Event::parse_all("EVENT_JSON:{").is_empty());
+    }
+
+    #[test]
+    fn test_parse_other_logs() {
+        assert_eq!(Event::parse("Share price: 10"), None);
+        assert_eq!(Event::parse("Memo: hi"), None);
+        assert_eq!(Event::parse("Redeem(bob.near)"), None);
+        assert_eq!(Event::parse("Transfer ten from alice.near to bob.near"), None);
+        assert_eq!(Event::parse("Claim(bob.near, nft.near, 0, vault.near, many)"), None);
+        // Older vaults logged claims as `Securitize`, which only the indexer still decodes
+        assert_eq!(Event::parse("Securitize(bob.near, nft.near, 0, vault.near, 25)"), None);
+    }
+}
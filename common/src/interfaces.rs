@@ -0,0 +1,66 @@
+//! Cross-contract interfaces called by the factory and vaults
+// #[ext_contract] expands every method into a function which also takes the receiver, deposit and gas
+#![allow(clippy::too_many_arguments)]
+
+use near_sdk::ext_contract;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+
+use crate::{TokenId, TrustTier};
+
+/// Constructor of the shares contract, called by the factory on the account of a new vault
+#[ext_contract(ext_shares)]
+pub trait Shares {
+    fn create(
+        &mut self,
+        nft_contract_address: AccountId,
+        nft_token_id: TokenId,
+        owner_id: ValidAccountId,
+        shares_count: U128,
+        decimals: u8,
+        share_price: U128,
+        min_lock_until: Option<U64>,
+        pool_id: Option<ValidAccountId>,
+        pool_shares: Option<U128>,
+        trust_tier: Option<TrustTier>
+    ) -> Self;
+}
+
+/// Callback of the factory through which vaults report share balances for its portfolio index
+#[ext_contract(ext_factory)]
+pub trait Factory {
+    fn on_holdings_changed(&mut self, holdings: Vec<(AccountId, U128)>, share_price: U128);
+}
+
+#[ext_contract]
+pub trait NonFungibleTokenCore {
+    fn nft_transfer(
+        &mut self,
+        receiver_id: ValidAccountId,
+        token_id: TokenId,
+        approval_id: Option<U64>,
+        memo: Option<String>,
+    );
+}
+
+#[ext_contract]
+pub trait NonFungibleTokenApproval {
+    fn nft_approve(
+        &mut self,
+        token_id: TokenId,
+        account_id: ValidAccountId,
+        msg: Option<String>,
+    );
+}
+
+/// NEP-4907 style usage rights: `user_id` may use the token until `expires_at`, a block timestamp
+/// in nanoseconds, without being able to transfer it. Only the owner sets the user
+#[ext_contract]
+pub trait NonFungibleTokenUser {
+    fn nft_set_user(&mut self, token_id: TokenId, user_id: Option<ValidAccountId>, expires_at: U64);
+}
+
+/// Returns the NEP-171 token, which each caller decodes into the fields it reads
+#[ext_contract]
+pub trait NonFungibleTokenView {
+    fn nft_token(&self, token_id: TokenId);
+}
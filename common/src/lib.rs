@@ -0,0 +1,115 @@
+//! Types and interfaces of the Fractose contracts, shared by the contracts and their clients
+mod events;
+pub mod interfaces;
+mod shares_metadata;
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, AccountId, Balance, Gas, Promise, StorageUsage};
+
+pub use events::Event;
+pub use shares_metadata::{SharesMetadata, SharesMetadataProvider, TokenId, TrustTier, SHARES_FT_METADATA_SPEC};
+
+/// Deposit NEP-171 requires on `nft_transfer`
+pub const NFT_TRANSFER_DEPOSIT: Balance = 1;
+
+const TGAS: Gas = 1_000_000_000_000;
+
+/// NEAR a new vault account receives to cover its storage
+pub const VAULT_STORAGE_BALANCE: Balance = 6_000_000_000_000_000_000_000_000;
+/// NEAR a new pool account receives to cover its storage
+pub const POOL_STORAGE_BALANCE: Balance = 5_000_000_000_000_000_000_000_000;
+
+/// Gas given to `nft_transfer` on NFT contracts
+pub const GAS_FOR_NFT_TRANSFER: Gas = 20_000_000_000_000;
+/// Gas given to `nft_token` on NFT contracts
+pub const GAS_FOR_NFT_TOKEN: Gas = 10_000_000_000_000;
+/// Gas given to `create` on a new vault, including the NFT metadata fetch and holdings report it schedules
+pub const GAS_FOR_SHARES_CREATE: Gas = 50_000_000_000_000;
+/// Gas given to `on_holdings_changed` on the factory
+pub const GAS_FOR_HOLDINGS_REPORT: Gas = 5_000_000_000_000;
+
+/// Panic unless the call was given `required` gas. Methods scheduling calls with fixed gas check
+/// it on entry, so that too little gas fails the call itself rather than one of its receipts
+pub fn assert_prepaid_gas(required: Gas) {
+    assert!(
+        env::prepaid_gas() >= required,
+        "not enough gas, attach at least {} TGas", required.div_ceil(TGAS)
+    );
+}
+
+/// Charge the attached deposit for `storage_used` bytes added by the call and refund the rest to
+/// the caller
+pub fn refund_deposit(storage_used: StorageUsage) {
+    let required = Balance::from(storage_used) * env::storage_byte_cost();
+    let attached = env::attached_deposit();
+    assert!(attached >= required, "attach at least {} yoctoNEAR to cover storage", required);
+
+    let refund = attached - required;
+    if refund > 1 {
+        Promise::new(env::predecessor_account_id()).transfer(refund);
+    }
+}
+
+/// Liquidity to seed the pool deployed alongside a vault. Both amounts can be zero
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolSeed {
+    /// Taken from the minted shares
+    pub shares: U128,
+    /// Taken from the attached deposit
+    pub near: U128,
+}
+
+/// Deposit `securitize` must be attached: the balances of the new accounts, the NFT transfer
+/// deposit and the NEAR seeding the pool
+pub fn securitize_deposit(pool_seed: Option<&PoolSeed>) -> Balance {
+    VAULT_STORAGE_BALANCE + NFT_TRANSFER_DEPOSIT + pool_seed.map_or(0, |seed| POOL_STORAGE_BALANCE + seed.near.0)
+}
+
+/// What `redeem` would take and pay out if an account called it now, as returned by `redeem_quote`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RedeemQuote {
+    /// Shares of the account which are burnt, including unsold shares of its primary sale
+    pub shares_burnt: U128,
+    /// NEAR to attach: the value of the other holders' shares
+    pub near_required: U128,
+    /// Deposit attached to the NFT transfer, paid by the vault
+    pub nft_transfer_deposit: U128,
+    /// Storage added by the redemption, such as the vault deed, paid by the vault
+    pub storage_cost: U128,
+    /// NEAR sent back to the account. A redeemer holding every share gets the balance of the deleted vault
+    pub refund: U128,
+    /// Earliest block timestamp in nanoseconds at which the NFT can be redeemed, after the lock and any rental
+    pub redeemable_at: U64,
+    pub redeemable: bool,
+    /// Why `redeem` would fail now, if it would
+    pub reason: Option<String>,
+}
+
+/// Deposit which covers the portfolio index of any account on the factory, attached to `register_portfolio`.
+/// The unused part is refunded
+pub const PORTFOLIO_STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000;
+
+/// Shares an account holds in a vault, as returned by `portfolio_of` on the factory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Position {
+    pub vault_id: AccountId,
+    pub balance: U128,
+    /// Last share price reported by the vault: the exit price per share, or the NEAR claimable per share once redeemed
+    pub share_price: U128,
+    /// `balance` at `share_price`
+    pub value: U128,
+}
+
+/// Page of the positions of an account, as returned by `portfolio_of` on the factory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Portfolio {
+    pub positions: Vec<Position>,
+    /// A position was left out since the portfolio was registered, as it already held
+    /// `MAX_PORTFOLIO_VAULTS` vaults. The portfolio then understates the account's holdings
+    pub truncated: bool,
+}
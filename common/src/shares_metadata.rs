@@ -1,12 +1,21 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::{Base64VecU8, U128};
+use near_sdk::json_types::{Base64VecU8, U64, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::AccountId;
 
 pub const SHARES_FT_METADATA_SPEC: &str = "shares-ft-1.0.0";
 pub type TokenId = String;
 
-#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+/// Whether the NFT contract was allowlisted by the Fractose factory when the NFT was securitized
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TrustTier {
+    Verified,
+    Unverified,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SharesMetadata {
     pub spec: String,
@@ -20,7 +29,10 @@ pub struct SharesMetadata {
     pub nft_contract_address: AccountId,
     pub nft_token_id: TokenId,
     pub share_price: U128,
-    pub released: bool
+    pub released: bool,
+    /// NFT can't be redeemed before this block timestamp, in nanoseconds
+    pub min_lock_until: Option<U64>,
+    pub trust_tier: TrustTier
 }
 
 pub trait SharesMetadataProvider {
@@ -1,6 +1,6 @@
 #!/bin/bash
 set -e
 
-RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
+RUSTFLAGS="-C link-arg=-s --remap-path-prefix=${CARGO_HOME:-$HOME/.cargo}=/cargo" cargo build --target wasm32-unknown-unknown --release
 
-cp target/wasm32-unknown-unknown/release/fractose.wasm ./res/fractose.wasm
+cp ../target/wasm32-unknown-unknown/release/fractose.wasm ./res/fractose.wasm
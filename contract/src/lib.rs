@@ -1,53 +1,96 @@
+// #[ext_contract] expands every method into a function which also takes the receiver, deposit and gas
+#![allow(clippy::too_many_arguments)]
+
 use std::convert::TryInto;
 
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
-    collections::LookupMap,
+    collections::{LazyOption, LookupMap, LookupSet, UnorderedMap, UnorderedSet},
     ext_contract, near_bindgen,
     setup_alloc, log, BorshStorageKey,
-    env, Promise, AccountId,
-    json_types::{ValidAccountId, U64, U128},
+    assert_one_yocto, env, Promise, AccountId, Balance, Gas, StorageUsage,
+    json_types::{Base64VecU8, ValidAccountId, U64, U128},
+    serde::Deserialize,
+    serde_json::json,
+};
+
+use fractose_common::interfaces::{ext_shares, non_fungible_token_core, non_fungible_token_view};
+use fractose_common::{
+    assert_prepaid_gas, refund_deposit, Event, GAS_FOR_NFT_TOKEN, GAS_FOR_NFT_TRANSFER, GAS_FOR_SHARES_CREATE, NFT_TRANSFER_DEPOSIT,
+    POOL_STORAGE_BALANCE, VAULT_STORAGE_BALANCE,
 };
+pub use fractose_common::{securitize_deposit, PoolSeed, Portfolio, Position, TokenId, TrustTier, PORTFOLIO_STORAGE_DEPOSIT};
 
 setup_alloc!();
 
-pub type TokenId = String;
 pub type AccountAndTokenId = String;
 
-#[ext_contract]
-pub trait Shares {
-    fn create(&mut self,
-        nft_contract_address: AccountId,
-        nft_token_id: TokenId,
-        owner_id: ValidAccountId,
-        shares_count: U128,
-        decimals: u8,
-        share_price: U128
-    ) -> Self;
+/// Gas used by `securitize` itself, deploying the vault and scheduling its calls. Most of it
+/// prepays the execution fees of deploying the vault code
+pub const GAS_FOR_SECURITIZE: Gas = 80_000_000_000_000;
+/// Gas `securitize` uses on top of that to read the pool code and deploy a seeded pool
+pub const GAS_FOR_POOL_DEPLOY: Gas = 45_000_000_000_000;
+/// Gas given to `new` on a seeded pool
+pub const GAS_FOR_POOL_NEW: Gas = 10_000_000_000_000;
+const GAS_FOR_CHECK_CUSTODY: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_CUSTODY_CHECK: Gas = 5_000_000_000_000;
+/// Most vaults the portfolio of an account lists. Positions in further vaults are left out, and
+/// the portfolio is flagged as truncated
+pub const MAX_PORTFOLIO_VAULTS: u64 = 20;
+
+#[ext_contract(ext_self)]
+pub trait Fractose {
+    fn on_custody_check(&mut self, shares_contract: AccountId) -> bool;
 }
 
-#[ext_contract]
-pub trait NonFungibleTokenCore {
-    fn nft_transfer(
-        &mut self,
-        receiver_id: ValidAccountId,
-        token_id: TokenId,
-        approval_id: Option<U64>,
-        memo: Option<String>,
-    );
+/// Token as returned by `nft_token`. Only the owner is read
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftToken {
+    pub owner_id: AccountId,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
 enum StorageKeyEnum {
     NftToSharesAddress,
     SharesToNftAddress,
+    PoolCode,
+    InvalidVaults,
+    AllowedContracts,
+    DeniedContracts,
+    TrustTiers,
+    Vaults,
+    Holdings,
+    HoldingsOf { account_hash: Vec<u8> },
+    SharePrices,
+    TruncatedPortfolios,
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Fractose {
     pub nft_to_shares_address: LookupMap<AccountAndTokenId, AccountId>,
-    pub shares_to_nft_address: LookupMap<AccountId, AccountAndTokenId>
+    pub shares_to_nft_address: LookupMap<AccountId, AccountAndTokenId>,
+    /// Wasm of the shares pool contract, deployed by `securitize` on request
+    pub pool_code: LazyOption<Vec<u8>>,
+    /// Vaults found not to hold their NFT
+    pub invalid_vaults: LookupSet<AccountId>,
+    /// NFT contracts whose vaults are verified
+    pub allowed_contracts: UnorderedSet<AccountId>,
+    /// NFT contracts which can't be securitized
+    pub denied_contracts: UnorderedSet<AccountId>,
+    /// If false, only allowlisted NFT contracts can be securitized
+    pub permissionless: bool,
+    pub trust_tiers: LookupMap<AccountId, TrustTier>,
+    /// Every vault created by this factory, in creation order
+    pub vaults: UnorderedSet<AccountId>,
+    /// Share balance of each account in each vault, as reported by the vaults. Only accounts which
+    /// registered a portfolio are indexed
+    pub holdings: LookupMap<AccountId, UnorderedMap<AccountId, Balance>>,
+    /// Share price last reported by each vault
+    pub share_prices: LookupMap<AccountId, Balance>,
+    /// Portfolios which left out a position, as they were full
+    pub truncated_portfolios: LookupSet<AccountId>,
 }
 
 impl Default for Fractose {
@@ -55,6 +98,16 @@ impl Default for Fractose {
         Self {
             nft_to_shares_address: LookupMap::<AccountAndTokenId, AccountId>::new(StorageKeyEnum::NftToSharesAddress),
             shares_to_nft_address: LookupMap::<AccountId, AccountAndTokenId>::new(StorageKeyEnum::SharesToNftAddress),
+            pool_code: LazyOption::new(StorageKeyEnum::PoolCode, None),
+            invalid_vaults: LookupSet::new(StorageKeyEnum::InvalidVaults),
+            allowed_contracts: UnorderedSet::new(StorageKeyEnum::AllowedContracts),
+            denied_contracts: UnorderedSet::new(StorageKeyEnum::DeniedContracts),
+            permissionless: true,
+            trust_tiers: LookupMap::new(StorageKeyEnum::TrustTiers),
+            vaults: UnorderedSet::new(StorageKeyEnum::Vaults),
+            holdings: LookupMap::new(StorageKeyEnum::Holdings),
+            share_prices: LookupMap::new(StorageKeyEnum::SharePrices),
+            truncated_portfolios: LookupSet::new(StorageKeyEnum::TruncatedPortfolios),
         }
     }
 }
@@ -71,6 +124,11 @@ impl Fractose {
     /// - `shares_count`: Number of fungible shares to be created
     /// - `decimals`: Number of decimal places in share fungible tokens
     /// - `exit_price`: Underlying NFT can be retrieved by paying the exit price
+    /// - `min_lock_duration`: Optional time in nanoseconds for which the NFT cannot be redeemed
+    /// - `pool_seed`: If present, a shares/NEAR pool is deployed alongside the shares contract and seeded with this liquidity
+    ///
+    /// The attached deposit must cover `securitize_deposit`, which funds the new accounts and the
+    /// pool seed. The rest is refunded
     #[payable]
     pub fn securitize(
         &mut self,
@@ -78,18 +136,40 @@ impl Fractose {
         nft_token_id: TokenId,
         shares_count: U128,
         decimals: u8,
-        exit_price: U128
+        exit_price: U128,
+        min_lock_duration: Option<U64>,
+        pool_seed: Option<PoolSeed>
         ) {
+        assert_prepaid_gas(securitize_gas(pool_seed.is_some()));
         log!("Securitizing token {} from contract {}", nft_token_id, nft_contract_address);
 
         // Check whether parameters are valid
         assert!(exit_price.0 > 0, "invalid exit price");
         assert!(shares_count.0 > 0, "invalid shares count");
-        assert!(exit_price.0 % shares_count.0 == 0, "share price cannot be fractional");
+        assert_eq!(exit_price.0 % shares_count.0, 0, "share price cannot be fractional");
+
+        assert!(!self.denied_contracts.contains(&nft_contract_address), "NFT contract is denylisted");
+        let trust_tier = if self.allowed_contracts.contains(&nft_contract_address) {
+            TrustTier::Verified
+        } else {
+            assert!(self.permissionless, "NFT contract is not allowlisted");
+            TrustTier::Unverified
+        };
+
+        if let Some(seed) = &pool_seed {
+            assert!(seed.shares.0 <= shares_count.0, "pool seed exceeds shares count");
+            assert_eq!(seed.shares.0 > 0, seed.near.0 > 0, "pool must be seeded with both NEAR and shares");
+        }
+        let deposit = securitize_deposit(pool_seed.as_ref());
+        assert!(env::attached_deposit() >= deposit, "attach at least {} yoctoNEAR", deposit);
 
         let share_price = exit_price.0 / shares_count.0;
         log!("Share price: {}", share_price);
 
+        let min_lock_until = min_lock_duration.map(|duration| {
+            env::block_timestamp().checked_add(duration.0).expect("invalid lock duration").into()
+        });
+
         // Include NFT ID
         let shares_contract = get_shares_contract_name(
             nft_contract_address.clone(), nft_token_id.clone()
@@ -98,23 +178,49 @@ impl Fractose {
         // Deploy shares contract
         Promise::new(shares_contract.clone())
             .create_account()
-            .transfer(25_00000000000000000000000)
-            .add_full_access_key(env::signer_account_pk())
+            .transfer(VAULT_STORAGE_BALANCE)
             .deploy_contract(include_bytes!("../../shares/res/shares.wasm").to_vec());
 
         let owner: ValidAccountId = env::signer_account_id().try_into().unwrap();
 
+        // Deploy pool contract
+        let pool_contract = pool_seed.as_ref().map(|seed| {
+            let code = self.pool_code.get().expect("pool code not set");
+
+            let pool_contract = get_pool_contract_name(nft_contract_address.clone(), nft_token_id.clone());
+            Promise::new(pool_contract.clone())
+                .create_account()
+                .transfer(POOL_STORAGE_BALANCE)
+                .deploy_contract(code)
+                .function_call(
+                    b"new".to_vec(),
+                    json!({
+                        "shares_id": shares_contract,
+                        "owner_id": owner,
+                        "initial_shares": seed.shares,
+                    }).to_string().into_bytes(),
+                    seed.near.0,
+                    GAS_FOR_POOL_NEW
+                );
+
+            pool_contract
+        });
+
         // Call shares contract constructor
-        shares::create(
+        ext_shares::create(
             nft_contract_address.clone(),
             nft_token_id.clone(),
             owner,
             shares_count,
             decimals,
             share_price.into(),
+            min_lock_until,
+            pool_contract.map(|pool_contract| pool_contract.try_into().unwrap()),
+            pool_seed.map(|seed| seed.shares),
+            Some(trust_tier),
             &shares_contract,
             0,
-            env::prepaid_gas() / 3
+            GAS_FOR_SHARES_CREATE
         );
 
         // Save metadata
@@ -122,6 +228,8 @@ impl Fractose {
 
         self.nft_to_shares_address.insert(&nft_address, &shares_contract);
         self.shares_to_nft_address.insert(&shares_contract, &nft_address);
+        self.trust_tiers.insert(&shares_contract, &trust_tier);
+        self.vaults.insert(&shares_contract);
 
         non_fungible_token_core::nft_transfer(
             shares_contract.try_into().unwrap(),
@@ -129,11 +237,237 @@ impl Fractose {
             None,
             None,
             &nft_contract_address,
-            1,
-            env::prepaid_gas() / 3
+            NFT_TRANSFER_DEPOSIT,
+            GAS_FOR_NFT_TRANSFER
         );
+
+        let refund = env::attached_deposit() - deposit;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+    }
+
+    /// NFT address, as `nft_contract_address/nft_token_id`, of a shares contract created by this factory.
+    /// None if the account is not a Fractose vault or was marked invalid
+    pub fn nft_address_of(&self, shares_contract: AccountId) -> Option<AccountAndTokenId> {
+        if self.invalid_vaults.contains(&shares_contract) {
+            return None;
+        }
+        self.shares_to_nft_address.get(&shares_contract)
+    }
+
+    /// Trust tier of a vault created by this factory
+    pub fn trust_tier_of(&self, shares_contract: AccountId) -> Option<TrustTier> {
+        self.shares_to_nft_address.get(&shares_contract)
+            .map(|_| self.trust_tiers.get(&shares_contract).unwrap_or(TrustTier::Unverified))
+    }
+
+    pub fn get_allowed_contracts(&self) -> Vec<AccountId> {
+        self.allowed_contracts.to_vec()
+    }
+
+    pub fn get_denied_contracts(&self) -> Vec<AccountId> {
+        self.denied_contracts.to_vec()
+    }
+
+    pub fn is_permissionless(&self) -> bool {
+        self.permissionless
+    }
+
+    /// Vaults of allowlisted NFT contracts are verified. An allowlisted contract is removed from the denylist
+    #[private]
+    pub fn allow_contract(&mut self, nft_contract_address: ValidAccountId) {
+        self.denied_contracts.remove(nft_contract_address.as_ref());
+        self.allowed_contracts.insert(nft_contract_address.as_ref());
+    }
+
+    #[private]
+    pub fn disallow_contract(&mut self, nft_contract_address: ValidAccountId) {
+        self.allowed_contracts.remove(nft_contract_address.as_ref());
+    }
+
+    /// NFTs of denylisted contracts can't be securitized. A denylisted contract is removed from the allowlist
+    #[private]
+    pub fn deny_contract(&mut self, nft_contract_address: ValidAccountId) {
+        self.allowed_contracts.remove(nft_contract_address.as_ref());
+        self.denied_contracts.insert(nft_contract_address.as_ref());
+    }
+
+    #[private]
+    pub fn undeny_contract(&mut self, nft_contract_address: ValidAccountId) {
+        self.denied_contracts.remove(nft_contract_address.as_ref());
+    }
+
+    /// In permissionless mode, NFTs of contracts which are neither allowlisted nor denylisted
+    /// can be securitized into unverified vaults
+    #[private]
+    pub fn set_permissionless(&mut self, permissionless: bool) {
+        self.permissionless = permissionless;
+    }
+
+    /// Whether a vault was marked invalid by `check_custody`
+    pub fn is_invalid_vault(&self, shares_contract: AccountId) -> bool {
+        self.invalid_vaults.contains(&shares_contract)
+    }
+
+    /// Check with the NFT contract that a vault owns its NFT. Vaults which don't are marked invalid,
+    /// and vaults which own it again are restored. Anyone can call it
+    pub fn check_custody(&mut self, shares_contract: AccountId) -> Promise {
+        assert_prepaid_gas(GAS_FOR_CHECK_CUSTODY + GAS_FOR_NFT_TOKEN + GAS_FOR_ON_CUSTODY_CHECK);
+        let nft_address = self.shares_to_nft_address.get(&shares_contract).expect("not a Fractose vault");
+        let (nft_contract_address, nft_token_id) = split_nft_address(&nft_address);
+
+        non_fungible_token_view::nft_token(
+            nft_token_id,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_TOKEN
+        ).then(ext_self::on_custody_check(
+            shares_contract,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_CUSTODY_CHECK
+        ))
+    }
+
+    #[private]
+    pub fn on_custody_check(&mut self, shares_contract: AccountId, #[callback] token: Option<NftToken>) -> bool {
+        let in_custody = token.map(|token| token.owner_id == shares_contract).unwrap_or(false);
+
+        if in_custody {
+            self.invalid_vaults.remove(&shares_contract);
+        } else if self.invalid_vaults.insert(&shares_contract) {
+            log!("{}", Event::VaultInvalidated { vault_id: shares_contract.clone() });
+        }
+
+        in_custody
+    }
+
+    /// Shares contract of a securitized NFT
+    pub fn shares_address_of(&self, nft_contract_address: AccountId, nft_token_id: TokenId) -> Option<AccountId> {
+        self.nft_to_shares_address.get(&get_nft_address(nft_contract_address, nft_token_id))
     }
 
+    /// Vaults created by this factory, paginated in creation order
+    pub fn get_vaults(&self, from_index: Option<U64>, limit: Option<u64>) -> Vec<AccountId> {
+        let vaults = self.vaults.as_vector();
+        page(vaults.len(), from_index, limit).map(|index| vaults.get(index).unwrap()).collect()
+    }
+
+    /// Start indexing the positions of the caller. The attached deposit pays for the storage of
+    /// `MAX_PORTFOLIO_VAULTS` positions and the rest is refunded
+    #[payable]
+    pub fn register_portfolio(&mut self) {
+        let account_id = env::predecessor_account_id();
+        assert!(self.holdings.get(&account_id).is_none(), "portfolio already registered");
+        refund_deposit(self.internal_register_portfolio(&account_id));
+    }
+
+    /// Stop indexing the positions of the caller and get the portfolio deposit back
+    #[payable]
+    pub fn unregister_portfolio(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut vaults = self.holdings.get(&account_id).expect("portfolio not registered");
+        vaults.clear();
+        self.holdings.remove(&account_id);
+        self.truncated_portfolios.remove(&account_id);
+
+        // The deposit is measured again, as it was on registration
+        let storage_usage = self.internal_register_portfolio(&account_id);
+        self.holdings.remove(&account_id);
+        Promise::new(account_id).transfer(Balance::from(storage_usage) * env::storage_byte_cost() + 1);
+    }
+
+    pub fn is_portfolio_registered(&self, account_id: AccountId) -> bool {
+        self.holdings.get(&account_id).is_some()
+    }
+
+    /// Called by vaults with the new share balances of accounts and their current share price
+    pub fn on_holdings_changed(&mut self, holdings: Vec<(AccountId, U128)>, share_price: U128) {
+        let vault_id = env::predecessor_account_id();
+        assert!(self.vaults.contains(&vault_id), "not a Fractose vault");
+        self.share_prices.insert(&vault_id, &share_price.0);
+
+        for (account_id, balance) in holdings {
+            let mut vaults = match self.holdings.get(&account_id) {
+                Some(vaults) => vaults,
+                None => continue,
+            };
+            if balance.0 == 0 {
+                vaults.remove(&vault_id);
+            } else if vaults.get(&vault_id).is_some() || vaults.len() < MAX_PORTFOLIO_VAULTS {
+                vaults.insert(&vault_id, &balance.0);
+            } else {
+                self.truncated_portfolios.insert(&account_id);
+            }
+            self.holdings.insert(&account_id, &vaults);
+        }
+    }
+
+    /// Shares `account_id` holds in the vaults of this factory, valued at their share price. Only
+    /// vaults which report their holdings are included, once the account registered its portfolio.
+    /// `truncated` is set once a position was left out because the portfolio was full
+    pub fn portfolio_of(&self, account_id: AccountId, from_index: Option<U64>, limit: Option<u64>) -> Portfolio {
+        let vaults = match self.holdings.get(&account_id) {
+            Some(vaults) => vaults,
+            None => return Portfolio { positions: vec![], truncated: false },
+        };
+
+        let (vault_ids, balances) = (vaults.keys_as_vector(), vaults.values_as_vector());
+        let positions = page(vaults.len(), from_index, limit)
+            .map(|index| (vault_ids.get(index).unwrap(), balances.get(index).unwrap()))
+            .map(|(vault_id, balance)| {
+                let share_price = self.share_prices.get(&vault_id).unwrap_or(0);
+                Position {
+                    balance: balance.into(),
+                    share_price: share_price.into(),
+                    value: balance.saturating_mul(share_price).into(),
+                    vault_id,
+                }
+            })
+            .collect();
+        Portfolio { positions, truncated: self.truncated_portfolios.contains(&account_id) }
+    }
+
+    /// Store the wasm of the shares pool contract deployed by `securitize`
+    #[private]
+    pub fn set_pool_code(&mut self, code: Base64VecU8) {
+        self.pool_code.set(&code.into());
+    }
+}
+
+impl Fractose {
+    /// Create the empty portfolio of `account_id`. Returns the storage of the portfolio once it
+    /// holds `MAX_PORTFOLIO_VAULTS` positions and is truncated, measured with the longest vault IDs
+    fn internal_register_portfolio(&mut self, account_id: &AccountId) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let mut vaults = UnorderedMap::new(StorageKeyEnum::HoldingsOf { account_hash: env::sha256(account_id.as_bytes()) });
+        self.holdings.insert(account_id, &vaults);
+        self.truncated_portfolios.insert(account_id);
+        let portfolio_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.truncated_portfolios.remove(account_id);
+
+        let tmp_vault_id = "a".repeat(64);
+        let empty_storage_usage = env::storage_usage();
+        vaults.insert(&tmp_vault_id, &0);
+        let position_storage_usage = env::storage_usage() - empty_storage_usage;
+        vaults.remove(&tmp_vault_id);
+
+        portfolio_storage_usage + MAX_PORTFOLIO_VAULTS * position_storage_usage
+    }
+}
+
+/// Indices of the page starting at `from_index` with at most `limit` items, out of `len`
+fn page(len: u64, from_index: Option<U64>, limit: Option<u64>) -> std::ops::Range<u64> {
+    let from_index = from_index.map(|index| index.0).unwrap_or(0).min(len);
+    from_index..from_index.saturating_add(limit.unwrap_or(u64::MAX)).min(len)
+}
+
+/// Gas `securitize` must be given, with or without a seeded pool
+pub fn securitize_gas(seeded_pool: bool) -> Gas {
+    let pool_gas = if seeded_pool { GAS_FOR_POOL_DEPLOY + GAS_FOR_POOL_NEW } else { 0 };
+    GAS_FOR_SECURITIZE + pool_gas + GAS_FOR_SHARES_CREATE + GAS_FOR_NFT_TRANSFER
 }
 
 fn get_shares_contract_name(_target: String, token_id: TokenId) -> String {
@@ -141,10 +475,21 @@ fn get_shares_contract_name(_target: String, token_id: TokenId) -> String {
     format!("{}-{}.{}", prefix, token_id, env::current_account_id())
 }
 
+fn get_pool_contract_name(_target: String, token_id: TokenId) -> String {
+    let prefix = _target.replace(".", "-");
+    format!("{}-{}-pool.{}", prefix, token_id, env::current_account_id())
+}
+
 fn get_nft_address(contract_address: AccountId, token_id: TokenId) -> String {
     format!("{}/{}", contract_address, token_id)
 }
 
+/// Inverse of `get_nft_address`. Account IDs can't contain `/`, token IDs can
+fn split_nft_address(nft_address: &str) -> (AccountId, TokenId) {
+    let (contract_address, token_id) = nft_address.split_at(nft_address.find('/').unwrap());
+    (contract_address.to_string(), token_id[1..].to_string())
+}
+
 #[cfg(test)]
 mod tests {
     // Testing boilerplate
@@ -165,7 +510,7 @@ mod tests {
             account_balance: 10u128.pow(25),
             account_locked_balance: 0,
             storage_usage: 0,
-            attached_deposit: 0,
+            attached_deposit: securitize_deposit(None),
             prepaid_gas: 10u64.pow(18),
             random_seed: vec![0, 1, 2],
             is_view,
@@ -191,7 +536,9 @@ mod tests {
             nft_token_id.clone(),
             1000.into(),
             18,
-            10u128.pow(30).into()
+            10u128.pow(30).into(),
+            None,
+            None
         );
 
         let nft_address = get_nft_address(target_nft_contract.clone(), nft_token_id.clone());
@@ -203,5 +550,345 @@ mod tests {
         // Ensure that mappings are correctly saved
         assert_eq!(saved_shares_address.expect("Saved shares address did not match"), expected_shares_contract);
         assert_eq!(saved_nft_address.expect("Saved NFT address did not match"), nft_address);
+
+        // Registry views
+        assert_eq!(contract.nft_address_of(expected_shares_contract.clone()), Some(nft_address));
+        assert_eq!(contract.shares_address_of(target_nft_contract, nft_token_id), Some(expected_shares_contract.clone()));
+        assert_eq!(contract.nft_address_of("spoofed.testnet".to_string()), None);
+        assert_eq!(contract.trust_tier_of(expected_shares_contract.clone()), Some(TrustTier::Unverified));
+        assert_eq!(contract.get_vaults(None, None), vec![expected_shares_contract]);
+
+        // Vaults are only controlled by their code, nobody holds a key to them
+        let receipts = format!("{:?}", near_sdk::test_utils::get_created_receipts());
+        assert!(receipts.contains("DeployContractAction"), "Receipts: {}", receipts);
+        assert!(!receipts.contains("AddKeyAction"), "Receipts: {}", receipts);
+    }
+
+    #[test]
+    fn get_vaults() {
+        let mut contract = Fractose::default();
+        for token_id in &["0", "1", "2"] {
+            // Each call gets fresh gas
+            testing_env!(get_context(vec![], false));
+            contract.securitize("nft.testnet".to_string(), token_id.to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+        }
+
+        let vault = |token_id: &str| get_shares_contract_name("nft.testnet".to_string(), token_id.to_string());
+        assert_eq!(contract.get_vaults(None, None), vec![vault("0"), vault("1"), vault("2")]);
+        assert_eq!(contract.get_vaults(Some(1.into()), Some(1)), vec![vault("1")]);
+        assert!(contract.get_vaults(Some(3.into()), None).is_empty());
+    }
+
+    #[test]
+    fn portfolio_of() {
+        let mut contract = Fractose::default();
+        for token_id in &["0", "1"] {
+            testing_env!(get_context(vec![], false));
+            contract.securitize("nft.testnet".to_string(), token_id.to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+        }
+        let vault = |token_id: &str| get_shares_contract_name("nft.testnet".to_string(), token_id.to_string());
+        let report = |contract: &mut Fractose, vault_id: String, holdings: Vec<(&str, u128)>, share_price: u128| {
+            testing_env!(VMContext { predecessor_account_id: vault_id, storage_usage: env::storage_usage(), ..get_context(vec![], false) });
+            let holdings = holdings.into_iter().map(|(account_id, balance)| (account_id.to_string(), balance.into())).collect();
+            contract.on_holdings_changed(holdings, share_price.into());
+        };
+
+        let register = |contract: &mut Fractose, account_id: &str| {
+            testing_env!(VMContext {
+                predecessor_account_id: account_id.to_string(),
+                attached_deposit: PORTFOLIO_STORAGE_DEPOSIT,
+                storage_usage: env::storage_usage(),
+                ..get_context(vec![], false)
+            });
+            contract.register_portfolio();
+        };
+        register(&mut contract, "bob.testnet");
+        register(&mut contract, "carol.testnet");
+
+        // Accounts without a portfolio are left out
+        report(&mut contract, vault("0"), vec![("bob.testnet", 600), ("carol.testnet", 400), ("dave.testnet", 1)], 10);
+        assert!(contract.portfolio_of("dave.testnet".to_string(), None, None).positions.is_empty());
+        report(&mut contract, vault("1"), vec![("bob.testnet", 5)], 7);
+        assert_eq!(contract.portfolio_of("bob.testnet".to_string(), None, None).positions, vec![
+            Position { vault_id: vault("0"), balance: 600.into(), share_price: 10.into(), value: 6000.into() },
+            Position { vault_id: vault("1"), balance: 5.into(), share_price: 7.into(), value: 35.into() },
+        ]);
+
+        // Emptied positions leave the index, and share prices follow the vaults
+        report(&mut contract, vault("0"), vec![("bob.testnet", 0), ("carol.testnet", 1000)], 12);
+        assert_eq!(contract.portfolio_of("bob.testnet".to_string(), None, None).positions.len(), 1);
+        assert_eq!(contract.portfolio_of("carol.testnet".to_string(), None, None).positions[0].value.0, 12000);
+        report(&mut contract, vault("1"), vec![("bob.testnet", 0)], 7);
+        assert_eq!(contract.portfolio_of("bob.testnet".to_string(), None, None), Portfolio { positions: vec![], truncated: false });
+        assert!(contract.is_portfolio_registered("bob.testnet".to_string()));
+    }
+
+    #[test]
+    fn portfolio_is_bounded() {
+        let mut contract = Fractose::default();
+        let vault = |index: u64| format!("{}{}", "v".repeat(62), index);
+        testing_env!(get_context(vec![], false));
+        for index in 0..=MAX_PORTFOLIO_VAULTS {
+            contract.vaults.insert(&vault(index));
+            contract.share_prices.insert(&vault(index), &1);
+        }
+        let initial_storage_usage = env::storage_usage();
+
+        let account_id = "a".repeat(64);
+        testing_env!(VMContext {
+            predecessor_account_id: account_id.clone(),
+            attached_deposit: PORTFOLIO_STORAGE_DEPOSIT,
+            storage_usage: initial_storage_usage,
+            ..get_context(vec![], false)
+        });
+        contract.register_portfolio();
+
+        // The deposit covers the longest IDs, and the refund the rest
+        let receipts = format!("{:?}", near_sdk::test_utils::get_created_receipts());
+        let refunded: Balance = receipts.split("TransferAction { deposit: ").skip(1)
+            .map(|rest| rest.split(' ').next().unwrap().parse::<Balance>().unwrap())
+            .sum();
+        let paid = PORTFOLIO_STORAGE_DEPOSIT - refunded;
+        assert!(paid > 0);
+
+        for index in 0..=MAX_PORTFOLIO_VAULTS {
+            testing_env!(VMContext { predecessor_account_id: vault(index), storage_usage: env::storage_usage(), ..get_context(vec![], false) });
+            contract.on_holdings_changed(vec![(account_id.clone(), 1.into())], 1.into());
+        }
+        assert_eq!(contract.portfolio_of(account_id.clone(), None, None).positions.len() as u64, MAX_PORTFOLIO_VAULTS);
+        assert!(Balance::from(env::storage_usage() - initial_storage_usage) * env::storage_byte_cost() <= paid);
+
+        // Pages are read by index
+        let page = contract.portfolio_of(account_id, Some(U64(MAX_PORTFOLIO_VAULTS - 1)), Some(5));
+        assert_eq!(page.positions.len(), 1);
+    }
+
+    #[test]
+    fn portfolio_beyond_cap_is_truncated() {
+        let mut contract = Fractose::default();
+        let vault = |index: u64| format!("v{}.testnet", index);
+        testing_env!(get_context(vec![], false));
+        for index in 0..=MAX_PORTFOLIO_VAULTS {
+            contract.vaults.insert(&vault(index));
+        }
+        testing_env!(VMContext { predecessor_account_id: "bob.testnet".to_string(), attached_deposit: PORTFOLIO_STORAGE_DEPOSIT, ..get_context(vec![], false) });
+        contract.register_portfolio();
+        let report = |contract: &mut Fractose, index: u64, balance: u128| {
+            testing_env!(VMContext { predecessor_account_id: vault(index), storage_usage: env::storage_usage(), ..get_context(vec![], false) });
+            contract.on_holdings_changed(vec![("bob.testnet".to_string(), balance.into())], 10.into());
+        };
+
+        // A full portfolio is complete
+        for index in 0..MAX_PORTFOLIO_VAULTS {
+            report(&mut contract, index, 1);
+        }
+        assert!(!contract.portfolio_of("bob.testnet".to_string(), None, None).truncated);
+
+        // The position beyond the cap is left out, and the portfolio says so
+        report(&mut contract, MAX_PORTFOLIO_VAULTS, 1);
+        let portfolio = contract.portfolio_of("bob.testnet".to_string(), None, None);
+        assert_eq!((portfolio.positions.len() as u64, portfolio.truncated), (MAX_PORTFOLIO_VAULTS, true));
+        assert!(portfolio.positions.iter().all(|position| position.vault_id != vault(MAX_PORTFOLIO_VAULTS)));
+
+        // Freeing a slot doesn't bring the position back until its vault reports again
+        report(&mut contract, 0, 0);
+        assert!(contract.portfolio_of("bob.testnet".to_string(), None, None).truncated);
+
+        // Registering again starts a complete portfolio
+        testing_env!(VMContext {
+            predecessor_account_id: "bob.testnet".to_string(),
+            attached_deposit: 1,
+            storage_usage: env::storage_usage(),
+            ..get_context(vec![], false)
+        });
+        contract.unregister_portfolio();
+        testing_env!(VMContext {
+            predecessor_account_id: "bob.testnet".to_string(),
+            attached_deposit: PORTFOLIO_STORAGE_DEPOSIT,
+            storage_usage: env::storage_usage(),
+            ..get_context(vec![], false)
+        });
+        contract.register_portfolio();
+        assert_eq!(contract.portfolio_of("bob.testnet".to_string(), None, None), Portfolio { positions: vec![], truncated: false });
+    }
+
+    #[test]
+    fn unregister_portfolio() {
+        let mut contract = Fractose::default();
+        testing_env!(VMContext { predecessor_account_id: "bob.testnet".to_string(), attached_deposit: PORTFOLIO_STORAGE_DEPOSIT, ..get_context(vec![], false) });
+        contract.register_portfolio();
+        let storage_usage = env::storage_usage();
+
+        testing_env!(VMContext {
+            predecessor_account_id: "bob.testnet".to_string(),
+            attached_deposit: 1,
+            storage_usage,
+            ..get_context(vec![], false)
+        });
+        contract.unregister_portfolio();
+        assert!(!contract.is_portfolio_registered("bob.testnet".to_string()));
+        assert_eq!(env::storage_usage(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a Fractose vault")]
+    fn holdings_reported_by_other_contract() {
+        let mut contract = Fractose::default();
+        testing_env!(get_context(vec![], false));
+        contract.on_holdings_changed(vec![("bob.testnet".to_string(), 1.into())], 1.into());
+    }
+
+    #[test]
+    fn securitize_allowlisted_nft() {
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context);
+
+        let mut contract = Fractose::default();
+        contract.allow_contract("nft.testnet".try_into().unwrap());
+        contract.set_permissionless(false);
+
+        contract.securitize("nft.testnet".to_string(), "0".to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+
+        let shares_contract = get_shares_contract_name("nft.testnet".to_string(), "0".to_string());
+        assert_eq!(contract.trust_tier_of(shares_contract), Some(TrustTier::Verified));
+        assert_eq!(contract.get_allowed_contracts(), vec!["nft.testnet".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT contract is not allowlisted")]
+    fn securitize_unlisted_nft_without_permissionless_mode() {
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context);
+
+        let mut contract = Fractose::default();
+        contract.set_permissionless(false);
+        contract.securitize("nft.testnet".to_string(), "0".to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT contract is denylisted")]
+    fn securitize_denylisted_nft() {
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context);
+
+        let mut contract = Fractose::default();
+        contract.allow_contract("nft.testnet".try_into().unwrap());
+        contract.deny_contract("nft.testnet".try_into().unwrap());
+        assert!(contract.get_allowed_contracts().is_empty());
+
+        contract.securitize("nft.testnet".to_string(), "0".to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+    }
+
+    #[test]
+    fn securitize_nft_with_pool() {
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = context.current_account_id.clone();
+        let seed = PoolSeed { shares: 100.into(), near: 10u128.pow(24).into() };
+        context.attached_deposit = securitize_deposit(Some(&seed));
+        testing_env!(context);
+
+        let target_nft_contract = "nft.testnet".to_string();
+        let nft_token_id = "0".to_string();
+
+        let mut contract = Fractose::default();
+        contract.set_pool_code(vec![0, 1, 2].into());
+
+        contract.securitize(
+            target_nft_contract.clone(),
+            nft_token_id.clone(),
+            1000.into(),
+            18,
+            10u128.pow(30).into(),
+            None,
+            Some(seed)
+        );
+
+        let expected_pool_contract = get_pool_contract_name(target_nft_contract, nft_token_id);
+        let receipts = format!("{:?}", near_sdk::test_utils::get_created_receipts());
+        assert!(receipts.contains(&format!("receiver_id: {:?}", expected_pool_contract)), "Receipts: {}", receipts);
+    }
+
+    #[test]
+    fn check_custody() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+
+        let mut contract = Fractose::default();
+        contract.securitize("nft.testnet".to_string(), "0".to_string(), 1000.into(), 18, 10u128.pow(30).into(), None, None);
+        let shares_contract = get_shares_contract_name("nft.testnet".to_string(), "0".to_string());
+        assert_eq!(split_nft_address("nft.testnet/a/b"), ("nft.testnet".to_string(), "a/b".to_string()));
+
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context);
+        contract.check_custody(shares_contract.clone());
+
+        assert!(!contract.on_custody_check(shares_contract.clone(), Some(NftToken { owner_id: "thief.testnet".to_string() })));
+        assert!(contract.is_invalid_vault(shares_contract.clone()));
+        assert_eq!(contract.nft_address_of(shares_contract.clone()), None);
+
+        assert!(contract.on_custody_check(shares_contract.clone(), Some(NftToken { owner_id: shares_contract.clone() })));
+        assert!(!contract.is_invalid_vault(shares_contract.clone()));
+        assert!(contract.nft_address_of(shares_contract).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "attach at least")]
+    fn securitize_nft_with_pool_without_deposit() {
+        // The deposit covers the vault but not the pool account and seed
+        testing_env!(get_context(vec![], false));
+
+        let mut contract = Fractose::default();
+        contract.set_pool_code(vec![0, 1, 2].into());
+        contract.securitize(
+            "nft.testnet".to_string(),
+            "0".to_string(),
+            1000.into(),
+            18,
+            10u128.pow(30).into(),
+            None,
+            Some(PoolSeed { shares: 100.into(), near: 10u128.pow(24).into() })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "pool must be seeded with both NEAR and shares")]
+    fn securitize_nft_with_one_sided_pool_seed() {
+        let mut context = get_context(vec![], false);
+        context.attached_deposit = 10u128.pow(26);
+        testing_env!(context);
+
+        let mut contract = Fractose::default();
+        contract.set_pool_code(vec![0, 1, 2].into());
+        contract.securitize(
+            "nft.testnet".to_string(),
+            "0".to_string(),
+            1000.into(),
+            18,
+            10u128.pow(30).into(),
+            None,
+            Some(PoolSeed { shares: 100.into(), near: 0.into() })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "pool code not set")]
+    fn securitize_nft_with_pool_without_code() {
+        let mut context = get_context(vec![], false);
+        context.attached_deposit = securitize_deposit(Some(&PoolSeed { shares: 0.into(), near: 0.into() }));
+        testing_env!(context);
+
+        let mut contract = Fractose::default();
+        contract.securitize(
+            "nft.testnet".to_string(),
+            "0".to_string(),
+            1000.into(),
+            18,
+            10u128.pow(30).into(),
+            None,
+            Some(PoolSeed { shares: 0.into(), near: 0.into() })
+        );
     }
 }
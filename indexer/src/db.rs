@@ -0,0 +1,427 @@
+//! SQLite store of the indexed state. Balances and prices are stored as decimal strings, as
+//! they don't fit SQLite integers
+use std::path::Path;
+
+use fractose_common::Event;
+use rusqlite::types::{Type, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::{AccountId, Balance, Block, Error, Receipt, Result};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS vaults (
+    vault_id TEXT PRIMARY KEY,
+    nft_contract_id TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    owner_id TEXT NOT NULL,
+    share_price TEXT,
+    created_height INTEGER NOT NULL,
+    custody_verified_at INTEGER,
+    invalidated INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS holders (
+    vault_id TEXT NOT NULL,
+    account_id TEXT NOT NULL,
+    balance TEXT NOT NULL,
+    PRIMARY KEY (vault_id, account_id)
+);
+CREATE TABLE IF NOT EXISTS redemptions (
+    vault_id TEXT PRIMARY KEY,
+    redeemer_id TEXT NOT NULL,
+    block_height INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    receipt_id TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS claims (
+    vault_id TEXT NOT NULL,
+    claimant_id TEXT NOT NULL,
+    shares TEXT NOT NULL,
+    block_height INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    receipt_id TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS prices (
+    vault_id TEXT NOT NULL,
+    share_price TEXT NOT NULL,
+    source TEXT NOT NULL,
+    block_height INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+-- Share prices logged by the factory for vaults it is creating
+CREATE TABLE IF NOT EXISTS pending_prices (
+    vault_id TEXT PRIMARY KEY,
+    share_price TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cursor (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    block_height INTEGER NOT NULL
+);
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vault {
+    pub vault_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: String,
+    /// Account which securitized the NFT
+    pub owner_id: AccountId,
+    /// Share price set at securitization, if the factory's logs were indexed
+    pub share_price: Option<Balance>,
+    pub created_height: u64,
+    /// Block timestamp at which the vault last proved it holds the NFT
+    pub custody_verified_at: Option<u64>,
+    /// The factory found that the vault doesn't hold its NFT
+    pub invalidated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redemption {
+    pub vault_id: AccountId,
+    pub redeemer_id: AccountId,
+    pub block_height: u64,
+    pub timestamp: u64,
+    pub receipt_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claim {
+    pub vault_id: AccountId,
+    pub claimant_id: AccountId,
+    /// Shares burnt for NEAR
+    pub shares: Balance,
+    pub block_height: u64,
+    pub timestamp: u64,
+    pub receipt_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceSource {
+    /// Exit price divided by the shares, set when the NFT was securitized
+    Securitize,
+    /// Price of the shares in the vault's pool
+    Pool,
+    /// Price set by the vault: by a passed proposal, or the claim price of a buyout or market sale
+    Vault,
+}
+
+impl PriceSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            PriceSource::Securitize => "securitize",
+            PriceSource::Pool => "pool",
+            PriceSource::Vault => "vault",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Price {
+    pub vault_id: AccountId,
+    /// NEAR per share, in yocto per smallest unit of the shares
+    pub share_price: Balance,
+    pub source: PriceSource,
+    pub block_height: u64,
+    pub timestamp: u64,
+}
+
+/// Indexes the vaults of one factory
+pub struct Indexer {
+    conn: Connection,
+    factory_id: AccountId,
+}
+
+impl Indexer {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>, factory_id: impl Into<AccountId>) -> Result<Self> {
+        Self::new(Connection::open(path)?, factory_id.into())
+    }
+
+    pub fn open_in_memory(factory_id: impl Into<AccountId>) -> Result<Self> {
+        Self::new(Connection::open_in_memory()?, factory_id.into())
+    }
+
+    fn new(conn: Connection, factory_id: AccountId) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn, factory_id })
+    }
+
+    /// Height of the last indexed block
+    pub fn last_height(&self) -> Result<Option<u64>> {
+        Ok(self.conn.query_row("SELECT block_height FROM cursor", [], |row| row.get(0)).optional()?)
+    }
+
+    /// Apply the events of a block. Blocks at or below the last indexed height are skipped, so
+    /// that dumps can be indexed again. Returns whether the block was applied
+    pub fn index_block(&mut self, block: &Block) -> Result<bool> {
+        if self.last_height()?.is_some_and(|height| block.height <= height) {
+            return Ok(false);
+        }
+
+        let tx = self.conn.transaction()?;
+        for receipt in &block.receipts {
+            if receipt.executor_id == self.factory_id {
+                apply_factory_logs(&tx, &self.factory_id, receipt)?;
+            } else {
+                for event in receipt.logs.iter().flat_map(|log| parse_vault_log(log)) {
+                    apply_vault_event(&tx, &self.factory_id, block, receipt, event)?;
+                }
+            }
+        }
+        tx.execute("INSERT OR REPLACE INTO cursor (id, block_height) VALUES (0, ?1)", params![block.height])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Index blocks in order. Returns how many were applied
+    pub fn index_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a Block>) -> Result<usize> {
+        let mut applied = 0;
+        for block in blocks {
+            applied += self.index_block(block)? as usize;
+        }
+        Ok(applied)
+    }
+
+    pub fn vaults(&self) -> Result<Vec<Vault>> {
+        let mut statement = self.conn.prepare(&format!("{} ORDER BY created_height, vault_id", VAULT_QUERY))?;
+        let vaults = statement.query_map([], vault_from_row)?.collect::<rusqlite::Result<_>>()?;
+        Ok(vaults)
+    }
+
+    pub fn vault(&self, vault_id: &str) -> Result<Option<Vault>> {
+        Ok(self.conn.query_row(&format!("{} WHERE vault_id = ?1", VAULT_QUERY), params![vault_id], vault_from_row).optional()?)
+    }
+
+    /// Accounts holding shares of a vault, with their balance
+    pub fn holders(&self, vault_id: &str) -> Result<Vec<(AccountId, Balance)>> {
+        let mut statement = self.conn.prepare("SELECT account_id, balance FROM holders WHERE vault_id = ?1 ORDER BY account_id")?;
+        let holders = statement.query_map(params![vault_id], |row| Ok((row.get(0)?, balance_column(row, 1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(holders)
+    }
+
+    pub fn redemption(&self, vault_id: &str) -> Result<Option<Redemption>> {
+        Ok(self.conn.query_row(
+            "SELECT vault_id, redeemer_id, block_height, timestamp, receipt_id FROM redemptions WHERE vault_id = ?1",
+            params![vault_id],
+            |row| Ok(Redemption {
+                vault_id: row.get(0)?,
+                redeemer_id: row.get(1)?,
+                block_height: row.get(2)?,
+                timestamp: row.get(3)?,
+                receipt_id: row.get(4)?,
+            }),
+        ).optional()?)
+    }
+
+    pub fn claims(&self, vault_id: &str) -> Result<Vec<Claim>> {
+        let mut statement = self.conn.prepare(
+            "SELECT vault_id, claimant_id, shares, block_height, timestamp, receipt_id FROM claims WHERE vault_id = ?1 ORDER BY rowid"
+        )?;
+        let claims = statement.query_map(params![vault_id], |row| Ok(Claim {
+            vault_id: row.get(0)?,
+            claimant_id: row.get(1)?,
+            shares: balance_column(row, 2)?,
+            block_height: row.get(3)?,
+            timestamp: row.get(4)?,
+            receipt_id: row.get(5)?,
+        }))?.collect::<rusqlite::Result<_>>()?;
+        Ok(claims)
+    }
+
+    /// Share prices of a vault, oldest first
+    pub fn prices(&self, vault_id: &str) -> Result<Vec<Price>> {
+        let mut statement = self.conn.prepare(
+            "SELECT vault_id, share_price, source, block_height, timestamp FROM prices WHERE vault_id = ?1 ORDER BY rowid"
+        )?;
+        let prices = statement.query_map(params![vault_id], |row| Ok(Price {
+            vault_id: row.get(0)?,
+            share_price: balance_column(row, 1)?,
+            source: match row.get_ref(2)?.as_str()? {
+                "securitize" => PriceSource::Securitize,
+                "pool" => PriceSource::Pool,
+                "vault" => PriceSource::Vault,
+                source => {
+                    let err = format!("unknown price source {}", source);
+                    return Err(rusqlite::Error::FromSqlConversionFailure(2, Type::Text, err.into()));
+                }
+            },
+            block_height: row.get(3)?,
+            timestamp: row.get(4)?,
+        }))?.collect::<rusqlite::Result<_>>()?;
+        Ok(prices)
+    }
+}
+
+const VAULT_QUERY: &str = "SELECT vault_id, nft_contract_id, token_id, owner_id, share_price, created_height, custody_verified_at, invalidated FROM vaults";
+
+fn vault_from_row(row: &Row) -> rusqlite::Result<Vault> {
+    Ok(Vault {
+        vault_id: row.get(0)?,
+        nft_contract_id: row.get(1)?,
+        token_id: row.get(2)?,
+        owner_id: row.get(3)?,
+        share_price: match row.get_ref(4)? {
+            ValueRef::Null => None,
+            _ => Some(balance_column(row, 4)?),
+        },
+        created_height: row.get(5)?,
+        custody_verified_at: row.get(6)?,
+        invalidated: row.get(7)?,
+    })
+}
+
+fn balance_column(row: &Row, index: usize) -> rusqlite::Result<Balance> {
+    row.get_ref(index)?.as_str()?.parse()
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+fn parse_balance(balance: &str) -> Result<Balance> {
+    balance.parse().map_err(|_| Error::Decode(format!("invalid balance {}", balance)))
+}
+
+/// Logs of `securitize`, before the vault is created: "Securitizing token <token> from contract <contract>"
+/// then "Share price: <price>"
+fn apply_factory_logs(conn: &Connection, factory_id: &str, receipt: &Receipt) -> Result<()> {
+    let mut nft = None;
+    for log in &receipt.logs {
+        if let Some((token_id, nft_contract_id)) = log.strip_prefix("Securitizing token ").and_then(|rest| rest.split_once(" from contract ")) {
+            nft = Some((nft_contract_id, token_id));
+        } else if let (Some(share_price), Some((nft_contract_id, token_id))) = (log.strip_prefix("Share price: "), nft) {
+            // Same account as the factory gives the vault
+            let vault_id = format!("{}-{}.{}", nft_contract_id.replace('.', "-"), token_id, factory_id);
+            conn.execute(
+                "INSERT OR REPLACE INTO pending_prices (vault_id, share_price) VALUES (?1, ?2)",
+                params![vault_id, parse_balance(share_price)?.to_string()],
+            )?;
+        } else if let Some(Event::VaultInvalidated { vault_id }) = Event::parse(log) {
+            conn.execute("UPDATE vaults SET invalidated = 1 WHERE vault_id = ?1", params![vault_id])?;
+        }
+    }
+    Ok(())
+}
+
+/// Decode the events of a vault log, including claims logged by older vaults
+fn parse_vault_log(log: &str) -> Vec<Event> {
+    let events = Event::parse_all(log);
+    if !events.is_empty() {
+        return events;
+    }
+    parse_legacy_claim(log).into_iter().collect()
+}
+
+/// Claims used to be logged as `Securitize` with the burnt shares as fifth argument
+fn parse_legacy_claim(log: &str) -> Option<Event> {
+    let args: Vec<&str> = log.strip_prefix("Securitize(")?.strip_suffix(')')?.split(", ").collect();
+    match args.as_slice() {
+        [claimant_id, nft_contract_id, token_id, vault_id, shares] => Some(Event::Claim {
+            claimant_id: claimant_id.to_string(),
+            nft_contract_id: nft_contract_id.to_string(),
+            token_id: token_id.to_string(),
+            vault_id: vault_id.to_string(),
+            shares: shares.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn apply_vault_event(conn: &Connection, factory_id: &str, block: &Block, receipt: &Receipt, event: Event) -> Result<()> {
+    let vault_id = &receipt.executor_id;
+
+    // Only vaults created by the factory log their creation on their own account
+    if let Event::Securitize { owner_id, nft_contract_id, token_id, vault_id: logged_id } = &event {
+        if logged_id == vault_id && vault_id.ends_with(&format!(".{}", factory_id)) {
+            let share_price = conn.query_row(
+                "SELECT share_price FROM pending_prices WHERE vault_id = ?1", params![vault_id], |row| balance_column(row, 0),
+            ).optional()?;
+            conn.execute("DELETE FROM pending_prices WHERE vault_id = ?1", params![vault_id])?;
+            conn.execute(
+                "INSERT OR REPLACE INTO vaults (vault_id, nft_contract_id, token_id, owner_id, share_price, created_height)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![vault_id, nft_contract_id, token_id, owner_id, share_price.map(|price| price.to_string()), block.height],
+            )?;
+            if let Some(share_price) = share_price {
+                insert_price(conn, vault_id, share_price, PriceSource::Securitize, block)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let is_vault: bool = conn.query_row("SELECT COUNT(*) > 0 FROM vaults WHERE vault_id = ?1", params![vault_id], |row| row.get(0))?;
+    if !is_vault {
+        return Ok(());
+    }
+
+    match event {
+        Event::Mint { owner_id, amount } => add_balance(conn, vault_id, &owner_id, amount)?,
+        Event::Transfer { sender_id, receiver_id, amount } | Event::Refund { sender_id, receiver_id, amount } => {
+            sub_balance(conn, vault_id, &sender_id, amount)?;
+            add_balance(conn, vault_id, &receiver_id, amount)?;
+        }
+        Event::Burn { account_id, amount } => sub_balance(conn, vault_id, &account_id, amount)?,
+        Event::AccountClosed { account_id, .. } => {
+            conn.execute("DELETE FROM holders WHERE vault_id = ?1 AND account_id = ?2", params![vault_id, account_id])?;
+        }
+        // A market sale takes the NFT out of the vault like a redemption
+        Event::Redeem { redeemer_id, .. } | Event::NftSold { buyer_id: redeemer_id, .. } => {
+            conn.execute(
+                "INSERT OR REPLACE INTO redemptions (vault_id, redeemer_id, block_height, timestamp, receipt_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![vault_id, redeemer_id, block.height, block.timestamp, receipt.receipt_id],
+            )?;
+        }
+        Event::Claim { claimant_id, shares, .. } => {
+            conn.execute(
+                "INSERT INTO claims (vault_id, claimant_id, shares, block_height, timestamp, receipt_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![vault_id, claimant_id, shares.to_string(), block.height, block.timestamp, receipt.receipt_id],
+            )?;
+        }
+        Event::CustodyVerified { timestamp, .. } => {
+            conn.execute("UPDATE vaults SET custody_verified_at = ?2 WHERE vault_id = ?1", params![vault_id, timestamp])?;
+        }
+        Event::CustodyMissing { .. } => {
+            conn.execute("UPDATE vaults SET custody_verified_at = NULL WHERE vault_id = ?1", params![vault_id])?;
+        }
+        Event::PoolPrice { share_price, .. } => insert_price(conn, vault_id, share_price, PriceSource::Pool, block)?,
+        Event::SharePrice { share_price, .. } => insert_price(conn, vault_id, share_price, PriceSource::Vault, block)?,
+        Event::Securitize { .. } | Event::VaultInvalidated { .. } => {}
+    }
+    Ok(())
+}
+
+fn insert_price(conn: &Connection, vault_id: &str, share_price: Balance, source: PriceSource, block: &Block) -> Result<()> {
+    conn.execute(
+        "INSERT INTO prices (vault_id, share_price, source, block_height, timestamp) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![vault_id, share_price.to_string(), source.as_str(), block.height, block.timestamp],
+    )?;
+    Ok(())
+}
+
+fn balance_of(conn: &Connection, vault_id: &str, account_id: &str) -> Result<Balance> {
+    let balance = conn.query_row(
+        "SELECT balance FROM holders WHERE vault_id = ?1 AND account_id = ?2", params![vault_id, account_id], |row| balance_column(row, 0),
+    ).optional()?;
+    Ok(balance.unwrap_or(0))
+}
+
+/// Store a balance, removing holders left without shares
+fn set_balance(conn: &Connection, vault_id: &str, account_id: &str, balance: Balance) -> Result<()> {
+    if balance == 0 {
+        conn.execute("DELETE FROM holders WHERE vault_id = ?1 AND account_id = ?2", params![vault_id, account_id])?;
+    } else {
+        conn.execute(
+            "INSERT OR REPLACE INTO holders (vault_id, account_id, balance) VALUES (?1, ?2, ?3)",
+            params![vault_id, account_id, balance.to_string()],
+        )?;
+    }
+    Ok(())
+}
+
+fn add_balance(conn: &Connection, vault_id: &str, account_id: &str, amount: Balance) -> Result<()> {
+    let balance = balance_of(conn, vault_id, account_id)?;
+    set_balance(conn, vault_id, account_id, balance.saturating_add(amount))
+}
+
+/// Balances can't go below zero if indexing started after the vault was created
+fn sub_balance(conn: &Connection, vault_id: &str, account_id: &str, amount: Balance) -> Result<()> {
+    let balance = balance_of(conn, vault_id, account_id)?;
+    set_balance(conn, vault_id, account_id, balance.saturating_sub(amount))
+}
@@ -0,0 +1,123 @@
+//! Reader of NEAR Lake dumps.
+//!
+//! A dump is either a directory laid out like the NEAR Lake buckets, with a folder per block
+//! holding `block.json` and a `shard_<id>.json` per shard, or a file of streamer messages
+//! (`{ "block": ..., "shards": [...] }`) as a JSON array or one message per line.
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{Block, Error, Receipt, Result};
+
+/// Read the blocks of a dump, in height order
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Block>> {
+    let path = path.as_ref();
+    let mut blocks = if path.is_dir() { read_dir(path)? } else { read_messages(&fs::read_to_string(path)?)? };
+    blocks.sort_by_key(|block| block.height);
+    Ok(blocks)
+}
+
+fn read_dir(path: &Path) -> Result<Vec<Block>> {
+    let mut blocks = vec![];
+    for entry in fs::read_dir(path)? {
+        let block_dir = entry?.path();
+        if !block_dir.join("block.json").is_file() {
+            continue;
+        }
+
+        let mut shards = vec![];
+        for shard in fs::read_dir(&block_dir)? {
+            let shard = shard?.path();
+            let name = shard.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if name.starts_with("shard_") && name.ends_with(".json") {
+                shards.push(parse_json(&fs::read_to_string(&shard)?)?);
+            }
+        }
+        shards.sort_by_key(|shard| shard["shard_id"].as_u64());
+
+        let block = parse_json(&fs::read_to_string(block_dir.join("block.json"))?)?;
+        blocks.push(parse_block(&block, &shards)?);
+    }
+    Ok(blocks)
+}
+
+/// Streamer messages as a JSON array or one per line
+fn read_messages(dump: &str) -> Result<Vec<Block>> {
+    let messages = if dump.trim_start().starts_with('[') {
+        match parse_json(dump)? {
+            Value::Array(messages) => messages,
+            _ => unreachable!(),
+        }
+    } else {
+        dump.lines().filter(|line| !line.trim().is_empty()).map(parse_json).collect::<Result<_>>()?
+    };
+    messages.iter().map(parse_message).collect()
+}
+
+/// Block of a streamer message
+pub fn parse_message(message: &Value) -> Result<Block> {
+    let shards = message["shards"].as_array().map(Vec::as_slice).unwrap_or_default();
+    parse_block(&message["block"], shards)
+}
+
+fn parse_block(block: &Value, shards: &[Value]) -> Result<Block> {
+    let header = &block["header"];
+    let height = header["height"].as_u64().ok_or_else(|| Error::Decode("block without height".to_string()))?;
+    // Lake writes the timestamp both as a number, which JSON parsers may round, and as a string
+    let timestamp = header["timestamp_nanosec"].as_str().and_then(|timestamp| timestamp.parse().ok())
+        .or_else(|| header["timestamp"].as_u64())
+        .ok_or_else(|| Error::Decode(format!("block {} without timestamp", height)))?;
+
+    let mut receipts = vec![];
+    for shard in shards {
+        for execution in shard["receipt_execution_outcomes"].as_array().into_iter().flatten() {
+            let outcome = &execution["execution_outcome"]["outcome"];
+            if outcome["status"].get("Failure").is_some() {
+                continue;
+            }
+            receipts.push(Receipt {
+                receipt_id: execution["execution_outcome"]["id"].as_str().unwrap_or_default().to_string(),
+                executor_id: outcome["executor_id"].as_str()
+                    .ok_or_else(|| Error::Decode(format!("outcome without executor in block {}", height)))?
+                    .to_string(),
+                logs: outcome["logs"].as_array().into_iter().flatten()
+                    .filter_map(|log| log.as_str().map(str::to_string))
+                    .collect(),
+            });
+        }
+    }
+    Ok(Block { height, timestamp, receipts })
+}
+
+fn parse_json(json: &str) -> Result<Value> {
+    serde_json::from_str(json).map_err(|err| Error::Decode(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        let block = parse_message(&json!({
+            "block": { "header": { "height": 7, "timestamp": 1, "timestamp_nanosec": "1700000000000000001" } },
+            "shards": [{
+                "shard_id": 0,
+                "receipt_execution_outcomes": [
+                    { "execution_outcome": { "id": "a", "outcome": { "executor_id": "v.near", "logs": ["x"], "status": { "SuccessValue": "" } } } },
+                    { "execution_outcome": { "id": "b", "outcome": { "executor_id": "v.near", "logs": ["y"], "status": { "Failure": {} } } } },
+                ],
+            }],
+        })).unwrap();
+
+        assert_eq!(block, Block {
+            height: 7,
+            timestamp: 1_700_000_000_000_000_001,
+            receipts: vec![Receipt { receipt_id: "a".to_string(), executor_id: "v.near".to_string(), logs: vec!["x".to_string()] }],
+        });
+        assert!(matches!(parse_message(&json!({ "block": { "header": {} } })), Err(Error::Decode(_))));
+    }
+}
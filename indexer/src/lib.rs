@@ -0,0 +1,70 @@
+//! Indexer building the state of Fractose vaults from the logs of their receipts.
+//!
+//! Blocks are read from a NEAR Lake dump with [`lake`] or, with the `sandbox` feature, from
+//! transactions run on the simulation runtime. [`Indexer`] decodes the events of a factory and of
+//! the vaults it created, NEP-297 structured events and the ad-hoc logs of older vaults such as
+//! `Securitize(...)`, and stores vaults, holders, redemptions, claims and prices in SQLite.
+use std::fmt;
+
+mod db;
+pub mod lake;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+
+pub use db::{Claim, Indexer, Price, PriceSource, Redemption, Vault};
+pub use fractose_common::Event;
+
+pub type AccountId = String;
+pub type Balance = u128;
+
+/// Block and the receipts which succeeded in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub height: u64,
+    /// Nanoseconds since the Unix epoch
+    pub timestamp: u64,
+    pub receipts: Vec<Receipt>,
+}
+
+/// Logs of a successful receipt. Failed receipts are left out, as their changes were reverted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt {
+    pub receipt_id: String,
+    /// Account which executed the receipt
+    pub executor_id: AccountId,
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Sqlite(rusqlite::Error),
+    /// A dump or a stored value could not be decoded
+    Decode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Sqlite(err) => write!(f, "database error: {}", err),
+            Error::Decode(message) => write!(f, "invalid data: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Sqlite(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,49 @@
+//! Index NEAR Lake dumps of a Fractose factory into SQLite
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use fractose_indexer::{lake, Indexer};
+
+#[derive(Parser)]
+#[command(name = "fractose-indexer", version, about = "Build the state of Fractose vaults from receipt logs")]
+struct Cli {
+    /// SQLite database, created if missing
+    #[arg(long, default_value = "fractose.sqlite")]
+    db: PathBuf,
+
+    /// Fractose factory whose vaults are indexed
+    #[arg(long, env = "FRACTOSE_FACTORY", default_value = "fractose.monkeyis.testnet")]
+    factory: String,
+
+    /// NEAR Lake dumps: directories of blocks, or files of streamer messages
+    #[arg(required = true)]
+    dumps: Vec<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut indexer = Indexer::open(&cli.db, cli.factory.as_str()).with_context(|| format!("opening {}", cli.db.display()))?;
+
+    for dump in &cli.dumps {
+        let blocks = lake::read(dump).with_context(|| format!("reading {}", dump.display()))?;
+        let applied = indexer.index_blocks(&blocks)?;
+        println!("{}: indexed {} of {} blocks", dump.display(), applied, blocks.len());
+    }
+
+    for vault in indexer.vaults()? {
+        let status = match indexer.redemption(&vault.vault_id)? {
+            Some(redemption) => format!("redeemed by {}", redemption.redeemer_id),
+            None if vault.invalidated => "invalidated".to_string(),
+            None => "active".to_string(),
+        };
+        println!(
+            "{}  {}/{}  {} holders  {}",
+            vault.vault_id, vault.nft_contract_id, vault.token_id, indexer.holders(&vault.vault_id)?.len(), status
+        );
+    }
+    if let Some(height) = indexer.last_height()? {
+        println!("Last indexed block: {}", height);
+    }
+    Ok(())
+}
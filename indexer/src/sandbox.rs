@@ -0,0 +1,18 @@
+//! Blocks from transactions run on the simulation runtime of `fractose_sim`
+use fractose_sim::ExecutionResult;
+
+use crate::{Block, Receipt};
+
+/// Block holding the receipts of a transaction which succeeded. The runtime has no blocks, so
+/// the caller numbers them
+pub fn block(height: u64, timestamp: u64, result: &ExecutionResult) -> Block {
+    let receipts = result.outcomes.iter().enumerate()
+        .filter(|(_, outcome)| outcome.error.is_none())
+        .map(|(index, outcome)| Receipt {
+            receipt_id: format!("{}-{}", height, index),
+            executor_id: outcome.receiver_id.clone(),
+            logs: outcome.logs.clone(),
+        })
+        .collect();
+    Block { height, timestamp, receipts }
+}
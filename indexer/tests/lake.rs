@@ -0,0 +1,129 @@
+use fractose_indexer::{lake, Block, Event, Indexer, PriceSource, Receipt, Vault};
+
+const FACTORY: &str = "fractose.near";
+const VAULT: &str = "nft-near-0.fractose.near";
+const POOL: &str = "nft-near-0-pool.fractose.near";
+const NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Vault 0 is securitized at block 100, traded at 101 and redeemed and claimed at 102
+fn index_lake() -> Indexer {
+    let mut indexer = Indexer::open_in_memory(FACTORY).unwrap();
+    let blocks = lake::read(fixture("lake")).unwrap();
+    assert_eq!(blocks.iter().map(|block| block.height).collect::<Vec<_>>(), vec![100, 101, 102]);
+    assert_eq!(indexer.index_blocks(&blocks).unwrap(), 3);
+    indexer
+}
+
+#[test]
+fn test_vault_lifecycle() {
+    let indexer = index_lake();
+
+    // Only the vault of the factory is indexed
+    assert_eq!(indexer.vaults().unwrap(), vec![Vault {
+        vault_id: VAULT.to_string(),
+        nft_contract_id: "nft.near".to_string(),
+        token_id: "0".to_string(),
+        owner_id: "alice.near".to_string(),
+        share_price: Some(NEAR / 10),
+        created_height: 100,
+        custody_verified_at: Some(102_000),
+        invalidated: false,
+    }]);
+
+    // Alice minted 900, sent 300 to Bob and 50 to the market, got 20 back and burnt the rest
+    // redeeming. Bob burnt his claiming. The failed transfer and the pool's LP tokens are ignored
+    assert_eq!(indexer.holders(VAULT).unwrap(), vec![
+        ("market.near".to_string(), 30),
+        (POOL.to_string(), 100),
+    ]);
+
+    let redemption = indexer.redemption(VAULT).unwrap().unwrap();
+    assert_eq!((redemption.redeemer_id.as_str(), redemption.block_height, redemption.receipt_id.as_str()), ("alice.near", 102, "r11"));
+
+    let claims = indexer.claims(VAULT).unwrap();
+    assert_eq!(claims.len(), 1);
+    assert_eq!((claims[0].claimant_id.as_str(), claims[0].shares, claims[0].timestamp), ("bob.near", 300, 102_000));
+
+    let prices: Vec<_> = indexer.prices(VAULT).unwrap().into_iter().map(|price| (price.share_price, price.source, price.block_height)).collect();
+    assert_eq!(prices, vec![(NEAR / 10, PriceSource::Securitize, 100), (NEAR * 12 / 100, PriceSource::Pool, 101)]);
+}
+
+#[test]
+fn test_structured_events_and_invalidation() {
+    let mut indexer = Indexer::open_in_memory(FACTORY).unwrap();
+    let blocks = lake::read(fixture("messages.jsonl")).unwrap();
+    assert_eq!(indexer.index_blocks(&blocks).unwrap(), 2);
+
+    let vault_id = "nft-near-1.fractose.near";
+    let vault = indexer.vault(vault_id).unwrap().unwrap();
+    assert_eq!((vault.owner_id.as_str(), vault.share_price, vault.custody_verified_at, vault.invalidated), ("carol.near", Some(5), None, true));
+
+    // Erin received shares in a structured transfer, then closed her account
+    assert_eq!(indexer.holders(vault_id).unwrap(), vec![("carol.near".to_string(), 750), ("dave.near".to_string(), 100)]);
+    assert_eq!(indexer.redemption(vault_id).unwrap(), None);
+}
+
+fn block(height: u64, vault_id: &str, events: Vec<Event>) -> Block {
+    let receipt = Receipt {
+        receipt_id: format!("r{}", height),
+        executor_id: vault_id.to_string(),
+        logs: events.iter().map(ToString::to_string).collect(),
+    };
+    Block { height, timestamp: height * 1_000, receipts: vec![receipt] }
+}
+
+#[test]
+fn test_market_sale_and_share_prices() {
+    let mut indexer = Indexer::open_in_memory(FACTORY).unwrap();
+    let vault_id = "nft-near-2.fractose.near";
+    let token_id = "2, x)";
+    let event_vault_id = vault_id.to_string();
+    let blocks = vec![
+        block(300, vault_id, vec![
+            Event::Securitize { owner_id: "alice.near".to_string(), nft_contract_id: "nft.near".to_string(), token_id: token_id.to_string(), vault_id: event_vault_id.clone() },
+            Event::Mint { owner_id: "alice.near".to_string(), amount: 100 },
+        ]),
+        block(301, vault_id, vec![Event::SharePrice { share_price: 7, vault_id: event_vault_id.clone() }]),
+        block(302, vault_id, vec![
+            Event::NftSold { buyer_id: "bob.near".to_string(), proceeds: 900, vault_id: event_vault_id.clone() },
+            Event::SharePrice { share_price: 9, vault_id: event_vault_id },
+        ]),
+    ];
+    assert_eq!(indexer.index_blocks(&blocks).unwrap(), 3);
+
+    // The token ID is decoded whole from the structured log
+    assert_eq!(indexer.vault(vault_id).unwrap().unwrap().token_id, token_id);
+
+    // The market sale is recorded as the vault's exit, and claims are then paid at the sale price
+    let redemption = indexer.redemption(vault_id).unwrap().unwrap();
+    assert_eq!((redemption.redeemer_id.as_str(), redemption.block_height), ("bob.near", 302));
+    let prices: Vec<_> = indexer.prices(vault_id).unwrap().into_iter().map(|price| (price.share_price, price.source, price.block_height)).collect();
+    assert_eq!(prices, vec![(7, PriceSource::Vault, 301), (9, PriceSource::Vault, 302)]);
+}
+
+#[test]
+fn test_reindex_and_resume() {
+    let path = std::env::temp_dir().join(format!("fractose-indexer-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let blocks = lake::read(fixture("lake")).unwrap();
+    {
+        let mut indexer = Indexer::open(&path, FACTORY).unwrap();
+        assert_eq!(indexer.index_blocks(&blocks[..2]).unwrap(), 2);
+        assert_eq!(indexer.last_height().unwrap(), Some(101));
+    }
+
+    // Reopening resumes after the last block, and blocks already indexed are skipped
+    let mut indexer = Indexer::open(&path, FACTORY).unwrap();
+    assert_eq!(indexer.index_blocks(&blocks).unwrap(), 1);
+    assert_eq!(indexer.index_blocks(&blocks).unwrap(), 0);
+    assert_eq!(indexer.holders(VAULT).unwrap(), index_lake().holders(VAULT).unwrap());
+    assert_eq!(indexer.claims(VAULT).unwrap().len(), 1);
+
+    drop(indexer);
+    std::fs::remove_file(&path).unwrap();
+}
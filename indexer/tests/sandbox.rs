@@ -0,0 +1,80 @@
+use fractose_indexer::{sandbox, Indexer, PriceSource};
+use fractose_common::securitize_deposit;
+use fractose_sim::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::Balance;
+
+const FACTORY: &str = "fractose.near";
+const NFT: &str = "nft.near";
+const ALICE: &str = "alice.near";
+const BOB: &str = "bob.near";
+
+const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+const EXIT_PRICE: Balance = 10 * NEAR;
+
+/// Runs transactions and indexes each as a block
+struct Chain {
+    runtime: Runtime,
+    indexer: Indexer,
+    height: u64,
+}
+
+impl Chain {
+    fn call(&mut self, signer_id: &str, receiver_id: &str, method: &str, args: near_sdk::serde_json::Value, deposit: Balance) -> ExecutionResult {
+        let result = self.runtime.call(signer_id, receiver_id, method, args, deposit, MAX_GAS);
+        result.assert_success();
+        self.height += 1;
+        assert!(self.indexer.index_block(&sandbox::block(self.height, self.runtime.block_timestamp(), &result)).unwrap());
+        result
+    }
+}
+
+#[test]
+fn test_index_sandbox() {
+    let mut runtime = runtime();
+    runtime.deploy(FACTORY, FRACTOSE_WASM, 100 * NEAR);
+    runtime.deploy(NFT, MOCK_NFT_WASM, 10 * NEAR);
+    for user in &[ALICE, BOB] {
+        runtime.create_account(user, 100 * NEAR);
+    }
+    let mut chain = Chain { runtime, indexer: Indexer::open_in_memory(FACTORY).unwrap(), height: 0 };
+
+    chain.call(NFT, NFT, "new", json!({ "owner_id": ALICE }), 0);
+    chain.call(ALICE, NFT, "nft_mint", json!({ "token_id": "0", "receiver_id": ALICE, "token_metadata": {} }), NEAR);
+    chain.call(ALICE, NFT, "nft_approve", json!({ "token_id": "0", "account_id": FACTORY }), NEAR / 100);
+    chain.call(ALICE, FACTORY, "securitize", json!({
+        "nft_contract_address": NFT,
+        "nft_token_id": "0",
+        "shares_count": U128(100),
+        "decimals": 0,
+        "exit_price": U128(EXIT_PRICE),
+    }), securitize_deposit(None));
+
+    let vault_id: String = chain.runtime.view(FACTORY, "shares_address_of", json!({ "nft_contract_address": NFT, "nft_token_id": "0" }));
+    let vault = chain.indexer.vault(&vault_id).unwrap().unwrap();
+    assert_eq!((vault.owner_id.as_str(), vault.share_price, vault.created_height), (ALICE, Some(EXIT_PRICE / 100), 4));
+    assert_eq!(chain.indexer.holders(&vault_id).unwrap(), vec![(ALICE.to_string(), 100)]);
+
+    // Balances follow transfers
+    chain.call(ALICE, &vault_id, "verify_custody", json!({}), 0);
+    chain.call(BOB, &vault_id, "storage_deposit", json!({}), NEAR / 10);
+    chain.call(ALICE, &vault_id, "ft_transfer", json!({ "receiver_id": BOB, "amount": U128(40) }), 1);
+    let holders = chain.indexer.holders(&vault_id).unwrap();
+    assert_eq!(holders, vec![(ALICE.to_string(), 60), (BOB.to_string(), 40)]);
+    for (account_id, balance) in holders {
+        assert_eq!(chain.runtime.view::<U128>(&vault_id, "ft_balance_of", json!({ "account_id": account_id })).0, balance);
+    }
+    assert!(chain.indexer.vault(&vault_id).unwrap().unwrap().custody_verified_at.is_some());
+
+    // Alice redeems and Bob claims, burning every share
+    let payment: U128 = chain.runtime.view(&vault_id, "redeem_amount_of", json!({ "from": ALICE }));
+    chain.call(ALICE, &vault_id, "redeem", json!({}), payment.0);
+    chain.call(BOB, &vault_id, "claim", json!({}), 0);
+
+    assert_eq!(chain.indexer.redemption(&vault_id).unwrap().unwrap().redeemer_id, ALICE);
+    let claims = chain.indexer.claims(&vault_id).unwrap();
+    assert_eq!((claims[0].claimant_id.as_str(), claims[0].shares), (BOB, 40));
+    assert!(chain.indexer.holders(&vault_id).unwrap().is_empty());
+    assert_eq!(chain.indexer.prices(&vault_id).unwrap()[0].source, PriceSource::Securitize);
+}
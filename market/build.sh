@@ -0,0 +1,6 @@
+#!/bin/bash
+set -e
+
+RUSTFLAGS="-C link-arg=-s --remap-path-prefix=${CARGO_HOME:-$HOME/.cargo}=/cargo" cargo build --target wasm32-unknown-unknown --release
+
+cp ../target/wasm32-unknown-unknown/release/shares_market.wasm ./res/market.wasm
@@ -0,0 +1,1018 @@
+// #[near_bindgen] and #[ext_contract] expand methods into functions taking extra arguments
+#![allow(clippy::too_many_arguments)]
+
+use std::convert::TryInto;
+
+use near_contract_standards::fungible_token::{
+    core_impl::ext_fungible_token,
+    receiver::FungibleTokenReceiver,
+};
+use near_sdk::{
+    env, AccountId, Balance, Gas, PromiseOrValue, Promise, PromiseResult,
+    BorshStorageKey, PanicOnDefault, log,
+    near_bindgen, ext_contract,
+    collections::{LookupMap, UnorderedMap, UnorderedSet},
+    json_types::{ValidAccountId, U64, U128},
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    serde_json,
+};
+
+near_sdk::setup_alloc!();
+
+pub type OrderId = u64;
+pub type AccountAndTokenId = String;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_REGISTRY_LOOKUP: Gas = 10_000_000_000_000;
+const GAS_FOR_RESOLVE_LOOKUP: Gas = 30_000_000_000_000;
+const GAS_FOR_IS_BACKED: Gas = 10_000_000_000_000;
+/// Gas given to callbacks placing or filling an order once the vault is checked, which may send shares
+const GAS_FOR_RESOLVE_CHECK: Gas = 40_000_000_000_000;
+const GAS_FOR_STORAGE_DEPOSIT: Gas = 10_000_000_000_000;
+const GAS_FOR_EXCLUDE_FROM_DIVIDENDS: Gas = 10_000_000_000_000;
+
+/// Registers the market with a shares token, which keeps what its minimum balance leaves over
+const SHARES_STORAGE_DEPOSIT: Balance = 50_000_000_000_000_000_000_000;
+/// Pays for the exclusion of the market from dividends. The excess is refunded to the market
+const DIVIDENDS_EXCLUSION_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+/// Deposit `register_shares` must be attached
+pub const REGISTRATION_DEPOSIT: Balance = SHARES_STORAGE_DEPOSIT + DIVIDENDS_EXCLUSION_DEPOSIT;
+
+/// Default and maximum number of orders returned by paginated views
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[ext_contract(ext_fractose)]
+pub trait Fractose {
+    fn nft_address_of(&self, shares_contract: AccountId) -> Option<AccountAndTokenId>;
+}
+
+#[ext_contract(ext_shares)]
+pub trait Shares {
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>);
+    fn exclude_from_dividends(&mut self);
+    fn is_backed(&self) -> bool;
+}
+
+#[ext_contract(ext_self)]
+pub trait Market {
+    fn on_registry_lookup(&mut self, shares_id: AccountId, account_id: AccountId) -> bool;
+    fn on_buy_order_check(&mut self, owner_id: AccountId, shares_id: AccountId, amount: U128, price: U128) -> Option<U64>;
+    fn on_sell_order_fill_check(&mut self, buyer_id: AccountId, order_id: U64, amount: U128) -> U128;
+    fn on_shares_check(&mut self, sender_id: AccountId, shares_id: AccountId, amount: U128, msg: String) -> U128;
+    fn on_shares_transfer(&mut self, account_id: AccountId, shares_id: AccountId, amount: U128);
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// Escrows NEAR to buy shares
+    Buy,
+    /// Escrows shares to sell them for NEAR
+    Sell,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Order {
+    pub owner_id: AccountId,
+    pub shares_id: AccountId,
+    pub side: Side,
+    /// NEAR per share
+    pub price: Balance,
+    /// Shares left to be filled
+    pub amount: Balance,
+    pub filled: Balance,
+    pub created_at: u64,
+    /// NEAR paid by the owner for the storage of the order, refunded once it is removed
+    pub storage_deposit: Balance,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderView {
+    pub order_id: U64,
+    pub owner_id: AccountId,
+    pub shares_id: AccountId,
+    pub side: Side,
+    pub price: U128,
+    pub amount: U128,
+    pub filled: U128,
+    pub created_at: U64,
+}
+
+/// `ft_transfer_call` message sent along with shares
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+enum TokenReceiverMessage {
+    /// Escrow the shares in a new sell order
+    Sell { price: U128 },
+    /// Sell the shares into an existing buy order
+    Fill { order_id: U64 },
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    VerifiedTokens,
+    Orders,
+    OrdersByToken,
+    TokenOrders { shares_id_hash: Vec<u8> },
+    SharesRefunds,
+    StorageDeposits,
+}
+
+/// Limit order book for Fractose shares against NEAR
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Market {
+    /// Fractose factory whose registry proves that a token is a vault
+    factory_id: AccountId,
+    /// Shares tokens found in the factory registry, with their NFT address
+    verified_tokens: UnorderedMap<AccountId, AccountAndTokenId>,
+    orders: UnorderedMap<OrderId, Order>,
+    orders_by_token: LookupMap<AccountId, UnorderedSet<OrderId>>,
+    next_order_id: OrderId,
+    /// Shares owed to accounts after a failed transfer, by `account_id:shares_id`
+    shares_refunds: LookupMap<String, Balance>,
+    /// NEAR deposited for the storage of sell orders, which can't attach any
+    storage_deposits: LookupMap<AccountId, Balance>,
+}
+
+#[near_bindgen]
+impl Market {
+    #[init]
+    pub fn new(factory_id: ValidAccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            factory_id: factory_id.into(),
+            verified_tokens: UnorderedMap::new(StorageKey::VerifiedTokens),
+            orders: UnorderedMap::new(StorageKey::Orders),
+            orders_by_token: LookupMap::new(StorageKey::OrdersByToken),
+            next_order_id: 0,
+            shares_refunds: LookupMap::new(StorageKey::SharesRefunds),
+            storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
+        }
+    }
+
+    /// Look up a shares token in the factory registry so that it can be traded. The attached
+    /// `REGISTRATION_DEPOSIT` registers the market with the token, and is refunded if the token
+    /// is not a vault
+    #[payable]
+    pub fn register_shares(&mut self, shares_id: ValidAccountId) -> Promise {
+        let shares_id: AccountId = shares_id.into();
+        assert!(self.verified_tokens.get(&shares_id).is_none(), "{} is already registered", shares_id);
+        assert!(env::attached_deposit() >= REGISTRATION_DEPOSIT, "attach {} yoctoNEAR to register", REGISTRATION_DEPOSIT);
+
+        ext_fractose::nft_address_of(
+            shares_id.clone(),
+            &self.factory_id,
+            0,
+            GAS_FOR_REGISTRY_LOOKUP
+        ).then(ext_self::on_registry_lookup(
+            shares_id,
+            env::predecessor_account_id(),
+            &env::current_account_id(),
+            env::attached_deposit(),
+            GAS_FOR_RESOLVE_LOOKUP
+        ))
+    }
+
+    /// Record a vault found in the registry. The market registers with it to hold escrowed
+    /// shares, and opts out of dividends it could not pass on to the sellers
+    #[private]
+    #[payable]
+    pub fn on_registry_lookup(&mut self, shares_id: AccountId, account_id: AccountId) -> bool {
+        // A failed lookup is handled like an unknown token, so that the deposit is refunded
+        match promise_value::<Option<AccountAndTokenId>>(0).flatten() {
+            Some(nft_address) => {
+                self.verified_tokens.insert(&shares_id, &nft_address);
+                ext_shares::storage_deposit(
+                    Some(env::current_account_id().try_into().unwrap()),
+                    Some(true),
+                    &shares_id,
+                    SHARES_STORAGE_DEPOSIT,
+                    GAS_FOR_STORAGE_DEPOSIT
+                ).then(ext_shares::exclude_from_dividends(
+                    &shares_id,
+                    DIVIDENDS_EXCLUSION_DEPOSIT,
+                    GAS_FOR_EXCLUDE_FROM_DIVIDENDS
+                ));
+                let refund = env::attached_deposit() - REGISTRATION_DEPOSIT;
+                if refund > 0 {
+                    Promise::new(account_id).transfer(refund);
+                }
+
+                self.on_shares_registered(shares_id, nft_address);
+                true
+            }
+            None => {
+                Promise::new(account_id).transfer(env::attached_deposit());
+                log!("{} is not a Fractose vault", shares_id);
+                false
+            }
+        }
+    }
+
+    /// Escrow the attached NEAR in an order to buy `amount` shares at `price` NEAR each. The
+    /// deposit also pays for the storage of the order. NEAR in excess of both is refunded. The
+    /// order is placed once the vault is checked, and returns its ID or None if the NEAR was refunded
+    #[payable]
+    pub fn place_buy_order(&mut self, shares_id: ValidAccountId, amount: U128, price: U128) -> Promise {
+        let shares_id: AccountId = shares_id.into();
+        self.assert_order(&shares_id, amount.0, price.0);
+        assert!(env::attached_deposit() >= order_value(amount.0, price.0), "attached deposit does not cover the order");
+
+        self.check_vault(&shares_id).then(ext_self::on_buy_order_check(
+            env::predecessor_account_id(),
+            shares_id,
+            amount,
+            price,
+            &env::current_account_id(),
+            env::attached_deposit(),
+            GAS_FOR_RESOLVE_CHECK
+        ))
+    }
+
+    /// Place a buy order if the vault is still genuine and backed, or refund its NEAR
+    #[private]
+    #[payable]
+    pub fn on_buy_order_check(&mut self, owner_id: AccountId, shares_id: AccountId, amount: U128, price: U128) -> Option<U64> {
+        let deposit = env::attached_deposit();
+        if !is_checked_vault() {
+            log!("{} is no longer a backed Fractose vault", shares_id);
+            Promise::new(owner_id).transfer(deposit);
+            return None;
+        }
+
+        let value = order_value(amount.0, price.0);
+        match self.internal_add_order(owner_id.clone(), shares_id, Side::Buy, price.0, amount.0, deposit - value) {
+            Ok(order_id) => {
+                let storage_deposit = self.orders.get(&order_id).unwrap().storage_deposit;
+                let refund = deposit - value - storage_deposit;
+                if refund > 0 {
+                    Promise::new(owner_id).transfer(refund);
+                }
+                Some(order_id.into())
+            }
+            Err(error) => {
+                log!("{}", error);
+                Promise::new(owner_id).transfer(deposit);
+                None
+            }
+        }
+    }
+
+    /// Deposit NEAR for the storage of sell orders placed by `account_id`, or the caller
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<ValidAccountId>) -> U128 {
+        let account_id = account_id.map(AccountId::from).unwrap_or_else(env::predecessor_account_id);
+        let deposit = env::attached_deposit();
+        assert!(deposit > 0, "attach NEAR to deposit");
+
+        let balance = self.storage_deposits.get(&account_id).unwrap_or(0) + deposit;
+        self.storage_deposits.insert(&account_id, &balance);
+        balance.into()
+    }
+
+    /// Withdraw storage deposit not used by orders, all of it by default
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let balance = self.storage_deposits.get(&account_id).unwrap_or(0);
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        assert!(amount > 0 && amount <= balance, "storage deposit is {}", balance);
+
+        if amount == balance {
+            self.storage_deposits.remove(&account_id);
+        } else {
+            self.storage_deposits.insert(&account_id, &(balance - amount));
+        }
+        Promise::new(account_id).transfer(amount);
+        amount.into()
+    }
+
+    /// Storage deposit of an account not used by orders
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.storage_deposits.get(account_id.as_ref()).unwrap_or(0).into()
+    }
+
+    /// Buy up to `amount` shares from a sell order with the attached NEAR. Excess NEAR is refunded.
+    /// The order is filled once the vault is checked, and returns the shares bought
+    #[payable]
+    pub fn fill_sell_order(&mut self, order_id: U64, amount: U128) -> Promise {
+        let order = self.orders.get(&order_id.0).expect("order not found");
+        assert_eq!(order.side, Side::Sell, "not a sell order");
+        assert!(fill_amount(&order, amount.0, env::attached_deposit()) > 0, "attached deposit does not cover any shares");
+
+        self.check_vault(&order.shares_id).then(ext_self::on_sell_order_fill_check(
+            env::predecessor_account_id(),
+            order_id,
+            amount,
+            &env::current_account_id(),
+            env::attached_deposit(),
+            GAS_FOR_RESOLVE_CHECK
+        ))
+    }
+
+    /// Fill a sell order if its vault is still genuine and backed, or refund the NEAR
+    #[private]
+    #[payable]
+    pub fn on_sell_order_fill_check(&mut self, buyer_id: AccountId, order_id: U64, amount: U128) -> U128 {
+        let deposit = env::attached_deposit();
+        // The order may have been filled or cancelled while the vault was checked
+        let order = match self.orders.get(&order_id.0) {
+            Some(order) if is_checked_vault() && fill_amount(&order, amount.0, deposit) > 0 => order,
+            _ => {
+                log!("Order {} can no longer be filled", order_id.0);
+                Promise::new(buyer_id).transfer(deposit);
+                return 0.into();
+            }
+        };
+        let amount = fill_amount(&order, amount.0, deposit);
+        let cost = order_value(amount, order.price);
+
+        let order = self.internal_fill(order_id.0, order, &buyer_id, amount);
+        Promise::new(order.owner_id.clone()).transfer(cost + order_refund(&order));
+        self.internal_send_shares(&buyer_id, &order.shares_id, amount);
+        if deposit > cost {
+            Promise::new(buyer_id).transfer(deposit - cost);
+        }
+
+        amount.into()
+    }
+
+    /// Cancel an order and return the escrowed NEAR or shares to its owner
+    pub fn cancel_order(&mut self, order_id: U64) {
+        let order = self.orders.get(&order_id.0).expect("order not found");
+        assert_eq!(order.owner_id, env::predecessor_account_id(), "only the owner can cancel an order");
+
+        self.internal_remove_order(order_id.0, &order);
+        match order.side {
+            Side::Buy => {
+                Promise::new(order.owner_id.clone())
+                    .transfer(order_value(order.amount, order.price) + order.storage_deposit);
+            }
+            Side::Sell => {
+                Promise::new(order.owner_id.clone()).transfer(order.storage_deposit);
+                self.internal_send_shares(&order.owner_id, &order.shares_id, order.amount);
+            }
+        }
+
+        self.on_order_cancelled(order_id.0, &order);
+    }
+
+    /// Claim shares whose transfer to the caller failed
+    pub fn withdraw_shares(&mut self, shares_id: ValidAccountId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let key = refund_key(&account_id, shares_id.as_ref());
+        let amount = self.shares_refunds.remove(&key).expect("nothing to withdraw");
+
+        self.internal_send_shares(&account_id, shares_id.as_ref(), amount);
+        amount.into()
+    }
+
+    /// Records shares as owed to `account_id` if sending them failed
+    #[private]
+    pub fn on_shares_transfer(&mut self, account_id: AccountId, shares_id: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            let key = refund_key(&account_id, &shares_id);
+            let owed = self.shares_refunds.get(&key).unwrap_or(0);
+            self.shares_refunds.insert(&key, &(owed + amount.0));
+            log!("Transfer of {} {} to @{} failed, withdraw with withdraw_shares", amount.0, shares_id, account_id);
+        }
+    }
+
+    /// Escrow or sell shares received with `ft_transfer_call` if their vault is still genuine and
+    /// backed. Returns the shares which are not used, and so refunded by the shares contract
+    #[private]
+    pub fn on_shares_check(&mut self, sender_id: AccountId, shares_id: AccountId, amount: U128, msg: String) -> U128 {
+        if !is_checked_vault() {
+            log!("{} is no longer a backed Fractose vault", shares_id);
+            return amount;
+        }
+
+        let message: TokenReceiverMessage = serde_json::from_str(&msg).expect("invalid transfer message");
+        match message {
+            TokenReceiverMessage::Sell { price } => {
+                let storage_available = self.storage_deposits.get(&sender_id).unwrap_or(0);
+                self.internal_add_order(sender_id, shares_id, Side::Sell, price.0, amount.0, storage_available)
+                    .unwrap_or_else(|error| panic!("{}", error));
+                0.into()
+            }
+            TokenReceiverMessage::Fill { order_id } => {
+                // The order may have been filled or cancelled while the vault was checked
+                let order = match self.orders.get(&order_id.0) {
+                    Some(order) => order,
+                    None => return amount,
+                };
+
+                let filled = std::cmp::min(amount.0, order.amount);
+                let order = self.internal_fill(order_id.0, order, &sender_id, filled);
+                Promise::new(sender_id).transfer(order_value(filled, order.price));
+                self.internal_send_shares(&order.owner_id, &shares_id, filled);
+                let refund = order_refund(&order);
+                if refund > 0 {
+                    Promise::new(order.owner_id.clone()).transfer(refund);
+                }
+
+                (amount.0 - filled).into()
+            }
+        }
+    }
+
+    pub fn is_verified(&self, shares_id: ValidAccountId) -> bool {
+        self.verified_tokens.get(shares_id.as_ref()).is_some()
+    }
+
+    pub fn get_order(&self, order_id: U64) -> Option<OrderView> {
+        self.orders.get(&order_id.0).map(|order| order_view(order_id.0, order))
+    }
+
+    /// Open orders for a shares token, in insertion order with cancelled and filled orders swapped out
+    pub fn get_orders(&self, shares_id: ValidAccountId, from_index: Option<U64>, limit: Option<U64>) -> Vec<OrderView> {
+        let order_ids = match self.orders_by_token.get(shares_id.as_ref()) {
+            Some(order_ids) => order_ids,
+            None => return vec![],
+        };
+        let from_index = from_index.map(|index| index.0).unwrap_or(0);
+        let limit = std::cmp::min(limit.map(|limit| limit.0).unwrap_or(MAX_PAGE_SIZE), MAX_PAGE_SIZE);
+
+        let order_ids = order_ids.as_vector();
+        (from_index..std::cmp::min(from_index.saturating_add(limit), order_ids.len()))
+            .filter_map(|index| {
+                let order_id = order_ids.get(index)?;
+                self.get_order(order_id.into())
+            })
+            .collect()
+    }
+
+    pub fn get_shares_refund(&self, account_id: ValidAccountId, shares_id: ValidAccountId) -> U128 {
+        self.shares_refunds.get(&refund_key(account_id.as_ref(), shares_id.as_ref())).unwrap_or(0).into()
+    }
+}
+
+impl Market {
+    /// Panic unless an order of `amount` shares at `price` can be placed for `shares_id`
+    fn assert_order(&self, shares_id: &AccountId, amount: Balance, price: Balance) {
+        assert!(self.verified_tokens.get(shares_id).is_some(), "{} is not a registered Fractose vault", shares_id);
+        assert!(price > 0, "invalid price");
+        assert!(amount > 0, "invalid amount");
+        order_value(amount, price);
+    }
+
+    /// Ask the factory whether `shares_id` is still a vault, and the vault whether it still holds
+    /// its NFT. Orders are only placed and filled once both agree, see `is_checked_vault`
+    fn check_vault(&self, shares_id: &AccountId) -> Promise {
+        ext_fractose::nft_address_of(
+            shares_id.clone(),
+            &self.factory_id,
+            0,
+            GAS_FOR_REGISTRY_LOOKUP
+        ).and(ext_shares::is_backed(
+            shares_id,
+            0,
+            GAS_FOR_IS_BACKED
+        ))
+    }
+
+    /// Store a new order and record the cost of its storage in it. The order is not placed if the
+    /// cost exceeds `storage_available`, which sell orders take from the storage deposit of their
+    /// owner and buy orders from the attached deposit
+    fn internal_add_order(
+        &mut self,
+        owner_id: AccountId,
+        shares_id: AccountId,
+        side: Side,
+        price: Balance,
+        amount: Balance,
+        storage_available: Balance,
+    ) -> Result<OrderId, String> {
+        self.assert_order(&shares_id, amount, price);
+
+        let initial_storage_usage = env::storage_usage();
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+
+        let mut order = Order {
+            owner_id,
+            shares_id: shares_id.clone(),
+            side,
+            price,
+            amount,
+            filled: 0,
+            created_at: env::block_timestamp(),
+            storage_deposit: 0,
+        };
+        self.orders.insert(&order_id, &order);
+
+        let mut order_ids = self.orders_by_token.get(&shares_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::TokenOrders { shares_id_hash: env::sha256(shares_id.as_bytes()) })
+        });
+        order_ids.insert(&order_id);
+        self.orders_by_token.insert(&shares_id, &order_ids);
+
+        order.storage_deposit = Balance::from(env::storage_usage() - initial_storage_usage) * env::storage_byte_cost();
+        if order.storage_deposit > storage_available {
+            self.internal_remove_order(order_id, &order);
+            self.next_order_id -= 1;
+            return Err(match side {
+                Side::Buy => format!("attach {} yoctoNEAR on top of the order value to cover storage", order.storage_deposit),
+                Side::Sell => format!(
+                    "storage deposit of {} is {}, sell orders need {}", order.owner_id, storage_available, order.storage_deposit
+                ),
+            });
+        }
+        self.orders.insert(&order_id, &order);
+        if side == Side::Sell {
+            self.storage_deposits.insert(&order.owner_id, &(storage_available - order.storage_deposit));
+        }
+
+        self.on_order_placed(order_id, &order);
+        Ok(order_id)
+    }
+
+    /// Fill `amount` shares of an order. Orders are removed once completely filled
+    fn internal_fill(&mut self, order_id: OrderId, mut order: Order, taker_id: &AccountId, amount: Balance) -> Order {
+        order.amount -= amount;
+        order.filled += amount;
+        self.on_order_filled(order_id, taker_id, amount, order.price);
+
+        if order.amount == 0 {
+            self.internal_remove_order(order_id, &order);
+        } else {
+            self.orders.insert(&order_id, &order);
+        }
+        order
+    }
+
+    fn internal_remove_order(&mut self, order_id: OrderId, order: &Order) {
+        self.orders.remove(&order_id);
+        let mut order_ids = self.orders_by_token.get(&order.shares_id).expect("token has no orders");
+        order_ids.remove(&order_id);
+        if order_ids.is_empty() {
+            self.orders_by_token.remove(&order.shares_id);
+        } else {
+            self.orders_by_token.insert(&order.shares_id, &order_ids);
+        }
+    }
+
+    fn internal_send_shares(&self, account_id: &AccountId, shares_id: &AccountId, amount: Balance) {
+        ext_fungible_token::ft_transfer(
+            account_id.clone(),
+            amount.into(),
+            None,
+            shares_id,
+            1,
+            GAS_FOR_FT_TRANSFER
+        ).then(ext_self::on_shares_transfer(
+            account_id.clone(),
+            shares_id.clone(),
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER
+        ));
+    }
+
+    fn on_shares_registered(&self, shares_id: AccountId, nft_address: AccountAndTokenId) {
+        log!("SharesRegistered({}, {}, {})", shares_id, nft_address, env::current_account_id());
+    }
+
+    fn on_order_placed(&self, order_id: OrderId, order: &Order) {
+        log!("OrderPlaced({}, {}, {}, {:?}, {}, {})", order_id, order.owner_id, order.shares_id, order.side, order.price, order.amount);
+    }
+
+    fn on_order_filled(&self, order_id: OrderId, taker_id: &AccountId, amount: Balance, price: Balance) {
+        log!("OrderFilled({}, {}, {}, {})", order_id, taker_id, amount, price);
+    }
+
+    fn on_order_cancelled(&self, order_id: OrderId, order: &Order) {
+        log!("OrderCancelled({}, {}, {})", order_id, order.owner_id, order.amount);
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Market {
+    /// Receives shares to escrow in a sell order or to fill a buy order, once the vault is checked.
+    /// Shares which do not fill the buy order are returned
+    fn ft_on_transfer(&mut self, sender_id: ValidAccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let shares_id = env::predecessor_account_id();
+        let message: TokenReceiverMessage = serde_json::from_str(&msg).expect("invalid transfer message");
+
+        match message {
+            TokenReceiverMessage::Sell { price } => self.assert_order(&shares_id, amount.0, price.0),
+            TokenReceiverMessage::Fill { order_id } => {
+                let order = self.orders.get(&order_id.0).expect("order not found");
+                assert_eq!(order.side, Side::Buy, "not a buy order");
+                assert_eq!(order.shares_id, shares_id, "order is for another token");
+            }
+        }
+
+        self.check_vault(&shares_id).then(ext_self::on_shares_check(
+            sender_id.into(),
+            shares_id,
+            amount,
+            msg,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_CHECK
+        )).into()
+    }
+}
+
+fn order_value(amount: Balance, price: Balance) -> Balance {
+    amount.checked_mul(price).expect("order value overflows u128")
+}
+
+/// Shares of a sell order that `deposit` buys, up to `amount`
+fn fill_amount(order: &Order, amount: Balance, deposit: Balance) -> Balance {
+    std::cmp::min(std::cmp::min(amount, order.amount), deposit / order.price)
+}
+
+/// Result of a call, None if it failed or returned something else
+fn promise_value<T: DeserializeOwned>(index: u64) -> Option<T> {
+    match env::promise_result(index) {
+        PromiseResult::Successful(value) => serde_json::from_slice(&value).ok(),
+        _ => None,
+    }
+}
+
+/// Whether the factory still lists the vault and the vault still holds its NFT, as checked by `check_vault`
+fn is_checked_vault() -> bool {
+    promise_value::<Option<AccountAndTokenId>>(0).flatten().is_some() && promise_value::<bool>(1) == Some(true)
+}
+
+/// Storage deposit returned to the owner of an order once it is completely filled
+fn order_refund(order: &Order) -> Balance {
+    if order.amount == 0 { order.storage_deposit } else { 0 }
+}
+
+fn refund_key(account_id: &str, shares_id: &str) -> String {
+    format!("{}:{}", account_id, shares_id)
+}
+
+fn order_view(order_id: OrderId, order: Order) -> OrderView {
+    OrderView {
+        order_id: order_id.into(),
+        owner_id: order.owner_id,
+        shares_id: order.shares_id,
+        side: order.side,
+        price: order.price.into(),
+        amount: order.amount.into(),
+        filled: order.filled.into(),
+        created_at: order.created_at.into(),
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const PRICE: Balance = 10;
+    /// Covers the storage of one order
+    const ORDER_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    /// Market of factory accounts(5) with accounts(1) registered as a shares token. Seller
+    /// accounts(2) deposited storage for 5 orders
+    fn setup(context: &mut VMContextBuilder) -> Market {
+        testing_env!(context.build());
+        let mut market = Market::new(accounts(5));
+
+        context.attached_deposit(REGISTRATION_DEPOSIT);
+        set_callback_results(context, vec![json_result(&Some("nft.near/0"))]);
+        assert!(market.on_registry_lookup(accounts(1).into(), accounts(3).into()));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(5 * ORDER_DEPOSIT).build());
+        market.storage_deposit(None);
+        testing_env!(context.attached_deposit(0).build());
+        market
+    }
+
+    fn json_result<T: Serialize>(value: &T) -> PromiseResult {
+        PromiseResult::Successful(serde_json::to_vec(value).unwrap())
+    }
+
+    /// Run the next call as a callback of the market receiving `results`
+    fn set_callback_results(context: &mut VMContextBuilder, results: Vec<PromiseResult>) {
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            results
+        );
+    }
+
+    /// Run the next call as the callback of `check_vault`, which found the vault in the factory
+    /// registry if `registered` and holding its NFT if `backed`
+    fn set_vault_check(context: &mut VMContextBuilder, registered: bool, backed: bool) {
+        let nft_address = if registered { Some("nft.near/0") } else { None };
+        set_callback_results(context, vec![json_result(&nft_address), json_result(&backed)]);
+    }
+
+    /// NEAR sent to `account_id` by the receipts created in the current context
+    fn near_sent_to(account_id: &str) -> Balance {
+        get_created_receipts().iter()
+            .map(|receipt| format!("{:?}", receipt))
+            .filter(|receipt| receipt.contains(&format!("receiver_id: {:?}", account_id)))
+            .flat_map(|receipt| receipt.split("TransferAction { deposit: ").skip(1)
+                .map(|rest| rest.split(' ').next().unwrap().parse::<Balance>().unwrap())
+                .collect::<Vec<_>>())
+            .sum()
+    }
+
+    /// Send shares from `sender_id` to the market with `msg`, and return what the market doesn't use
+    fn transfer_shares(context: &mut VMContextBuilder, market: &mut Market, sender_id: ValidAccountId, amount: Balance, msg: String) -> Balance {
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(0).build());
+        market.ft_on_transfer(sender_id.clone(), amount.into(), msg.clone());
+
+        set_vault_check(context, true, true);
+        market.on_shares_check(sender_id.into(), accounts(1).into(), amount.into(), msg).0
+    }
+
+    fn place_sell_order(context: &mut VMContextBuilder, market: &mut Market, amount: Balance) -> OrderId {
+        transfer_shares(context, market, accounts(2), amount, format!(r#"{{"sell": {{"price": "{}"}}}}"#, PRICE));
+        market.next_order_id - 1
+    }
+
+    /// Place a buy order of `amount` shares from accounts(3) with `deposit` attached
+    fn place_buy_order(context: &mut VMContextBuilder, market: &mut Market, amount: Balance, deposit: Balance) -> Option<U64> {
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(deposit).build());
+        market.place_buy_order(accounts(1), amount.into(), PRICE.into());
+
+        set_vault_check(context, true, true);
+        market.on_buy_order_check(accounts(3).into(), accounts(1).into(), amount.into(), PRICE.into())
+    }
+
+    /// Buy up to `amount` shares of a sell order from accounts(3) with `deposit` attached
+    fn fill_sell_order(context: &mut VMContextBuilder, market: &mut Market, order_id: OrderId, amount: Balance, deposit: Balance) -> Balance {
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(deposit).build());
+        market.fill_sell_order(order_id.into(), amount.into());
+
+        set_vault_check(context, true, true);
+        market.on_sell_order_fill_check(accounts(3).into(), order_id.into(), amount.into()).0
+    }
+
+    #[test]
+    fn test_registry_lookup_rejects_unknown_token() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        context.attached_deposit(REGISTRATION_DEPOSIT);
+        set_callback_results(&mut context, vec![json_result(&None::<String>)]);
+        assert!(!market.on_registry_lookup(accounts(4).into(), accounts(3).into()));
+        assert!(!market.is_verified(accounts(4)));
+        assert!(market.is_verified(accounts(1)));
+        assert_eq!(near_sent_to(accounts(3).as_ref()), REGISTRATION_DEPOSIT);
+    }
+
+    #[test]
+    fn test_failed_registry_lookup_is_refunded() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        context.attached_deposit(REGISTRATION_DEPOSIT);
+        set_callback_results(&mut context, vec![PromiseResult::Failed]);
+        assert!(!market.on_registry_lookup(accounts(4).into(), accounts(3).into()));
+        assert!(!market.is_verified(accounts(4)));
+        assert_eq!(near_sent_to(accounts(3).as_ref()), REGISTRATION_DEPOSIT);
+    }
+
+    #[test]
+    fn test_registry_lookup_registers_market_with_token() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        context.attached_deposit(REGISTRATION_DEPOSIT + 5);
+        set_callback_results(&mut context, vec![json_result(&Some("nft.near/1"))]);
+        assert!(market.on_registry_lookup(accounts(4).into(), accounts(3).into()));
+
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains(&format!("{:?}", b"storage_deposit")));
+        assert!(receipts.contains(&format!("{:?}", b"exclude_from_dividends")));
+        assert_eq!(near_sent_to(accounts(3).as_ref()), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "is already registered")]
+    fn test_register_shares_twice() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(REGISTRATION_DEPOSIT).build());
+        market.register_shares(accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a registered Fractose vault")]
+    fn test_sell_spoofed_token() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        market.ft_on_transfer(accounts(2), 100.into(), r#"{"sell": {"price": "10"}}"#.to_string());
+    }
+
+    #[test]
+    fn test_partial_fill_sell_order() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_sell_order(&mut context, &mut market, 100);
+
+        assert_eq!(fill_sell_order(&mut context, &mut market, order_id, 60, 405), 40);
+
+        let order = market.get_order(order_id.into()).unwrap();
+        assert_eq!((order.amount.0, order.filled.0), (60, 40));
+
+        fill_sell_order(&mut context, &mut market, order_id, 60, 600);
+        assert!(market.get_order(order_id.into()).is_none());
+        assert!(market.get_orders(accounts(1), None, None).is_empty());
+
+        // The seller gets the storage deposit of the filled order back with the last payment
+        let storage_used = 5 * ORDER_DEPOSIT - market.storage_balance_of(accounts(2)).0;
+        assert_eq!(near_sent_to(accounts(2).as_ref()), 600 + storage_used);
+    }
+
+    #[test]
+    fn test_fill_buy_order() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        let order_id = place_buy_order(&mut context, &mut market, 100, 1000 + ORDER_DEPOSIT).unwrap();
+        let storage_deposit = market.orders.get(&order_id.0).unwrap().storage_deposit;
+        assert_eq!(near_sent_to(accounts(3).as_ref()), ORDER_DEPOSIT - storage_deposit);
+
+        let unused = transfer_shares(&mut context, &mut market, accounts(2), 150, format!(r#"{{"fill": {{"order_id": "{}"}}}}"#, order_id.0));
+        assert_eq!(unused, 50);
+        assert!(market.get_order(order_id).is_none());
+        assert_eq!(near_sent_to(accounts(3).as_ref()), storage_deposit);
+    }
+
+    #[test]
+    fn test_buy_order_without_storage_deposit() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        // The vault check has taken the deposit, so the callback refunds it rather than panic
+        assert!(place_buy_order(&mut context, &mut market, 100, 1000).is_none());
+        assert_eq!(near_sent_to(accounts(3).as_ref()), 1000);
+        assert!(market.get_orders(accounts(1), None, None).is_empty());
+        assert_eq!(market.next_order_id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "storage deposit of danny is 0")]
+    fn test_sell_order_without_storage_deposit() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        transfer_shares(&mut context, &mut market, accounts(3), 100, r#"{"sell": {"price": "10"}}"#.to_string());
+    }
+
+    #[test]
+    fn test_fill_cancelled_order_is_refunded() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_sell_order(&mut context, &mut market, 100);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(500).build());
+        market.fill_sell_order(order_id.into(), 50.into());
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(0).build());
+        market.cancel_order(order_id.into());
+
+        context.attached_deposit(500);
+        set_vault_check(&mut context, true, true);
+        assert_eq!(market.on_sell_order_fill_check(accounts(3).into(), order_id.into(), 50.into()).0, 0);
+        assert_eq!(near_sent_to(accounts(3).as_ref()), 500);
+    }
+
+    #[test]
+    #[should_panic(expected = "attached deposit does not cover the order")]
+    fn test_buy_order_underfunded() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(999).build());
+        market.place_buy_order(accounts(1), 100.into(), PRICE.into());
+    }
+
+    #[test]
+    fn test_orders_refused_once_vault_is_no_longer_backed() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_sell_order(&mut context, &mut market, 100);
+
+        // The vault released its NFT: buy orders and fills are refunded
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1000 + ORDER_DEPOSIT).build());
+        market.place_buy_order(accounts(1), 100.into(), PRICE.into());
+        set_vault_check(&mut context, true, false);
+        assert!(market.on_buy_order_check(accounts(3).into(), accounts(1).into(), 100.into(), PRICE.into()).is_none());
+        assert_eq!(near_sent_to(accounts(3).as_ref()), 1000 + ORDER_DEPOSIT);
+
+        context.attached_deposit(500);
+        set_vault_check(&mut context, true, false);
+        assert_eq!(market.on_sell_order_fill_check(accounts(3).into(), order_id.into(), 50.into()).0, 0);
+        assert_eq!(near_sent_to(accounts(3).as_ref()), 500);
+        assert_eq!(market.get_order(order_id.into()).unwrap().amount.0, 100);
+    }
+
+    #[test]
+    fn test_orders_refused_once_vault_is_invalid() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_buy_order(&mut context, &mut market, 100, 1000 + ORDER_DEPOSIT).unwrap();
+
+        // The factory no longer lists the vault: shares sent to the market are returned
+        for msg in [r#"{"sell": {"price": "10"}}"#.to_string(), format!(r#"{{"fill": {{"order_id": "{}"}}}}"#, order_id.0)] {
+            testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(0).build());
+            market.ft_on_transfer(accounts(2), 100.into(), msg.clone());
+            set_vault_check(&mut context, false, true);
+            assert_eq!(market.on_shares_check(accounts(2).into(), accounts(1).into(), 100.into(), msg).0, 100);
+        }
+        assert_eq!(market.get_orders(accounts(1), None, None).len(), 1);
+        assert_eq!(market.get_order(order_id).unwrap().amount.0, 100);
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_sell_order(&mut context, &mut market, 100);
+
+        let storage_deposit = market.orders.get(&order_id).unwrap().storage_deposit;
+        assert_eq!(market.storage_balance_of(accounts(2)).0, 5 * ORDER_DEPOSIT - storage_deposit);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        market.cancel_order(order_id.into());
+        assert!(market.get_order(order_id.into()).is_none());
+        assert_eq!(near_sent_to(accounts(2).as_ref()), storage_deposit);
+    }
+
+    #[test]
+    fn test_storage_withdraw() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert_eq!(market.storage_withdraw(Some(ORDER_DEPOSIT.into())).0, ORDER_DEPOSIT);
+        assert_eq!(market.storage_withdraw(None).0, 4 * ORDER_DEPOSIT);
+        assert_eq!(market.storage_balance_of(accounts(2)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "only the owner can cancel an order")]
+    fn test_cancel_order_not_owner() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        let order_id = place_sell_order(&mut context, &mut market, 100);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        market.cancel_order(order_id.into());
+    }
+
+    #[test]
+    fn test_get_orders_paginated() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+        for amount in 1..=5 {
+            place_sell_order(&mut context, &mut market, amount);
+        }
+
+        let page = market.get_orders(accounts(1), Some(1.into()), Some(3.into()));
+        let amounts: Vec<Balance> = page.iter().map(|order| order.amount.0).collect();
+        assert_eq!(amounts, vec![2, 3, 4]);
+        assert_eq!(market.get_orders(accounts(1), Some(4.into()), None).len(), 1);
+        assert!(market.get_orders(accounts(1), Some(10.into()), None).is_empty());
+    }
+
+    #[test]
+    fn test_failed_share_transfer_is_refundable() {
+        let mut context = get_context(accounts(2));
+        let mut market = setup(&mut context);
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        market.on_shares_transfer(accounts(3).into(), accounts(1).into(), 100.into());
+
+        assert_eq!(market.get_shares_refund(accounts(3), accounts(1)).0, 100);
+    }
+}
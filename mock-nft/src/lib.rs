@@ -0,0 +1,98 @@
+//! Minimal NEP-171 NFT contract with approvals, enumeration and NEP-4907 style users, used to test the shares lifecycle
+use near_contract_standards::non_fungible_token::metadata::{
+    NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC,
+};
+use near_contract_standards::non_fungible_token::{Token, TokenId, NonFungibleToken};
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, AccountId, PanicOnDefault, Promise, PromiseOrValue, BorshStorageKey,
+    collections::{LazyOption, LookupMap},
+    json_types::{ValidAccountId, U64},
+    borsh::{self, BorshDeserialize, BorshSerialize},
+};
+
+near_sdk::setup_alloc!();
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct MockNft {
+    pub tokens: NonFungibleToken,
+    metadata: LazyOption<NFTContractMetadata>,
+    /// User of each token and when its usage expires
+    users: LookupMap<TokenId, (AccountId, u64)>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    NonFungibleToken,
+    Metadata,
+    TokenMetadata,
+    Enumeration,
+    Approval,
+    Users,
+}
+
+#[near_bindgen]
+impl MockNft {
+    /// `owner_id` is the only account allowed to mint
+    #[init]
+    pub fn new(owner_id: ValidAccountId) -> Self {
+        let metadata = NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Mock NFT".to_string(),
+            symbol: "MOCK".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        };
+
+        Self {
+            tokens: NonFungibleToken::new(
+                StorageKey::NonFungibleToken,
+                owner_id,
+                Some(StorageKey::TokenMetadata),
+                Some(StorageKey::Enumeration),
+                Some(StorageKey::Approval),
+            ),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            users: LookupMap::new(StorageKey::Users),
+        }
+    }
+
+    /// Mint a token. The attached deposit pays for its storage and the excess is refunded
+    #[payable]
+    pub fn nft_mint(&mut self, token_id: TokenId, receiver_id: ValidAccountId, token_metadata: TokenMetadata) -> Token {
+        self.tokens.mint(token_id, receiver_id, Some(token_metadata))
+    }
+
+    /// Let `user_id` use the token until `expires_at` without owning it. Only the owner sets the user
+    #[payable]
+    pub fn nft_set_user(&mut self, token_id: TokenId, user_id: Option<ValidAccountId>, expires_at: U64) {
+        assert_one_yocto();
+        let owner_id = self.tokens.owner_by_id.get(&token_id).expect("token not found");
+        assert_eq!(env::predecessor_account_id(), owner_id, "only the owner can set the user");
+
+        match user_id {
+            Some(user_id) => self.users.insert(&token_id, &(user_id.into(), expires_at.0)),
+            None => self.users.remove(&token_id),
+        };
+    }
+
+    /// Current user of the token, until its usage expires
+    pub fn nft_user_of(&self, token_id: TokenId) -> Option<AccountId> {
+        self.users.get(&token_id)
+            .filter(|(_, expires_at)| env::block_timestamp() < *expires_at)
+            .map(|(user_id, _)| user_id)
+    }
+}
+
+near_contract_standards::impl_non_fungible_token_core!(MockNft, tokens);
+near_contract_standards::impl_non_fungible_token_approval!(MockNft, tokens);
+near_contract_standards::impl_non_fungible_token_enumeration!(MockNft, tokens);
+
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for MockNft {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        self.metadata.get().unwrap()
+    }
+}
@@ -0,0 +1,643 @@
+// #[near_bindgen] and #[ext_contract] expand methods into functions taking extra arguments
+#![allow(clippy::too_many_arguments)]
+
+use near_contract_standards::fungible_token::{
+    FungibleToken,
+    core_impl::ext_fungible_token,
+    metadata::{FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC},
+    receiver::FungibleTokenReceiver,
+};
+use near_sdk::{
+    env, AccountId, Balance, Gas, PromiseOrValue, Promise, PromiseResult,
+    BorshStorageKey, PanicOnDefault, log,
+    near_bindgen, ext_contract,
+    collections::LookupMap,
+    json_types::{ValidAccountId, U128},
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    serde::{Deserialize, Serialize},
+    serde_json,
+};
+
+near_sdk::setup_alloc!();
+
+#[allow(clippy::all)]
+mod uint256 {
+    uint::construct_uint! {
+        /// 256-bit unsigned integer for intermediate pool math
+        pub struct U256(4);
+    }
+}
+use uint256::U256;
+
+/// Swap fee kept in the pool for liquidity providers, in basis points
+pub const DEFAULT_FEE_BPS: u32 = 30;
+const BPS_DENOMINATOR: u32 = 10_000;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_RESOLVE_WITHDRAW: Gas = 10_000_000_000_000;
+/// Gas given to `claim` on the vault, covering the cleanup it schedules
+const GAS_FOR_VAULT_CLAIM: Gas = 40_000_000_000_000;
+const GAS_FOR_ON_REDEMPTION_CLAIMED: Gas = 10_000_000_000_000;
+
+#[ext_contract(ext_self)]
+pub trait Pool {
+    fn on_shares_withdrawn(&mut self, account_id: AccountId, amount: U128);
+    fn on_redemption_claimed(&mut self);
+}
+
+#[ext_contract(ext_vault)]
+pub trait Vault {
+    fn claim(&mut self) -> (U128, U128);
+}
+
+/// Constant product pool between a Fractose shares token and NEAR
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Pool {
+    /// Liquidity provider tokens
+    lp: FungibleToken,
+    shares_id: AccountId,
+    near_reserve: Balance,
+    shares_reserve: Balance,
+    fee_bps: u32,
+    /// Shares sent through `ft_transfer_call` which are not in the pool yet
+    deposits: LookupMap<AccountId, Balance>,
+    deposits_total: Balance,
+    /// NEAR per share claimed from the vault once its NFT was redeemed. Reserve shares are
+    /// converted to NEAR and deposits are withdrawn in NEAR at this price
+    redemption_price: Option<Balance>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolInfo {
+    pub shares_id: AccountId,
+    pub near_reserve: U128,
+    pub shares_reserve: U128,
+    pub lp_supply: U128,
+    pub fee_bps: u32,
+    pub redemption_price: Option<U128>,
+}
+
+/// `ft_transfer_call` message. An empty message deposits shares for `add_liquidity`
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+enum TokenReceiverMessage {
+    Deposit,
+    /// Swap all transferred shares for NEAR
+    Swap { min_near_out: U128 },
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Lp,
+    Deposits,
+}
+
+#[near_bindgen]
+impl Pool {
+    /// Initialize a pool. It can be seeded in the same call by attaching NEAR and passing the
+    /// amount of shares already credited to the pool account by the shares contract
+    ///
+    /// # Parameters
+    ///
+    /// - `shares_id`: Address of the shares contract
+    /// - `owner_id`: Receives the LP tokens of the seed liquidity
+    /// - `initial_shares`: Shares held by the pool account at creation
+    /// - `fee_bps`: Swap fee in basis points. Defaults to 0.3%
+    #[init]
+    #[payable]
+    pub fn new(shares_id: ValidAccountId, owner_id: ValidAccountId, initial_shares: U128, fee_bps: Option<u32>) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        let fee_bps = fee_bps.unwrap_or(DEFAULT_FEE_BPS);
+        assert!(fee_bps < BPS_DENOMINATOR, "invalid fee");
+
+        let initial_near = env::attached_deposit();
+        assert_eq!(initial_near > 0, initial_shares.0 > 0, "pool must be seeded with both NEAR and shares");
+
+        let mut this = Self {
+            lp: FungibleToken::new(StorageKey::Lp),
+            shares_id: shares_id.into(),
+            near_reserve: initial_near,
+            shares_reserve: initial_shares.0,
+            fee_bps,
+            deposits: LookupMap::new(StorageKey::Deposits),
+            deposits_total: 0,
+            redemption_price: None,
+        };
+        this.lp.internal_register_account(owner_id.as_ref());
+        if initial_near > 0 {
+            // First liquidity is valued in NEAR
+            this.lp.internal_deposit(owner_id.as_ref(), initial_near);
+            this.on_liquidity_added(owner_id.into(), initial_near, initial_shares.0, initial_near);
+        }
+
+        this
+    }
+
+    /// Add liquidity with the attached NEAR and previously deposited shares.
+    /// Shares are taken at the current pool ratio, up to `max_shares`
+    #[payable]
+    pub fn add_liquidity(&mut self, max_shares: U128, min_lp: Option<U128>) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let near_amount = env::attached_deposit();
+        assert!(near_amount > 0, "attach NEAR to add liquidity");
+        assert!(self.lp.accounts.contains_key(&account_id), "The account {} is not registered", account_id);
+
+        let lp_supply = self.lp.total_supply;
+        let (shares_amount, lp_amount) = if lp_supply == 0 {
+            (max_shares.0, near_amount)
+        } else {
+            // Round shares up so that existing providers are never diluted
+            let shares = mul_div_ceil(near_amount, self.shares_reserve, self.near_reserve);
+            (shares, mul_div(near_amount, lp_supply, self.near_reserve))
+        };
+        assert!(shares_amount > 0, "invalid shares amount");
+        assert!(shares_amount <= max_shares.0, "liquidity needs {} shares", shares_amount);
+        assert!(lp_amount >= min_lp.map(|min| min.0).unwrap_or(0), "slippage: lp tokens below minimum");
+
+        self.internal_withdraw_deposit(&account_id, shares_amount);
+        self.near_reserve += near_amount;
+        self.shares_reserve += shares_amount;
+        self.lp.internal_deposit(&account_id, lp_amount);
+
+        self.on_liquidity_added(account_id, near_amount, shares_amount, lp_amount);
+        lp_amount.into()
+    }
+
+    /// Burn LP tokens for a proportional amount of both reserves
+    pub fn remove_liquidity(&mut self, lp_amount: U128, min_near: U128, min_shares: U128) -> (U128, U128) {
+        let account_id = env::predecessor_account_id();
+        let lp_supply = self.lp.total_supply;
+        assert!(lp_amount.0 > 0, "invalid lp amount");
+
+        let near_amount = mul_div(lp_amount.0, self.near_reserve, lp_supply);
+        let shares_amount = mul_div(lp_amount.0, self.shares_reserve, lp_supply);
+        assert!(near_amount >= min_near.0, "slippage: NEAR below minimum");
+        assert!(shares_amount >= min_shares.0, "slippage: shares below minimum");
+
+        self.lp.internal_withdraw(&account_id, lp_amount.0);
+        self.near_reserve -= near_amount;
+        self.shares_reserve -= shares_amount;
+
+        Promise::new(account_id.clone()).transfer(near_amount);
+        if shares_amount > 0 {
+            self.internal_send_shares(&account_id, shares_amount);
+        }
+
+        self.on_liquidity_removed(account_id, near_amount, shares_amount, lp_amount.0);
+        (near_amount.into(), shares_amount.into())
+    }
+
+    /// Swap the attached NEAR for shares
+    #[payable]
+    pub fn swap_near_for_shares(&mut self, min_shares_out: U128) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let near_in = env::attached_deposit();
+
+        let shares_out = self.get_return(near_in, self.near_reserve, self.shares_reserve);
+        assert!(shares_out > 0, "swap amount too small");
+        assert!(shares_out >= min_shares_out.0, "slippage: shares below minimum");
+
+        self.near_reserve += near_in;
+        self.shares_reserve -= shares_out;
+        self.internal_send_shares(&account_id, shares_out);
+
+        self.on_swap(account_id, "near", near_in, shares_out);
+        shares_out.into()
+    }
+
+    /// Return shares deposited but not added to the pool. Once the pool claimed the redemption
+    /// of its shares, deposits are paid out in NEAR instead
+    pub fn withdraw_shares(&mut self, amount: Option<U128>) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let amount = amount.map(|amount| amount.0).unwrap_or_else(|| self.get_deposit(account_id.clone()).0);
+        assert!(amount > 0, "nothing to withdraw");
+
+        self.internal_withdraw_deposit(&account_id, amount);
+        match self.redemption_price {
+            Some(price) => {
+                Promise::new(account_id).transfer(amount * price);
+            }
+            None => self.internal_send_shares(&account_id, amount),
+        }
+        amount.into()
+    }
+
+    /// Credits shares back to the deposit of `account_id` if sending them failed
+    #[private]
+    pub fn on_shares_withdrawn(&mut self, account_id: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.internal_credit_deposit(&account_id, amount.0);
+            log!("Transfer of {} shares to @{} failed, credited to deposit", amount.0, account_id);
+        }
+    }
+
+    /// Claim the NEAR value of every share held by the pool once the vault's NFT was redeemed.
+    /// Anyone can call it
+    pub fn claim_redemption(&mut self) -> Promise {
+        assert!(self.redemption_price.is_none(), "redemption already claimed");
+        assert!(
+            env::prepaid_gas() >= GAS_FOR_VAULT_CLAIM + GAS_FOR_ON_REDEMPTION_CLAIMED,
+            "not enough gas"
+        );
+
+        ext_vault::claim(
+            &self.shares_id,
+            0,
+            GAS_FOR_VAULT_CLAIM
+        ).then(ext_self::on_redemption_claimed(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_REDEMPTION_CLAIMED
+        ))
+    }
+
+    /// Converts the reserve shares to NEAR at the price the vault paid
+    #[private]
+    pub fn on_redemption_claimed(&mut self) {
+        let (shares, near): (U128, U128) = match env::promise_result(0) {
+            PromiseResult::Successful(result) => serde_json::from_slice(&result).expect("invalid claim result"),
+            _ => panic!("vault claim failed"),
+        };
+        let price = near.0 / shares.0;
+
+        self.near_reserve += self.shares_reserve * price;
+        self.shares_reserve = 0;
+        self.redemption_price = Some(price);
+        log!("ClaimRedemption({}, {}, {})", shares.0, near.0, env::current_account_id());
+    }
+
+    /// Shares received for `near_in` NEAR
+    pub fn get_return_near_to_shares(&self, near_in: U128) -> U128 {
+        self.get_return(near_in.0, self.near_reserve, self.shares_reserve).into()
+    }
+
+    /// NEAR received for `shares_in` shares
+    pub fn get_return_shares_to_near(&self, shares_in: U128) -> U128 {
+        self.get_return(shares_in.0, self.shares_reserve, self.near_reserve).into()
+    }
+
+    pub fn get_pool_info(&self) -> PoolInfo {
+        PoolInfo {
+            shares_id: self.shares_id.clone(),
+            near_reserve: self.near_reserve.into(),
+            shares_reserve: self.shares_reserve.into(),
+            lp_supply: self.lp.total_supply.into(),
+            fee_bps: self.fee_bps,
+            redemption_price: self.redemption_price.map(U128),
+        }
+    }
+
+    /// Shares deposited by an account which are not in the pool
+    pub fn get_deposit(&self, account_id: AccountId) -> U128 {
+        self.deposits.get(&account_id).unwrap_or(0).into()
+    }
+}
+
+impl Pool {
+    /// Constant product output for `amount_in` after the swap fee
+    fn get_return(&self, amount_in: Balance, reserve_in: Balance, reserve_out: Balance) -> Balance {
+        assert!(reserve_in > 0 && reserve_out > 0, "pool has no liquidity");
+        let amount_in_with_fee = U256::from(amount_in) * U256::from(BPS_DENOMINATOR - self.fee_bps);
+        let numerator = amount_in_with_fee * U256::from(reserve_out);
+        let denominator = U256::from(reserve_in) * U256::from(BPS_DENOMINATOR) + amount_in_with_fee;
+        (numerator / denominator).as_u128()
+    }
+
+    fn internal_withdraw_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        let deposit = self.deposits.get(account_id).unwrap_or(0);
+        assert!(deposit >= amount, "not enough shares deposited");
+        if deposit == amount {
+            self.deposits.remove(account_id);
+        } else {
+            self.deposits.insert(account_id, &(deposit - amount));
+        }
+        self.deposits_total -= amount;
+    }
+
+    fn internal_credit_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        let deposit = self.deposits.get(account_id).unwrap_or(0);
+        self.deposits.insert(account_id, &(deposit + amount));
+        self.deposits_total += amount;
+    }
+
+    fn internal_send_shares(&self, account_id: &AccountId, amount: Balance) {
+        ext_fungible_token::ft_transfer(
+            account_id.clone(),
+            amount.into(),
+            None,
+            &self.shares_id,
+            1,
+            GAS_FOR_FT_TRANSFER
+        ).then(ext_self::on_shares_withdrawn(
+            account_id.clone(),
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_WITHDRAW
+        ));
+    }
+
+    fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        log!("Account @{} burned {} LP tokens", account_id, amount);
+    }
+
+    fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
+        log!("Closed @{} with {}", account_id, balance);
+    }
+
+    fn on_liquidity_added(&self, account_id: AccountId, near_amount: Balance, shares_amount: Balance, lp_amount: Balance) {
+        log!("AddLiquidity({}, {}, {}, {}, {})", account_id, near_amount, shares_amount, lp_amount, env::current_account_id());
+    }
+
+    fn on_liquidity_removed(&self, account_id: AccountId, near_amount: Balance, shares_amount: Balance, lp_amount: Balance) {
+        log!("RemoveLiquidity({}, {}, {}, {}, {})", account_id, near_amount, shares_amount, lp_amount, env::current_account_id());
+    }
+
+    fn on_swap(&self, account_id: AccountId, token_in: &str, amount_in: Balance, amount_out: Balance) {
+        log!("Swap({}, {}, {}, {}, {})", account_id, token_in, amount_in, amount_out, env::current_account_id());
+    }
+}
+
+fn mul_div(a: Balance, b: Balance, denominator: Balance) -> Balance {
+    (U256::from(a) * U256::from(b) / U256::from(denominator)).as_u128()
+}
+
+fn mul_div_ceil(a: Balance, b: Balance, denominator: Balance) -> Balance {
+    let denominator = U256::from(denominator);
+    ((U256::from(a) * U256::from(b) + denominator - 1) / denominator).as_u128()
+}
+
+near_contract_standards::impl_fungible_token_core!(Pool, lp, on_tokens_burned);
+near_contract_standards::impl_fungible_token_storage!(Pool, lp, on_account_closed);
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Pool {
+    /// Receives shares either as a deposit for `add_liquidity` or to swap them for NEAR
+    fn ft_on_transfer(&mut self, sender_id: ValidAccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        assert_eq!(env::predecessor_account_id(), self.shares_id, "only pool shares are accepted");
+        assert!(self.redemption_price.is_none(), "shares were redeemed");
+        let sender_id: AccountId = sender_id.into();
+
+        let message = if msg.is_empty() {
+            TokenReceiverMessage::Deposit
+        } else {
+            serde_json::from_str(&msg).expect("invalid transfer message")
+        };
+
+        match message {
+            TokenReceiverMessage::Deposit => self.internal_credit_deposit(&sender_id, amount.0),
+            TokenReceiverMessage::Swap { min_near_out } => {
+                let near_out = self.get_return(amount.0, self.shares_reserve, self.near_reserve);
+                assert!(near_out > 0, "swap amount too small");
+                assert!(near_out >= min_near_out.0, "slippage: NEAR below minimum");
+
+                self.shares_reserve += amount.0;
+                self.near_reserve -= near_out;
+                Promise::new(sender_id.clone()).transfer(near_out);
+
+                self.on_swap(sender_id, "shares", amount.0, near_out);
+            }
+        }
+
+        PromiseOrValue::Value(0.into())
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Pool {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: format!("Fractose LP {}", self.shares_id),
+            symbol: "FLP".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const NEAR_SEED: Balance = 1_000_000;
+    const SHARES_SEED: Balance = 2_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    /// Pool for shares contract accounts(1), seeded by accounts(2)
+    fn setup(context: &mut VMContextBuilder) -> Pool {
+        testing_env!(context.attached_deposit(NEAR_SEED).build());
+        let pool = Pool::new(accounts(1), accounts(2), SHARES_SEED.into(), None);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+        pool
+    }
+
+    #[test]
+    fn test_new_seeded() {
+        let mut context = get_context(accounts(5));
+        let pool = setup(&mut context);
+
+        let info = pool.get_pool_info();
+        assert_eq!(info.near_reserve.0, NEAR_SEED);
+        assert_eq!(info.shares_reserve.0, SHARES_SEED);
+        assert_eq!(pool.ft_balance_of(accounts(2)).0, NEAR_SEED);
+        assert_eq!(pool.ft_total_supply().0, NEAR_SEED);
+    }
+
+    #[test]
+    #[should_panic(expected = "pool must be seeded with both NEAR and shares")]
+    fn test_new_one_sided_seed() {
+        let context = get_context(accounts(5));
+        testing_env!(context.build());
+        Pool::new(accounts(1), accounts(2), SHARES_SEED.into(), None);
+    }
+
+    #[test]
+    fn test_get_return() {
+        let mut context = get_context(accounts(5));
+        let pool = setup(&mut context);
+
+        // 1000 * 0.997 * 2_000_000 / (1_000_000 + 997)
+        assert_eq!(pool.get_return_near_to_shares(1000.into()).0, 1992);
+        assert_eq!(pool.get_return_shares_to_near(2000.into()).0, 996);
+    }
+
+    #[test]
+    fn test_swap_near_for_shares() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.attached_deposit(1000).predecessor_account_id(accounts(3)).build());
+        let shares_out = pool.swap_near_for_shares(1992.into());
+
+        assert_eq!(shares_out.0, 1992);
+        let info = pool.get_pool_info();
+        assert_eq!(info.near_reserve.0, NEAR_SEED + 1000);
+        assert_eq!(info.shares_reserve.0, SHARES_SEED - 1992);
+    }
+
+    #[test]
+    #[should_panic(expected = "slippage: shares below minimum")]
+    fn test_swap_slippage() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.attached_deposit(1000).predecessor_account_id(accounts(3)).build());
+        pool.swap_near_for_shares(1993.into());
+    }
+
+    #[test]
+    fn test_swap_shares_for_near() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        pool.ft_on_transfer(accounts(3), 2000.into(), r#"{"swap": {"min_near_out": "996"}}"#.to_string());
+
+        let info = pool.get_pool_info();
+        assert_eq!(info.near_reserve.0, NEAR_SEED - 996);
+        assert_eq!(info.shares_reserve.0, SHARES_SEED + 2000);
+    }
+
+    #[test]
+    #[should_panic(expected = "only pool shares are accepted")]
+    fn test_transfer_wrong_token() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        pool.ft_on_transfer(accounts(3), 2000.into(), "".to_string());
+    }
+
+    #[test]
+    fn test_add_and_remove_liquidity() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        // Deposit shares, then register for LP tokens and add liquidity
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        pool.ft_on_transfer(accounts(3), 5000.into(), "".to_string());
+        assert_eq!(pool.get_deposit(accounts(3).into()).0, 5000);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(pool.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(3))
+            .build());
+        pool.storage_deposit(None, None);
+
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1000).build());
+        let lp = pool.add_liquidity(5000.into(), Some(1000.into()));
+
+        assert_eq!(lp.0, 1000);
+        assert_eq!(pool.get_deposit(accounts(3).into()).0, 3000);
+        assert_eq!(pool.get_pool_info().shares_reserve.0, SHARES_SEED + 2000);
+
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+        let (near_out, shares_out) = pool.remove_liquidity(lp, 1000.into(), 2000.into());
+
+        assert_eq!((near_out.0, shares_out.0), (1000, 2000));
+        assert_eq!(pool.ft_balance_of(accounts(3)).0, 0);
+        assert_eq!(pool.get_pool_info().near_reserve.0, NEAR_SEED);
+    }
+
+    #[test]
+    #[should_panic(expected = "liquidity needs 2000 shares")]
+    fn test_add_liquidity_above_max_shares() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.attached_deposit(1000).predecessor_account_id(accounts(2)).build());
+        pool.add_liquidity(1999.into(), None);
+    }
+
+    /// Claim the redemption of the pool's shares at `price` NEAR per share
+    fn claim_redemption(context: &mut VMContextBuilder, pool: &mut Pool, price: Balance) {
+        testing_env!(context.storage_usage(env::storage_usage()).prepaid_gas(10u64.pow(14)).build());
+        pool.claim_redemption();
+
+        let shares_held = pool.get_pool_info().shares_reserve.0 + pool.deposits_total;
+        let result = serde_json::to_vec(&(U128(shares_held), U128(shares_held * price))).unwrap();
+        testing_env!(
+            context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(result)]
+        );
+        pool.on_redemption_claimed();
+    }
+
+    #[test]
+    fn test_claim_redemption() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        pool.ft_on_transfer(accounts(3), 5000.into(), "".to_string());
+
+        claim_redemption(&mut context, &mut pool, 3);
+
+        let info = pool.get_pool_info();
+        assert_eq!(info.shares_reserve.0, 0);
+        assert_eq!(info.near_reserve.0, NEAR_SEED + SHARES_SEED * 3);
+        assert_eq!(info.redemption_price, Some(U128(3)));
+
+        // Liquidity is removed in NEAR only
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(2)).build());
+        let (near_out, shares_out) = pool.remove_liquidity(NEAR_SEED.into(), 0.into(), 0.into());
+        assert_eq!((near_out.0, shares_out.0), (NEAR_SEED + SHARES_SEED * 3, 0));
+        assert_eq!(get_created_receipts().len(), 1);
+
+        // Deposits are withdrawn in NEAR at the redemption price
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(3)).build());
+        assert_eq!(pool.withdraw_shares(None).0, 5000);
+        assert_eq!(format!("{:?}", get_created_receipts()).matches("TransferAction { deposit: 15000 }").count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "redemption already claimed")]
+    fn test_claim_redemption_twice() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+        claim_redemption(&mut context, &mut pool, 3);
+
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(5)).build());
+        pool.claim_redemption();
+    }
+
+    #[test]
+    #[should_panic(expected = "shares were redeemed")]
+    fn test_deposit_after_redemption() {
+        let mut context = get_context(accounts(5));
+        let mut pool = setup(&mut context);
+        claim_redemption(&mut context, &mut pool, 3);
+
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(1)).build());
+        pool.ft_on_transfer(accounts(3), 5000.into(), "".to_string());
+    }
+
+    #[test]
+    fn test_math_rounding() {
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(mul_div_ceil(10, 1, 3), 4);
+        assert_eq!(mul_div_ceil(9, 1, 3), 3);
+    }
+}
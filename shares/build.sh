@@ -1,10 +1,6 @@
 #!/bin/bash
 set -e
 
-# cargo build --all --target wasm32-unknown-unknown --release
-RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
-mkdir -p ../../out
-cp target/wasm32-unknown-unknown/release/*.wasm ../../out/main.wasm
+RUSTFLAGS="-C link-arg=-s --remap-path-prefix=${CARGO_HOME:-$HOME/.cargo}=/cargo" cargo build --target wasm32-unknown-unknown --release
 
-mkdir ./res
-cp ../../out/main.wasm ./res/nft_wrapper.wasm
+cp ../target/wasm32-unknown-unknown/release/nft_shares.wasm ./res/shares.wasm
@@ -0,0 +1,451 @@
+use near_sdk::{BlockHeight, StorageUsage, collections::Vector};
+
+use crate::*;
+
+/// Balance at the end of `block`
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct Checkpoint {
+    pub block: BlockHeight,
+    pub balance: Balance,
+}
+
+/// Checkpoints kept for each account. Registration deposits pay for their storage
+pub const MAX_BALANCE_CHECKPOINTS: u64 = 16;
+
+/// History of share balances and total supply by block height.
+///
+/// A checkpoint is written when a balance changes, at most one per account per block. Once an
+/// account has `MAX_BALANCE_CHECKPOINTS`, a checkpoint which no open proposal reads is dropped,
+/// the oldest one if possible. Balance changes are refused while every checkpoint is read.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Checkpoints {
+    /// Index range of the checkpoints kept for each account
+    ranges: LookupMap<AccountId, (u64, u64)>,
+    balances: LookupMap<(AccountId, u64), Checkpoint>,
+    supply: Vector<Checkpoint>,
+    range_storage_usage: StorageUsage,
+    checkpoint_storage_usage: StorageUsage,
+    /// Storage prepaid for the checkpoints of registered accounts which they don't use yet
+    pub unused_storage_usage: StorageUsage,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Self {
+            ranges: LookupMap::new(StorageKey::CheckpointRanges),
+            balances: LookupMap::new(StorageKey::BalanceCheckpoints),
+            supply: Vector::new(StorageKey::SupplyCheckpoints),
+            range_storage_usage: 0,
+            checkpoint_storage_usage: 0,
+            unused_storage_usage: 0,
+        }
+    }
+
+    /// Storage of the checkpoints of one account when it has the most it can keep
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        self.ranges.insert(&tmp_account_id, &(0, 1));
+        self.range_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.balances.insert(&(tmp_account_id.clone(), 0), &Checkpoint { block: 0, balance: 0 });
+        self.checkpoint_storage_usage = env::storage_usage() - initial_storage_usage - self.range_storage_usage;
+        self.balances.remove(&(tmp_account_id.clone(), 0));
+        self.ranges.remove(&tmp_account_id);
+
+        self.account_storage_usage()
+    }
+
+    fn account_storage_usage(&self) -> StorageUsage {
+        self.range_storage_usage + MAX_BALANCE_CHECKPOINTS * self.checkpoint_storage_usage
+    }
+
+    /// Storage used by the checkpoints of an account
+    fn storage_usage_of(&self, account_id: &AccountId) -> StorageUsage {
+        match self.ranges.get(account_id) {
+            Some((first, end)) => self.range_storage_usage + (end - first) * self.checkpoint_storage_usage,
+            None => 0,
+        }
+    }
+
+    /// Count the checkpoint storage a newly registered account prepaid
+    pub fn prepay(&mut self, account_id: &AccountId) {
+        self.unused_storage_usage += self.account_storage_usage().saturating_sub(self.storage_usage_of(account_id));
+    }
+
+    /// Stop counting the checkpoint storage of an unregistered account, which got its prepayment back
+    pub fn refund(&mut self, account_id: &AccountId) {
+        let unused = self.account_storage_usage().saturating_sub(self.storage_usage_of(account_id));
+        self.unused_storage_usage = self.unused_storage_usage.saturating_sub(unused);
+    }
+
+    /// Storage the first balance checkpoint of an account and a new supply checkpoint add
+    pub fn measure_new_checkpoints_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        let checkpoint = Checkpoint { block: 0, balance: 0 };
+        self.ranges.insert(&tmp_account_id, &(0, 1));
+        self.balances.insert(&(tmp_account_id.clone(), 0), &checkpoint);
+        self.supply.push(&checkpoint);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.supply.pop();
+        self.balances.remove(&(tmp_account_id.clone(), 0));
+        self.ranges.remove(&tmp_account_id);
+
+        storage_usage
+    }
+
+    /// Record the current balance of an account, which `prepaid` its checkpoints if registered. `is_read(from, to)`
+    /// tells whether votes may still read balances at a block in `from..to`
+    pub fn record_balance(&mut self, account_id: &AccountId, balance: Balance, prepaid: bool, is_read: impl Fn(BlockHeight, BlockHeight) -> bool) {
+        let storage_usage = self.storage_usage_of(account_id);
+        self.internal_record_balance(account_id, balance, is_read);
+        if prepaid {
+            let added = self.storage_usage_of(account_id) - storage_usage;
+            self.unused_storage_usage = self.unused_storage_usage.saturating_sub(added);
+        }
+    }
+
+    fn internal_record_balance(&mut self, account_id: &AccountId, balance: Balance, is_read: impl Fn(BlockHeight, BlockHeight) -> bool) {
+        let (first, end) = self.ranges.get(account_id).unwrap_or((0, 0));
+        let checkpoint = Checkpoint { block: env::block_index(), balance };
+
+        let last = if end > first { self.balances.get(&(account_id.clone(), end - 1)) } else { None };
+        match last {
+            Some(last) if last.balance == balance => {}
+            Some(last) if last.block == checkpoint.block => {
+                self.balances.insert(&(account_id.clone(), end - 1), &checkpoint);
+            }
+            _ if end - first == MAX_BALANCE_CHECKPOINTS => {
+                let index = (first..end)
+                    .find(|index| !self.is_checkpoint_read(account_id, *index, end, &is_read))
+                    .unwrap_or_else(|| panic!("balance history of {} is full until a proposal reading it closes", account_id));
+                if index == first {
+                    self.balances.remove(&(account_id.clone(), first));
+                    self.balances.insert(&(account_id.clone(), end), &checkpoint);
+                    self.ranges.insert(account_id, &(first + 1, end + 1));
+                } else {
+                    // Later checkpoints move down to fill the gap
+                    for index in index..end - 1 {
+                        let next = self.balances.get(&(account_id.clone(), index + 1)).unwrap();
+                        self.balances.insert(&(account_id.clone(), index), &next);
+                    }
+                    self.balances.insert(&(account_id.clone(), end - 1), &checkpoint);
+                }
+            }
+            _ => {
+                self.balances.insert(&(account_id.clone(), end), &checkpoint);
+                self.ranges.insert(account_id, &(first, end + 1));
+            }
+        }
+    }
+
+    /// Drop the checkpoints of a closed account which are no longer read, see `record_balance`
+    pub fn prune(&mut self, account_id: &AccountId, is_read: impl Fn(BlockHeight, BlockHeight) -> bool) {
+        let (mut first, end) = match self.ranges.get(account_id) {
+            Some(range) => range,
+            None => return,
+        };
+        while end - first > 1 && !self.is_checkpoint_read(account_id, first, end, &is_read) {
+            self.balances.remove(&(account_id.clone(), first));
+            first += 1;
+        }
+
+        // A single zero balance checkpoint reads the same as no history
+        let last = self.balances.get(&(account_id.clone(), end - 1)).unwrap();
+        if end - first == 1 && last.balance == 0 {
+            self.balances.remove(&(account_id.clone(), first));
+            self.ranges.remove(account_id);
+        } else {
+            self.ranges.insert(account_id, &(first, end));
+        }
+    }
+
+    /// Record the current total supply
+    pub fn record_supply(&mut self, supply: Balance) {
+        let checkpoint = Checkpoint { block: env::block_index(), balance: supply };
+
+        match self.supply.len().checked_sub(1).map(|last| (last, self.supply.get(last).unwrap())) {
+            Some((_, last)) if last.balance == supply => {}
+            Some((index, last)) if last.block == checkpoint.block => {
+                self.supply.replace(index, &checkpoint);
+            }
+            _ => self.supply.push(&checkpoint),
+        }
+    }
+
+    pub fn balance_at(&self, account_id: &AccountId, block: BlockHeight) -> Balance {
+        let (first, end) = self.ranges.get(account_id).unwrap_or((0, 0));
+        search(end - first, block, |index| self.balances.get(&(account_id.clone(), first + index)).unwrap())
+    }
+
+    pub fn supply_at(&self, block: BlockHeight) -> Balance {
+        search(self.supply.len(), block, |index| self.supply.get(index).unwrap())
+    }
+
+    /// Whether votes may read the checkpoint at `index`, which gives the balance from its block up
+    /// to the next checkpoint. Once dropped, the previous checkpoint or no history gives it instead
+    fn is_checkpoint_read(&self, account_id: &AccountId, index: u64, end: u64, is_read: impl Fn(BlockHeight, BlockHeight) -> bool) -> bool {
+        let block = self.balances.get(&(account_id.clone(), index)).unwrap().block;
+        let next_block = match index + 1 {
+            next if next < end => self.balances.get(&(account_id.clone(), next)).unwrap().block,
+            _ => env::block_index(),
+        };
+        is_read(block, next_block)
+    }
+}
+
+/// Balance of the last checkpoint at or before `block`, or 0 if there is none
+fn search(count: u64, block: BlockHeight, get: impl Fn(u64) -> Checkpoint) -> Balance {
+    // Binary search for the number of checkpoints written up to `block`
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if get(mid).block <= block {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    low.checked_sub(1).map(|index| get(index).balance).unwrap_or(0)
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Share balance of an account at the end of a block
+    pub fn ft_balance_at(&self, account_id: ValidAccountId, block: U64) -> U128 {
+        self.checkpoints.balance_at(account_id.as_ref(), block.0).into()
+    }
+
+    /// Total supply at the end of a block
+    pub fn ft_total_supply_at(&self, block: U64) -> U128 {
+        self.checkpoints.supply_at(block.0).into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    /// Covers the storage of a proposal
+    const PROPOSAL_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    #[test]
+    fn test_balance_history() {
+        let mut context = VMContextBuilder::new();
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).block_index(10);
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .block_index(20)
+            .build());
+        contract.ft_transfer(accounts(2), 100.into(), None);
+        contract.ft_transfer(accounts(2), 100.into(), None);
+
+        testing_env!(context.storage_usage(env::storage_usage()).block_index(30).build());
+        contract.ft_transfer(accounts(2), 300.into(), None);
+
+        assert_eq!(contract.ft_balance_at(accounts(1), 9.into()).0, 0);
+        assert_eq!(contract.ft_balance_at(accounts(1), 10.into()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_at(accounts(1), 19.into()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_at(accounts(1), 20.into()).0, TOTAL_SUPPLY - 200);
+        assert_eq!(contract.ft_balance_at(accounts(2), 25.into()).0, 200);
+        assert_eq!(contract.ft_balance_at(accounts(2), 30.into()).0, 500);
+        assert_eq!(contract.ft_balance_at(accounts(2), 1_000.into()).0, 500);
+
+        assert_eq!(contract.ft_total_supply_at(9.into()).0, 0);
+        assert_eq!(contract.ft_total_supply_at(30.into()).0, TOTAL_SUPPLY);
+    }
+
+    /// Vault of accounts(1) with accounts(2) registered, at block 10
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).block_index(10);
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+        contract
+    }
+
+    /// Send 1 share from accounts(1) to accounts(2) in each of `blocks`
+    fn transfer_in_blocks(context: &mut VMContextBuilder, contract: &mut Shares, blocks: std::ops::Range<BlockHeight>) {
+        for block in blocks {
+            testing_env!(context
+                .storage_usage(env::storage_usage())
+                .attached_deposit(1)
+                .predecessor_account_id(accounts(1))
+                .block_index(block)
+                .build());
+            contract.ft_transfer(accounts(2), 1.into(), None);
+        }
+    }
+
+    #[test]
+    fn test_checkpoints_are_capped() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        transfer_in_blocks(&mut context, &mut contract, 20..40);
+
+        assert_eq!(contract.checkpoints.ranges.get(&accounts(2).into()), Some((4, 20)));
+        assert_eq!(contract.ft_balance_at(accounts(2), 23.into()).0, 0);
+        assert_eq!(contract.ft_balance_at(accounts(2), 24.into()).0, 5);
+        assert_eq!(contract.ft_balance_at(accounts(2), 39.into()).0, 20);
+    }
+
+    #[test]
+    fn test_checkpoints_needed_by_open_proposal_are_kept() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        transfer_in_blocks(&mut context, &mut contract, 20..22);
+
+        // The proposal reads balances at block 29
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(PROPOSAL_DEPOSIT).predecessor_account_id(accounts(1)).block_index(30).build());
+        contract.propose(governance::ProposalAction::ChangeCurator { curator_id: accounts(3).into() }, "".to_string());
+        transfer_in_blocks(&mut context, &mut contract, 30..50);
+
+        // The checkpoint of block 21 gives the balance at block 29, so later ones are dropped instead
+        assert_eq!(contract.checkpoints.ranges.get(&accounts(2).into()), Some((1, 17)));
+        assert_eq!(contract.ft_balance_at(accounts(2), 29.into()).0, 2);
+        assert_eq!(contract.ft_balance_at(accounts(2), 49.into()).0, 22);
+
+        // Once voting ended they can be dropped
+        testing_env!(context.block_timestamp(governance::VOTING_PERIOD).build());
+        transfer_in_blocks(&mut context, &mut contract, 50..52);
+        assert_eq!(contract.checkpoints.ranges.get(&accounts(2).into()), Some((3, 19)));
+        assert_eq!(contract.ft_balance_at(accounts(2), 51.into()).0, 24);
+    }
+
+    /// Create a proposal at `block`, which reads balances at the block before
+    fn propose_at(context: &mut VMContextBuilder, contract: &mut Shares, block: BlockHeight) {
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(PROPOSAL_DEPOSIT)
+            .predecessor_account_id(accounts(1))
+            .block_index(block)
+            .build());
+        contract.propose(governance::ProposalAction::ChangeCurator { curator_id: accounts(3).into() }, "".to_string());
+    }
+
+    #[test]
+    fn test_checkpoints_read_by_two_open_proposals_are_kept() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        transfer_in_blocks(&mut context, &mut contract, 20..22);
+        propose_at(&mut context, &mut contract, 30);
+        transfer_in_blocks(&mut context, &mut contract, 30..35);
+        propose_at(&mut context, &mut contract, 40);
+
+        // The history fills up while both proposals are open, and neither snapshot moves
+        transfer_in_blocks(&mut context, &mut contract, 40..60);
+        assert_eq!(contract.checkpoints.ranges.get(&accounts(2).into()).map(|(first, end)| end - first), Some(MAX_BALANCE_CHECKPOINTS));
+        assert_eq!(contract.voting_power_of(0.into(), accounts(2)).0, 2);
+        assert_eq!(contract.voting_power_of(1.into(), accounts(2)).0, 7);
+        assert_eq!(contract.voting_power_of(1.into(), accounts(1)).0, TOTAL_SUPPLY - 7);
+        assert_eq!(contract.ft_balance_at(accounts(2), 59.into()).0, 27);
+    }
+
+    #[test]
+    #[should_panic(expected = "is full until a proposal reading it closes")]
+    fn test_balance_change_refused_while_every_checkpoint_is_read() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        // Each checkpoint is the snapshot of a proposal
+        for block in (20..20 + 2 * MAX_BALANCE_CHECKPOINTS).step_by(2) {
+            transfer_in_blocks(&mut context, &mut contract, block..block + 1);
+            propose_at(&mut context, &mut contract, block + 1);
+        }
+        transfer_in_blocks(&mut context, &mut contract, 100..101);
+    }
+
+    #[test]
+    fn test_closed_account_checkpoints_are_pruned() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        transfer_in_blocks(&mut context, &mut contract, 20..25);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .block_index(30)
+            .build());
+        contract.ft_transfer(accounts(1), 5.into(), None);
+
+        testing_env!(context.storage_usage(env::storage_usage()).block_index(31).build());
+        assert!(contract.storage_unregister(None));
+        assert!(contract.checkpoints.ranges.get(&accounts(2).into()).is_none());
+        assert_eq!(contract.ft_balance_at(accounts(2), 24.into()).0, 0);
+    }
+
+    #[test]
+    fn test_supply_history_after_redeem() {
+        let mut context = VMContextBuilder::new();
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        testing_env!(context.storage_usage(env::storage_usage()).block_index(5).build());
+        contract.redeem();
+
+        assert_eq!(contract.ft_total_supply_at(4.into()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_total_supply_at(5.into()).0, 0);
+        assert_eq!(contract.ft_balance_at(accounts(1), 5.into()).0, 0);
+    }
+}
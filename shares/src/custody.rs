@@ -0,0 +1,215 @@
+use near_sdk::PromiseResult;
+
+use crate::*;
+use crate::nft_info::NftToken;
+
+const GAS_FOR_VERIFY_CUSTODY: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_CUSTODY_CHECK: Gas = 5_000_000_000_000;
+/// Gas `redeem` needs when custody was not verified yet: its own, the check, and the redemption
+/// done by the callback
+pub const GAS_FOR_REDEEM_CUSTODY_CHECK: Gas =
+    GAS_FOR_VERIFY_CUSTODY + GAS_FOR_NFT_TOKEN + GAS_FOR_ON_CUSTODY_CHECK + GAS_FOR_REDEEM + GAS_FOR_NFT_TRANSFER;
+
+#[near_bindgen]
+impl Shares {
+    /// Check with the NFT contract that the vault owns the NFT
+    pub fn verify_custody(&mut self) -> Promise {
+        assert_prepaid_gas(GAS_FOR_VERIFY_CUSTODY + GAS_FOR_NFT_TOKEN + GAS_FOR_ON_CUSTODY_CHECK);
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        non_fungible_token_view::nft_token(
+            nft_token_id,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_TOKEN
+        ).then(shares::on_custody_check(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_CUSTODY_CHECK
+        ))
+    }
+
+    #[private]
+    pub fn on_custody_check(&mut self, #[callback] token: Option<NftToken>) -> bool {
+        self.internal_record_custody(token)
+    }
+
+    /// Redeem once the vault is known to hold the NFT. Otherwise, or if the redemption is no longer
+    /// possible, the payment is refunded. Returns whether the NFT was redeemed
+    #[private]
+    pub fn on_redeem_custody_check(&mut self, redeemer_id: AccountId, payment: U128) -> bool {
+        let token = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Option<NftToken>>(&value).ok().flatten(),
+            _ => None,
+        };
+        self.pending_redeem_payments -= payment.0;
+        let redeemable = self.internal_record_custody(token)
+            && self.redeem_error().is_none()
+            && payment.0 >= self.redeem_amount_due(&redeemer_id);
+
+        if redeemable {
+            self.internal_redeem(redeemer_id, payment.0);
+        } else if payment.0 > 0 {
+            Promise::new(redeemer_id).transfer(payment.0);
+        }
+        redeemable
+    }
+
+    /// Block timestamp of the last successful `verify_custody()`. None if custody was never verified or was lost
+    pub fn custody_verified_at(&self) -> Option<U64> {
+        self.custody_verified_at.map(|timestamp| timestamp.into())
+    }
+
+    /// Whether the shares are backed by the NFT held in the vault
+    pub fn is_backed(&self) -> bool {
+        self.custody_verified_at.is_some() && !self.ft_metadata().released
+    }
+}
+
+impl Shares {
+    /// Check custody with the NFT contract before redeeming for `redeemer_id`
+    pub(crate) fn internal_verify_custody_for_redeem(&mut self, redeemer_id: AccountId, payment: Balance) {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        non_fungible_token_view::nft_token(
+            nft_token_id,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_TOKEN
+        ).then(shares::on_redeem_custody_check(
+            redeemer_id,
+            payment.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_CUSTODY_CHECK + GAS_FOR_REDEEM + GAS_FOR_NFT_TRANSFER
+        ));
+    }
+
+    /// Record whether the vault owns `token`
+    fn internal_record_custody(&mut self, token: Option<NftToken>) -> bool {
+        let owner_id = token.map(|token| token.owner_id);
+        let in_custody = owner_id.as_ref() == Some(&env::current_account_id());
+
+        if in_custody {
+            self.custody_verified_at = Some(env::block_timestamp());
+            self.on_custody_verified();
+        } else {
+            self.custody_verified_at = None;
+            self.on_custody_missing(owner_id.unwrap_or_default());
+        }
+
+        in_custody
+    }
+
+    /// NFT can only change hands through the vault once the vault is known to hold it
+    pub(crate) fn assert_custody(&self) {
+        assert!(self.custody_verified_at.is_some(), "custody not verified");
+    }
+
+    fn on_custody_verified(&self) {
+        log!("{}", Event::CustodyVerified { timestamp: env::block_timestamp(), vault_id: env::current_account_id() });
+    }
+
+    fn on_custody_missing(&self, owner_id: AccountId) {
+        log!("{}", Event::CustodyMissing { owner_id, vault_id: env::current_account_id() });
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::serde_json::json;
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(0)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            1_000.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        )
+    }
+
+    fn owned_by(owner_id: ValidAccountId) -> Option<NftToken> {
+        Some(NftToken { owner_id: owner_id.into(), metadata: None })
+    }
+
+    #[test]
+    fn test_custody_verified() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        assert!(!contract.is_backed());
+
+        testing_env!(context.block_timestamp(42).build());
+        assert!(contract.on_custody_check(owned_by(accounts(0))));
+        assert_eq!(contract.custody_verified_at(), Some(42.into()));
+        assert!(contract.is_backed());
+    }
+
+    #[test]
+    fn test_custody_lost() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.on_custody_check(owned_by(accounts(0)));
+
+        assert!(!contract.on_custody_check(owned_by(accounts(3))));
+        assert!(contract.custody_verified_at().is_none());
+        let custody_missing = Event::CustodyMissing { owner_id: accounts(3).to_string(), vault_id: accounts(0).to_string() };
+        assert_eq!(near_sdk::test_utils::get_logs().last().unwrap(), &custody_missing.to_string());
+    }
+
+    #[test]
+    fn test_redeem_verifies_custody() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.redeem();
+        assert!(!contract.ft_metadata().released);
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains(&format!("{:?}", b"on_redeem_custody_check")), "Receipts: {}", receipts);
+
+        let token = near_sdk::serde_json::to_vec(&json!({ "owner_id": accounts(0) })).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(token)]
+        );
+        assert!(contract.on_redeem_custody_check(accounts(1).into(), 0.into()));
+        assert!(contract.ft_metadata().released);
+        assert!(contract.custody_verified_at().is_none());
+    }
+
+    #[test]
+    fn test_redeem_without_custody_refunds() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(1)).attached_deposit(500).build());
+        contract.redeem();
+
+        let token = near_sdk::serde_json::to_vec(&json!({ "owner_id": accounts(3) })).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(token)]
+        );
+        assert!(!contract.on_redeem_custody_check(accounts(1).into(), 500.into()));
+        assert!(!contract.ft_metadata().released);
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains("TransferAction { deposit: 500 }"), "Receipts: {}", receipts);
+    }
+}
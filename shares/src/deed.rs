@@ -0,0 +1,376 @@
+use near_contract_standards::non_fungible_token::{
+    NonFungibleToken, Token,
+    core::NonFungibleTokenCore,
+    enumeration::NonFungibleTokenEnumeration,
+    metadata::{NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC},
+};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::serde_json::json;
+
+use crate::*;
+
+/// The vault issues a single deed. It can't be transferred, since it stands for the shares or the redemption
+pub const DEED_TOKEN_ID: &str = "deed";
+
+pub fn new_deeds() -> NonFungibleToken {
+    NonFungibleToken::new(
+        StorageKey::DeedOwners,
+        env::current_account_id().try_into().unwrap(),
+        Some(StorageKey::DeedMetadata),
+        Some(StorageKey::DeedEnumeration),
+        // Never written since the deed can't be transferred, but NEP-181 views list approvals
+        Some(StorageKey::DeedApprovals)
+    )
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Issue the vault deed to the caller, who must hold every share
+    pub fn claim_deed(&mut self) -> Token {
+        assert!(!self.ft_metadata().released, "token already redeemed");
+        let account_id = env::predecessor_account_id();
+        let balance = self.token.accounts.get(&account_id).unwrap_or(0);
+        assert!(balance > 0 && balance == self.token.total_supply, "deed requires every share");
+
+        self.internal_issue_deed(&account_id)
+    }
+
+    /// Give up the caller's deed. Once the NFT is redeemed, the vault is kept while the deed exists
+    pub fn burn_deed(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let owner_id = self.deeds.owner_by_id.get(&DEED_TOKEN_ID.to_string());
+        assert_eq!(owner_id.as_ref(), Some(&account_id), "only the deed holder can burn it");
+
+        self.internal_burn_deed();
+        self.on_deed_burnt(&account_id);
+        self.cleanup();
+    }
+}
+
+impl Shares {
+    /// Mint the deed to `owner_id`, or move it there if it was already minted
+    pub(crate) fn internal_issue_deed(&mut self, owner_id: &AccountId) -> Token {
+        let token_id = DEED_TOKEN_ID.to_string();
+        let metadata = self.deed_metadata();
+        match self.deeds.owner_by_id.get(&token_id) {
+            Some(previous_owner_id) => {
+                self.deeds.internal_transfer_unguarded(&token_id, &previous_owner_id, owner_id);
+            }
+            None => self.internal_mint_deed(owner_id, &metadata),
+        }
+        self.on_deed_issued(owner_id);
+
+        Token { token_id, owner_id: owner_id.clone(), metadata: Some(metadata), approved_account_ids: None }
+    }
+
+    /// Storage of the deed issued to an account with the longest ID
+    pub(crate) fn measure_deed_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let metadata = self.deed_metadata();
+        self.internal_mint_deed(&"a".repeat(64), &metadata);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.internal_burn_deed();
+
+        storage_usage
+    }
+
+    /// Write the deed of `owner_id` and list it among the owner's tokens
+    fn internal_mint_deed(&mut self, owner_id: &AccountId, metadata: &TokenMetadata) {
+        let token_id = DEED_TOKEN_ID.to_string();
+        self.deeds.owner_by_id.insert(&token_id, owner_id);
+        if let Some(metadata_by_id) = self.deeds.token_metadata_by_id.as_mut() {
+            metadata_by_id.insert(&token_id, metadata);
+        }
+        if let Some(tokens_per_owner) = self.deeds.tokens_per_owner.as_mut() {
+            let mut token_ids = UnorderedSet::new(StorageKey::DeedsPerOwner { account_hash: env::sha256(owner_id.as_bytes()) });
+            token_ids.insert(&token_id);
+            tokens_per_owner.insert(owner_id, &token_ids);
+        }
+    }
+
+    /// Remove the deed and its storage, if it was issued
+    pub(crate) fn internal_burn_deed(&mut self) {
+        let token_id = DEED_TOKEN_ID.to_string();
+        let owner_id = match self.deeds.owner_by_id.remove(&token_id) {
+            Some(owner_id) => owner_id,
+            None => return,
+        };
+        if let Some(metadata_by_id) = self.deeds.token_metadata_by_id.as_mut() {
+            metadata_by_id.remove(&token_id);
+        }
+        if let Some(tokens_per_owner) = self.deeds.tokens_per_owner.as_mut() {
+            if let Some(mut token_ids) = tokens_per_owner.remove(&owner_id) {
+                token_ids.clear();
+            }
+        }
+    }
+
+    /// Burn the deed once its holder no longer holds every share, until the NFT is redeemed
+    pub(crate) fn burn_stale_deed(&mut self, account_id: &AccountId) {
+        let token_id = DEED_TOKEN_ID.to_string();
+        if self.deeds.owner_by_id.get(&token_id).as_ref() != Some(account_id) {
+            return;
+        }
+        let balance = self.token.accounts.get(account_id).unwrap_or(0);
+        if balance == self.token.total_supply || self.ft_metadata().released {
+            return;
+        }
+
+        self.internal_burn_deed();
+        self.on_deed_burnt(account_id);
+    }
+
+    /// Links the deed to the underlying NFT and to this shares contract
+    pub(crate) fn deed_metadata(&self) -> TokenMetadata {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        TokenMetadata {
+            title: Some("Fractose vault deed".to_string()),
+            description: Some(format!(
+                "NFT {} on {} fractionalized into shares of {}",
+                nft_token_id, nft_contract_address, env::current_account_id()
+            )),
+            media: None,
+            media_hash: None,
+            copies: Some(1),
+            issued_at: None,
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some(json!({
+                "nft_contract_address": nft_contract_address,
+                "nft_token_id": nft_token_id,
+                "shares_contract": env::current_account_id(),
+            }).to_string()),
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    fn on_deed_issued(&self, owner_id: &AccountId) {
+        log!("DeedIssued({}, {})", owner_id, env::current_account_id());
+    }
+
+    fn on_deed_burnt(&self, owner_id: &AccountId) {
+        log!("DeedBurnt({}, {})", owner_id, env::current_account_id());
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenCore for Shares {
+    #[payable]
+    #[allow(unused_variables)]
+    fn nft_transfer(&mut self, receiver_id: ValidAccountId, token_id: TokenId, approval_id: Option<u64>, memo: Option<String>) {
+        panic!("the deed is not transferable")
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String
+    ) -> PromiseOrValue<bool> {
+        panic!("the deed is not transferable")
+    }
+
+    fn nft_token(self, token_id: TokenId) -> Option<Token> {
+        self.deeds.nft_token(token_id)
+    }
+
+    #[allow(unused_variables)]
+    fn mint(&mut self, token_id: TokenId, token_owner_id: ValidAccountId, token_metadata: Option<TokenMetadata>) -> Token {
+        panic!("the deed is issued by the vault")
+    }
+}
+
+/// NEP-181 views, so wallets can find the deed of an account
+#[near_bindgen]
+impl NonFungibleTokenEnumeration for Shares {
+    fn nft_total_supply(self) -> U128 {
+        self.deeds.nft_total_supply()
+    }
+
+    fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        self.deeds.nft_tokens(from_index, limit)
+    }
+
+    fn nft_supply_for_owner(self, account_id: ValidAccountId) -> U128 {
+        self.deeds.nft_supply_for_owner(account_id)
+    }
+
+    fn nft_tokens_for_owner(&self, account_id: ValidAccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        self.deeds.nft_tokens_for_owner(account_id, from_index, limit)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for Shares {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Fractose vault deed".to_string(),
+            symbol: "DEED".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000;
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "7".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        contract
+    }
+
+    #[test]
+    fn test_claim_deed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        let token = contract.claim_deed();
+        assert_eq!(token.owner_id, accounts(1).to_string());
+
+        // Wallets find it through the enumeration views
+        let tokens = contract.nft_tokens_for_owner(accounts(1), None, None);
+        assert_eq!(tokens.iter().map(|token| token.token_id.as_str()).collect::<Vec<_>>(), vec![DEED_TOKEN_ID]);
+        assert_eq!(contract.nft_tokens(None, None).len(), 1);
+
+        let metadata = contract.nft_token(DEED_TOKEN_ID.to_string()).unwrap().metadata.unwrap();
+        assert!(metadata.extra.unwrap().contains("\"nft_token_id\":\"7\""));
+    }
+
+    #[test]
+    #[should_panic(expected = "deed requires every share")]
+    fn test_claim_deed_without_every_share() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1).predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(2), 1.into(), None);
+
+        contract.claim_deed();
+    }
+
+    #[test]
+    fn test_deed_burnt_when_shares_leave() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.claim_deed();
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1).predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(2), 1.into(), None);
+
+        assert!(contract.nft_tokens_for_owner(accounts(1), None, None).is_empty());
+        assert!(contract.nft_token(DEED_TOKEN_ID.to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "the deed is not transferable")]
+    fn test_deed_not_transferable() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.claim_deed();
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.nft_transfer(accounts(2), DEED_TOKEN_ID.to_string(), None, None);
+    }
+
+    #[test]
+    fn test_redeemer_gets_deed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.claim_deed();
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(TOTAL_SUPPLY * 10)
+            .predecessor_account_id(accounts(3))
+            .signer_account_id(accounts(3))
+            .build());
+        contract.redeem();
+
+        assert!(contract.nft_tokens_for_owner(accounts(1), None, None).is_empty());
+        assert_eq!(contract.nft_tokens_for_owner(accounts(3), None, None).len(), 1);
+
+        // The last claim burns every share, but the vault is kept with the deed
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).predecessor_account_id(accounts(1)).build());
+        contract.claim();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.cleanup();
+        assert_eq!(contract.ft_total_supply().0, 0);
+        assert!(!format!("{:?}", get_created_receipts()).contains("DeleteAccount"));
+        assert_eq!(contract.nft_tokens_for_owner(accounts(3), None, None).len(), 1);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).signer_account_id(accounts(3)).build());
+        contract.burn_deed();
+        assert!(format!("{:?}", get_created_receipts()).contains("DeleteAccount"));
+        assert!(contract.nft_token(DEED_TOKEN_ID.to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "only the deed holder can burn it")]
+    fn test_burn_deed_not_holder() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.claim_deed();
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.burn_deed();
+    }
+
+    #[test]
+    fn test_redeeming_every_share_issues_no_deed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.claim_deed();
+
+        // The redemption deletes the vault, which takes no deed along
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+        contract.redeem();
+        assert!(format!("{:?}", get_created_receipts()).contains("DeleteAccount"));
+        assert!(contract.nft_token(DEED_TOKEN_ID.to_string()).is_none());
+    }
+}
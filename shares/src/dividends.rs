@@ -0,0 +1,663 @@
+use std::io;
+
+use fractose_common::refund_deposit;
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::{Gas, PromiseResult, collections::LookupSet};
+
+use crate::*;
+
+// Silences lints on code generated by `construct_uint!`
+#[allow(clippy::all)]
+mod uint256 {
+    uint::construct_uint! {
+        /// 256-bit unsigned integer for reward per share math
+        pub struct U256(4);
+    }
+}
+use uint256::U256;
+
+/// Fixed point precision of the reward per share accumulator
+const PRECISION: u128 = 1_000_000_000_000_000_000_000_000;
+/// Every token paid out is settled on every transfer, so their number is capped
+pub const MAX_DIVIDEND_TOKENS: usize = 5;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_RESOLVE_WITHDRAWAL: Gas = 10_000_000_000_000;
+
+/// Asset paid out as dividends, NEAR or a NEP-141 token
+pub type Asset = Option<AccountId>;
+
+/// Cumulative amount distributed per share, scaled by `PRECISION`
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct RewardPerShare(U256);
+
+impl BorshSerialize for RewardPerShare {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.0).0.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for RewardPerShare {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Self(U256(<[u64; 4]>::deserialize(buf)?)))
+    }
+}
+
+/// Accumulator of a single asset
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct DividendPool {
+    reward_per_share: RewardPerShare,
+    pub total_distributed: Balance,
+    /// Amount credited to accounts when they were settled
+    pub total_settled: Balance,
+    pub total_withdrawn: Balance,
+}
+
+impl DividendPool {
+    /// Distributed amount which has not been withdrawn yet, rounding dust included
+    pub fn outstanding(&self) -> Balance {
+        self.total_distributed - self.total_withdrawn
+    }
+}
+
+/// Income distributed to shareholders with a reward per share accumulator per asset.
+///
+/// Each account remembers the accumulator value it was last settled at. Settling
+/// moves the rewards earned by its balance since then into `unpaid`, so it must
+/// happen before every balance change.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Dividends {
+    pub near: DividendPool,
+    /// NEP-141 tokens paid out next to NEAR
+    pub tokens: Vec<AccountId>,
+    token_pools: LookupMap<AccountId, DividendPool>,
+    /// Accumulator value at which each account was last settled
+    paid_per_share: LookupMap<(Asset, AccountId), RewardPerShare>,
+    unpaid: LookupMap<(Asset, AccountId), Balance>,
+    /// Accounts whose shares earn nothing, like escrows and pools which could not withdraw it
+    excluded: LookupSet<AccountId>,
+    /// Shares held by excluded accounts
+    pub excluded_supply: Balance,
+    /// Token withdrawals waiting for their transfer to resolve
+    pending_withdrawals: u32,
+}
+
+impl Dividends {
+    pub fn new() -> Self {
+        Self {
+            near: DividendPool::default(),
+            tokens: vec![],
+            token_pools: LookupMap::new(StorageKey::DividendTokenPools),
+            paid_per_share: LookupMap::new(StorageKey::DividendsPaidPerShare),
+            unpaid: LookupMap::new(StorageKey::DividendsUnpaid),
+            excluded: LookupSet::new(StorageKey::DividendsExcluded),
+            excluded_supply: 0,
+            pending_withdrawals: 0,
+        }
+    }
+
+    pub fn pool(&self, asset: &Asset) -> DividendPool {
+        match asset {
+            None => self.near.clone(),
+            Some(token) => self.token_pools.get(token).unwrap_or_default(),
+        }
+    }
+
+    fn save_pool(&mut self, asset: &Asset, pool: DividendPool) {
+        match asset {
+            None => self.near = pool,
+            Some(token) => {
+                self.token_pools.insert(token, &pool);
+            }
+        }
+    }
+
+    /// Dividends in `asset` owed to an account holding `balance` shares
+    pub fn dividends_of(&self, asset: &Asset, account_id: &AccountId, balance: Balance) -> Balance {
+        let key = (asset.clone(), account_id.clone());
+        self.unpaid.get(&key).unwrap_or(0) + self.earned(asset, account_id, balance)
+    }
+
+    /// Dividends earned by `balance` shares since the account was last settled
+    fn earned(&self, asset: &Asset, account_id: &AccountId, balance: Balance) -> Balance {
+        if self.excluded.contains(account_id) {
+            return 0;
+        }
+        let reward_per_share = self.pool(asset).reward_per_share;
+        let paid_per_share = self.paid_per_share.get(&(asset.clone(), account_id.clone())).unwrap_or_default();
+        (U256::from(balance) * (reward_per_share.0 - paid_per_share.0) / U256::from(PRECISION)).as_u128()
+    }
+
+    fn settle(&mut self, asset: &Asset, account_id: &AccountId, balance: Balance) {
+        let key = (asset.clone(), account_id.clone());
+        let mut pool = self.pool(asset);
+
+        let earned = self.earned(asset, account_id, balance);
+        if earned > 0 {
+            self.unpaid.insert(&key, &(self.unpaid.get(&key).unwrap_or(0) + earned));
+            pool.total_settled += earned;
+            self.save_pool(asset, pool.clone());
+        }
+        if self.paid_per_share.get(&key).unwrap_or_default() != pool.reward_per_share {
+            self.paid_per_share.insert(&key, &pool.reward_per_share);
+        }
+    }
+
+    /// Settle an account in every asset
+    pub fn settle_all(&mut self, account_id: &AccountId, balance: Balance) {
+        self.settle(&None, account_id, balance);
+        for token in self.tokens.clone() {
+            self.settle(&Some(token), account_id, balance);
+        }
+    }
+
+    /// Settle an account and take out everything it is owed in `asset`
+    pub fn take(&mut self, asset: &Asset, account_id: &AccountId, balance: Balance) -> Balance {
+        self.settle(asset, account_id, balance);
+        let amount = self.unpaid.remove(&(asset.clone(), account_id.clone())).unwrap_or(0);
+
+        let mut pool = self.pool(asset);
+        pool.total_withdrawn += amount;
+        self.save_pool(asset, pool);
+
+        amount
+    }
+
+    /// Give back an amount taken out with `take` which could not be paid
+    pub fn restore(&mut self, asset: &Asset, account_id: &AccountId, amount: Balance) {
+        let key = (asset.clone(), account_id.clone());
+        self.unpaid.insert(&key, &(self.unpaid.get(&key).unwrap_or(0) + amount));
+
+        let mut pool = self.pool(asset);
+        pool.total_withdrawn -= amount;
+        self.save_pool(asset, pool);
+    }
+
+    pub fn is_excluded(&self, account_id: &AccountId) -> bool {
+        self.excluded.contains(account_id)
+    }
+
+    /// Stop the `balance` shares of an account from earning dividends. It must be settled first
+    pub fn exclude(&mut self, account_id: &AccountId, balance: Balance) {
+        if self.excluded.insert(account_id) {
+            self.excluded_supply += balance;
+        }
+    }
+
+    /// Whether everything credited to accounts was paid out. Rounding dust is never credited
+    pub fn all_paid(&self) -> bool {
+        self.pending_withdrawals == 0 && std::iter::once(None).chain(self.tokens.iter().cloned().map(Some))
+            .map(|asset| self.pool(&asset))
+            .all(|pool| pool.total_settled == pool.total_withdrawn)
+    }
+
+    /// Distribute `amount` over the shares of `supply` not held by excluded accounts
+    pub fn distribute(&mut self, asset: &Asset, amount: Balance, supply: Balance) {
+        let supply = supply - self.excluded_supply;
+        assert!(supply > 0, "no shares to distribute to");
+        if let Some(token) = asset {
+            if !self.tokens.contains(token) {
+                assert!(self.tokens.len() < MAX_DIVIDEND_TOKENS, "too many dividend tokens");
+                self.tokens.push(token.clone());
+            }
+        }
+
+        let mut pool = self.pool(asset);
+        pool.reward_per_share.0 += U256::from(amount) * U256::from(PRECISION) / U256::from(supply);
+        pool.total_distributed += amount;
+        self.save_pool(asset, pool);
+    }
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Distribute the attached NEAR to shareholders in proportion of shares held
+    #[payable]
+    pub fn deposit_income(&mut self) {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "no income attached");
+        self.internal_distribute(None, amount);
+    }
+
+    /// Distribute income which was sent to the vault account directly. Only the curator can call it
+    ///
+    /// # Parameters
+    ///
+    /// - `amount`: NEAR to distribute, all of the undistributed balance by default
+    pub fn distribute(&mut self, amount: Option<U128>) -> U128 {
+        assert_eq!(env::predecessor_account_id(), self.curator, "only the curator can distribute");
+
+        let available = self.undistributed_balance().0;
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        assert!(amount > 0 && amount <= available, "invalid distribution amount");
+        self.internal_distribute(None, amount);
+
+        amount.into()
+    }
+
+    /// Pay out the dividends owed to the caller
+    ///
+    /// # Parameters
+    ///
+    /// - `token_id`: NEP-141 token to withdraw, NEAR by default
+    pub fn withdraw_dividends(&mut self, token_id: Option<ValidAccountId>) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let asset: Asset = token_id.map(|token_id| token_id.into());
+        let balance = self.token.accounts.get(&account_id).unwrap_or(0);
+        let amount = self.dividends.take(&asset, &account_id, balance);
+        assert!(amount > 0, "no dividends to withdraw");
+
+        match &asset {
+            None => {
+                Promise::new(account_id.clone()).transfer(amount);
+            }
+            Some(token) => {
+                self.dividends.pending_withdrawals += 1;
+                ext_fungible_token::ft_transfer(
+                    account_id.clone(),
+                    amount.into(),
+                    Some("Fractose dividends".to_string()),
+                    token,
+                    1,
+                    GAS_FOR_FT_TRANSFER
+                ).then(shares::resolve_dividends_withdrawal(
+                    account_id.clone(),
+                    token.clone(),
+                    amount.into(),
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_RESOLVE_WITHDRAWAL
+                ));
+            }
+        }
+        let paid = asset.is_none();
+        self.on_dividends_withdrawn(account_id, asset, amount);
+        if paid {
+            self.cleanup();
+        }
+
+        amount.into()
+    }
+
+    /// Give back token dividends if the transfer failed, e.g. because the account is not registered with the token.
+    /// Once the last dividends are paid out of a vault without shares, it is deleted
+    #[private]
+    pub fn resolve_dividends_withdrawal(&mut self, account_id: AccountId, token_id: AccountId, amount: U128) {
+        self.dividends.pending_withdrawals -= 1;
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.dividends.restore(&Some(token_id), &account_id, amount.0);
+        } else {
+            self.cleanup();
+        }
+    }
+
+    /// Stop the caller's shares from earning dividends, for contracts holding shares for others
+    /// which can't withdraw them, like the order book. Dividends earned so far stay withdrawable.
+    /// The attached deposit pays for storage
+    #[payable]
+    pub fn exclude_from_dividends(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let balance = self.token.accounts.get(&account_id).expect("account is not registered");
+
+        self.dividends.settle_all(&account_id, balance);
+        self.dividends.exclude(&account_id, balance);
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+    }
+
+    pub fn is_excluded_from_dividends(&self, account_id: ValidAccountId) -> bool {
+        self.dividends.is_excluded(account_id.as_ref())
+    }
+
+    /// Dividends owed to an account, in NEAR or in the given NEP-141 token
+    pub fn dividends_of(&self, account_id: ValidAccountId, token_id: Option<ValidAccountId>) -> U128 {
+        let asset: Asset = token_id.map(|token_id| token_id.into());
+        let balance = self.token.accounts.get(account_id.as_ref()).unwrap_or(0);
+        self.dividends.dividends_of(&asset, account_id.as_ref(), balance).into()
+    }
+
+    /// NEP-141 tokens which were distributed as dividends
+    pub fn dividend_tokens(&self) -> Vec<AccountId> {
+        self.dividends.tokens.clone()
+    }
+
+    /// NEAR on the vault account which is not reserved for storage, including the checkpoints accounts
+    /// prepaid, dividends, claims or redeem payments waiting for their custody check
+    pub fn undistributed_balance(&self) -> U128 {
+        let reserved = Balance::from(env::storage_usage() + self.checkpoints.unused_storage_usage) * env::storage_byte_cost()
+            + self.dividends.near.outstanding()
+            + self.vault_balance().0
+            + self.pending_redeem_payments;
+
+        env::account_balance().saturating_sub(reserved).into()
+    }
+}
+
+impl Shares {
+    pub(crate) fn internal_distribute(&mut self, asset: Asset, amount: Balance) {
+        self.dividends.distribute(&asset, amount, self.token.total_supply);
+        self.on_income_distributed(asset, amount);
+    }
+
+    /// Settle the dividends of an account before its share balance changes
+    pub(crate) fn settle_dividends(&mut self, account_id: &AccountId) {
+        let balance = self.token.accounts.get(account_id).unwrap_or(0);
+        self.dividends.settle_all(account_id, balance);
+        if self.dividends.is_excluded(account_id) {
+            self.dividends.excluded_supply -= balance;
+        }
+    }
+
+    /// Count the new balance of an excluded account after it changed
+    pub(crate) fn track_excluded_balance(&mut self, account_id: &AccountId) {
+        if self.dividends.is_excluded(account_id) {
+            self.dividends.excluded_supply += self.token.accounts.get(account_id).unwrap_or(0);
+        }
+    }
+
+    /// Pay out the NEAR a force-closed account was owed for the `balance` it held.
+    /// Its token dividends stay withdrawable
+    pub(crate) fn pay_closed_account_dividends(&mut self, account_id: &AccountId, balance: Balance) {
+        self.dividends.settle_all(account_id, balance);
+        if self.dividends.is_excluded(account_id) {
+            self.dividends.excluded_supply -= balance;
+        }
+        let amount = self.dividends.take(&None, account_id, balance);
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+            self.on_dividends_withdrawn(account_id.clone(), None, amount);
+        }
+    }
+
+    fn on_income_distributed(&self, asset: Asset, amount: Balance) {
+        log!("IncomeDistributed({}, {}, {})", asset_name(&asset), amount, env::current_account_id());
+    }
+
+    fn on_dividends_withdrawn(&self, account_id: AccountId, asset: Asset, amount: Balance) {
+        log!("DividendsWithdrawn({}, {}, {}, {})", account_id, asset_name(&asset), amount, env::current_account_id());
+    }
+}
+
+/// Name of an asset in events
+pub fn asset_name(asset: &Asset) -> &str {
+    asset.as_deref().unwrap_or("NEAR")
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+    /// accounts(1) holds 75% of shares and accounts(2) 25%
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.ft_transfer(accounts(2), (TOTAL_SUPPLY / 4).into(), None);
+
+        contract
+    }
+
+    fn deposit_income(context: &mut VMContextBuilder, contract: &mut Shares, amount: Balance) {
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(amount)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.deposit_income();
+    }
+
+    #[test]
+    fn test_dividends_follow_transfers() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        deposit_income(&mut context, &mut contract, 4 * ONE_NEAR);
+
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 3 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, ONE_NEAR);
+
+        // Shares moved after a distribution don't take its dividends with them
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.ft_transfer(accounts(2), (TOTAL_SUPPLY / 4).into(), None);
+        deposit_income(&mut context, &mut contract, 4 * ONE_NEAR);
+
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 5 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, 3 * ONE_NEAR);
+
+        testing_env!(context.attached_deposit(0).predecessor_account_id(accounts(2)).build());
+        assert_eq!(contract.withdraw_dividends(None).0, 3 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, 0);
+        assert_eq!(contract.dividends.near.outstanding(), 5 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_dividends_survive_claim() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        deposit_income(&mut context, &mut contract, 4 * ONE_NEAR);
+
+        // accounts(3) buys the NFT out, accounts(2) claims its share of the exit price
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(TOTAL_SUPPLY * 10)
+            .predecessor_account_id(accounts(3))
+            .signer_account_id(accounts(3))
+            .build());
+        contract.redeem();
+        testing_env!(context.attached_deposit(0).predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).build());
+        contract.claim();
+
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+        assert_eq!(contract.withdraw_dividends(None).0, ONE_NEAR);
+    }
+
+    #[test]
+    fn test_excluded_account_earns_nothing() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        deposit_income(&mut context, &mut contract, 4 * ONE_NEAR);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(ONE_NEAR / 100)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.exclude_from_dividends();
+        assert!(contract.is_excluded_from_dividends(accounts(2)));
+
+        // Later income goes to the other holders only, earlier dividends stay withdrawable
+        deposit_income(&mut context, &mut contract, 3 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 6 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, ONE_NEAR);
+
+        // Shares moving in and out of the excluded account change the shares income is split over
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.ft_transfer(accounts(1), (TOTAL_SUPPLY / 8).into(), None);
+        assert_eq!(contract.dividends.excluded_supply, TOTAL_SUPPLY / 8);
+        deposit_income(&mut context, &mut contract, 7 * ONE_NEAR);
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 13 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_vault_deleted_once_dividends_are_withdrawn() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        deposit_income(&mut context, &mut contract, 4 * ONE_NEAR);
+
+        // accounts(1) redeems and accounts(2) claims, burning every share
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(TOTAL_SUPPLY / 4 * 10)
+            .predecessor_account_id(accounts(1))
+            .signer_account_id(accounts(1))
+            .build());
+        contract.redeem();
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(2))
+            .signer_account_id(accounts(2))
+            .build());
+        contract.claim();
+        assert_eq!(contract.ft_total_supply().0, 0);
+
+        let deleted = || format!("{:?}", get_created_receipts()).contains("DeleteAccount");
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(0)).build());
+        contract.cleanup();
+        assert!(!deleted());
+
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(1)).build());
+        contract.withdraw_dividends(None);
+        assert!(!deleted());
+
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(2)).build());
+        contract.withdraw_dividends(None);
+        assert!(!deleted());
+
+        // The redeemer's deed keeps the vault until it is burnt
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(1)).build());
+        contract.burn_deed();
+        assert!(deleted());
+    }
+
+    #[test]
+    fn test_distribute_undistributed_balance() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        // Holders prepaid checkpoints they don't use yet, which stay reserved
+        assert!(contract.checkpoints.unused_storage_usage > 0);
+        let storage_cost = Balance::from(env::storage_usage() + contract.checkpoints.unused_storage_usage) * env::storage_byte_cost();
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .account_balance(storage_cost + 8 * ONE_NEAR)
+            .build());
+        assert_eq!(contract.distribute(None).0, 8 * ONE_NEAR);
+        assert_eq!(contract.undistributed_balance().0, 0);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, 2 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_distribute_while_redeem_in_flight() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.custody_verified_at = None;
+        let payment = contract.redeem_amount_of(accounts(2)).0;
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(payment)
+            .predecessor_account_id(accounts(2))
+            .signer_account_id(accounts(2))
+            .build());
+        contract.redeem();
+
+        // The payment waiting for the custody check is not income
+        let storage_cost = Balance::from(env::storage_usage() + contract.checkpoints.unused_storage_usage) * env::storage_byte_cost();
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(1))
+            .account_balance(storage_cost + payment + 8 * ONE_NEAR)
+            .build());
+        assert_eq!(contract.undistributed_balance().0, 8 * ONE_NEAR);
+        assert_eq!(contract.distribute(None).0, 8 * ONE_NEAR);
+
+        // Custody is not confirmed, so the whole payment is refunded
+        let token = near_sdk::serde_json::to_vec(&near_sdk::serde_json::json!({ "owner_id": accounts(3) })).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).account_balance(storage_cost + payment).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(token)]
+        );
+        assert!(!contract.on_redeem_custody_check(accounts(2).into(), payment.into()));
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains(&format!("TransferAction {{ deposit: {} }}", payment)), "Receipts: {}", receipts);
+        assert_eq!(contract.undistributed_balance().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "only the curator can distribute")]
+    fn test_distribute_not_curator() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.distribute(None);
+    }
+
+    #[test]
+    fn test_failed_token_withdrawal_is_restored() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let token: AccountId = accounts(4).into();
+        contract.internal_distribute(Some(token.clone()), 400);
+
+        testing_env!(context.attached_deposit(0).predecessor_account_id(accounts(2)).build());
+        assert_eq!(contract.withdraw_dividends(Some(accounts(4))).0, 100);
+        assert_eq!(contract.dividends_of(accounts(2), Some(accounts(4))).0, 0);
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.resolve_dividends_withdrawal(accounts(2).into(), token.clone(), 100.into());
+        assert_eq!(contract.dividends_of(accounts(2), Some(accounts(4))).0, 100);
+        assert_eq!(contract.dividends.pool(&Some(token)).outstanding(), 400);
+    }
+
+    #[test]
+    #[should_panic(expected = "no dividends to withdraw")]
+    fn test_withdraw_without_dividends() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(0).build());
+        contract.withdraw_dividends(None);
+    }
+}
@@ -0,0 +1,722 @@
+use near_sdk::{
+    BlockHeight,
+    collections::Vector,
+    serde::{Deserialize, Serialize},
+};
+use fractose_common::refund_deposit;
+
+use crate::*;
+
+pub type ProposalId = u64;
+
+/// Balance needed to create a proposal, in basis points of the total supply
+pub const PROPOSAL_THRESHOLD_BPS: u128 = 100;
+/// Votes needed for a proposal to pass, in basis points of the supply when it was created
+pub const QUORUM_BPS: u128 = 2_000;
+/// Time in nanoseconds for which a proposal can be voted on
+pub const VOTING_PERIOD: u64 = 3 * 24 * 60 * 60 * 1_000_000_000;
+/// Time in nanoseconds after voting ends during which a passed proposal can be executed
+pub const EXECUTION_PERIOD: u64 = 3 * 24 * 60 * 60 * 1_000_000_000;
+/// Longest description of a proposal, in bytes
+pub const MAX_DESCRIPTION_LEN: usize = 1_000;
+
+const GAS_FOR_BUYOUT: Gas = 30_000_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalAction {
+    /// Set a new price per share, which changes the exit price
+    ChangeSharePrice { share_price: U128 },
+    /// Approve a NEP-171 marketplace to sell the NFT for `price` NEAR
+    ListNft { market_id: AccountId, price: U128 },
+    /// Let `buyer_id` buy the NFT out for `price` NEAR through `buyout()`
+    ApproveBuyer { buyer_id: AccountId, price: U128 },
+    /// Offer the NFT for rent on these terms. The renter accepts with `rent()`
+    ApproveRental(RentalOffer),
+    ChangeCurator { curator_id: AccountId },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Voting,
+    /// Passed and waiting for execution
+    Passed,
+    Rejected,
+    Executed,
+    /// Passed but not executed in time
+    Expired,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Proposal {
+    pub proposer: AccountId,
+    pub action: ProposalAction,
+    pub description: String,
+    /// Votes are weighted by balances at the end of this block, the one before the proposal was created
+    pub snapshot_block: BlockHeight,
+    pub snapshot_supply: Balance,
+    pub votes_for: Balance,
+    pub votes_against: Balance,
+    pub voting_ends_at: u64,
+    pub execution_ends_at: u64,
+    pub executed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalView {
+    pub id: U64,
+    pub proposer: AccountId,
+    pub action: ProposalAction,
+    pub description: String,
+    pub status: ProposalStatus,
+    pub snapshot_block: U64,
+    pub snapshot_supply: U128,
+    pub votes_for: U128,
+    pub votes_against: U128,
+    pub voting_ends_at: U64,
+    pub execution_ends_at: U64,
+}
+
+/// Proposals and the votes cast on them
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Governance {
+    pub proposals: Vector<Proposal>,
+    pub votes: LookupMap<(ProposalId, AccountId), bool>,
+    pub approved_buyer: Option<(AccountId, Balance)>,
+}
+
+impl Governance {
+    pub fn new() -> Self {
+        Self {
+            proposals: Vector::new(StorageKey::Proposals),
+            votes: LookupMap::new(StorageKey::Votes),
+            approved_buyer: None,
+        }
+    }
+
+    /// Whether votes may still read balances at a block in `from..to`: the snapshot of a proposal
+    /// being voted on, or the block before the current one for proposals created in it
+    pub fn reads_snapshot_in(&self, from: BlockHeight, to: BlockHeight) -> bool {
+        let latest = env::block_index().saturating_sub(1);
+        if from <= latest && latest < to {
+            return true;
+        }
+
+        // Voting periods have the same length, so proposals still being voted on are the latest
+        // ones, and their snapshots increase with their IDs
+        let now = env::block_timestamp();
+        let first_open = self.partition_point(0, |proposal| proposal.voting_ends_at <= now);
+        let index = self.partition_point(first_open, |proposal| proposal.snapshot_block < from);
+        self.proposals.get(index).is_some_and(|proposal| proposal.snapshot_block < to)
+    }
+
+    /// Index of the first proposal from `low` for which `before` is false, given that it is true
+    /// for the ones before it
+    fn partition_point(&self, mut low: ProposalId, before: impl Fn(&Proposal) -> bool) -> ProposalId {
+        let mut high = self.proposals.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if before(&self.proposals.get(mid).unwrap()) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl Proposal {
+    pub fn status(&self) -> ProposalStatus {
+        let now = env::block_timestamp();
+        if self.executed {
+            ProposalStatus::Executed
+        } else if now < self.voting_ends_at {
+            ProposalStatus::Voting
+        } else if !self.passed() {
+            ProposalStatus::Rejected
+        } else if now < self.execution_ends_at {
+            ProposalStatus::Passed
+        } else {
+            ProposalStatus::Expired
+        }
+    }
+
+    fn passed(&self) -> bool {
+        let quorum = bps_of(self.snapshot_supply, QUORUM_BPS);
+        self.votes_for > self.votes_against && self.votes_for + self.votes_against >= quorum
+    }
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Propose an action on the vault. The proposer needs at least `PROPOSAL_THRESHOLD_BPS` of the
+    /// supply, and attaches a deposit paying for the storage of the proposal. The rest is refunded
+    #[payable]
+    pub fn propose(&mut self, action: ProposalAction, description: String) -> U64 {
+        assert!(!self.ft_metadata().released, "token already redeemed");
+        assert!(description.len() <= MAX_DESCRIPTION_LEN, "description is longer than {} bytes", MAX_DESCRIPTION_LEN);
+        let initial_storage_usage = env::storage_usage();
+        let proposer = env::predecessor_account_id();
+        // Balances moved within the current block can't count
+        let snapshot_block = env::block_index().saturating_sub(1);
+        let supply = self.checkpoints.supply_at(snapshot_block);
+        assert!(
+            self.checkpoints.balance_at(&proposer, snapshot_block) >= bps_of(supply, PROPOSAL_THRESHOLD_BPS),
+            "balance below proposal threshold"
+        );
+
+        match &action {
+            ProposalAction::ChangeSharePrice { share_price } => {
+                assert!(share_price.0 > 0, "invalid share price");
+                checked_value(self.token.total_supply, share_price.0);
+            }
+            ProposalAction::ApproveBuyer { price, .. } => {
+                let (_, remainder) = split_price(price.0, self.token.total_supply);
+                assert_eq!(remainder, 0, "buyout price must be a multiple of the share supply");
+            }
+            _ => {}
+        }
+
+        let now = env::block_timestamp();
+        let proposal = Proposal {
+            proposer: proposer.clone(),
+            action,
+            description,
+            snapshot_block,
+            snapshot_supply: supply,
+            votes_for: 0,
+            votes_against: 0,
+            voting_ends_at: now + VOTING_PERIOD,
+            execution_ends_at: now + VOTING_PERIOD + EXECUTION_PERIOD,
+            executed: false,
+        };
+        let id = self.governance.proposals.len();
+        self.governance.proposals.push(&proposal);
+
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+        self.on_proposal_created(id, proposer);
+        id.into()
+    }
+
+    /// Vote with the balance held just before the proposal was created. The attached deposit pays
+    /// for the storage of the vote, and the rest is refunded
+    #[payable]
+    pub fn vote(&mut self, proposal_id: U64, approve: bool) {
+        let initial_storage_usage = env::storage_usage();
+        let voter = env::predecessor_account_id();
+        let mut proposal = self.governance.proposals.get(proposal_id.0).expect("proposal not found");
+        assert_eq!(proposal.status(), ProposalStatus::Voting, "voting has ended");
+
+        let key = (proposal_id.0, voter.clone());
+        assert!(self.governance.votes.get(&key).is_none(), "already voted");
+
+        let weight = self.voting_power_of(proposal_id, voter.clone().try_into().unwrap()).0;
+        assert!(weight > 0, "no voting power");
+
+        if approve {
+            proposal.votes_for += weight;
+        } else {
+            proposal.votes_against += weight;
+        }
+        self.governance.votes.insert(&key, &approve);
+        self.governance.proposals.replace(proposal_id.0, &proposal);
+
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+        self.on_vote(proposal_id.0, voter, approve, weight);
+    }
+
+    /// Carry out a passed proposal. Anyone can execute it during the execution window
+    pub fn execute_proposal(&mut self, proposal_id: U64) {
+        let mut proposal = self.governance.proposals.get(proposal_id.0).expect("proposal not found");
+        assert_eq!(proposal.status(), ProposalStatus::Passed, "proposal cannot be executed");
+        assert!(!self.ft_metadata().released, "token already redeemed");
+
+        proposal.executed = true;
+        self.governance.proposals.replace(proposal_id.0, &proposal);
+
+        match proposal.action.clone() {
+            ProposalAction::ChangeSharePrice { share_price } => {
+                checked_value(self.token.total_supply, share_price.0);
+                let mut metadata = self.ft_metadata();
+                metadata.share_price = share_price;
+                self.metadata.replace(&metadata);
+                self.on_share_price_changed();
+            }
+            ProposalAction::ListNft { market_id, price } => {
+                self.internal_list_nft(market_id, price.0);
+            }
+            ProposalAction::ApproveBuyer { buyer_id, price } => {
+                self.governance.approved_buyer = Some((buyer_id, price.0));
+            }
+            ProposalAction::ApproveRental(offer) => {
+                self.rental_offer = Some(offer);
+            }
+            ProposalAction::ChangeCurator { curator_id } => {
+                self.curator = curator_id;
+            }
+        }
+
+        self.on_proposal_executed(proposal_id.0);
+    }
+
+    /// Buy the NFT out at the price approved by shareholders. Shares become claimable against the payment.
+    /// If shares were burnt since the approval, the part of the price not divisible by the remaining
+    /// supply is refunded along with any excess deposit
+    #[payable]
+    pub fn buyout(&mut self) {
+        assert_prepaid_gas(GAS_FOR_BUYOUT + GAS_FOR_NFT_TRANSFER);
+        let SharesMetadata { released, nft_token_id, nft_contract_address, .. } = self.ft_metadata();
+        assert!(!released, "token already redeemed");
+        self.assert_custody();
+        self.assert_unlocked();
+
+        let buyer = env::predecessor_account_id();
+        let (approved_buyer, price) = self.governance.approved_buyer.clone().expect("no approved buyer");
+        assert_eq!(buyer, approved_buyer, "only the approved buyer can buy out");
+        assert!(env::attached_deposit() >= price, "insufficient payment amount");
+
+        self.internal_close_sale();
+        self.governance.approved_buyer = None;
+
+        // Shareholders claim the payment at the implied price per share
+        let (share_price, remainder) = split_price(price, self.token.total_supply);
+        let mut metadata = self.ft_metadata();
+        metadata.share_price = share_price.into();
+        metadata.set_as_released();
+        self.metadata.replace(&metadata);
+        self.on_share_price_changed();
+
+        let change = env::attached_deposit() - price + remainder;
+        if change > 0 {
+            Promise::new(buyer.clone()).transfer(change);
+        }
+
+        self.internal_issue_deed(&buyer);
+        self.custody_verified_at = None;
+        non_fungible_token_core::nft_transfer(
+            buyer.clone().try_into().unwrap(),
+            nft_token_id.clone(),
+            None,
+            None,
+            &nft_contract_address,
+            NFT_TRANSFER_DEPOSIT,
+            GAS_FOR_NFT_TRANSFER
+        );
+
+        self.on_redeem(buyer, nft_contract_address, nft_token_id);
+    }
+
+    pub fn get_proposal(&self, proposal_id: U64) -> Option<ProposalView> {
+        self.governance.proposals.get(proposal_id.0).map(|proposal| proposal_view(proposal_id.0, proposal))
+    }
+
+    pub fn get_proposals(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<ProposalView> {
+        let from_index = from_index.map(|index| index.0).unwrap_or(0);
+        let to_index = std::cmp::min(
+            from_index.saturating_add(limit.map(|limit| limit.0).unwrap_or(u64::MAX)),
+            self.governance.proposals.len()
+        );
+
+        (from_index..to_index)
+            .filter_map(|id| self.get_proposal(id.into()))
+            .collect()
+    }
+
+    /// Votes an account can cast on a proposal, its balance at the proposal's snapshot block
+    pub fn voting_power_of(&self, proposal_id: U64, account_id: ValidAccountId) -> U128 {
+        let proposal = self.governance.proposals.get(proposal_id.0).expect("proposal not found");
+        self.ft_balance_at(account_id, proposal.snapshot_block.into())
+    }
+
+    pub fn curator(&self) -> AccountId {
+        self.curator.clone()
+    }
+
+    pub fn approved_buyer(&self) -> Option<(AccountId, U128)> {
+        self.governance.approved_buyer.clone().map(|(buyer, price)| (buyer, price.into()))
+    }
+}
+
+impl Shares {
+    fn on_proposal_created(&self, proposal_id: ProposalId, proposer: AccountId) {
+        log!("ProposalCreated({}, {}, {})", proposal_id, proposer, env::current_account_id());
+    }
+
+    fn on_vote(&self, proposal_id: ProposalId, voter: AccountId, approve: bool, weight: Balance) {
+        log!("Vote({}, {}, {}, {}, {})", proposal_id, voter, approve, weight, env::current_account_id());
+    }
+
+    fn on_proposal_executed(&self, proposal_id: ProposalId) {
+        log!("ProposalExecuted({}, {})", proposal_id, env::current_account_id());
+    }
+}
+
+fn proposal_view(id: ProposalId, proposal: Proposal) -> ProposalView {
+    ProposalView {
+        id: id.into(),
+        status: proposal.status(),
+        proposer: proposal.proposer,
+        action: proposal.action,
+        description: proposal.description,
+        snapshot_block: proposal.snapshot_block.into(),
+        snapshot_supply: proposal.snapshot_supply.into(),
+        votes_for: proposal.votes_for.into(),
+        votes_against: proposal.votes_against.into(),
+        voting_ends_at: proposal.voting_ends_at.into(),
+        execution_ends_at: proposal.execution_ends_at.into(),
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    const SHARE_PRICE: Balance = 10;
+    /// Covers the storage of a proposal or a vote
+    const GOVERNANCE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn set_predecessor(context: &mut VMContextBuilder, account_id: ValidAccountId) {
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .signer_account_id(account_id.clone())
+            .predecessor_account_id(account_id)
+            .build());
+    }
+
+    /// accounts(1) holds 60% of shares and accounts(2) 40%
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.ft_transfer(accounts(2), (TOTAL_SUPPLY * 2 / 5).into(), None);
+
+        // Proposals are created in the next block, so they see the balances above
+        context.block_index(1);
+        set_predecessor(context, accounts(1));
+        contract
+    }
+
+    fn propose(context: &mut VMContextBuilder, contract: &mut Shares, action: ProposalAction) -> U64 {
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(GOVERNANCE_DEPOSIT).build());
+        let id = contract.propose(action, "".to_string());
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+        id
+    }
+
+    fn vote(context: &mut VMContextBuilder, contract: &mut Shares, proposal_id: U64, approve: bool) {
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(GOVERNANCE_DEPOSIT).build());
+        contract.vote(proposal_id, approve);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+    }
+
+    #[test]
+    fn test_proposal_passes_and_executes() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeSharePrice { share_price: 20.into() });
+
+        vote(&mut context, &mut contract, id, true);
+        set_predecessor(&mut context, accounts(2));
+        vote(&mut context, &mut contract, id, false);
+
+        let proposal = contract.get_proposal(id).unwrap();
+        assert_eq!(proposal.votes_for.0, TOTAL_SUPPLY * 3 / 5);
+        assert_eq!(proposal.votes_against.0, TOTAL_SUPPLY * 2 / 5);
+        assert_eq!(proposal.status, ProposalStatus::Voting);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        assert_eq!(contract.get_proposal(id).unwrap().status, ProposalStatus::Passed);
+        contract.execute_proposal(id);
+
+        assert_eq!(contract.ft_metadata().share_price.0, 20);
+        assert_eq!(contract.exit_price().0, TOTAL_SUPPLY * 20);
+        assert_eq!(contract.get_proposal(id).unwrap().status, ProposalStatus::Executed);
+    }
+
+    #[test]
+    fn test_votes_use_snapshot_balance() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(2).into() });
+
+        // Moving shares after the proposal was created does not move votes
+        testing_env!(context.attached_deposit(1).build());
+        contract.ft_transfer(accounts(2), (TOTAL_SUPPLY / 2).into(), None);
+
+        assert_eq!(contract.voting_power_of(id, accounts(1)).0, TOTAL_SUPPLY * 3 / 5);
+        assert_eq!(contract.voting_power_of(id, accounts(2)).0, TOTAL_SUPPLY * 2 / 5);
+
+        set_predecessor(&mut context, accounts(2));
+        vote(&mut context, &mut contract, id, false);
+        set_predecessor(&mut context, accounts(1));
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        contract.execute_proposal(id);
+        assert_eq!(contract.curator(), accounts(2).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "balance below proposal threshold")]
+    fn test_propose_below_threshold() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        set_predecessor(&mut context, accounts(3));
+        propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+    }
+
+    #[test]
+    #[should_panic(expected = "balance below proposal threshold")]
+    fn test_propose_with_shares_received_in_same_block() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.storage_deposit(None, None);
+        set_predecessor(&mut context, accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        contract.ft_transfer(accounts(3), (TOTAL_SUPPLY / 2).into(), None);
+
+        set_predecessor(&mut context, accounts(3));
+        propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+    }
+
+    #[test]
+    #[should_panic(expected = "already voted")]
+    fn test_double_vote() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+
+        vote(&mut context, &mut contract, id, true);
+        vote(&mut context, &mut contract, id, true);
+    }
+
+    #[test]
+    fn test_proposal_without_quorum_is_rejected() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        assert_eq!(contract.get_proposal(id).unwrap().status, ProposalStatus::Rejected);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal cannot be executed")]
+    fn test_execute_after_window() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD + EXECUTION_PERIOD).build());
+        assert_eq!(contract.get_proposal(id).unwrap().status, ProposalStatus::Expired);
+        contract.execute_proposal(id);
+    }
+
+    #[test]
+    fn test_approved_buyer_buyout() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let price = TOTAL_SUPPLY * 15;
+        let id = propose(&mut context, &mut contract, ProposalAction::ApproveBuyer { buyer_id: accounts(3).into(), price: price.into() });
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        contract.execute_proposal(id);
+
+        set_predecessor(&mut context, accounts(3));
+        testing_env!(context.attached_deposit(price).build());
+        contract.buyout();
+
+        let metadata = contract.ft_metadata();
+        assert!(metadata.released);
+        assert_eq!(metadata.share_price.0, 15);
+        assert_eq!(contract.vault_balance_of(accounts(2)).0, TOTAL_SUPPLY * 2 / 5 * 15);
+        assert!(contract.approved_buyer().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "buyout price must be a multiple of the share supply")]
+    fn test_approve_buyer_price_not_multiple_of_supply() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        propose(&mut context, &mut contract, ProposalAction::ApproveBuyer { buyer_id: accounts(3).into(), price: (TOTAL_SUPPLY * 15 + 1).into() });
+    }
+
+    #[test]
+    #[should_panic(expected = "price is below one yoctoNEAR per share")]
+    fn test_approve_buyer_price_below_supply() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        propose(&mut context, &mut contract, ProposalAction::ApproveBuyer { buyer_id: accounts(3).into(), price: 0.into() });
+    }
+
+    #[test]
+    fn test_buyout_after_burn_refunds_remainder() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let price = TOTAL_SUPPLY * 7;
+        let id = propose(&mut context, &mut contract, ProposalAction::ApproveBuyer { buyer_id: accounts(3).into(), price: price.into() });
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        contract.execute_proposal(id);
+
+        // accounts(2) burns its 40% by unregistering, leaving a supply which doesn't divide the price
+        set_predecessor(&mut context, accounts(2));
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_unregister(Some(true));
+
+        set_predecessor(&mut context, accounts(3));
+        testing_env!(context.attached_deposit(price).build());
+        contract.buyout();
+
+        // 7_000_000 = 600_000 * 11 + 400_000
+        assert_eq!(contract.ft_metadata().share_price.0, 11);
+        let refund: Vec<String> = get_created_receipts().iter()
+            .map(|receipt| format!("{:?}", receipt))
+            .filter(|receipt| receipt.contains("TransferAction { deposit: 400000 }"))
+            .collect();
+        assert_eq!(refund.len(), 1);
+        assert!(refund[0].contains(&format!("receiver_id: {:?}", accounts(3).as_ref())));
+    }
+
+    #[test]
+    fn test_approve_rental() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let offer = RentalOffer { renter_id: accounts(3).into(), rent: 100.into(), duration: 1_000.into() };
+        let id = propose(&mut context, &mut contract, ProposalAction::ApproveRental(offer.clone()));
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        contract.execute_proposal(id);
+
+        assert_eq!(contract.rental_offer(), Some(offer));
+    }
+
+    /// NEAR refunded to accounts(1) by the receipts created in the current context
+    fn refund() -> Balance {
+        get_created_receipts().iter()
+            .map(|receipt| format!("{:?}", receipt))
+            .filter(|receipt| receipt.contains(&format!("receiver_id: {:?}", accounts(1).as_ref())))
+            .map(|receipt| receipt.split("TransferAction { deposit: ").nth(1).unwrap().split(' ').next().unwrap().parse::<Balance>().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_proposal_and_vote_pay_for_storage() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        let storage_usage = env::storage_usage();
+        testing_env!(context.storage_usage(storage_usage).attached_deposit(GOVERNANCE_DEPOSIT).build());
+        let id = contract.propose(ProposalAction::ChangeCurator { curator_id: accounts(3).into() }, "a".repeat(100));
+        let storage_cost = Balance::from(env::storage_usage() - storage_usage) * env::storage_byte_cost();
+        assert_eq!(refund(), GOVERNANCE_DEPOSIT - storage_cost);
+
+        let storage_usage = env::storage_usage();
+        testing_env!(context.storage_usage(storage_usage).attached_deposit(GOVERNANCE_DEPOSIT).build());
+        contract.vote(id, true);
+        let storage_cost = Balance::from(env::storage_usage() - storage_usage) * env::storage_byte_cost();
+        assert!(storage_cost > 0);
+        assert_eq!(refund(), GOVERNANCE_DEPOSIT - storage_cost);
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover storage")]
+    fn test_propose_without_storage_deposit() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        contract.propose(ProposalAction::ChangeCurator { curator_id: accounts(3).into() }, "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover storage")]
+    fn test_vote_without_storage_deposit() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ChangeCurator { curator_id: accounts(3).into() });
+
+        contract.vote(id, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "description is longer than 1000 bytes")]
+    fn test_propose_long_description() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(GOVERNANCE_DEPOSIT).build());
+        contract.propose(ProposalAction::ChangeCurator { curator_id: accounts(3).into() }, "a".repeat(MAX_DESCRIPTION_LEN + 1));
+    }
+
+    #[test]
+    fn test_list_nft() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        let id = propose(&mut context, &mut contract, ProposalAction::ListNft { market_id: accounts(4).into(), price: 100.into() });
+        vote(&mut context, &mut contract, id, true);
+
+        testing_env!(context.block_timestamp(VOTING_PERIOD).build());
+        contract.execute_proposal(id);
+
+        // The marketplace is approved once the NFT contract reports the vault's payout
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains(&format!("{:?}", b"nft_payout")), "Receipts: {}", receipts);
+        assert!(receipts.contains(&format!("{:?}", b"on_listing_payout")), "Receipts: {}", receipts);
+    }
+}
@@ -1,42 +1,116 @@
+// #[near_bindgen] and #[ext_contract] expand methods into functions taking extra arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::convert::TryInto;
 
-use near_contract_standards::fungible_token::FungibleToken;
+use near_contract_standards::non_fungible_token::NonFungibleToken;
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement};
+use near_contract_standards::fungible_token::{
+    FungibleToken,
+    core::FungibleTokenCore,
+    core_impl::ext_fungible_token_receiver,
+    receiver::FungibleTokenReceiver,
+    resolver::FungibleTokenResolver,
+};
 use near_sdk::{
-    env, AccountId, Balance, PromiseOrValue, Promise,
+    assert_one_yocto, env, AccountId, Balance, Gas, StorageUsage, PromiseOrValue, Promise,
     BorshStorageKey, PanicOnDefault, log,
     near_bindgen, ext_contract,
-    collections::LazyOption,
+    collections::{LazyOption, LookupMap},
     json_types::{ValidAccountId, U64, U128},
     borsh::{self, BorshDeserialize, BorshSerialize}
 };
-mod shares_metadata;
-use shares_metadata::{SharesMetadata, SharesMetadataProvider, SHARES_FT_METADATA_SPEC};
+mod checkpoints;
+mod custody;
+mod deed;
+mod dividends;
+mod governance;
+mod listing;
+mod nft_info;
+mod pool;
+mod portfolio;
+mod quote;
+mod rental;
+mod royalties;
+mod sale;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod state_machine;
+use checkpoints::Checkpoints;
+use dividends::Dividends;
+use governance::Governance;
+use listing::Listing;
+use nft_info::NftTokenMetadata;
+use pool::PoolReference;
+use rental::{Rental, RentalOffer};
+use sale::Sale;
+use fractose_common::interfaces::{
+    ext_factory, non_fungible_token_approval, non_fungible_token_core, non_fungible_token_user, non_fungible_token_view,
+};
+pub use custody::GAS_FOR_REDEEM_CUSTODY_CHECK;
+pub use fractose_common::{RedeemQuote, SharesMetadata, SharesMetadataProvider, TokenId, TrustTier};
+use fractose_common::{
+    assert_prepaid_gas, Event, GAS_FOR_HOLDINGS_REPORT, GAS_FOR_NFT_TOKEN, GAS_FOR_NFT_TRANSFER, NFT_TRANSFER_DEPOSIT,
+    SHARES_FT_METADATA_SPEC,
+};
 
 near_sdk::setup_alloc!();
 
-pub type TokenId = String;
-
-#[ext_contract]
-pub trait NonFungibleTokenCore {
-    fn nft_transfer(
-        &mut self,
-        receiver_id: ValidAccountId,
-        token_id: TokenId,
-        approval_id: Option<U64>,
-        memo: Option<String>,
-    );
-}
+/// Gas used by `redeem` itself, including the cleanup of the vault and holdings reports
+pub const GAS_FOR_REDEEM: Gas = 15_000_000_000_000;
+/// Gas used by `claim` itself, including the holdings report
+pub const GAS_FOR_CLAIM: Gas = 15_000_000_000_000;
+/// Gas given to `cleanup` after a claim
+pub const GAS_FOR_CLEANUP: Gas = 5_000_000_000_000;
+/// Gas given to `ft_resolve_transfer`, including the holdings report it may send
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 10_000_000_000_000;
+/// Gas kept by `ft_transfer_call` for itself and the resolution, the receiver gets the rest
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 20_000_000_000_000 + GAS_FOR_HOLDINGS_REPORT + GAS_FOR_RESOLVE_TRANSFER;
 
 #[ext_contract]
 pub trait Shares {
     fn cleanup(&mut self);
+    fn ft_resolve_transfer(&mut self, sender_id: AccountId, receiver_id: AccountId, amount: U128) -> U128;
+    fn on_pool_info(&mut self) -> PoolReference;
+    fn resolve_dividends_withdrawal(&mut self, account_id: AccountId, token_id: AccountId, amount: U128);
+    fn on_nft_payout(&mut self) -> U128;
+    fn on_listing_payout(&mut self, market_id: AccountId, price: U128);
+    fn on_market_sale(&mut self) -> bool;
+    fn on_nft_metadata(&mut self) -> Option<NftTokenMetadata>;
+    fn on_custody_check(&mut self) -> bool;
+    fn on_redeem_custody_check(&mut self, redeemer_id: AccountId, payment: U128);
+    fn on_proceeds_transfer(&mut self, receiver_id: AccountId, amount: U128, fee: bool);
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Shares {
     token: FungibleToken,
-    metadata: LazyOption<SharesMetadata>
+    metadata: LazyOption<SharesMetadata>,
+    /// Account managing the vault, initially the one which securitized the NFT
+    curator: AccountId,
+    /// Fractose factory which deployed this contract. Receives protocol fees
+    factory_id: AccountId,
+    sale: Option<Sale>,
+    pool_id: Option<AccountId>,
+    pool_reference: Option<PoolReference>,
+    governance: Governance,
+    checkpoints: Checkpoints,
+    dividends: Dividends,
+    /// NEP-141 tokens accepted as royalties, allowlisted by the curator
+    royalty_tokens: Vec<AccountId>,
+    listing: Option<Listing>,
+    rental_offer: Option<RentalOffer>,
+    rental: Option<Rental>,
+    /// Vault deed NFT, issued to a holder of every share or to the redeemer
+    deeds: NonFungibleToken,
+    /// Metadata of the underlying NFT and when it was fetched
+    nft_metadata_cache: Option<(NftTokenMetadata, u64)>,
+    /// Block timestamp at which the vault was last seen owning the NFT
+    custody_verified_at: Option<u64>,
+    /// Most storage a redemption adds, measured on creation
+    redeem_storage_usage: StorageUsage,
+    /// Redeem payments waiting for their custody check
+    pending_redeem_payments: Balance,
 }
 
 const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 288 288'%3E%3Cg id='l' data-name='l'%3E%3Cpath d='M187.58,79.81l-30.1,44.69a3.2,3.2,0,0,0,4.75,4.2L191.86,103a1.2,1.2,0,0,1,2,.91v80.46a1.2,1.2,0,0,1-2.12.77L102.18,77.93A15.35,15.35,0,0,0,90.47,72.5H87.34A15.34,15.34,0,0,0,72,87.84V201.16A15.34,15.34,0,0,0,87.34,216.5h0a15.35,15.35,0,0,0,13.08-7.31l30.1-44.69a3.2,3.2,0,0,0-4.75-4.2L96.14,186a1.2,1.2,0,0,1-2-.91V104.61a1.2,1.2,0,0,1,2.12-.77l89.55,107.23a15.35,15.35,0,0,0,11.71,5.43h3.13A15.34,15.34,0,0,0,216,201.16V87.84A15.34,15.34,0,0,0,200.66,72.5h0A15.35,15.35,0,0,0,187.58,79.81Z'/%3E%3C/g%3E%3C/svg%3E";
@@ -45,15 +119,42 @@ const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://
 enum StorageKey {
     FungibleToken,
     Metadata,
+    SaleAllowlist { sale_id: u32 },
+    SalePurchases { sale_id: u32 },
+    Proposals,
+    Votes,
+    CheckpointRanges,
+    BalanceCheckpoints,
+    SupplyCheckpoints,
+    DividendTokenPools,
+    DividendsPaidPerShare,
+    DividendsUnpaid,
+    DividendsExcluded,
+    DeedOwners,
+    DeedMetadata,
+    DeedEnumeration,
+    DeedApprovals,
+    DeedsPerOwner { account_hash: Vec<u8> },
 }
 
 #[near_bindgen]
 impl Shares {
     #[init]
-    pub fn create(nft_contract_address: AccountId, nft_token_id: TokenId, owner_id: ValidAccountId, shares_count: U128, decimals: u8, share_price: U128) -> Self {
+    pub fn create(nft_contract_address: AccountId, nft_token_id: TokenId, owner_id: ValidAccountId, shares_count: U128, decimals: u8, share_price: U128, min_lock_until: Option<U64>, pool_id: Option<ValidAccountId>, pool_shares: Option<U128>, trust_tier: Option<TrustTier>) -> Self {
         // TODO allow payment in NEP-141 fungible tokens
 
         assert!(!env::state_exists(), "Already initialized");
+        assert!(shares_count.0 > 0, "invalid shares count");
+        assert!(share_price.0 > 0, "invalid share price");
+
+        // Every value computed from shares is bounded by the exit price. Validating it here
+        // ensures that it stays representable for the lifetime of the contract since the
+        // supply can only shrink.
+        checked_value(shares_count.0, share_price.0);
+
+        let pool_shares = pool_shares.map(|shares| shares.0).unwrap_or(0);
+        assert!(pool_shares <= shares_count.0, "pool seed exceeds shares count");
+        assert!(pool_shares == 0 || pool_id.is_some(), "pool seed requires a pool");
 
         let metadata = SharesMetadata {
             spec: SHARES_FT_METADATA_SPEC.to_string(),
@@ -68,36 +169,79 @@ impl Shares {
             nft_contract_address: nft_contract_address.clone(),
             nft_token_id: nft_token_id.clone(),
             share_price,
-            released: false
+            released: false,
+            min_lock_until,
+            trust_tier: trust_tier.unwrap_or(TrustTier::Unverified)
         };
         metadata.assert_valid();
 
         let mut this = Self {
             token: FungibleToken::new(StorageKey::FungibleToken),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            curator: owner_id.to_string(),
+            factory_id: env::predecessor_account_id(),
+            sale: None,
+            pool_id: pool_id.clone().map(|pool_id| pool_id.into()),
+            pool_reference: None,
+            governance: Governance::new(),
+            checkpoints: Checkpoints::new(),
+            dividends: Dividends::new(),
+            royalty_tokens: Vec::new(),
+            listing: None,
+            rental_offer: None,
+            rental: None,
+            deeds: deed::new_deeds(),
+            nft_metadata_cache: None,
+            custody_verified_at: None,
+            redeem_storage_usage: 0,
+            pending_redeem_payments: 0,
         };
+        // Registration deposits also pay for the balance history of the account
+        this.token.account_storage_usage += this.checkpoints.measure_account_storage_usage();
+        // A redemption issues the deed and records balance and supply checkpoints
+        this.redeem_storage_usage = this.measure_deed_storage_usage() + this.checkpoints.measure_new_checkpoints_storage_usage();
+        // Shares escrowed by sales of the vault and held by its pool earn no dividends
+        this.dividends.exclude(&env::current_account_id(), 0);
+        if let Some(pool_id) = &pool_id {
+            this.dividends.exclude(pool_id.as_ref(), 0);
+        }
         this.token.internal_register_account(owner_id.as_ref());
-        this.token.internal_deposit(owner_id.as_ref(), shares_count.0);
+        this.checkpoints.prepay(owner_id.as_ref());
+        this.token.internal_deposit(owner_id.as_ref(), shares_count.0 - pool_shares);
+        this.after_balance_change(owner_id.as_ref());
+
+        // Seed shares go straight to the pool deployed alongside the vault
+        if let Some(pool_id) = &pool_id {
+            this.token.internal_register_account(pool_id.as_ref());
+            this.checkpoints.prepay(pool_id.as_ref());
+            this.token.internal_deposit(pool_id.as_ref(), pool_shares);
+            this.after_balance_change(pool_id.as_ref());
+        }
+        let mut holders = vec![owner_id.as_ref()];
+        holders.extend(pool_id.as_ref().map(|pool_id| pool_id.as_ref()));
+        this.report_holdings(&holders);
 
-        // Emit event
+        this.internal_fetch_nft_metadata();
+
+        // Emit events
         this.on_securitize(owner_id.to_string(), nft_contract_address, nft_token_id);
+        this.on_tokens_minted(owner_id.to_string(), shares_count.0 - pool_shares);
+        if let Some(pool_id) = pool_id {
+            this.on_tokens_minted(pool_id.to_string(), pool_shares);
+        }
 
         this
     }
 
     /// Exit price in Near to redeem underlying NFT
     pub fn exit_price(&self) -> U128 {
-        (self.ft_total_supply().0 * self.ft_metadata().share_price.0).into()
+        checked_value(self.ft_total_supply().0, self.ft_metadata().share_price.0).into()
     }
 
     /// Near tokens required by a user in addition to held shares to redeem NFT
     pub fn redeem_amount_of(&self, from: ValidAccountId) -> U128 {
-        let SharesMetadata { released, share_price, .. } = self.ft_metadata();
-        assert!(!released, "token already redeemed");
-
-        let user_shares = self.ft_balance_of(from);
-
-        (self.exit_price().0 - user_shares.0 * share_price.0).into()
+        assert!(!self.ft_metadata().released, "token already redeemed");
+        self.redeem_amount_due(from.as_ref()).into()
     }
 
     /// Returns balance Near tokens in vault
@@ -107,7 +251,7 @@ impl Shares {
         let balance = if !released {
             0
         } else {
-            self.ft_total_supply().0 * share_price.0
+            checked_value(self.ft_total_supply().0, share_price.0)
         };
 
         balance.into()
@@ -121,27 +265,51 @@ impl Shares {
             0
         } else {
             let user_shares = self.ft_balance_of(from);
-            user_shares.0 * share_price.0
+            checked_value(user_shares.0, share_price.0)
         };
 
         balance.into()
     }
 
-    /// Redeem NFT through owned shares or NEAR payment
+    /// Block timestamp in nanoseconds from which the NFT can be redeemed. 0 if the vault has no lock
+    pub fn redeemable_at(&self) -> U64 {
+        self.ft_metadata().min_lock_until.unwrap_or(U64(0))
+    }
+
+    /// Redeem NFT through owned shares or NEAR payment. If custody was not verified yet, the vault
+    /// first checks with the NFT contract that it holds the NFT, and refunds the payment if it doesn't
     #[payable]
     pub fn redeem(&mut self) {
-        let SharesMetadata { released, nft_token_id, nft_contract_address, .. } = self.ft_metadata();
-        assert!(!released, "token already redeemed");
-
+        assert_prepaid_gas(GAS_FOR_REDEEM + GAS_FOR_NFT_TRANSFER);
         let user_account = env::signer_account_id();
+        let payment_amount = env::attached_deposit();
+        if self.custody_verified_at.is_some() {
+            self.internal_redeem(user_account, payment_amount);
+            return;
+        }
 
-        let user_account_object: ValidAccountId = (user_account.clone()).try_into().unwrap();
+        assert_prepaid_gas(GAS_FOR_REDEEM_CUSTODY_CHECK);
+        if let Some(error) = self.redeem_error() {
+            panic!("{}", error);
+        }
+        // Fail early, the check is repeated once custody is verified
+        self.assert_redeem_payment(&user_account, payment_amount);
+        self.pending_redeem_payments += payment_amount;
+        self.internal_verify_custody_for_redeem(user_account, payment_amount);
+    }
 
-        let payment_amount = env::attached_deposit();
-        let redeem_amount = self.redeem_amount_of(user_account_object.clone()).0;
+    /// Redeem the NFT for `user_account`, who attached `payment_amount`. Custody must be verified
+    pub(crate) fn internal_redeem(&mut self, user_account: AccountId, payment_amount: Balance) {
+        let SharesMetadata { nft_token_id, nft_contract_address, .. } = self.ft_metadata();
+        if let Some(error) = self.redeem_error() {
+            panic!("{}", error);
+        }
 
-        // TODO allow payment in NEP-141 fungible tokens
-        assert!(payment_amount >= redeem_amount, "insufficient payment amount");
+        // Unsold shares go back to the seller before the exit price is computed
+        self.internal_close_sale();
+
+        let user_account_object: ValidAccountId = (user_account.clone()).try_into().unwrap();
+        let redeem_amount = self.assert_redeem_payment(&user_account, payment_amount);
 
         // Return change amount to redeemer
         let change_amount = payment_amount - redeem_amount;
@@ -155,21 +323,34 @@ impl Shares {
 
         self.metadata.replace(&new_metadata);
 
-        // Burn shares
+        // Burn shares. Redeemers paying the full exit price may not be registered and must stay so
         let user_shares = self.ft_balance_of(user_account_object.clone());
-        self.token.accounts.insert(&user_account, &0);
-        self.token.total_supply -= user_shares.0;
-        self.on_tokens_burned(user_account.clone(), user_shares.0);
+        if user_shares.0 > 0 {
+            self.before_balance_change(&user_account);
+            self.token.accounts.insert(&user_account, &0);
+            self.token.total_supply -= user_shares.0;
+            self.after_balance_change(&user_account);
+            self.report_holdings(&[&user_account]);
+            self.on_tokens_burned(user_account.clone(), user_shares.0);
+        }
+
+        // Burning every share deletes the vault, which could not keep a deed
+        if self.token.total_supply > 0 || !self.dividends.all_paid() {
+            self.internal_issue_deed(&user_account);
+        } else {
+            self.internal_burn_deed();
+        }
 
         // Transfer NFT to redeemer
+        self.custody_verified_at = None;
         non_fungible_token_core::nft_transfer(
             user_account_object.clone(),
             nft_token_id.clone(),
             None,
             None,
             &nft_contract_address,
-            1,
-            env::prepaid_gas() / 2
+            NFT_TRANSFER_DEPOSIT,
+            GAS_FOR_NFT_TRANSFER
         );
 
         // Emit event
@@ -179,12 +360,22 @@ impl Shares {
         self.cleanup();
     }
 
-    /// Once NFT is redeemed by paying NEAR tokens, remaining shareholders can claim their share of NEAR in vault
-    pub fn claim(&mut self) {
+    /// Panic unless `payment_amount` covers the shares `user_account` doesn't hold. Returns the amount due
+    fn assert_redeem_payment(&self, user_account: &AccountId, payment_amount: Balance) -> Balance {
+        let redeem_amount = self.redeem_amount_due(user_account);
+        // TODO allow payment in NEP-141 fungible tokens
+        assert!(payment_amount >= redeem_amount, "insufficient payment amount");
+        redeem_amount
+    }
+
+    /// Once NFT is redeemed by paying NEAR tokens, remaining shareholders can claim their share of NEAR in vault.
+    /// Returns the shares burnt and the NEAR sent, so that contracts holding shares such as the pool can claim
+    pub fn claim(&mut self) -> (U128, U128) {
+        assert_prepaid_gas(GAS_FOR_CLAIM + GAS_FOR_CLEANUP);
         let SharesMetadata { released,  nft_contract_address, nft_token_id, .. } = self.ft_metadata();
         assert!(released, "token not redeemed");
 
-        let user_account = env::signer_account_id();
+        let user_account = env::predecessor_account_id();
         let user_account_object: ValidAccountId = user_account.clone().try_into().unwrap();
 
         let user_shares = self.ft_balance_of(user_account_object.clone());
@@ -193,9 +384,12 @@ impl Shares {
         let claim_amount = self.vault_balance_of(user_account_object.clone());
         assert!(claim_amount.0 > 0, "balance has already been claimed");
 
-        // Burn tokens- TODO check correctness
+        // Burn the claimed shares. Dividends are settled first so that they stay withdrawable
+        self.before_balance_change(&user_account);
         self.token.accounts.insert(&user_account, &0);
         self.token.total_supply -= user_shares.0;
+        self.after_balance_change(&user_account);
+        self.report_holdings(&[&user_account]);
         self.on_tokens_burned(user_account.clone(), user_shares.0);
 
         // Emit event
@@ -207,18 +401,38 @@ impl Shares {
         ).then(shares::cleanup(
             &env::current_account_id(),
             0,
-            env::prepaid_gas() / 2
+            GAS_FOR_CLEANUP
         )); // TODO allow payment in NEP-141 fungible tokens
 
-        // self.cleanup();
+        (user_shares, claim_amount)
+    }
+
+
+    /// Buyouts of the NFT are only allowed once the minimum holding period is over and no rental is active
+    fn assert_unlocked(&self) {
+        if let Some(error) = self.lock_error() {
+            panic!("{}", error);
+        }
     }
 
+    fn lock_error(&self) -> Option<String> {
+        let redeemable_at = self.redeemable_at().0;
+        if env::block_timestamp() < redeemable_at {
+            Some(format!("vault is locked until {}", redeemable_at))
+        } else {
+            self.rental().map(|rental| format!("NFT is rented until {}", rental.ends_at.0))
+        }
+    }
 
-    fn cleanup(&mut self) {
+    /// Delete the vault once every share is burnt. Called back after each claim
+    #[private]
+    pub fn cleanup(&mut self) {
         // Emit event
 
+        // Vaults are kept until past holders withdrew every dividend they are owed, and while they hold
+        // the deed of a redemption
         let shares_left = self.ft_total_supply();
-        if shares_left.0 == 0 {
+        if shares_left.0 == 0 && self.dividends.all_paid() && self.deeds.owner_by_id.get(&deed::DEED_TOKEN_ID.to_string()).is_none() {
             // TODO Remove current contract address Fractose contract
 
             // Delete contract if all shares have been burnt
@@ -230,29 +444,254 @@ impl Shares {
     }
 
     fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
-        log!("Closed @{} with {}", account_id, balance);
+        self.pay_closed_account_dividends(&account_id, balance);
+        self.checkpoints.refund(&account_id);
+        self.after_balance_change(&account_id);
+        let governance = &self.governance;
+        self.checkpoints.prune(&account_id, |from, to| governance.reads_snapshot_in(from, to));
+        self.report_holdings(&[&account_id]);
+
+        // Once the NFT is redeemed, burnt shares are paid out as if they were claimed
+        let SharesMetadata { released, share_price, .. } = self.ft_metadata();
+        if released && balance > 0 {
+            Promise::new(account_id.clone()).transfer(checked_value(balance, share_price.0));
+        }
+        log!("{}", Event::AccountClosed { account_id, balance });
+    }
+
+    fn on_tokens_minted(&self, owner_id: AccountId, amount: Balance) {
+        log!("{}", Event::Mint { owner_id, amount });
     }
 
     fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
-        log!("Account @{} burned {}", account_id, amount);
+        log!("{}", Event::Burn { account_id, amount });
     }
 
     fn on_securitize(&self, owner_address: AccountId, nft_contract_address: AccountId, nft_token_id: TokenId) {
-        log!("Securitize({}, {}, {}, {})", owner_address, nft_contract_address, nft_token_id, env::current_account_id());
-        // log!("Account @{} securitized NFT #{} on contract {}", owner_address, nft_token_id, nft_contract_address);
+        log!("{}", Event::Securitize {
+            owner_id: owner_address,
+            nft_contract_id: nft_contract_address,
+            token_id: nft_token_id,
+            vault_id: env::current_account_id(),
+        });
     }
 
     fn on_redeem(&mut self, redeemer_address: AccountId, nft_contract_address: AccountId, nft_token_id: TokenId) {
-        log!("Redeem({}, {}, {}, {})", redeemer_address, nft_contract_address, nft_token_id, env::current_account_id());
+        log!("{}", Event::Redeem {
+            redeemer_id: redeemer_address,
+            nft_contract_id: nft_contract_address,
+            token_id: nft_token_id,
+            vault_id: env::current_account_id(),
+        });
     }
 
     fn on_claim(&mut self, claimant_address: AccountId, nft_contract_address: AccountId, nft_token_id: TokenId, shares_count: U128) {
-        log!("Securitize({}, {}, {}, {}, {})", claimant_address, nft_contract_address, nft_token_id, env::current_account_id(), shares_count.0);
+        log!("{}", Event::Claim {
+            claimant_id: claimant_address,
+            nft_contract_id: nft_contract_address,
+            token_id: nft_token_id,
+            vault_id: env::current_account_id(),
+            shares: shares_count.0,
+        });
+    }
+}
+
+impl Shares {
+    /// Called before the share balance of `account_id` changes
+    fn before_balance_change(&mut self, account_id: &AccountId) {
+        self.settle_dividends(account_id);
+    }
+
+    /// Called after the share balance of `account_id` or the total supply changes
+    fn after_balance_change(&mut self, account_id: &AccountId) {
+        self.track_excluded_balance(account_id);
+        let balance = self.token.accounts.get(account_id);
+        // Registered accounts other than the vault prepaid their checkpoints
+        let prepaid = balance.is_some() && account_id != &env::current_account_id();
+        let governance = &self.governance;
+        self.checkpoints.record_balance(account_id, balance.unwrap_or(0), prepaid, |from, to| governance.reads_snapshot_in(from, to));
+        self.checkpoints.record_supply(self.token.total_supply);
+        self.burn_stale_deed(account_id);
+    }
+
+    /// Move shares between accounts. Transfers made by the contract itself go through here
+    pub(crate) fn internal_transfer_shares(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: Balance) {
+        self.before_balance_change(sender_id);
+        self.before_balance_change(receiver_id);
+        self.token.internal_transfer(sender_id, receiver_id, amount, None);
+        self.after_balance_change(sender_id);
+        self.after_balance_change(receiver_id);
+        self.report_holdings(&[sender_id, receiver_id]);
+    }
+
+    /// Log the new share price and report it to the factory
+    pub(crate) fn on_share_price_changed(&self) {
+        log!("{}", Event::SharePrice { share_price: self.ft_metadata().share_price.0, vault_id: env::current_account_id() });
+        self.report_share_price();
+    }
+}
+
+/// NEAR value of `shares` at `share_price`. Panics instead of wrapping if it does not fit in a u128
+fn checked_value(shares: Balance, share_price: Balance) -> Balance {
+    shares.checked_mul(share_price).expect("share value overflows u128")
+}
+
+/// Price per share when `supply` shares are bought out for `price`, and the remainder of `price` the
+/// share price can't represent
+fn split_price(price: Balance, supply: Balance) -> (Balance, Balance) {
+    let share_price = price.checked_div(supply).expect("no shares left");
+    assert!(share_price > 0, "price is below one yoctoNEAR per share");
+    (share_price, price % supply)
+}
+
+/// `bps` basis points of `amount`, computed without overflowing for any u128 amount
+fn bps_of(amount: Balance, bps: u128) -> Balance {
+    amount / 10_000 * bps + amount % 10_000 * bps / 10_000
+}
+
+// Same as `impl_fungible_token_core!`, with balance hooks around every transfer
+#[near_bindgen]
+impl FungibleTokenCore for Shares {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        let sender_id = env::predecessor_account_id();
+        self.before_balance_change(&sender_id);
+        self.before_balance_change(receiver_id.as_ref());
+        self.token.ft_transfer(receiver_id.clone(), amount, memo);
+        self.after_balance_change(&sender_id);
+        self.after_balance_change(receiver_id.as_ref());
+        self.report_holdings(&[&sender_id, receiver_id.as_ref()]);
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        assert_prepaid_gas(GAS_FOR_FT_TRANSFER_CALL);
+        let sender_id = env::predecessor_account_id();
+        self.before_balance_change(&sender_id);
+        self.before_balance_change(receiver_id.as_ref());
+        self.token.internal_transfer(&sender_id, receiver_id.as_ref(), amount.0, memo);
+        self.after_balance_change(&sender_id);
+        self.after_balance_change(receiver_id.as_ref());
+        self.report_holdings(&[&sender_id, receiver_id.as_ref()]);
+
+        // The resolution is given enough gas to report the balances of a refund
+        ext_fungible_token_receiver::ft_on_transfer(
+            sender_id.clone(),
+            amount,
+            msg,
+            receiver_id.as_ref(),
+            0,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL
+        ).then(shares::ft_resolve_transfer(
+            sender_id,
+            receiver_id.into(),
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER
+        )).into()
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
     }
 }
 
-near_contract_standards::impl_fungible_token_core!(Shares, token, on_tokens_burned);
-near_contract_standards::impl_fungible_token_storage!(Shares, token, on_account_closed);
+#[near_bindgen]
+impl FungibleTokenResolver for Shares {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+    ) -> U128 {
+        let sender_id: AccountId = sender_id.into();
+        self.before_balance_change(&sender_id);
+        self.before_balance_change(receiver_id.as_ref());
+        let (used_amount, burned_amount) =
+            self.token.internal_ft_resolve_transfer(&sender_id, receiver_id.clone(), amount);
+        self.after_balance_change(&sender_id);
+        self.after_balance_change(receiver_id.as_ref());
+        if used_amount != amount.0 {
+            self.report_holdings(&[&sender_id, receiver_id.as_ref()]);
+        }
+        if burned_amount > 0 {
+            self.on_tokens_burned(sender_id, burned_amount);
+        }
+        used_amount.into()
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Shares {
+    /// Registration deposits also prepay the balance checkpoints of the account
+    #[payable]
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let registered_id = account_id.as_ref().map(|account_id| account_id.to_string()).unwrap_or_else(env::predecessor_account_id);
+        let registered = self.token.accounts.contains_key(&registered_id);
+        let storage_balance = self.token.storage_deposit(account_id, registration_only);
+        if !registered && registered_id != env::current_account_id() {
+            self.checkpoints.prepay(&registered_id);
+        }
+        storage_balance
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.token.storage_withdraw(amount)
+    }
+
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        if let Some((account_id, balance)) = self.token.internal_storage_unregister(force) {
+            self.on_account_closed(account_id, balance);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        self.token.storage_balance_bounds()
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.token.storage_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Shares {
+    /// Accepts NEP-141 payment for the share sale. Tokens which do not cover a whole share are returned
+    fn ft_on_transfer(&mut self, sender_id: ValidAccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        if msg == royalties::ROYALTY_MESSAGE {
+            assert!(self.royalty_tokens.contains(&env::predecessor_account_id()), "token is not accepted for royalties");
+            self.internal_receive_royalty(Some(env::predecessor_account_id()), amount.0);
+            return PromiseOrValue::Value(0.into());
+        }
+
+        assert!(msg.is_empty(), "unsupported transfer message");
+        let payment_token = self.sale.as_ref().and_then(|sale| sale.payment_token.clone());
+        assert_eq!(Some(env::predecessor_account_id()), payment_token, "token is not accepted by the sale");
+
+        let (_, cost) = self.internal_buy(sender_id.as_ref(), amount.0);
+        self.pay_sale_proceeds(cost);
+
+        PromiseOrValue::Value((amount.0 - cost).into())
+    }
+}
 
 #[near_bindgen]
 impl SharesMetadataProvider for Shares {
@@ -263,15 +702,16 @@ impl SharesMetadataProvider for Shares {
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
     use near_sdk::MockedBlockchain;
     use near_sdk::{testing_env, Balance};
+    use proptest::prelude::*;
 
     use super::*;
 
     const TOTAL_SUPPLY: Balance = 1_000_000_000_000_000;
-    const NFT_CONTRACT_ADDRESS: &'static str = "nft.near";
-    const NFT_TOKEN_ID: &'static str = "0";
+    const NFT_CONTRACT_ADDRESS: &str = "nft.near";
+    const NFT_TOKEN_ID: &str = "0";
     const DECIMALS: u8 = 8;
     const SHARE_PRICE: u128 = 100000;
 
@@ -296,12 +736,17 @@ mod tests {
             accounts(0),
             TOTAL_SUPPLY.into(),
             DECIMALS,
-            SHARE_PRICE.into()
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
         );
         testing_env!(context.is_view(true).build());
 
         assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
         assert_eq!(contract.ft_balance_of(accounts(0)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_metadata().trust_tier, TrustTier::Unverified);
 
         let expected_exit_price = TOTAL_SUPPLY * SHARE_PRICE;
         assert_eq!(contract.exit_price().0, expected_exit_price);
@@ -325,7 +770,11 @@ mod tests {
             accounts(2),
             TOTAL_SUPPLY.into(),
             DECIMALS,
-            SHARE_PRICE.into()
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
         );
         testing_env!(context
             .storage_usage(env::storage_usage())
@@ -364,9 +813,15 @@ mod tests {
             accounts(0),
             TOTAL_SUPPLY.into(),
             DECIMALS,
-            SHARE_PRICE.into()
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
         );
+        contract.custody_verified_at = Some(0);
 
+        testing_env!(context.build());
         contract.redeem();
 
         // Tests
@@ -388,8 +843,13 @@ mod tests {
             accounts(1),
             TOTAL_SUPPLY.into(),
             DECIMALS,
-            SHARE_PRICE.into()
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
         );
+        contract.custody_verified_at = Some(0);
 
         let redeem_amount = contract.redeem_amount_of(accounts(0));
         testing_env!(context.attached_deposit(redeem_amount.0).build());
@@ -415,8 +875,13 @@ mod tests {
             accounts(0),
             TOTAL_SUPPLY.into(),
             DECIMALS,
-            SHARE_PRICE.into()
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
         );
+        contract.custody_verified_at = Some(0);
 
         // Paying for account registration for account 1
         testing_env!(context
@@ -471,5 +936,456 @@ mod tests {
         assert!(redeemer_balance.0 == 0, "Redeemer balance: {}, shareholder balance: {}", redeemer_balance.0, shareholder_balance.0);
     }
 
-    // TODO tests for claim() function
+    #[test]
+    #[should_panic(expected = "share value overflows u128")]
+    fn test_create_exit_price_overflow() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+
+        Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(0),
+            (u128::MAX / 2 + 1).into(),
+            DECIMALS,
+            2.into(),
+            None,
+            None,
+            None,
+            None
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid share price")]
+    fn test_create_zero_share_price() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+
+        Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(0),
+            TOTAL_SUPPLY.into(),
+            DECIMALS,
+            0.into(),
+            None,
+            None,
+            None,
+            None
+        );
+    }
+
+    #[test]
+    fn test_exit_price_at_u128_bound() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+
+        let mut contract = Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(1),
+            u128::MAX.into(),
+            24,
+            1.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+        assert_eq!(contract.exit_price().0, u128::MAX);
+        assert_eq!(contract.redeem_amount_of(accounts(0)).0, u128::MAX);
+
+        // Redeeming at the bound must not brick the vault
+        testing_env!(context.account_balance(0).attached_deposit(u128::MAX).build());
+        contract.redeem();
+        assert_eq!(contract.vault_balance().0, u128::MAX);
+        assert_eq!(contract.vault_balance_of(accounts(1)).0, u128::MAX);
+    }
+
+    #[test]
+    fn test_redeem_after_lock() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.block_timestamp(100).build());
+
+        let mut contract = Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(0),
+            TOTAL_SUPPLY.into(),
+            DECIMALS,
+            SHARE_PRICE.into(),
+            Some(1000.into()),
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+        assert_eq!(contract.redeemable_at().0, 1000);
+
+        testing_env!(context.block_timestamp(1000).build());
+        contract.redeem();
+
+        assert!(contract.ft_metadata().released);
+    }
+
+    #[test]
+    #[should_panic(expected = "vault is locked until 1000")]
+    fn test_redeem_before_lock() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.block_timestamp(100).build());
+
+        let mut contract = Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(0),
+            TOTAL_SUPPLY.into(),
+            DECIMALS,
+            SHARE_PRICE.into(),
+            Some(1000.into()),
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        testing_env!(context.block_timestamp(999).build());
+        contract.redeem();
+    }
+
+    /// Total supply and a share price whose product fits in a u128
+    fn supply_and_price() -> impl Strategy<Value = (u128, u128)> {
+        (1..=u128::MAX).prop_flat_map(|supply| (Just(supply), 1..=u128::MAX / supply))
+    }
+
+    proptest! {
+        #[test]
+        fn prop_value_math_never_overflows(
+            (supply, price) in supply_and_price(),
+            transfer_ratio in 0..=100u128,
+        ) {
+            // testing_env! carries storage over, so drop the previous case's blockchain
+            env::take_blockchain_interface();
+            let mut context = get_context(accounts(0));
+            testing_env!(context.build());
+
+            let mut contract = Shares::create(
+                NFT_CONTRACT_ADDRESS.into(),
+                NFT_TOKEN_ID.into(),
+                accounts(0),
+                supply.into(),
+                DECIMALS,
+                price.into(),
+                None,
+                None,
+                None,
+                None
+            );
+            contract.custody_verified_at = Some(0);
+            prop_assert_eq!(contract.exit_price().0, supply * price);
+
+            testing_env!(context
+                .storage_usage(env::storage_usage())
+                .attached_deposit(contract.storage_balance_bounds().min.into())
+                .predecessor_account_id(accounts(1))
+                .build());
+            contract.storage_deposit(None, None);
+
+            let transferred = supply / 100 * transfer_ratio;
+            if transferred > 0 {
+                testing_env!(context
+                    .storage_usage(env::storage_usage())
+                    .attached_deposit(1)
+                    .predecessor_account_id(accounts(0))
+                    .build());
+                contract.ft_transfer(accounts(1), transferred.into(), None);
+            }
+
+            // Shares held plus NEAR owed always add up to the exit price
+            let redeem_amount = contract.redeem_amount_of(accounts(1)).0;
+            prop_assert_eq!(redeem_amount + transferred * price, contract.exit_price().0);
+            prop_assert_eq!(contract.vault_balance().0, 0);
+
+            // The vault pays the NFT transfer deposit, even when nothing is attached
+            testing_env!(context
+                .storage_usage(env::storage_usage())
+                .account_balance(NFT_TRANSFER_DEPOSIT)
+                .attached_deposit(redeem_amount)
+                .signer_account_id(accounts(1))
+                .predecessor_account_id(accounts(1))
+                .build());
+            contract.redeem();
+
+            // The vault backs exactly the shares left with other holders
+            let remaining = supply - transferred;
+            prop_assert_eq!(contract.vault_balance().0, remaining * price);
+            prop_assert_eq!(contract.vault_balance_of(accounts(0)).0, remaining * price);
+            prop_assert_eq!(contract.vault_balance_of(accounts(1)).0, 0);
+        }
+    }
+
+    /// Vault whose shares are spread as `balances` over `accounts(0)`, `accounts(1)`...
+    fn create_with_holders(context: &mut VMContextBuilder, balances: &[Balance]) -> Shares {
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(0),
+            balances.iter().sum::<Balance>().into(),
+            DECIMALS,
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        for (index, balance) in balances.iter().enumerate().skip(1) {
+            testing_env!(context
+                .storage_usage(env::storage_usage())
+                .attached_deposit(contract.storage_balance_bounds().min.into())
+                .predecessor_account_id(accounts(index))
+                .build());
+            contract.storage_deposit(None, None);
+
+            testing_env!(context
+                .storage_usage(env::storage_usage())
+                .attached_deposit(1)
+                .predecessor_account_id(accounts(0))
+                .build());
+            contract.ft_transfer(accounts(index), (*balance).into(), None);
+        }
+
+        contract
+    }
+
+    /// Redeem as `redeemer`, paying what its shares don't cover
+    fn redeem_as(context: &mut VMContextBuilder, contract: &mut Shares, redeemer: ValidAccountId) -> Balance {
+        let redeem_amount = contract.redeem_amount_of(redeemer.clone()).0;
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .account_balance(0)
+            .attached_deposit(redeem_amount)
+            .signer_account_id(redeemer.clone())
+            .predecessor_account_id(redeemer)
+            .build());
+        contract.redeem();
+        redeem_amount
+    }
+
+    /// Claim as `claimant` and return the NEAR sent to it
+    fn claim_as(context: &mut VMContextBuilder, contract: &mut Shares, claimant: ValidAccountId) -> Balance {
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .account_balance(contract.vault_balance().0)
+            .attached_deposit(0)
+            .signer_account_id(claimant.clone())
+            .predecessor_account_id(claimant.clone())
+            .build());
+        contract.claim();
+        near_sent_to(claimant.as_ref())
+    }
+
+    /// NEAR sent to `account_id` by the receipts created in the current context
+    fn near_sent_to(account_id: &str) -> Balance {
+        get_created_receipts().iter()
+            .map(|receipt| format!("{:?}", receipt))
+            .filter(|receipt| receipt.contains(&format!("receiver_id: {:?}", account_id)))
+            .flat_map(|receipt| receipt.split("TransferAction { deposit: ").skip(1)
+                .map(|rest| rest.split(' ').next().unwrap().parse::<Balance>().unwrap())
+                .collect::<Vec<_>>())
+            .sum()
+    }
+
+    #[test]
+    fn test_vault_balance_before_redeem() {
+        let mut context = get_context(accounts(0));
+        let contract = create_with_holders(&mut context, &[600, 400]);
+
+        assert_eq!(contract.vault_balance().0, 0);
+        assert_eq!(contract.vault_balance_of(accounts(0)).0, 0);
+        assert_eq!(contract.vault_balance_of(accounts(1)).0, 0);
+    }
+
+    #[test]
+    fn test_claim() {
+        let mut context = get_context(accounts(0));
+        let mut contract = create_with_holders(&mut context, &[600, 400]);
+
+        let paid = redeem_as(&mut context, &mut contract, accounts(1));
+        assert_eq!(paid, 600 * SHARE_PRICE);
+        assert_eq!(contract.vault_balance().0, paid);
+        assert_eq!(contract.vault_balance_of(accounts(0)).0, paid);
+        assert_eq!(contract.vault_balance_of(accounts(1)).0, 0);
+
+        let claimed = claim_as(&mut context, &mut contract, accounts(0));
+        assert_eq!(claimed, paid);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+        assert_eq!(contract.ft_total_supply().0, 0);
+        assert_eq!(contract.vault_balance().0, 0);
+        assert_eq!(contract.ft_total_supply_at(env::block_index().into()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "token not redeemed")]
+    fn test_claim_before_redeem() {
+        let mut context = get_context(accounts(0));
+        let mut contract = create_with_holders(&mut context, &[600, 400]);
+
+        claim_as(&mut context, &mut contract, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing to claim")]
+    fn test_claim_by_redeemer() {
+        let mut context = get_context(accounts(0));
+        let mut contract = create_with_holders(&mut context, &[600, 400]);
+        redeem_as(&mut context, &mut contract, accounts(1));
+
+        claim_as(&mut context, &mut contract, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing to claim")]
+    fn test_claim_twice() {
+        let mut context = get_context(accounts(0));
+        let mut contract = create_with_holders(&mut context, &[500, 300, 200]);
+        redeem_as(&mut context, &mut contract, accounts(2));
+
+        claim_as(&mut context, &mut contract, accounts(0));
+        claim_as(&mut context, &mut contract, accounts(0));
+    }
+
+    #[test]
+    fn test_claim_schedules_cleanup() {
+        let mut context = get_context(accounts(0));
+        let mut contract = create_with_holders(&mut context, &[500, 300, 200]);
+        redeem_as(&mut context, &mut contract, accounts(2));
+
+        claim_as(&mut context, &mut contract, accounts(1));
+        let receipts = format!("{:?}", get_created_receipts());
+        assert!(receipts.contains(&format!("receiver_id: {:?}", accounts(0).to_string())));
+        assert!(receipts.contains(&format!("{:?}", b"cleanup")));
+    }
+
+    #[test]
+    fn test_create_args_match_factory() {
+        testing_env!(get_context(accounts(1)).build());
+
+        // Call sent by the factory through the shared interface
+        fractose_common::interfaces::ext_shares::create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            DECIMALS,
+            SHARE_PRICE.into(),
+            Some(U64(5)),
+            Some(accounts(2)),
+            Some(U128(10)),
+            Some(TrustTier::Verified),
+            &accounts(0).to_string(),
+            0,
+            0
+        );
+        let receipts = near_sdk::serde_json::to_value(get_created_receipts()).unwrap();
+        let sent = &receipts[0]["actions"][0]["FunctionCall"];
+        assert_eq!(sent["method_name"], "create");
+
+        // Arguments `create` decodes, named after its parameters by #[near_bindgen]
+        let expected = SharesContract { account_id: accounts(0).to_string() }.create(
+            NFT_CONTRACT_ADDRESS.into(),
+            NFT_TOKEN_ID.into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            DECIMALS,
+            SHARE_PRICE.into(),
+            Some(U64(5)),
+            Some(accounts(2)),
+            Some(U128(10)),
+            Some(TrustTier::Verified)
+        );
+        let sent_args: near_sdk::serde_json::Value = near_sdk::serde_json::from_str(sent["args"].as_str().unwrap()).unwrap();
+        let expected_args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(&expected.args).unwrap();
+        assert_eq!(sent_args, expected_args);
+    }
+
+    #[test]
+    fn test_multi_holder_payouts() {
+        let mut context = get_context(accounts(0));
+        let balances = [400, 300, 200, 100];
+        let mut contract = create_with_holders(&mut context, &balances);
+
+        // accounts(2) redeems with its 200 shares and pays for the other 800
+        let paid = redeem_as(&mut context, &mut contract, accounts(2));
+        assert_eq!(paid, 800 * SHARE_PRICE);
+        assert_eq!(contract.ft_total_supply().0, 800);
+
+        let mut claimed = 0;
+        for index in [0, 1, 3] {
+            let vault_balance = contract.vault_balance().0;
+            let claim = claim_as(&mut context, &mut contract, accounts(index));
+
+            assert_eq!(claim, balances[index] * SHARE_PRICE);
+            assert_eq!(contract.vault_balance().0, vault_balance - claim);
+            claimed += claim;
+        }
+
+        // Holders got back exactly what the redeemer paid
+        assert_eq!(claimed, paid);
+        assert_eq!(contract.ft_total_supply().0, 0);
+        assert_eq!(contract.vault_balance().0, 0);
+    }
+
+    #[test]
+    fn test_claim_keeps_dividends() {
+        let mut context = get_context(accounts(0));
+        // accounts(0) is also the vault account, whose shares earn nothing
+        let mut contract = create_with_holders(&mut context, &[1, 600, 400]);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1_000)
+            .build());
+        contract.deposit_income();
+
+        redeem_as(&mut context, &mut contract, accounts(2));
+        claim_as(&mut context, &mut contract, accounts(1));
+
+        // Income earned while holding shares can still be withdrawn after they are burnt
+        assert_eq!(contract.dividends_of(accounts(0), None).0, 0);
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 600);
+        assert_eq!(contract.dividends_of(accounts(2), None).0, 400);
+    }
+
+    /// Share balances of two to five holders
+    fn holder_balances() -> impl Strategy<Value = Vec<u128>> {
+        prop::collection::vec(1..=TOTAL_SUPPLY, 2..=5)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_claims_add_up_to_redeem_payment(balances in holder_balances(), redeemer in 0..5usize) {
+            env::take_blockchain_interface();
+            let redeemer = redeemer % balances.len();
+            let mut context = get_context(accounts(0));
+            let mut contract = create_with_holders(&mut context, &balances);
+
+            let paid = redeem_as(&mut context, &mut contract, accounts(redeemer));
+            prop_assert_eq!(paid, contract.vault_balance().0);
+
+            let mut claimed = 0;
+            for index in (0..balances.len()).filter(|index| *index != redeemer) {
+                let claim = claim_as(&mut context, &mut contract, accounts(index));
+                prop_assert_eq!(claim, balances[index] * SHARE_PRICE);
+                claimed += claim;
+            }
+
+            prop_assert_eq!(claimed, paid);
+            prop_assert_eq!(contract.ft_total_supply().0, 0);
+            prop_assert_eq!(contract.vault_balance().0, 0);
+        }
+    }
 }
@@ -0,0 +1,297 @@
+use near_sdk::{
+    serde::Serialize,
+    serde_json::json,
+};
+
+use crate::*;
+use crate::nft_info::NftToken;
+use crate::royalties::{ext_nft_payout, Payout, GAS_FOR_NFT_PAYOUT};
+
+/// NEAR attached to `nft_approve` to pay for the approval's storage on the NFT contract
+const NFT_APPROVE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+/// NEAR deposited on the marketplace to pay for the storage of one sale
+const MARKET_STORAGE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+const GAS_FOR_RESOLVE_MARKET_SALE: Gas = 20_000_000_000_000;
+const GAS_FOR_ON_MARKET_SALE: Gas = 20_000_000_000_000;
+const GAS_FOR_LIST_NFT: Gas = 20_000_000_000_000;
+const GAS_FOR_MARKET_STORAGE_DEPOSIT: Gas = 10_000_000_000_000;
+const GAS_FOR_NFT_APPROVE: Gas = 30_000_000_000_000;
+const GAS_FOR_ON_LISTING_PAYOUT: Gas = 20_000_000_000_000;
+/// Most accounts the NFT's payout of a listing can be split between
+const MAX_LEN_PAYOUT: u32 = 10;
+
+#[ext_contract(ext_market)]
+pub trait MarketStorage {
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>);
+}
+
+/// NFT listed on a NEP-171 marketplace after a shareholder vote
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Listing {
+    pub market_id: AccountId,
+    /// Price in yoctoNEAR
+    pub price: U128,
+    /// Part of the price the marketplace pays the vault, as reported by the NFT's NEP-199 `nft_payout`
+    pub payout: U128,
+    pub listed_at: U64,
+}
+
+#[near_bindgen]
+impl Shares {
+    pub fn listing(&self) -> Option<Listing> {
+        self.listing.clone()
+    }
+
+    /// Check whether the listed NFT was sold. Anyone can call it once the marketplace paid the vault
+    pub fn resolve_market_sale(&mut self) -> Promise {
+        assert_prepaid_gas(GAS_FOR_RESOLVE_MARKET_SALE + GAS_FOR_NFT_TOKEN + GAS_FOR_ON_MARKET_SALE);
+        assert!(self.listing.is_some(), "NFT is not listed");
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        non_fungible_token_view::nft_token(
+            nft_token_id,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_TOKEN
+        ).then(shares::on_market_sale(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_MARKET_SALE
+        ))
+    }
+
+    /// Approve the marketplace once the NFT contract reported the vault's payout for the listing price
+    #[private]
+    pub fn on_listing_payout(&mut self, market_id: AccountId, price: U128, #[callback] payout: Payout) {
+        self.assert_custody();
+        self.assert_unlocked();
+        let vault_payout = payout.payout.get(&env::current_account_id()).copied().unwrap_or(U128(0));
+        assert!(vault_payout.0 > 0, "the vault is not paid by the NFT payout");
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        ext_market::storage_deposit(
+            Some(env::current_account_id().try_into().unwrap()),
+            &market_id,
+            MARKET_STORAGE_DEPOSIT,
+            GAS_FOR_MARKET_STORAGE_DEPOSIT
+        );
+        non_fungible_token_approval::nft_approve(
+            nft_token_id,
+            market_id.clone().try_into().unwrap(),
+            Some(json!({ "sale_conditions": { "near": price } }).to_string()),
+            &nft_contract_address,
+            NFT_APPROVE_DEPOSIT,
+            GAS_FOR_NFT_APPROVE
+        );
+
+        self.listing = Some(Listing {
+            market_id: market_id.clone(),
+            price,
+            payout: vault_payout,
+            listed_at: env::block_timestamp().into(),
+        });
+        self.on_nft_listed(market_id, price.0);
+    }
+
+    /// If the NFT changed owner, the NEAR received from the marketplace becomes the claim vault.
+    /// It must cover the vault's payout for the listing price
+    #[private]
+    pub fn on_market_sale(&mut self, #[callback] token: Option<NftToken>) -> bool {
+        let buyer = match token {
+            Some(token) if token.owner_id != env::current_account_id() => token.owner_id,
+            _ => return false,
+        };
+        let listing = match &self.listing {
+            Some(listing) if !self.ft_metadata().released => listing.clone(),
+            _ => return false,
+        };
+        self.assert_unlocked();
+
+        let proceeds = self.undistributed_balance().0;
+        assert!(proceeds >= listing.payout.0, "sale proceeds not received yet");
+        self.internal_close_sale();
+
+        // What can't be split per share stays on the vault and can be distributed later
+        let mut metadata = self.ft_metadata();
+        metadata.share_price = (proceeds / self.token.total_supply).into();
+        metadata.set_as_released();
+        self.metadata.replace(&metadata);
+        self.listing = None;
+        self.custody_verified_at = None;
+        self.on_share_price_changed();
+
+        self.on_nft_sold(buyer, proceeds);
+        true
+    }
+}
+
+impl Shares {
+    /// Approve `market_id` to sell the NFT for `price` NEAR, paying for the sale's storage on the market first.
+    /// The NFT's NEP-199 payout for the price is fetched first, to know what the sale must pay the vault
+    pub(crate) fn internal_list_nft(&mut self, market_id: AccountId, price: Balance) {
+        assert_prepaid_gas(GAS_FOR_LIST_NFT + GAS_FOR_NFT_PAYOUT + GAS_FOR_ON_LISTING_PAYOUT
+            + GAS_FOR_MARKET_STORAGE_DEPOSIT + GAS_FOR_NFT_APPROVE);
+        self.assert_custody();
+        self.assert_unlocked();
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        ext_nft_payout::nft_payout(
+            nft_token_id,
+            price.into(),
+            MAX_LEN_PAYOUT,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_PAYOUT
+        ).then(shares::on_listing_payout(
+            market_id,
+            price.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_LISTING_PAYOUT + GAS_FOR_MARKET_STORAGE_DEPOSIT + GAS_FOR_NFT_APPROVE
+        ));
+    }
+
+    fn on_nft_listed(&self, market_id: AccountId, price: Balance) {
+        log!("NftListed({}, {}, {})", market_id, price, env::current_account_id());
+    }
+
+    fn on_nft_sold(&self, buyer_id: AccountId, proceeds: Balance) {
+        log!("{}", Event::NftSold { buyer_id, proceeds, vault_id: env::current_account_id() });
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000;
+    const PROCEEDS: Balance = 1_000_000_000_000_000_000_000_000;
+    /// Royalty of the NFT's creator on the listing
+    const ROYALTY: Balance = 50_000_000_000_000_000_000_000;
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+        testing_env!(context.storage_usage(env::storage_usage()).build());
+        contract.internal_list_nft(accounts(4).into(), PROCEEDS);
+        contract.on_listing_payout(accounts(4).into(), PROCEEDS.into(), payout(PROCEEDS - ROYALTY));
+
+        contract
+    }
+
+    fn payout(vault_payout: Balance) -> Payout {
+        let mut payout = HashMap::new();
+        payout.insert(accounts(0).to_string(), U128(vault_payout));
+        payout.insert(accounts(5).to_string(), U128(ROYALTY));
+        Payout { payout }
+    }
+
+    fn nft_token(owner_id: ValidAccountId) -> Option<NftToken> {
+        Some(NftToken { owner_id: owner_id.into(), metadata: None })
+    }
+
+    #[test]
+    fn test_list_nft() {
+        let mut context = VMContextBuilder::new();
+        let contract = setup(&mut context);
+
+        let listing = contract.listing().unwrap();
+        assert_eq!(listing.market_id, accounts(4).to_string());
+        assert_eq!(listing.payout.0, PROCEEDS - ROYALTY);
+        let receipts = format!("{:?}", near_sdk::test_utils::get_created_receipts());
+        assert!(receipts.contains(&format!("receiver_id: \"{}\"", accounts(4))), "Receipts: {}", receipts);
+        assert!(receipts.contains("receiver_id: \"nft.near\""), "Receipts: {}", receipts);
+        assert!(receipts.contains(&format!("{:?}", b"nft_payout")), "Receipts: {}", receipts);
+    }
+
+    #[test]
+    #[should_panic(expected = "the vault is not paid by the NFT payout")]
+    fn test_list_nft_without_vault_payout() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        let mut payout = payout(0);
+        payout.payout.remove(&accounts(0).to_string());
+        contract.on_listing_payout(accounts(4).into(), PROCEEDS.into(), payout);
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT is rented until 100")]
+    fn test_list_nft_while_rented() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.rental = Some(Rental { renter_id: accounts(2).into(), rent: 0.into(), starts_at: 0.into(), ends_at: 100.into() });
+
+        contract.internal_list_nft(accounts(4).into(), PROCEEDS);
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT is rented until 100")]
+    fn test_market_sale_while_rented() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.rental = Some(Rental { renter_id: accounts(2).into(), rent: 0.into(), starts_at: 0.into(), ends_at: 100.into() });
+
+        contract.on_market_sale(nft_token(accounts(3)));
+    }
+
+    #[test]
+    fn test_market_sale_becomes_claim_vault() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        // Checkpoint storage prepaid by holders is not part of the proceeds
+        let storage_cost = Balance::from(env::storage_usage() + contract.checkpoints.unused_storage_usage) * env::storage_byte_cost();
+
+        testing_env!(context.storage_usage(env::storage_usage()).account_balance(storage_cost + PROCEEDS - ROYALTY).build());
+        assert!(contract.on_market_sale(nft_token(accounts(3))));
+
+        let metadata = contract.ft_metadata();
+        assert!(metadata.released);
+        assert_eq!(metadata.share_price.0, (PROCEEDS - ROYALTY) / TOTAL_SUPPLY);
+        assert_eq!(contract.vault_balance_of(accounts(1)).0, PROCEEDS - ROYALTY);
+        assert!(contract.listing().is_none());
+        assert!(contract.custody_verified_at().is_none());
+    }
+
+    #[test]
+    fn test_market_sale_not_sold_yet() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        assert!(!contract.on_market_sale(nft_token(accounts(0))));
+        assert!(!contract.ft_metadata().released);
+    }
+
+    #[test]
+    #[should_panic(expected = "sale proceeds not received yet")]
+    fn test_market_sale_before_payment() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        let storage_cost = Balance::from(env::storage_usage() + contract.checkpoints.unused_storage_usage) * env::storage_byte_cost();
+
+        testing_env!(context.storage_usage(env::storage_usage()).account_balance(storage_cost + PROCEEDS - ROYALTY - 1).build());
+        contract.on_market_sale(nft_token(accounts(3)));
+    }
+}
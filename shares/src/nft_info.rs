@@ -0,0 +1,216 @@
+use near_sdk::{
+    json_types::Base64VecU8,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::*;
+
+const GAS_FOR_REFRESH_NFT_METADATA: Gas = 20_000_000_000_000;
+const GAS_FOR_ON_NFT_METADATA: Gas = 15_000_000_000_000;
+/// Longest title, media, extra or reference cached. The vault pays for the cache, so longer fields are dropped
+pub const MAX_NFT_METADATA_FIELD_LEN: usize = 1_000;
+
+/// Token as returned by `nft_token`. Only the fields used by the vault are read
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftToken {
+    pub owner_id: AccountId,
+    #[serde(default)]
+    pub metadata: Option<NftTokenMetadata>,
+}
+
+/// Subset of the NEP-177 token metadata cached by the vault
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftTokenMetadata {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub media: Option<String>,
+    #[serde(default)]
+    pub media_hash: Option<Base64VecU8>,
+    #[serde(default)]
+    pub extra: Option<String>,
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+impl NftTokenMetadata {
+    /// Drop fields too long to cache, and hashes which are not SHA-256
+    fn bounded(self) -> Self {
+        let bounded = |field: Option<String>| field.filter(|field| field.len() <= MAX_NFT_METADATA_FIELD_LEN);
+        let sha256 = |hash: Option<Base64VecU8>| hash.filter(|hash| hash.0.len() == 32);
+
+        Self {
+            title: bounded(self.title),
+            media: bounded(self.media),
+            media_hash: sha256(self.media_hash),
+            extra: bounded(self.extra),
+            reference: bounded(self.reference),
+            reference_hash: sha256(self.reference_hash),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftInfo {
+    pub nft_contract_address: AccountId,
+    pub nft_token_id: TokenId,
+    /// None until the NFT contract answered
+    pub metadata: Option<NftTokenMetadata>,
+    pub updated_at: Option<U64>,
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Underlying NFT and its cached metadata
+    pub fn nft_info(&self) -> NftInfo {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        NftInfo {
+            nft_contract_address,
+            nft_token_id,
+            metadata: self.nft_metadata_cache.clone().map(|(metadata, _)| metadata),
+            updated_at: self.nft_metadata_cache.as_ref().map(|(_, updated_at)| (*updated_at).into()),
+        }
+    }
+
+    /// Fetch the underlying NFT metadata again, e.g. after it was updated on the NFT contract
+    pub fn refresh_nft_metadata(&mut self) -> Promise {
+        assert_prepaid_gas(GAS_FOR_REFRESH_NFT_METADATA + GAS_FOR_NFT_TOKEN + GAS_FOR_ON_NFT_METADATA);
+        self.internal_fetch_nft_metadata()
+    }
+
+    #[private]
+    pub fn on_nft_metadata(&mut self, #[callback] token: Option<NftToken>) -> Option<NftTokenMetadata> {
+        let metadata = token.expect("NFT not found").metadata.unwrap_or_default().bounded();
+        self.nft_metadata_cache = Some((metadata.clone(), env::block_timestamp()));
+
+        // Wallets showing the shares follow the NFT's own reference, or its media
+        let mut shares_metadata = self.ft_metadata();
+        let (reference, reference_hash) = vec![
+            (metadata.reference.clone(), metadata.reference_hash.clone()),
+            (metadata.media.clone(), metadata.media_hash.clone()),
+        ].into_iter()
+            .find(|(reference, hash)| reference.is_some() && hash.is_some())
+            .unwrap_or((None, None));
+        shares_metadata.reference = reference;
+        shares_metadata.reference_hash = reference_hash;
+        self.metadata.replace(&shares_metadata);
+
+        Some(metadata)
+    }
+}
+
+impl Shares {
+    pub(crate) fn internal_fetch_nft_metadata(&self) -> Promise {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        non_fungible_token_view::nft_token(
+            nft_token_id,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_TOKEN
+        ).then(shares::on_nft_metadata(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_NFT_METADATA
+        ))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            1_000.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        )
+    }
+
+    fn token(json: &str) -> Option<NftToken> {
+        near_sdk::serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_create_fetches_metadata() {
+        let mut context = VMContextBuilder::new();
+        let contract = setup(&mut context);
+
+        assert!(contract.nft_info().metadata.is_none());
+        let receipts = format!("{:?}", near_sdk::test_utils::get_created_receipts());
+        assert!(receipts.contains("receiver_id: \"nft.near\""), "Receipts: {}", receipts);
+    }
+
+    #[test]
+    fn test_on_nft_metadata() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.block_timestamp(42).build());
+        contract.on_nft_metadata(token(r#"{
+            "token_id": "0",
+            "owner_id": "alice",
+            "metadata": {
+                "title": "Kat",
+                "media": "https://example.com/kat.svg",
+                "media_hash": "AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA=",
+                "extra": null
+            }
+        }"#));
+
+        let info = contract.nft_info();
+        assert_eq!(info.metadata.unwrap().title, Some("Kat".to_string()));
+        assert_eq!(info.updated_at, Some(42.into()));
+        assert_eq!(contract.ft_metadata().reference, Some("https://example.com/kat.svg".to_string()));
+    }
+
+    #[test]
+    fn test_on_nft_metadata_without_hash() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        contract.on_nft_metadata(token(r#"{"owner_id": "alice", "metadata": {"media": "https://example.com/kat.svg"}}"#));
+
+        let metadata = contract.ft_metadata();
+        assert!(metadata.reference.is_none());
+        metadata.assert_valid();
+    }
+
+    #[test]
+    fn test_on_nft_metadata_drops_long_fields() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        let long = "a".repeat(MAX_NFT_METADATA_FIELD_LEN + 1);
+        let token = near_sdk::serde_json::json!({
+            "owner_id": "alice",
+            "metadata": { "title": "Kat", "media": long, "extra": long, "reference": long, "media_hash": "AQID" },
+        });
+        contract.on_nft_metadata(near_sdk::serde_json::from_value(token).unwrap());
+
+        let metadata = contract.nft_info().metadata.unwrap();
+        assert_eq!(metadata.title, Some("Kat".to_string()));
+        assert!(metadata.media.is_none() && metadata.extra.is_none() && metadata.reference.is_none());
+        assert!(metadata.media_hash.is_none());
+    }
+}
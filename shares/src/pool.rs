@@ -0,0 +1,146 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+const GAS_FOR_SYNC_POOL_PRICE: Gas = 20_000_000_000_000;
+const GAS_FOR_GET_POOL_INFO: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_POOL_INFO: Gas = 10_000_000_000_000;
+
+#[ext_contract(ext_pool)]
+pub trait SharesPool {
+    fn get_pool_info(&self) -> PoolReserves;
+}
+
+/// Reserves reported by the pool's `get_pool_info` view
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolReserves {
+    pub near_reserve: U128,
+    pub shares_reserve: U128,
+}
+
+/// Last pool price seen by the shares contract. A reference for buyouts, not an oracle
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolReference {
+    pub near_reserve: U128,
+    pub shares_reserve: U128,
+    /// NEAR per share, rounded down. Same unit as `share_price` in the metadata
+    pub share_price: U128,
+    pub updated_at: U64,
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Constant product pool deployed alongside this vault
+    pub fn pool_id(&self) -> Option<AccountId> {
+        self.pool_id.clone()
+    }
+
+    pub fn pool_reference(&self) -> Option<PoolReference> {
+        self.pool_reference.clone()
+    }
+
+    /// Fetch the current reserves of the pool and record them as the reference price
+    pub fn sync_pool_price(&mut self) -> Promise {
+        assert_prepaid_gas(GAS_FOR_SYNC_POOL_PRICE + GAS_FOR_GET_POOL_INFO + GAS_FOR_ON_POOL_INFO);
+        let pool_id = self.pool_id.as_ref().expect("vault has no pool");
+
+        ext_pool::get_pool_info(
+            pool_id,
+            0,
+            GAS_FOR_GET_POOL_INFO
+        ).then(shares::on_pool_info(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_POOL_INFO
+        ))
+    }
+
+    #[private]
+    pub fn on_pool_info(&mut self, #[callback] reserves: PoolReserves) -> PoolReference {
+        let PoolReserves { near_reserve, shares_reserve } = reserves;
+        let share_price = near_reserve.0.checked_div(shares_reserve.0).unwrap_or(0);
+
+        let reference = PoolReference {
+            near_reserve,
+            shares_reserve,
+            share_price: share_price.into(),
+            updated_at: env::block_timestamp().into(),
+        };
+        self.pool_reference = Some(reference.clone());
+        self.on_pool_price(share_price);
+
+        reference
+    }
+}
+
+impl Shares {
+    fn on_pool_price(&self, share_price: Balance) {
+        log!("{}", Event::PoolPrice { share_price, vault_id: env::current_account_id() });
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    const POOL_SHARES: Balance = 1_000;
+
+    fn create(pool_id: Option<ValidAccountId>, pool_shares: Option<U128>) -> Shares {
+        Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            pool_id,
+            pool_shares,
+            None
+        )
+    }
+
+    #[test]
+    fn test_create_with_pool_seed() {
+        testing_env!(VMContextBuilder::new().current_account_id(accounts(0)).build());
+        let contract = create(Some(accounts(2)), Some(POOL_SHARES.into()));
+
+        assert_eq!(contract.pool_id(), Some(accounts(2).into()));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, POOL_SHARES);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - POOL_SHARES);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "pool seed requires a pool")]
+    fn test_create_seed_without_pool() {
+        testing_env!(VMContextBuilder::new().current_account_id(accounts(0)).build());
+        create(None, Some(POOL_SHARES.into()));
+    }
+
+    #[test]
+    fn test_on_pool_info() {
+        let mut context = VMContextBuilder::new();
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = create(Some(accounts(2)), Some(POOL_SHARES.into()));
+
+        testing_env!(context.block_timestamp(42).build());
+        contract.on_pool_info(PoolReserves {
+            near_reserve: 25_000.into(),
+            shares_reserve: POOL_SHARES.into(),
+        });
+
+        let reference = contract.pool_reference().unwrap();
+        assert_eq!(reference.share_price.0, 25);
+        assert_eq!(reference.updated_at.0, 42);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::*;
+
+impl Shares {
+    /// Report the share balances of `account_ids` to the factory, which indexes the portfolio of
+    /// each account. Shares escrowed by the vault itself are left out
+    pub(crate) fn report_holdings(&self, account_ids: &[&AccountId]) {
+        let vault_id = env::current_account_id();
+        let holdings: Vec<(AccountId, U128)> = account_ids.iter()
+            .filter(|account_id| ***account_id != vault_id)
+            .map(|account_id| ((*account_id).clone(), self.token.accounts.get(account_id).unwrap_or(0).into()))
+            .collect();
+
+        if !holdings.is_empty() {
+            self.internal_report(holdings);
+        }
+    }
+
+    /// Report a new share price to the factory, which values portfolios with it
+    pub(crate) fn report_share_price(&self) {
+        self.internal_report(vec![]);
+    }
+
+    fn internal_report(&self, holdings: Vec<(AccountId, U128)>) {
+        ext_factory::on_holdings_changed(
+            holdings,
+            self.ft_metadata().share_price,
+            &self.factory_id,
+            0,
+            GAS_FOR_HOLDINGS_REPORT
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{MockedBlockchain, PromiseResult};
+    use near_sdk::testing_env;
+    use near_sdk::serde_json::{self, json, Value};
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    /// Arguments of the reports sent to the factory, accounts(5)
+    fn reports() -> Vec<Value> {
+        // Refunds of large deposits don't fit in JSON numbers and are skipped
+        get_created_receipts().iter()
+            .filter_map(|receipt| serde_json::to_value(receipt).ok())
+            .filter(|receipt| receipt["receiver_id"] == accounts(5).to_string())
+            .map(|receipt| receipt["actions"][0]["FunctionCall"].clone())
+            .filter(|call| call["method_name"] == "on_holdings_changed")
+            .map(|call| serde_json::from_str(call["args"].as_str().unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_report_holdings() {
+        let mut context = VMContextBuilder::new();
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(5)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            1_000.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        assert_eq!(reports(), vec![json!({ "holdings": [[accounts(1), "1000"]], "share_price": "10" })]);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+        assert!(reports().is_empty());
+
+        // A transfer reports both balances at once
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1).predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(2), 1_000.into(), None);
+        assert_eq!(reports(), vec![json!({ "holdings": [[accounts(1), "0"], [accounts(2), "1000"]], "share_price": "10" })]);
+
+        // Shares escrowed for a sale stay out of the index
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(10_000_000_000_000_000_000_000)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.curator = accounts(2).into();
+        contract.start_sale(100.into(), 1.into(), 0.into(), 10.into(), None, None, None);
+        assert_eq!(reports(), vec![json!({ "holdings": [[accounts(2), "900"]], "share_price": "10" })]);
+
+        // Shares refunded by the receiver of `ft_transfer_call` are reported when resolved
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1).build());
+        contract.ft_transfer(accounts(1), 300.into(), None);
+        assert_eq!(reports(), vec![json!({ "holdings": [[accounts(2), "600"], [accounts(1), "300"]], "share_price": "10" })]);
+        testing_env!(
+            context.storage_usage(env::storage_usage()).attached_deposit(0).predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"100\"".to_vec())]
+        );
+        contract.ft_resolve_transfer(accounts(2), accounts(1), 300.into());
+        assert_eq!(reports(), vec![json!({ "holdings": [[accounts(2), "700"], [accounts(1), "200"]], "share_price": "10" })]);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::*;
+
+#[near_bindgen]
+impl Shares {
+    /// Shares burnt, NEAR to attach and costs of `redeem` if `account_id` called it now, and
+    /// whether it would succeed. Unlike `redeem_amount_of`, it answers once the NFT is redeemed
+    pub fn redeem_quote(&self, account_id: ValidAccountId) -> RedeemQuote {
+        let account_id: AccountId = account_id.into();
+        let released = self.ft_metadata().released;
+        let redeemable_at = self.earliest_redeem_at().into();
+
+        if released {
+            return RedeemQuote {
+                shares_burnt: 0.into(),
+                near_required: 0.into(),
+                nft_transfer_deposit: 0.into(),
+                storage_cost: 0.into(),
+                refund: 0.into(),
+                redeemable_at,
+                redeemable: false,
+                reason: self.redeem_error(),
+            };
+        }
+
+        let shares_burnt = self.redeem_shares_of(&account_id);
+        let near_required = self.redeem_amount_due(&account_id);
+
+        // Burning every share deletes the vault in favour of the redeemer, unless dividends are left to withdraw
+        let deletes_vault = shares_burnt == self.token.total_supply && self.dividends.all_paid();
+        let balance_after = (env::account_balance() + near_required).saturating_sub(NFT_TRANSFER_DEPOSIT);
+        let (storage_cost, refund) = if deletes_vault {
+            (0, balance_after)
+        } else {
+            (Balance::from(self.redeem_storage_usage) * env::storage_byte_cost(), 0)
+        };
+
+        let storage_needed = Balance::from(env::storage_usage()) * env::storage_byte_cost() + storage_cost;
+        let reason = self.redeem_error().or_else(|| {
+            if !deletes_vault && balance_after < storage_needed {
+                Some("vault balance does not cover its storage".to_string())
+            } else {
+                None
+            }
+        });
+
+        RedeemQuote {
+            shares_burnt: shares_burnt.into(),
+            near_required: near_required.into(),
+            nft_transfer_deposit: NFT_TRANSFER_DEPOSIT.into(),
+            storage_cost: storage_cost.into(),
+            refund: refund.into(),
+            redeemable_at,
+            redeemable: reason.is_none(),
+            reason,
+        }
+    }
+}
+
+impl Shares {
+    /// Shares `account_id` burns by redeeming. Unsold shares go back to the seller before the exit price is computed
+    fn redeem_shares_of(&self, account_id: &AccountId) -> Balance {
+        let unsold_shares = match &self.sale {
+            Some(sale) if !sale.closed && &sale.seller == account_id => sale.shares_left,
+            _ => 0,
+        };
+        self.token.accounts.get(account_id).unwrap_or(0) + unsold_shares
+    }
+
+    /// NEAR `account_id` must attach to redeem, paying for the shares it doesn't burn
+    pub(crate) fn redeem_amount_due(&self, account_id: &AccountId) -> Balance {
+        let share_price = self.ft_metadata().share_price.0;
+        checked_value(self.token.total_supply - self.redeem_shares_of(account_id), share_price)
+    }
+
+    /// Why `redeem` would fail whatever the payment, if it would
+    pub(crate) fn redeem_error(&self) -> Option<String> {
+        if self.ft_metadata().released {
+            Some("token already redeemed".to_string())
+        } else {
+            self.lock_error()
+        }
+    }
+
+    /// End of the minimum holding period or of the current rental, whichever is later
+    fn earliest_redeem_at(&self) -> u64 {
+        let rental_end = self.rental().map(|rental| rental.ends_at.0).unwrap_or(0);
+        self.redeemable_at().0.max(rental_end)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000;
+    const SHARE_PRICE: Balance = 10;
+    const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+    /// Vault owned by accounts(1), who sent 400 shares to accounts(2)
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
+        );
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(1).predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(2), 400.into(), None);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).build());
+
+        contract
+    }
+
+    #[test]
+    fn test_quote_matches_redeem() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        contract.custody_verified_at = Some(0);
+        let quote = contract.redeem_quote(accounts(1));
+        assert!(quote.redeemable, "{:?}", quote.reason);
+        assert_eq!((quote.shares_burnt.0, quote.near_required.0), (600, 400 * SHARE_PRICE));
+        assert_eq!(quote.near_required, contract.redeem_amount_of(accounts(1)));
+        assert_eq!((quote.nft_transfer_deposit.0, quote.refund.0), (NFT_TRANSFER_DEPOSIT, 0));
+
+        // The storage estimate covers what the redemption adds
+        let storage_usage = env::storage_usage();
+        testing_env!(context.attached_deposit(quote.near_required.0).build());
+        contract.redeem();
+        let added = env::storage_usage() - storage_usage;
+        assert!(Balance::from(added) * env::storage_byte_cost() <= quote.storage_cost.0);
+
+        // Once redeemed the quote explains why, where `redeem_amount_of` panics
+        let quote = contract.redeem_quote(accounts(2));
+        assert_eq!((quote.redeemable, quote.reason.as_deref()), (false, Some("token already redeemed")));
+        assert_eq!(quote.near_required.0, 0);
+    }
+
+    #[test]
+    fn test_quote_for_every_share() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.custody_verified_at = Some(0);
+        testing_env!(context.attached_deposit(1).predecessor_account_id(accounts(2)).build());
+        contract.ft_transfer(accounts(1), 400.into(), None);
+
+        // The vault is deleted and its balance goes to the redeemer
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).account_balance(3 * NEAR).build());
+        let quote = contract.redeem_quote(accounts(1));
+        assert!(quote.redeemable);
+        assert_eq!((quote.shares_burnt.0, quote.near_required.0, quote.storage_cost.0), (TOTAL_SUPPLY, 0, 0));
+        assert_eq!(quote.refund.0, 3 * NEAR - NFT_TRANSFER_DEPOSIT);
+    }
+
+    #[test]
+    fn test_quote_includes_unsold_shares() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.custody_verified_at = Some(0);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(NEAR / 100).build());
+        contract.start_sale(100.into(), 1.into(), 0.into(), 10.into(), None, None, None);
+
+        let quote = contract.redeem_quote(accounts(1));
+        assert_eq!((quote.shares_burnt.0, quote.near_required.0), (600, 400 * SHARE_PRICE));
+        assert_eq!(contract.redeem_quote(accounts(2)).shares_burnt.0, 400);
+    }
+
+    #[test]
+    fn test_seller_redeems_quoted_amount_during_sale() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.custody_verified_at = Some(0);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(NEAR / 100).build());
+        contract.start_sale(100.into(), 1.into(), 0.into(), 10.into(), None, None, None);
+        contract.custody_verified_at = None;
+
+        // Custody is checked first, with the payment counted against the unsold shares too
+        let payment = contract.redeem_quote(accounts(1)).near_required.0;
+        assert_eq!(contract.redeem_amount_of(accounts(1)).0, payment);
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(payment).build());
+        contract.redeem();
+
+        let token = near_sdk::serde_json::to_vec(&near_sdk::serde_json::json!({ "owner_id": accounts(0) })).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![near_sdk::PromiseResult::Successful(token)]
+        );
+        assert!(contract.on_redeem_custody_check(accounts(1).into(), payment.into()));
+        assert!(contract.ft_metadata().released);
+        assert_eq!(contract.vault_balance().0, payment);
+    }
+
+    #[test]
+    fn test_quote_when_locked_or_underfunded() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.custody_verified_at = Some(0);
+        let mut metadata = contract.ft_metadata();
+        metadata.min_lock_until = Some(100.into());
+        contract.metadata.replace(&metadata);
+
+        let quote = contract.redeem_quote(accounts(2));
+        assert_eq!((quote.redeemable_at.0, quote.reason.as_deref()), (100, Some("vault is locked until 100")));
+
+        // The payment lands on the vault, but may not cover the storage a poor vault needs
+        testing_env!(context.block_timestamp(100).account_balance(0).build());
+        let quote = contract.redeem_quote(accounts(2));
+        assert_eq!(quote.reason.as_deref(), Some("vault balance does not cover its storage"));
+    }
+}
@@ -0,0 +1,286 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Deposit the NFT contract requires on `nft_set_user`
+const NFT_SET_USER_DEPOSIT: Balance = 1;
+const GAS_FOR_NFT_SET_USER: Gas = 10_000_000_000_000;
+
+/// Rental terms approved by shareholders
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RentalOffer {
+    pub renter_id: AccountId,
+    /// Rent in yoctoNEAR, paid up front
+    pub rent: U128,
+    /// Rental term in nanoseconds
+    pub duration: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Rental {
+    pub renter_id: AccountId,
+    pub rent: U128,
+    pub starts_at: U64,
+    pub ends_at: U64,
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Rent the NFT on the terms approved by shareholders. The rent is distributed as dividends.
+    /// The renter becomes the NFT's user until the rental ends, and attaches 1 yoctoNEAR on top of the rent for it
+    #[payable]
+    pub fn rent(&mut self) -> Rental {
+        assert_prepaid_gas(GAS_FOR_NFT_SET_USER);
+        assert!(!self.ft_metadata().released, "token already redeemed");
+        assert!(self.rental().is_none(), "NFT is already rented");
+        // A market sale during the rental could not release the NFT
+        assert!(self.listing.is_none(), "NFT is listed for sale");
+        self.assert_custody();
+
+        let renter_id = env::predecessor_account_id();
+        let RentalOffer { renter_id: approved_renter, rent, duration } = self.rental_offer.clone().expect("no rental offer");
+        assert_eq!(renter_id, approved_renter, "only the approved renter can rent");
+        assert!(env::attached_deposit() >= rent.0 + NFT_SET_USER_DEPOSIT, "insufficient payment amount");
+
+        let change = env::attached_deposit() - rent.0 - NFT_SET_USER_DEPOSIT;
+        if change > 0 {
+            Promise::new(renter_id.clone()).transfer(change);
+        }
+
+        let now = env::block_timestamp();
+        let rental = Rental {
+            renter_id: renter_id.clone(),
+            rent,
+            starts_at: now.into(),
+            ends_at: (now + duration.0).into(),
+        };
+        self.rental = Some(rental.clone());
+        self.rental_offer = None;
+        self.internal_set_nft_user(Some(renter_id.clone()), rental.ends_at);
+
+        if rent.0 > 0 {
+            self.internal_distribute(None, rent.0);
+        }
+        self.on_nft_rented(renter_id, rent.0, rental.ends_at.0);
+
+        rental
+    }
+
+    /// Revoke the renter's usage rights on the NFT once the rental is over. Anyone can call it,
+    /// attaching 1 yoctoNEAR for the NFT contract
+    #[payable]
+    pub fn end_rental(&mut self) {
+        assert_prepaid_gas(GAS_FOR_NFT_SET_USER);
+        assert_one_yocto();
+        let rental = self.rental.as_ref().expect("NFT is not rented");
+        assert!(env::block_timestamp() >= rental.ends_at.0, "NFT is rented until {}", rental.ends_at.0);
+
+        let renter_id = rental.renter_id.clone();
+        self.rental = None;
+        self.internal_set_nft_user(None, 0.into());
+        self.on_rental_ended(renter_id);
+    }
+
+    /// Active rental. Rentals end on their own once the term is over
+    pub fn rental(&self) -> Option<Rental> {
+        self.rental.clone().filter(|rental| env::block_timestamp() < rental.ends_at.0)
+    }
+
+    pub fn rental_offer(&self) -> Option<RentalOffer> {
+        self.rental_offer.clone()
+    }
+
+    /// Account with usage rights on the NFT, the renter during a rental.
+    /// Usage rights are granted with `nft_set_user` rather than `nft_approve`, since approvals allow transfers
+    pub fn nft_user(&self) -> Option<AccountId> {
+        self.rental().map(|rental| rental.renter_id)
+    }
+}
+
+impl Shares {
+    fn internal_set_nft_user(&self, user_id: Option<AccountId>, expires_at: U64) {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+        non_fungible_token_user::nft_set_user(
+            nft_token_id,
+            user_id.map(|user_id| user_id.try_into().unwrap()),
+            expires_at,
+            &nft_contract_address,
+            NFT_SET_USER_DEPOSIT,
+            GAS_FOR_NFT_SET_USER
+        );
+    }
+
+    fn on_rental_ended(&self, renter_id: AccountId) {
+        log!("NftRentalEnded({}, {})", renter_id, env::current_account_id());
+    }
+
+    fn on_nft_rented(&self, renter_id: AccountId, rent: Balance, ends_at: u64) {
+        log!("NftRented({}, {}, {}, {})", renter_id, rent, ends_at, env::current_account_id());
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000;
+    const RENT: Balance = 5_000;
+    const DURATION: u64 = 1_000;
+
+    /// Vault offering accounts(3) to rent the NFT
+    fn setup_offer(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1)).signer_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+        contract.rental_offer = Some(RentalOffer {
+            renter_id: accounts(3).into(),
+            rent: RENT.into(),
+            duration: DURATION.into(),
+        });
+
+        contract
+    }
+
+    fn rent(context: &mut VMContextBuilder, contract: &mut Shares) {
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(RENT + NFT_SET_USER_DEPOSIT)
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(100)
+            .build());
+        contract.rent();
+    }
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        let mut contract = setup_offer(context);
+        rent(context, &mut contract);
+        contract
+    }
+
+    /// Arguments of the `nft_set_user` call sent to the NFT contract
+    fn set_user_args() -> near_sdk::serde_json::Value {
+        let receipts = near_sdk::serde_json::to_value(get_created_receipts()).unwrap();
+        let call = receipts.as_array().unwrap().iter()
+            .filter(|receipt| receipt["receiver_id"] == "nft.near")
+            .map(|receipt| &receipt["actions"][0]["FunctionCall"])
+            .find(|call| call["method_name"] == "nft_set_user")
+            .expect("no nft_set_user call");
+        near_sdk::serde_json::from_str(call["args"].as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rent() {
+        let mut context = VMContextBuilder::new();
+        let contract = setup(&mut context);
+
+        assert_eq!(contract.nft_user(), Some(accounts(3).into()));
+        assert_eq!(contract.rental().unwrap().ends_at.0, 100 + DURATION);
+        assert!(contract.rental_offer().is_none());
+        assert_eq!(contract.dividends_of(accounts(1), None).0, RENT);
+
+        let args = set_user_args();
+        assert_eq!(args["user_id"], accounts(3).to_string());
+        assert_eq!(args["expires_at"], (100 + DURATION).to_string());
+    }
+
+    #[test]
+    fn test_end_rental() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(1).predecessor_account_id(accounts(2)).block_timestamp(100 + DURATION).build());
+        contract.end_rental();
+
+        assert!(contract.rental.is_none());
+        assert!(set_user_args()["user_id"].is_null());
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT is rented until 1100")]
+    fn test_end_rental_too_early() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(1).predecessor_account_id(accounts(2)).block_timestamp(100 + DURATION - 1).build());
+        contract.end_rental();
+    }
+
+    #[test]
+    fn test_rental_expires() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context
+            .attached_deposit(TOTAL_SUPPLY * 10)
+            .predecessor_account_id(accounts(2))
+            .signer_account_id(accounts(2))
+            .block_timestamp(100 + DURATION)
+            .build());
+        assert!(contract.nft_user().is_none());
+        contract.redeem();
+        assert!(contract.ft_metadata().released);
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT is rented until 1100")]
+    fn test_redeem_during_rental() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(0).predecessor_account_id(accounts(1)).signer_account_id(accounts(1)).build());
+        contract.redeem();
+    }
+
+    #[test]
+    #[should_panic(expected = "only the approved renter can rent")]
+    fn test_rent_not_approved() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        contract.rental_offer = Some(RentalOffer {
+            renter_id: accounts(3).into(),
+            rent: RENT.into(),
+            duration: DURATION.into(),
+        });
+        testing_env!(context.predecessor_account_id(accounts(4)).block_timestamp(100 + DURATION).build());
+        contract.rent();
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT is listed for sale")]
+    fn test_rent_while_listed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup_offer(&mut context);
+        contract.listing = Some(Listing { market_id: accounts(4).into(), price: 1.into(), payout: 1.into(), listed_at: 0.into() });
+
+        rent(&mut context, &mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "custody not verified")]
+    fn test_rent_without_custody() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup_offer(&mut context);
+        contract.custody_verified_at = None;
+
+        rent(&mut context, &mut contract);
+    }
+}
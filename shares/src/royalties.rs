@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use near_sdk::{Gas, serde::Deserialize};
+use fractose_common::refund_deposit;
+
+use crate::*;
+
+pub(crate) const GAS_FOR_NFT_PAYOUT: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_NFT_PAYOUT: Gas = 10_000_000_000_000;
+
+/// Transfer message of NEP-141 royalties
+pub const ROYALTY_MESSAGE: &str = "royalty";
+
+#[ext_contract(ext_nft_payout)]
+pub trait NonFungibleTokenPayout {
+    fn nft_payout(&self, token_id: TokenId, balance: U128, max_len_payout: u32) -> Payout;
+}
+
+/// NEP-199 payout of a sale, by receiving account
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Receive NEAR royalties of the underlying NFT. They are distributed as dividends
+    #[payable]
+    pub fn deposit_royalty(&mut self) {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "no royalty attached");
+        self.internal_receive_royalty(None, amount);
+    }
+
+    /// Royalty the vault would receive if the underlying NFT were sold for `balance`,
+    /// as reported by `nft_payout` on the NFT contract
+    pub fn expected_royalty(&self, balance: U128, max_len_payout: u32) -> Promise {
+        let SharesMetadata { nft_contract_address, nft_token_id, .. } = self.ft_metadata();
+
+        ext_nft_payout::nft_payout(
+            nft_token_id,
+            balance,
+            max_len_payout,
+            &nft_contract_address,
+            0,
+            GAS_FOR_NFT_PAYOUT
+        ).then(shares::on_nft_payout(
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_NFT_PAYOUT
+        ))
+    }
+
+    /// Accept NEP-141 royalties in `token_id`. Royalties in other tokens are refunded to the sender.
+    /// The attached deposit pays for storage
+    #[payable]
+    pub fn allow_royalty_token(&mut self, token_id: ValidAccountId) {
+        assert_eq!(env::predecessor_account_id(), self.curator, "only the curator can allow royalty tokens");
+        let initial_storage_usage = env::storage_usage();
+        let token_id: AccountId = token_id.into();
+        assert!(!self.royalty_tokens.contains(&token_id), "token is already allowed");
+
+        // Every allowed token must fit among the dividend tokens, so royalties never hit the limit
+        let dividend_tokens = self.royalty_tokens.iter().chain(std::iter::once(&token_id))
+            .filter(|token| !self.dividends.tokens.contains(token))
+            .count() + self.dividends.tokens.len();
+        assert!(dividend_tokens <= dividends::MAX_DIVIDEND_TOKENS, "too many dividend tokens");
+
+        self.royalty_tokens.push(token_id);
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+    }
+
+    /// Stop accepting NEP-141 royalties in `token_id`. Dividends already paid in it stay withdrawable
+    pub fn disallow_royalty_token(&mut self, token_id: ValidAccountId) {
+        assert_eq!(env::predecessor_account_id(), self.curator, "only the curator can disallow royalty tokens");
+        let token_id: AccountId = token_id.into();
+        let len = self.royalty_tokens.len();
+        self.royalty_tokens.retain(|token| token != &token_id);
+        assert!(self.royalty_tokens.len() < len, "token is not allowed");
+    }
+
+    /// NEP-141 tokens accepted as royalties
+    pub fn royalty_tokens(&self) -> Vec<AccountId> {
+        self.royalty_tokens.clone()
+    }
+
+    #[private]
+    pub fn on_nft_payout(&mut self, #[callback] payout: Payout) -> U128 {
+        payout.payout.get(&env::current_account_id()).copied().unwrap_or(U128(0))
+    }
+}
+
+impl Shares {
+    /// Royalties keep going to holders who did not claim yet after a buyout
+    pub(crate) fn internal_receive_royalty(&mut self, token_id: Option<AccountId>, amount: Balance) {
+        self.on_royalty_received(dividends::asset_name(&token_id), amount);
+        self.internal_distribute(token_id, amount);
+    }
+
+    fn on_royalty_received(&self, asset: &str, amount: Balance) {
+        log!("RoyaltyReceived({}, {}, {})", asset, amount, env::current_account_id());
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    const STORAGE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        context.current_account_id(accounts(0)).predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            10.into(),
+            None,
+            None,
+            None,
+            None
+        )
+    }
+
+    #[test]
+    fn test_near_royalty() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(500).predecessor_account_id(accounts(3)).build());
+        contract.deposit_royalty();
+
+        assert_eq!(contract.dividends_of(accounts(1), None).0, 500);
+        assert_eq!(near_sdk::test_utils::get_logs()[0], format!("RoyaltyReceived(NEAR, 500, {})", accounts(0)));
+    }
+
+    #[test]
+    fn test_token_royalty() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(STORAGE_DEPOSIT).build());
+        contract.allow_royalty_token(accounts(4));
+
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(0).build());
+        let unused = contract.ft_on_transfer(accounts(3), 300.into(), ROYALTY_MESSAGE.to_string());
+
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+        assert_eq!(contract.dividend_tokens(), vec![accounts(4).to_string()]);
+        assert_eq!(contract.dividends_of(accounts(1), Some(accounts(4))).0, 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "token is not accepted for royalties")]
+    fn test_token_royalty_not_allowed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(STORAGE_DEPOSIT).build());
+        contract.allow_royalty_token(accounts(4));
+        contract.disallow_royalty_token(accounts(4));
+
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(0).build());
+        contract.ft_on_transfer(accounts(3), 300.into(), ROYALTY_MESSAGE.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "only the curator can allow royalty tokens")]
+    fn test_allow_royalty_token_not_curator() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(STORAGE_DEPOSIT).build());
+        contract.allow_royalty_token(accounts(4));
+    }
+
+    #[test]
+    #[should_panic(expected = "too many dividend tokens")]
+    fn test_allow_too_many_royalty_tokens() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(STORAGE_DEPOSIT).build());
+        for i in 0..=dividends::MAX_DIVIDEND_TOKENS {
+            contract.allow_royalty_token(format!("token{}.near", i).try_into().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_on_nft_payout() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let mut payout = HashMap::new();
+        payout.insert(accounts(0).to_string(), U128(250));
+        payout.insert(accounts(1).to_string(), U128(9_750));
+
+        assert_eq!(contract.on_nft_payout(Payout { payout }).0, 250);
+    }
+}
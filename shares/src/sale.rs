@@ -0,0 +1,637 @@
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::{
+    Gas, PromiseResult,
+    collections::{LookupMap, LookupSet},
+    serde::Serialize,
+};
+use fractose_common::refund_deposit;
+
+use crate::*;
+
+/// Share of sale proceeds paid to the Fractose factory, in basis points
+pub const PROTOCOL_FEE_BPS: u128 = 100;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_PROCEEDS_TRANSFER: Gas = 10_000_000_000_000;
+
+/// Fixed price sale of shares escrowed from the seller
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Sale {
+    /// Sales of a vault are numbered from 0. Each one keeps its allowlist and purchases apart
+    pub id: u32,
+    pub seller: AccountId,
+    /// NEP-141 token accepted as payment. NEAR if None
+    pub payment_token: Option<AccountId>,
+    /// Price of one share in the payment token
+    pub price: Balance,
+    pub start_at: u64,
+    pub end_at: u64,
+    pub max_per_account: Option<Balance>,
+    pub allowlist: Option<LookupSet<AccountId>>,
+    pub purchased: LookupMap<AccountId, Balance>,
+    pub shares_sold: Balance,
+    /// Shares still held in escrow by the shares contract
+    pub shares_left: Balance,
+    pub closed: bool,
+    /// NEP-141 proceeds and protocol fee whose transfer failed, paid again by `pay_unpaid_proceeds`
+    pub unpaid_proceeds: Balance,
+    pub unpaid_fee: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleInfo {
+    pub seller: AccountId,
+    pub payment_token: Option<AccountId>,
+    pub price: U128,
+    pub start_at: U64,
+    pub end_at: U64,
+    pub max_per_account: Option<U128>,
+    pub has_allowlist: bool,
+    pub shares_sold: U128,
+    pub shares_left: U128,
+    pub closed: bool,
+    pub unpaid_proceeds: U128,
+    pub unpaid_fee: U128,
+}
+
+impl Sale {
+    fn is_active(&self) -> bool {
+        let now = env::block_timestamp();
+        !self.closed && self.start_at <= now && now < self.end_at
+    }
+}
+
+#[near_bindgen]
+impl Shares {
+    /// Put a part of the curator's shares up for sale at a fixed price. A new sale can start once
+    /// the previous one is closed. The attached deposit pays for the storage of the sale, such as
+    /// its allowlist, and the rest is refunded
+    ///
+    /// # Parameters
+    ///
+    /// - `amount`: Number of shares to sell. They are escrowed by the shares contract until the sale ends
+    /// - `price`: Price of one share in NEAR or the payment token
+    /// - `start_at`, `end_at`: Block timestamps in nanoseconds between which shares can be bought
+    /// - `max_per_account`: Optional limit on shares bought by a single account
+    /// - `allowlist`: If present, only these accounts can buy
+    /// - `payment_token`: NEP-141 token accepted as payment. NEAR is used if absent
+    #[payable]
+    pub fn start_sale(
+        &mut self,
+        amount: U128,
+        price: U128,
+        start_at: U64,
+        end_at: U64,
+        max_per_account: Option<U128>,
+        allowlist: Option<Vec<ValidAccountId>>,
+        payment_token: Option<ValidAccountId>,
+    ) {
+        let seller = env::predecessor_account_id();
+        assert_eq!(seller, self.curator, "only the curator can start a sale");
+        assert!(!self.ft_metadata().released, "token already redeemed");
+        let id = match &self.sale {
+            Some(sale) => {
+                assert!(sale.closed, "a sale is already open");
+                assert!(sale.unpaid_proceeds == 0 && sale.unpaid_fee == 0, "proceeds of the previous sale are unpaid");
+                sale.id + 1
+            }
+            None => 0,
+        };
+        let initial_storage_usage = env::storage_usage();
+
+        assert!(amount.0 > 0, "invalid sale amount");
+        assert!(price.0 > 0, "invalid sale price");
+        assert!(start_at.0 < end_at.0, "sale must end after it starts");
+        checked_value(amount.0, price.0);
+
+        let allowlist = allowlist.map(|accounts| {
+            let mut set = LookupSet::new(StorageKey::SaleAllowlist { sale_id: id });
+            for account in accounts {
+                set.insert(account.as_ref());
+            }
+            set
+        });
+
+        // Escrow shares for sale
+        let vault = env::current_account_id();
+        if !self.token.accounts.contains_key(&vault) {
+            self.token.internal_register_account(&vault);
+        }
+        self.internal_transfer_shares(&seller, &vault, amount.0);
+
+        self.sale = Some(Sale {
+            id,
+            seller: seller.clone(),
+            payment_token: payment_token.map(|token| token.into()),
+            price: price.0,
+            start_at: start_at.0,
+            end_at: end_at.0,
+            max_per_account: max_per_account.map(|max| max.0),
+            allowlist,
+            purchased: LookupMap::new(StorageKey::SalePurchases { sale_id: id }),
+            shares_sold: 0,
+            shares_left: amount.0,
+            closed: false,
+            unpaid_proceeds: 0,
+            unpaid_fee: 0,
+        });
+
+        refund_deposit(env::storage_usage().saturating_sub(initial_storage_usage));
+        self.on_sale_started(seller, amount, price);
+    }
+
+    /// Buy shares from a NEAR denominated sale. Payment in excess of whole shares is refunded
+    #[payable]
+    pub fn buy_shares(&mut self) -> U128 {
+        let sale = self.sale.as_ref().expect("no sale");
+        assert!(sale.payment_token.is_none(), "sale accepts NEP-141 payment");
+
+        let buyer = env::predecessor_account_id();
+        let payment = env::attached_deposit();
+        let (shares, cost) = self.internal_buy(&buyer, payment);
+
+        let refund = payment - cost;
+        if refund > 0 {
+            Promise::new(buyer).transfer(refund);
+        }
+        self.pay_sale_proceeds(cost);
+
+        shares.into()
+    }
+
+    /// Close the sale and return unsold shares to the seller.
+    /// The curator can end the sale at any time, other accounts only once it is over
+    pub fn end_sale(&mut self) {
+        let sale = self.sale.as_ref().expect("no sale");
+        assert!(!sale.closed, "sale already closed");
+        assert!(
+            env::predecessor_account_id() == self.curator || env::block_timestamp() >= sale.end_at,
+            "sale is not over"
+        );
+
+        self.internal_close_sale();
+    }
+
+    pub fn sale_info(&self) -> Option<SaleInfo> {
+        self.sale.as_ref().map(|sale| SaleInfo {
+            seller: sale.seller.clone(),
+            payment_token: sale.payment_token.clone(),
+            price: sale.price.into(),
+            start_at: sale.start_at.into(),
+            end_at: sale.end_at.into(),
+            max_per_account: sale.max_per_account.map(|max| max.into()),
+            has_allowlist: sale.allowlist.is_some(),
+            shares_sold: sale.shares_sold.into(),
+            shares_left: sale.shares_left.into(),
+            closed: sale.closed,
+            unpaid_proceeds: sale.unpaid_proceeds.into(),
+            unpaid_fee: sale.unpaid_fee.into(),
+        })
+    }
+
+    /// Send again the NEP-141 proceeds and protocol fee whose transfer failed, e.g. because the
+    /// seller was not registered with the token
+    pub fn pay_unpaid_proceeds(&mut self) {
+        let sale = self.sale.as_mut().expect("no sale");
+        let token = sale.payment_token.clone().expect("sale accepts NEAR");
+        let (seller, proceeds, fee) = (sale.seller.clone(), sale.unpaid_proceeds, sale.unpaid_fee);
+        assert!(proceeds > 0 || fee > 0, "no unpaid proceeds");
+        sale.unpaid_proceeds = 0;
+        sale.unpaid_fee = 0;
+
+        if fee > 0 {
+            self.transfer_proceeds(&token, self.factory_id.clone(), fee, true);
+        }
+        if proceeds > 0 {
+            self.transfer_proceeds(&token, seller, proceeds, false);
+        }
+    }
+
+    /// Keep NEP-141 proceeds whose transfer failed so that they can be paid again
+    #[private]
+    pub fn on_proceeds_transfer(&mut self, receiver_id: AccountId, amount: U128, fee: bool) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            let sale = self.sale.as_mut().expect("no sale");
+            if fee {
+                sale.unpaid_fee += amount.0;
+            } else {
+                sale.unpaid_proceeds += amount.0;
+            }
+            log!("SaleProceedsUnpaid({}, {}, {})", receiver_id, amount.0, env::current_account_id());
+        }
+    }
+
+    /// Shares bought by an account in the sale
+    pub fn sale_purchased_of(&self, account_id: ValidAccountId) -> U128 {
+        self.sale.as_ref()
+            .and_then(|sale| sale.purchased.get(account_id.as_ref()))
+            .unwrap_or(0)
+            .into()
+    }
+}
+
+impl Shares {
+    /// Transfer as many whole shares as `payment` covers to `buyer`.
+    /// Returns the shares bought and their cost in the payment token
+    pub(crate) fn internal_buy(&mut self, buyer: &AccountId, payment: Balance) -> (Balance, Balance) {
+        let vault = env::current_account_id();
+        let sale = self.sale.as_mut().expect("no sale");
+        assert!(sale.is_active(), "sale is not active");
+        if let Some(allowlist) = &sale.allowlist {
+            assert!(allowlist.contains(buyer), "account is not on the sale allowlist");
+        }
+        assert!(self.token.accounts.contains_key(buyer), "The account {} is not registered", buyer);
+
+        let purchased = sale.purchased.get(buyer).unwrap_or(0);
+        let mut shares = std::cmp::min(payment / sale.price, sale.shares_left);
+        if let Some(max_per_account) = sale.max_per_account {
+            shares = std::cmp::min(shares, max_per_account.saturating_sub(purchased));
+        }
+        assert!(shares > 0, "payment does not cover any shares");
+        let cost = checked_value(shares, sale.price);
+
+        sale.purchased.insert(buyer, &(purchased + shares));
+        sale.shares_sold += shares;
+        sale.shares_left -= shares;
+
+        self.internal_transfer_shares(&vault, buyer, shares);
+        self.on_shares_bought(buyer.clone(), shares, cost);
+
+        (shares, cost)
+    }
+
+    /// Return unsold shares to the seller. A no-op if there is no open sale
+    pub(crate) fn internal_close_sale(&mut self) {
+        let vault = env::current_account_id();
+        let sale = match self.sale.as_mut() {
+            Some(sale) if !sale.closed => sale,
+            _ => return,
+        };
+
+        sale.closed = true;
+        let (seller, shares_left) = (sale.seller.clone(), sale.shares_left);
+        sale.shares_left = 0;
+        if shares_left > 0 {
+            self.internal_transfer_shares(&vault, &seller, shares_left);
+        }
+
+        self.on_sale_closed(seller, shares_left);
+    }
+
+    /// Send sale proceeds to the seller, minus the protocol fee which goes to the factory
+    pub(crate) fn pay_sale_proceeds(&self, amount: Balance) {
+        let sale = self.sale.as_ref().expect("no sale");
+        let fee = protocol_fee(amount);
+
+        match &sale.payment_token {
+            None => {
+                if fee > 0 {
+                    Promise::new(self.factory_id.clone()).transfer(fee);
+                }
+                Promise::new(sale.seller.clone()).transfer(amount - fee);
+            }
+            Some(token) => {
+                if fee > 0 {
+                    self.transfer_proceeds(token, self.factory_id.clone(), fee, true);
+                }
+                self.transfer_proceeds(token, sale.seller.clone(), amount - fee, false);
+            }
+        }
+    }
+
+    /// Send NEP-141 proceeds, or the protocol fee if `fee`, restoring them if the transfer fails
+    fn transfer_proceeds(&self, token: &AccountId, receiver_id: AccountId, amount: Balance, fee: bool) {
+        let memo = if fee { "Fractose protocol fee" } else { "Fractose share sale" };
+        ext_fungible_token::ft_transfer(
+            receiver_id.clone(),
+            amount.into(),
+            Some(memo.to_string()),
+            token,
+            1,
+            GAS_FOR_FT_TRANSFER
+        ).then(shares::on_proceeds_transfer(
+            receiver_id,
+            amount.into(),
+            fee,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_ON_PROCEEDS_TRANSFER
+        ));
+    }
+
+    fn on_sale_started(&self, seller: AccountId, amount: U128, price: U128) {
+        log!("SaleStarted({}, {}, {}, {})", seller, amount.0, price.0, env::current_account_id());
+    }
+
+    fn on_shares_bought(&self, buyer: AccountId, shares: Balance, cost: Balance) {
+        log!("SharesBought({}, {}, {}, {})", buyer, shares, cost, env::current_account_id());
+    }
+
+    fn on_sale_closed(&self, seller: AccountId, shares_returned: Balance) {
+        log!("SaleClosed({}, {}, {})", seller, shares_returned, env::current_account_id());
+    }
+}
+
+pub fn protocol_fee(amount: Balance) -> Balance {
+    bps_of(amount, PROTOCOL_FEE_BPS)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::PromiseOrValue;
+
+    use super::*;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    const SHARE_PRICE: Balance = 1_000;
+    const SALE_PRICE: Balance = 10;
+    /// Covers the storage of a sale and its allowlist
+    const SALE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    /// Vault curated by accounts(1) with accounts(2) registered as a buyer
+    fn setup(context: &mut VMContextBuilder) -> Shares {
+        testing_env!(context.predecessor_account_id(accounts(5)).build());
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            0,
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.storage_balance_bounds().min.into())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(SALE_DEPOSIT)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract
+    }
+
+    fn start_sale(contract: &mut Shares, max_per_account: Option<U128>, allowlist: Option<Vec<ValidAccountId>>, payment_token: Option<ValidAccountId>) {
+        contract.start_sale(
+            1000.into(),
+            SALE_PRICE.into(),
+            100.into(),
+            200.into(),
+            max_per_account,
+            allowlist,
+            payment_token
+        );
+    }
+
+    #[test]
+    fn test_protocol_fee() {
+        assert_eq!(protocol_fee(10_000), 100);
+        assert_eq!(protocol_fee(99), 0);
+        assert_eq!(protocol_fee(u128::MAX), u128::MAX / 100);
+    }
+
+    #[test]
+    fn test_buy_shares_with_near() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, None);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 1000);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 1000);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .attached_deposit(105)
+            .predecessor_account_id(accounts(2))
+            .build());
+        let bought = contract.buy_shares();
+
+        assert_eq!(bought.0, 10);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 10);
+        assert_eq!(contract.sale_purchased_of(accounts(2)).0, 10);
+        let info = contract.sale_info().unwrap();
+        assert_eq!(info.shares_sold.0, 10);
+        assert_eq!(info.shares_left.0, 990);
+        // Holdings report, refund, protocol fee and proceeds
+        assert_eq!(get_created_receipts().len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "sale is not active")]
+    fn test_buy_before_start() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(99)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.buy_shares();
+    }
+
+    #[test]
+    #[should_panic(expected = "only the curator can start a sale")]
+    fn test_start_sale_not_curator() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        start_sale(&mut contract, None, None, None);
+    }
+
+    #[test]
+    fn test_buy_capped_per_account() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, Some(5.into()), None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert_eq!(contract.buy_shares().0, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "account is not on the sale allowlist")]
+    fn test_buy_not_allowlisted() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, Some(vec![accounts(3)]), None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.buy_shares();
+    }
+
+    #[test]
+    fn test_buy_shares_with_token() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, Some(accounts(4)));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .predecessor_account_id(accounts(4))
+            .build());
+        let unused = contract.ft_on_transfer(accounts(2), 10_005.into(), "".to_string());
+
+        match unused {
+            PromiseOrValue::Value(unused) => assert_eq!(unused.0, 5),
+            _ => panic!("expected unused amount"),
+        }
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1000);
+        assert!(contract.sale_info().unwrap().shares_left.0 == 0);
+    }
+
+    #[test]
+    fn test_end_sale_returns_unsold_shares() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(200)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.end_sale();
+
+        assert!(contract.sale_info().unwrap().closed);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "attach at least")]
+    fn test_start_sale_without_storage_deposit() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+
+        testing_env!(context.attached_deposit(0).build());
+        start_sale(&mut contract, None, Some(vec![accounts(2), accounts(3)]), None);
+    }
+
+    #[test]
+    fn test_sale_after_closed_sale() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, Some(5.into()), Some(vec![accounts(2)]), None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .attached_deposit(50)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.buy_shares();
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(0).predecessor_account_id(accounts(1)).build());
+        contract.end_sale();
+
+        // The next sale starts afresh, without the allowlist and purchases of the first one
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(SALE_DEPOSIT).build());
+        contract.start_sale(100.into(), SALE_PRICE.into(), 150.into(), 300.into(), Some(5.into()), None, None);
+        assert_eq!(contract.sale_purchased_of(accounts(2)).0, 0);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(50)
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert_eq!(contract.buy_shares().0, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "a sale is already open")]
+    fn test_start_sale_while_open() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, None);
+
+        testing_env!(context.storage_usage(env::storage_usage()).attached_deposit(SALE_DEPOSIT).build());
+        start_sale(&mut contract, None, None, None);
+    }
+
+    #[test]
+    fn test_failed_token_proceeds_are_restored() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, Some(accounts(4)));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(150)
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(4))
+            .build());
+        contract.ft_on_transfer(accounts(2), 10_000.into(), "".to_string());
+
+        // The seller is not registered with the token
+        testing_env!(
+            context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_proceeds_transfer(accounts(1).into(), 9_900.into(), false);
+        let info = contract.sale_info().unwrap();
+        assert_eq!((info.unpaid_proceeds.0, info.unpaid_fee.0), (9_900, 0));
+
+        testing_env!(context.storage_usage(env::storage_usage()).predecessor_account_id(accounts(2)).build());
+        contract.pay_unpaid_proceeds();
+        assert_eq!(contract.sale_info().unwrap().unpaid_proceeds.0, 0);
+        assert_eq!(get_created_receipts().len(), 2);
+    }
+
+    #[test]
+    fn test_redeem_closes_sale() {
+        let mut context = get_context(accounts(1));
+        let mut contract = setup(&mut context);
+        start_sale(&mut contract, None, None, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .signer_account_id(accounts(1))
+            .build());
+        contract.redeem();
+
+        assert!(contract.sale_info().unwrap().closed);
+        assert_eq!(contract.ft_total_supply().0, 0);
+    }
+}
@@ -0,0 +1,255 @@
+//! Random sequences of share operations, checked step by step against a model of the vault.
+//!
+//! The model tracks share balances and every NEAR flow of the vault. After each step the contract
+//! must agree with it, the vault must never have paid out more than it received, and the NEAR
+//! reserved for claims must match the shares left to claim.
+use std::collections::HashMap;
+
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, MockedBlockchain};
+use proptest::prelude::*;
+
+use crate::*;
+
+const HOLDERS: usize = 5;
+const SUPPLY: Balance = 1_000_000;
+const SHARE_PRICE: Balance = 1_000;
+
+#[derive(Clone, Debug)]
+enum Op {
+    /// Send `percent` of the sender's shares
+    Transfer { from: usize, to: usize, percent: u128 },
+    StorageDeposit { account: usize },
+    StorageUnregister { account: usize, force: bool },
+    /// Redeem, attaching `overpay` on top of the redeem amount
+    Redeem { account: usize, overpay: Balance },
+    Claim { account: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let account = 0..HOLDERS;
+    prop_oneof![
+        4 => (account.clone(), account.clone(), 1..=100u128).prop_map(|(from, to, percent)| Op::Transfer { from, to, percent }),
+        2 => account.clone().prop_map(|account| Op::StorageDeposit { account }),
+        1 => (account.clone(), any::<bool>()).prop_map(|(account, force)| Op::StorageUnregister { account, force }),
+        1 => (account.clone(), 0..1_000u128).prop_map(|(account, overpay)| Op::Redeem { account, overpay }),
+        2 => account.prop_map(|account| Op::Claim { account }),
+    ]
+}
+
+/// Expected state of the vault
+#[derive(Default)]
+struct Model {
+    /// Share balances of registered accounts
+    balances: HashMap<usize, Balance>,
+    released: bool,
+    /// NEAR paid to redeem, and NEAR paid out to claims
+    redeem_payment: Balance,
+    claimed: Balance,
+    /// Every NEAR received and sent by the vault
+    received: Balance,
+    sent: Balance,
+}
+
+impl Model {
+    fn balance(&self, account: usize) -> Balance {
+        self.balances.get(&account).copied().unwrap_or(0)
+    }
+
+    fn supply(&self) -> Balance {
+        self.balances.values().sum()
+    }
+
+    /// All shares are burnt and the vault deleted itself
+    fn deleted(&self) -> bool {
+        self.released && self.supply() == 0
+    }
+}
+
+struct Harness {
+    context: VMContextBuilder,
+    contract: Shares,
+    model: Model,
+    storage_deposit: Balance,
+}
+
+impl Harness {
+    fn new() -> Self {
+        // testing_env! carries storage over, so drop the previous case's blockchain
+        env::take_blockchain_interface();
+        let mut context = VMContextBuilder::new();
+        context.current_account_id("vault.near".try_into().unwrap()).predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+
+        let mut contract = Shares::create(
+            "nft.near".into(),
+            "0".into(),
+            accounts(0),
+            SUPPLY.into(),
+            0,
+            SHARE_PRICE.into(),
+            None,
+            None,
+            None,
+            None
+        );
+        contract.custody_verified_at = Some(0);
+        let storage_deposit = contract.storage_balance_bounds().min.0;
+
+        // The owner's registration is paid by the vault funding
+        let model = Model {
+            balances: vec![(0, SUPPLY)].into_iter().collect(),
+            received: storage_deposit,
+            ..Default::default()
+        };
+
+        Self { context, contract, model, storage_deposit }
+    }
+
+    fn act_as(&mut self, account: usize, deposit: Balance) {
+        testing_env!(self.context
+            .storage_usage(env::storage_usage())
+            .account_balance(u128::MAX / 2)
+            .attached_deposit(deposit)
+            .signer_account_id(accounts(account))
+            .predecessor_account_id(accounts(account))
+            .build());
+        self.model.received += deposit;
+    }
+
+    /// Apply `op` to the contract and the model. Operations the contract would refuse are skipped
+    fn apply(&mut self, op: &Op) {
+        let model = &self.model;
+        let mut expected_sent: HashMap<usize, Balance> = HashMap::new();
+
+        match *op {
+            Op::Transfer { from, to, percent } => {
+                let amount = model.balance(from) * percent / 100;
+                if from == to || amount == 0 || !model.balances.contains_key(&to) {
+                    return;
+                }
+                self.act_as(from, 1);
+                self.contract.ft_transfer(accounts(to), amount.into(), None);
+
+                // The yoctoNEAR attached to transfers stays on the vault
+                *self.model.balances.get_mut(&from).unwrap() -= amount;
+                *self.model.balances.get_mut(&to).unwrap() += amount;
+            }
+            Op::StorageDeposit { account } => {
+                let registered = model.balances.contains_key(&account);
+                self.act_as(account, self.storage_deposit);
+                self.contract.storage_deposit(None, None);
+
+                if registered {
+                    expected_sent.insert(account, self.storage_deposit);
+                } else {
+                    self.model.balances.insert(account, 0);
+                }
+            }
+            Op::StorageUnregister { account, force } => {
+                let balance = match model.balances.get(&account) {
+                    Some(balance) if *balance == 0 || force => *balance,
+                    _ => return,
+                };
+                // Shares burnt after the NFT was redeemed are paid like a claim
+                let claim = if model.released { balance * SHARE_PRICE } else { 0 };
+
+                self.act_as(account, 1);
+                assert!(self.contract.storage_unregister(Some(force)));
+                self.model.claimed += claim;
+                expected_sent.insert(account, self.storage_deposit + 1 + claim);
+                self.model.balances.remove(&account);
+            }
+            Op::Redeem { account, overpay } => {
+                if model.released {
+                    return;
+                }
+                let payment = (model.supply() - model.balance(account)) * SHARE_PRICE;
+                self.act_as(account, payment + overpay);
+                self.contract.redeem();
+
+                self.model.released = true;
+                self.model.redeem_payment = payment;
+                expected_sent.insert(account, overpay);
+                if let Some(balance) = self.model.balances.get_mut(&account) {
+                    *balance = 0;
+                }
+            }
+            Op::Claim { account } => {
+                let balance = model.balance(account);
+                if !model.released || balance == 0 {
+                    return;
+                }
+                self.act_as(account, 0);
+                self.contract.claim();
+
+                self.model.claimed += balance * SHARE_PRICE;
+                expected_sent.insert(account, balance * SHARE_PRICE);
+                *self.model.balances.get_mut(&account).unwrap() = 0;
+            }
+        }
+
+        let sent = near_sent();
+        self.model.sent += sent.values().sum::<Balance>();
+        for account in 0..HOLDERS {
+            let expected = expected_sent.get(&account).copied().unwrap_or(0);
+            let actual = sent.get(accounts(account).as_ref()).copied().unwrap_or(0);
+            assert_eq!(actual, expected, "NEAR sent to {} after {:?}", accounts(account), op);
+        }
+    }
+
+    fn check_invariants(&self) {
+        let Self { contract, model, .. } = self;
+
+        for account in 0..HOLDERS {
+            assert_eq!(contract.ft_balance_of(accounts(account)).0, model.balance(account), "balance of {}", accounts(account));
+            assert_eq!(contract.storage_balance_of(accounts(account)).is_some(), model.balances.contains_key(&account));
+        }
+        assert_eq!(contract.ft_total_supply().0, model.supply());
+        assert_eq!(contract.ft_total_supply_at(env::block_index().into()).0, model.supply());
+
+        // The vault never pays out more than it received
+        assert!(model.sent <= model.received, "sent {} but received {}", model.sent, model.received);
+
+        // NEAR paid by the redeemer backs exactly the shares left to claim
+        if model.released {
+            assert!(model.claimed <= model.redeem_payment);
+            assert_eq!(contract.vault_balance().0, model.redeem_payment - model.claimed);
+            assert_eq!(contract.vault_balance().0, model.supply() * SHARE_PRICE);
+        } else {
+            assert_eq!(contract.vault_balance().0, 0);
+            assert_eq!(contract.exit_price().0, model.supply() * SHARE_PRICE);
+        }
+    }
+}
+
+/// NEAR sent to each account by the receipts created in the current context
+fn near_sent() -> HashMap<AccountId, Balance> {
+    let mut sent = HashMap::new();
+    for receipt in get_created_receipts().iter().map(|receipt| format!("{:?}", receipt)) {
+        let receiver_id = receipt.split("receiver_id: \"").nth(1).unwrap().split('"').next().unwrap().to_string();
+        for rest in receipt.split("TransferAction { deposit: ").skip(1) {
+            *sent.entry(receiver_id.clone()).or_insert(0) += rest.split(' ').next().unwrap().parse::<Balance>().unwrap();
+        }
+    }
+    sent
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_share_operations_conserve_value(ops in prop::collection::vec(op(), 1..40)) {
+        let mut harness = Harness::new();
+        harness.check_invariants();
+
+        for op in &ops {
+            harness.apply(op);
+            if harness.model.deleted() {
+                break;
+            }
+            harness.check_invariants();
+        }
+    }
+}
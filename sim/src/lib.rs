@@ -0,0 +1,23 @@
+//! Simulation of the Fractose contracts on an in-process runtime.
+//!
+//! Contracts run from the wasm committed in the repository on nearcore's VM, one account each,
+//! with the gas costs and storage accounting of the current protocol version. Unlike unit tests,
+//! the receipts they create are executed, so promises, callbacks, refunds and account deletion
+//! behave as on chain.
+mod runtime;
+
+pub use runtime::*;
+
+/// Code deployed on the factory account
+pub const FRACTOSE_WASM: &[u8] = include_bytes!("../../contract/res/fractose.wasm");
+/// Code the factory deploys on vaults
+pub const SHARES_WASM: &[u8] = include_bytes!("../../shares/res/shares.wasm");
+/// Code the factory deploys on pools seeded by `securitize`
+pub const POOL_WASM: &[u8] = include_bytes!("../../pool/res/pool.wasm");
+/// NFT contract the tests securitize tokens of
+pub const MOCK_NFT_WASM: &[u8] = include_bytes!("../../mock-nft/res/mock_nft.wasm");
+
+/// Runtime running the Fractose contracts
+pub fn runtime() -> Runtime {
+    Runtime::new()
+}
@@ -0,0 +1,636 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use near_parameters::{vm::VMKind, ActionCosts, RuntimeConfig, RuntimeConfigStore};
+use near_primitives_core::{
+    account::AccountContract, config::ViewConfig, hash::CryptoHash, version::PROTOCOL_VERSION,
+};
+use near_sdk::{
+    AccountId, Balance, BlockHeight, Gas, StorageUsage,
+    serde::de::DeserializeOwned,
+    serde_json::{self, Value},
+};
+use near_vm_runner::logic::mocks::mock_external::{MockAction, MockedExternal};
+use near_vm_runner::logic::types::PromiseResult;
+use near_vm_runner::logic::{ReturnData, VMContext};
+use near_vm_runner::{Contract, ContractCode, MockContractRuntimeCache};
+
+/// Cost of storing one byte, in yoctoNEAR
+pub const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+/// Largest amount of gas a transaction can attach
+pub const MAX_GAS: Gas = 300_000_000_000_000;
+/// Storage taken by the record and the full access key of an account signing transactions
+pub const ACCOUNT_STORAGE_USAGE: StorageUsage = 182;
+/// Nanoseconds between two blocks
+pub const BLOCK_DURATION: u64 = 1_000_000_000;
+
+/// Predecessor of the refunds and of balances released by deleted accounts
+const SYSTEM_ACCOUNT: &str = "system";
+
+#[derive(Clone)]
+struct Account {
+    balance: Balance,
+    storage: HashMap<Vec<u8>, Vec<u8>>,
+    storage_usage: StorageUsage,
+    code: Option<Arc<ContractCode>>,
+}
+
+impl Account {
+    fn new(balance: Balance, storage_usage: StorageUsage) -> Self {
+        Self { balance, storage: HashMap::new(), storage_usage, code: None }
+    }
+
+    fn code_len(&self) -> u64 {
+        self.code.as_ref().map_or(0, |code| code.code().len() as u64)
+    }
+}
+
+/// Code of an account, as the VM loads it
+struct Code<'a>(&'a Arc<ContractCode>);
+
+impl Contract for Code<'_> {
+    fn hash(&self) -> CryptoHash {
+        *self.0.hash()
+    }
+
+    fn get_code(&self) -> Option<Arc<ContractCode>> {
+        Some(self.0.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    CreateAccount,
+    DeployContract(Vec<u8>),
+    FunctionCall { method_name: String, args: Vec<u8>, gas: Gas, deposit: Balance },
+    Transfer(Balance),
+    DeleteAccount(AccountId),
+}
+
+impl Action {
+    fn deposit(&self) -> Balance {
+        match self {
+            Action::FunctionCall { deposit, .. } | Action::Transfer(deposit) => *deposit,
+            _ => 0,
+        }
+    }
+}
+
+/// Receipt created by a function call. Indices are the ones the VM hands out to the contract
+struct CreatedReceipt {
+    index: u64,
+    receiver_id: AccountId,
+    dependencies: Vec<u64>,
+    actions: Vec<Action>,
+}
+
+/// Receipts recorded by the VM, with their actions. Key and staking actions don't affect the
+/// simulation and are left out
+fn created_receipts(action_log: Vec<MockAction>) -> Vec<CreatedReceipt> {
+    let mut receipts: Vec<CreatedReceipt> = vec![];
+    for (index, action) in action_log.into_iter().enumerate() {
+        let (receipt_index, action) = match action {
+            MockAction::CreateReceipt { receipt_indices, receiver_id } => {
+                receipts.push(CreatedReceipt {
+                    index: index as u64,
+                    receiver_id: receiver_id.to_string(),
+                    dependencies: receipt_indices,
+                    actions: vec![],
+                });
+                continue;
+            }
+            MockAction::CreateAccount { receipt_index } => (receipt_index, Action::CreateAccount),
+            MockAction::DeployContract { receipt_index, code } => (receipt_index, Action::DeployContract(code)),
+            MockAction::FunctionCallWeight { receipt_index, method_name, args, attached_deposit, prepaid_gas, .. } => {
+                (receipt_index, Action::FunctionCall {
+                    method_name: String::from_utf8(method_name).unwrap(),
+                    args,
+                    gas: prepaid_gas.as_gas(),
+                    deposit: attached_deposit.as_yoctonear(),
+                })
+            }
+            MockAction::Transfer { receipt_index, deposit } => (receipt_index, Action::Transfer(deposit.as_yoctonear())),
+            MockAction::DeleteAccount { receipt_index, beneficiary_id } => {
+                (receipt_index, Action::DeleteAccount(beneficiary_id.to_string()))
+            }
+            _ => continue,
+        };
+        receipts.iter_mut().find(|receipt| receipt.index == receipt_index).unwrap().actions.push(action);
+    }
+    receipts
+}
+
+struct Receipt {
+    id: usize,
+    signer_id: AccountId,
+    predecessor_id: AccountId,
+    receiver_id: AccountId,
+    actions: Vec<Action>,
+    /// Receipts whose results are passed to this one
+    dependencies: Vec<usize>,
+}
+
+/// Result of a receipt as seen by the receipts depending on it
+#[derive(Clone)]
+enum ReceiptResult {
+    Value(Vec<u8>),
+    Failed,
+    /// Result is the one of another receipt, returned as a promise
+    Receipt(usize),
+}
+
+/// Execution of a single receipt
+#[derive(Clone, Debug)]
+pub struct ReceiptOutcome {
+    pub predecessor_id: AccountId,
+    pub receiver_id: AccountId,
+    /// Method called by the receipt, if any
+    pub method: Option<String>,
+    /// Panic message if the receipt failed
+    pub error: Option<String>,
+    pub logs: Vec<String>,
+    /// Gas burnt by the receipt: the execution fees of its actions, the wasm instructions and host
+    /// functions of its call, and the fees of the receipts it sends
+    pub gas_burnt: Gas,
+    /// Gas the call took out of its prepaid gas: what it burnt, plus the gas attached to and the
+    /// execution fees prepaid for the receipts it sends. Budgets of calls are checked against it
+    pub gas_used: Gas,
+    /// Storage usage of the receiver after the receipt
+    pub storage_usage: StorageUsage,
+}
+
+/// Outcome of a transaction and of every receipt it spawned, in execution order
+#[derive(Debug)]
+pub struct ExecutionResult {
+    /// Value or failure of the transaction, following returned promises
+    result: Result<Vec<u8>, String>,
+    /// Gas burnt converting the transaction to its receipt
+    transaction_gas_burnt: Gas,
+    pub outcomes: Vec<ReceiptOutcome>,
+}
+
+impl ExecutionResult {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Panic unless the transaction succeeded
+    pub fn assert_success(&self) -> &Self {
+        if let Err(error) = &self.result {
+            panic!("transaction failed: {}", error);
+        }
+        self
+    }
+
+    /// Panic unless the transaction failed with an error containing `message`
+    pub fn assert_failure(&self, message: &str) -> &Self {
+        match &self.result {
+            Err(error) => assert!(error.contains(message), "expected error {:?}, got {:?}", message, error),
+            Ok(_) => panic!("expected error {:?}, transaction succeeded", message),
+        }
+        self
+    }
+
+    /// Value returned by the transaction, or the error which failed it
+    pub fn result(&self) -> Result<&[u8], &str> {
+        self.result.as_deref().map_err(String::as_str)
+    }
+
+    /// Value returned by the transaction
+    pub fn unwrap_json<T: DeserializeOwned>(&self) -> T {
+        let value = self.result.as_ref().unwrap_or_else(|error| panic!("transaction failed: {}", error));
+        serde_json::from_slice(value).unwrap()
+    }
+
+    /// Errors of the receipts which failed, including callbacks
+    pub fn promise_errors(&self) -> Vec<&str> {
+        self.outcomes.iter().filter_map(|outcome| outcome.error.as_deref()).collect()
+    }
+
+    /// Logs of every receipt
+    pub fn logs(&self) -> Vec<&str> {
+        self.outcomes.iter().flat_map(|outcome| outcome.logs.iter().map(String::as_str)).collect()
+    }
+
+    /// Gas burnt by the transaction and every receipt it spawned
+    pub fn gas_burnt(&self) -> Gas {
+        self.transaction_gas_burnt + self.outcomes.iter().map(|outcome| outcome.gas_burnt).sum::<Gas>()
+    }
+
+    /// Outcome of the first call of `method` on `receiver_id`
+    pub fn outcome_of(&self, receiver_id: &str, method: &str) -> Option<&ReceiptOutcome> {
+        self.outcomes.iter().find(|outcome| outcome.receiver_id == receiver_id && outcome.method.as_deref() == Some(method))
+    }
+}
+
+/// Result of a function call executed by the VM. The account is the one left by the call, which
+/// only applies if it didn't fail
+struct FunctionCallOutcome {
+    account: Account,
+    return_data: ReturnData,
+    receipts: Vec<CreatedReceipt>,
+    logs: Vec<String>,
+    gas_burnt: Gas,
+    gas_used: Gas,
+    error: Option<String>,
+}
+
+fn copy_result(result: &PromiseResult) -> PromiseResult {
+    match result {
+        PromiseResult::NotReady => PromiseResult::NotReady,
+        PromiseResult::Successful(value) => PromiseResult::Successful(value.clone()),
+        PromiseResult::Failed => PromiseResult::Failed,
+    }
+}
+
+fn parse_account_id(account_id: &str) -> near_primitives_core::types::AccountId {
+    account_id.parse().unwrap_or_else(|_| panic!("invalid account ID {}", account_id))
+}
+
+/// In-process NEAR runtime running the wasm of the contracts on nearcore's VM, with the gas
+/// schedule and storage accounting of the current protocol version. Each account keeps its own
+/// storage, and receipts and callbacks are executed in order, one block apart.
+///
+/// Gas is metered but not paid for, so balances only change with deposits and storage.
+pub struct Runtime {
+    config: Arc<RuntimeConfig>,
+    cache: MockContractRuntimeCache,
+    accounts: HashMap<AccountId, Account>,
+    next_receipt_id: usize,
+    block_index: BlockHeight,
+    block_timestamp: u64,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        // nearcore compiles with Wasmtime, which meters gas the same way but isn't built here
+        let mut config = RuntimeConfig::clone(RuntimeConfigStore::new(None).get_config(PROTOCOL_VERSION));
+        let mut wasm_config = near_parameters::vm::Config::clone(&config.wasm_config);
+        wasm_config.vm_kind = VMKind::NearVm;
+        config.wasm_config = Arc::new(wasm_config);
+        assert_eq!(config.fees.storage_usage_config.storage_amount_per_byte.as_yoctonear(), STORAGE_PRICE_PER_BYTE);
+
+        Self {
+            config: Arc::new(config),
+            cache: MockContractRuntimeCache::default(),
+            accounts: HashMap::new(),
+            next_receipt_id: 0,
+            block_index: 0,
+            block_timestamp: 0,
+        }
+    }
+
+    pub fn create_account(&mut self, account_id: &str, balance: Balance) {
+        assert!(!self.exists(account_id), "account {} already exists", account_id);
+        parse_account_id(account_id);
+        self.accounts.insert(account_id.to_string(), Account::new(balance, ACCOUNT_STORAGE_USAGE));
+    }
+
+    /// Create an account with `code` deployed on it
+    pub fn deploy(&mut self, account_id: &str, code: &[u8], balance: Balance) {
+        self.create_account(account_id, balance);
+        let account = self.accounts.get_mut(account_id).unwrap();
+        account.code = Some(Arc::new(ContractCode::new(code.to_vec(), None)));
+        account.storage_usage += code.len() as u64;
+    }
+
+    pub fn exists(&self, account_id: &str) -> bool {
+        self.accounts.contains_key(account_id)
+    }
+
+    pub fn balance(&self, account_id: &str) -> Balance {
+        self.account(account_id).balance
+    }
+
+    /// Bytes stored by the account, including its record and code
+    pub fn storage_usage(&self, account_id: &str) -> StorageUsage {
+        self.account(account_id).storage_usage
+    }
+
+    /// Balance locked to pay for the storage of the account
+    pub fn storage_cost(&self, account_id: &str) -> Balance {
+        self.storage_usage(account_id) as Balance * STORAGE_PRICE_PER_BYTE
+    }
+
+    pub fn block_timestamp(&self) -> u64 {
+        self.block_timestamp
+    }
+
+    /// Move the clock forward without producing receipts
+    pub fn advance_time(&mut self, duration: u64) {
+        self.block_timestamp += duration;
+    }
+
+    /// Call a view method
+    pub fn view<T: DeserializeOwned>(&self, account_id: &str, method: &str, args: Value) -> T {
+        let value = self.try_view(account_id, method, args).unwrap_or_else(|error| panic!("view {} failed: {}", method, error));
+        serde_json::from_slice(&value).unwrap()
+    }
+
+    /// Call a view method, returning its JSON result or its error
+    pub fn try_view(&self, account_id: &str, method: &str, args: Value) -> Result<Vec<u8>, String> {
+        let account = self.accounts.get(account_id).ok_or(format!("AccountDoesNotExist: {}", account_id))?;
+        let mut context = self.context(account_id, account_id, account_id, account);
+        context.input = args.to_string().into_bytes().into();
+        context.prepaid_gas = near_primitives_core::types::Gas::from_gas(MAX_GAS);
+        context.view_config = Some(ViewConfig { max_gas_burnt: context.prepaid_gas });
+        let call = self.run(account, context, method)?;
+        if let Some(error) = call.error {
+            return Err(error);
+        }
+
+        match call.return_data {
+            ReturnData::Value(value) => Ok(value),
+            _ => Err("view returned nothing".to_string()),
+        }
+    }
+
+    /// Sign and execute a function call transaction, then every receipt it spawns
+    pub fn call(&mut self, signer_id: &str, receiver_id: &str, method: &str, args: Value, deposit: Balance, gas: Gas) -> ExecutionResult {
+        assert!(gas <= MAX_GAS, "gas exceeds the transaction limit");
+        let signer = self.accounts.get_mut(signer_id).expect("signer does not exist");
+        assert!(signer.balance >= deposit, "signer cannot cover the deposit");
+        signer.balance -= deposit;
+
+        let id = self.next_id();
+        let args = args.to_string().into_bytes();
+        let transaction_gas_burnt = self.send_fees(signer_id == receiver_id, method.len() + args.len());
+        let transaction = Receipt {
+            id,
+            signer_id: signer_id.to_string(),
+            predecessor_id: signer_id.to_string(),
+            receiver_id: receiver_id.to_string(),
+            actions: vec![Action::FunctionCall { method_name: method.to_string(), args, gas, deposit }],
+            dependencies: vec![],
+        };
+
+        let mut results = HashMap::new();
+        let mut outcomes = vec![];
+        let mut queue = VecDeque::from(vec![transaction]);
+        while !queue.is_empty() {
+            self.block_index += 1;
+            self.block_timestamp += BLOCK_DURATION;
+
+            // Receipts waiting for results are postponed to the next block
+            let mut postponed = VecDeque::new();
+            let mut progress = false;
+            while let Some(receipt) = queue.pop_front() {
+                let promise_results: Option<Vec<_>> = receipt.dependencies.iter()
+                    .map(|id| Self::resolve(&results, *id))
+                    .collect();
+
+                match promise_results {
+                    Some(promise_results) => {
+                        progress = true;
+                        let (result, outcome, spawned) = self.execute(receipt, promise_results);
+                        results.insert(result.0, result.1);
+                        outcomes.push(outcome);
+                        postponed.extend(spawned);
+                    }
+                    None => postponed.push_back(receipt),
+                }
+            }
+            assert!(progress, "receipts wait on each other");
+            queue = postponed;
+        }
+
+        let result = match Self::resolve(&results, id).unwrap() {
+            PromiseResult::Successful(value) => Ok(value.to_vec()),
+            _ => Err(Self::first_error(&outcomes)),
+        };
+        ExecutionResult { result, transaction_gas_burnt, outcomes }
+    }
+
+    fn first_error(outcomes: &[ReceiptOutcome]) -> String {
+        outcomes.iter().find_map(|outcome| outcome.error.clone()).unwrap_or_default()
+    }
+
+    fn resolve(results: &HashMap<usize, ReceiptResult>, id: usize) -> Option<PromiseResult> {
+        match results.get(&id)? {
+            ReceiptResult::Value(value) => Some(PromiseResult::Successful(value.as_slice().into())),
+            ReceiptResult::Failed => Some(PromiseResult::Failed),
+            ReceiptResult::Receipt(id) => Self::resolve(results, *id),
+        }
+    }
+
+    /// Gas burnt sending a function call receipt with `len` bytes of method name and arguments
+    fn send_fees(&self, sir: bool, len: usize) -> Gas {
+        let fee = |cost| self.config.fees.fee(cost).send_fee(sir).gas.as_gas();
+        fee(ActionCosts::new_action_receipt) + fee(ActionCosts::function_call_base) + fee(ActionCosts::function_call_byte) * len as u64
+    }
+
+    /// Gas burnt executing the actions of a receipt, besides the function call itself
+    fn exec_fees(&self, actions: &[Action]) -> Gas {
+        let fee = |cost| self.config.fees.fee(cost).exec_fee().gas.as_gas();
+        actions.iter().fold(fee(ActionCosts::new_action_receipt), |gas, action| gas + match action {
+            Action::CreateAccount => fee(ActionCosts::create_account),
+            Action::DeployContract(code) => fee(ActionCosts::deploy_contract_base) + fee(ActionCosts::deploy_contract_byte) * code.len() as u64,
+            Action::FunctionCall { method_name, args, .. } => {
+                fee(ActionCosts::function_call_base) + fee(ActionCosts::function_call_byte) * (method_name.len() + args.len()) as u64
+            }
+            Action::Transfer(_) => fee(ActionCosts::transfer),
+            Action::DeleteAccount(_) => fee(ActionCosts::delete_account),
+        })
+    }
+
+    /// Apply the actions of a receipt. Failed receipts are reverted and their deposits refunded
+    fn execute(&mut self, receipt: Receipt, promise_results: Vec<PromiseResult>) -> ((usize, ReceiptResult), ReceiptOutcome, Vec<Receipt>) {
+        let snapshot = self.accounts.clone();
+        let mut outcome = ReceiptOutcome {
+            predecessor_id: receipt.predecessor_id.clone(),
+            receiver_id: receipt.receiver_id.clone(),
+            method: None,
+            error: None,
+            logs: vec![],
+            gas_burnt: if receipt.predecessor_id == SYSTEM_ACCOUNT { 0 } else { self.exec_fees(&receipt.actions) },
+            gas_used: 0,
+            storage_usage: 0,
+        };
+        let mut result = ReceiptResult::Value(vec![]);
+        let mut spawned = vec![];
+
+        let applied = receipt.actions.iter().try_for_each(|action| {
+            self.apply(&receipt, action, &promise_results, &mut outcome, &mut result, &mut spawned)
+        }).and_then(|()| {
+            match self.accounts.get(&receipt.receiver_id) {
+                Some(account) if account.balance < account.storage_usage as Balance * STORAGE_PRICE_PER_BYTE => {
+                    Err(format!("LackBalanceForState: {} needs {} to cover its storage", receipt.receiver_id, account.storage_usage as Balance * STORAGE_PRICE_PER_BYTE))
+                }
+                _ => Ok(()),
+            }
+        });
+
+        if let Err(error) = applied {
+            self.accounts = snapshot;
+            spawned.clear();
+            outcome.error = Some(error);
+            result = ReceiptResult::Failed;
+
+            let refund: Balance = receipt.actions.iter().map(Action::deposit).sum();
+            if refund > 0 && receipt.predecessor_id != SYSTEM_ACCOUNT {
+                spawned.push(self.transfer_receipt(&receipt.predecessor_id, refund));
+            }
+        }
+        outcome.storage_usage = self.accounts.get(&receipt.receiver_id).map(|account| account.storage_usage).unwrap_or(0);
+
+        ((receipt.id, result), outcome, spawned)
+    }
+
+    fn apply(
+        &mut self,
+        receipt: &Receipt,
+        action: &Action,
+        promise_results: &[PromiseResult],
+        outcome: &mut ReceiptOutcome,
+        result: &mut ReceiptResult,
+        spawned: &mut Vec<Receipt>,
+    ) -> Result<(), String> {
+        let receiver_id = &receipt.receiver_id;
+        if !matches!(action, Action::CreateAccount) && !self.exists(receiver_id) {
+            return Err(format!("AccountDoesNotExist: {}", receiver_id));
+        }
+
+        match action {
+            Action::CreateAccount => {
+                if self.exists(receiver_id) {
+                    return Err(format!("AccountAlreadyExists: {}", receiver_id));
+                }
+                if !receiver_id.ends_with(&format!(".{}", receipt.predecessor_id)) {
+                    return Err(format!("CreateAccountNotAllowed: {} can't be created by {}", receiver_id, receipt.predecessor_id));
+                }
+                let storage_usage = self.config.fees.storage_usage_config.num_bytes_account;
+                self.accounts.insert(receiver_id.clone(), Account::new(0, storage_usage));
+            }
+            Action::DeployContract(code) => {
+                let account = self.accounts.get_mut(receiver_id).unwrap();
+                account.storage_usage = account.storage_usage - account.code_len() + code.len() as u64;
+                account.code = Some(Arc::new(ContractCode::new(code.clone(), None)));
+            }
+            Action::Transfer(deposit) => {
+                self.accounts.get_mut(receiver_id).unwrap().balance += deposit;
+            }
+            Action::DeleteAccount(beneficiary_id) => {
+                let account = self.accounts.remove(receiver_id).unwrap();
+                spawned.push(self.transfer_receipt(beneficiary_id, account.balance));
+            }
+            Action::FunctionCall { method_name, args, gas, deposit } => {
+                outcome.method = Some(method_name.clone());
+                let mut account = self.accounts.get(receiver_id).unwrap().clone();
+                account.balance += deposit;
+
+                let mut context = self.context(receiver_id, &receipt.signer_id, &receipt.predecessor_id, &account);
+                context.input = args.as_slice().into();
+                context.attached_deposit = near_primitives_core::types::Balance::from_yoctonear(*deposit);
+                context.prepaid_gas = near_primitives_core::types::Gas::from_gas(*gas);
+                context.promise_results = promise_results.iter().map(copy_result).collect::<Vec<_>>().into();
+                let call = self.run(&account, context, method_name)?;
+                outcome.logs = call.logs;
+                outcome.gas_burnt += call.gas_burnt;
+                outcome.gas_used = call.gas_used;
+                if let Some(error) = call.error {
+                    return Err(error);
+                }
+                self.accounts.insert(receiver_id.clone(), call.account);
+
+                // Receipts created by the call are numbered after the ones already queued
+                let ids: HashMap<u64, usize> = call.receipts.iter().map(|created| (created.index, self.next_id())).collect();
+                for created in call.receipts {
+                    spawned.push(Receipt {
+                        id: ids[&created.index],
+                        signer_id: receipt.signer_id.clone(),
+                        predecessor_id: receiver_id.clone(),
+                        receiver_id: created.receiver_id,
+                        actions: created.actions,
+                        dependencies: created.dependencies.iter().map(|index| ids[index]).collect(),
+                    });
+                }
+
+                *result = match call.return_data {
+                    ReturnData::Value(value) => ReceiptResult::Value(value),
+                    ReturnData::ReceiptIndex(index) => ReceiptResult::Receipt(ids[&index]),
+                    ReturnData::None => ReceiptResult::Value(vec![]),
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a contract method on the VM
+    fn run(&self, account: &Account, context: VMContext, method: &str) -> Result<FunctionCallOutcome, String> {
+        let code = account.code.as_ref().ok_or("CodeDoesNotExist")?;
+        let mut ext = MockedExternal::with_code(ContractCode::clone(code));
+        ext.fake_trie = account.storage.clone();
+
+        let gas_counter = context.make_gas_counter(&self.config.wasm_config);
+        let prepared = near_vm_runner::prepare(&Code(code), self.config.wasm_config.clone(), Some(&self.cache), gas_counter, method);
+        let outcome = near_vm_runner::run(prepared, &mut ext, &context, self.config.fees.clone())
+            .unwrap_or_else(|error| panic!("the VM failed: {:?}", error));
+
+        Ok(FunctionCallOutcome {
+            account: Account {
+                balance: outcome.balance.as_yoctonear(),
+                storage: ext.fake_trie,
+                storage_usage: outcome.storage_usage,
+                code: account.code.clone(),
+            },
+            return_data: outcome.return_data,
+            receipts: created_receipts(ext.action_log),
+            logs: outcome.logs,
+            gas_burnt: outcome.burnt_gas.as_gas(),
+            gas_used: outcome.used_gas.as_gas(),
+            error: outcome.aborted.map(|error| error.to_string()),
+        })
+    }
+
+    /// Context of a call on `account`. Input, deposit, gas and promise results are set by the caller
+    fn context(&self, account_id: &str, signer_id: &str, predecessor_id: &str, account: &Account) -> VMContext {
+        VMContext {
+            current_account_id: parse_account_id(account_id),
+            signer_account_id: parse_account_id(signer_id),
+            signer_account_pk: vec![0; 33],
+            predecessor_account_id: parse_account_id(predecessor_id),
+            refund_to_account_id: parse_account_id(predecessor_id),
+            input: Vec::new().into(),
+            promise_results: Vec::new().into(),
+            block_height: self.block_index,
+            block_timestamp: self.block_timestamp,
+            epoch_height: 0,
+            account_balance: near_primitives_core::types::Balance::from_yoctonear(account.balance),
+            account_locked_balance: near_primitives_core::types::Balance::ZERO,
+            storage_usage: account.storage_usage,
+            account_contract: account.code.as_ref().map_or(AccountContract::None, |code| AccountContract::Local(*code.hash())),
+            attached_deposit: near_primitives_core::types::Balance::ZERO,
+            prepaid_gas: near_primitives_core::types::Gas::ZERO,
+            random_seed: vec![0; 32],
+            view_config: None,
+            output_data_receivers: vec![],
+        }
+    }
+
+    fn transfer_receipt(&mut self, receiver_id: &str, amount: Balance) -> Receipt {
+        Receipt {
+            id: self.next_id(),
+            signer_id: SYSTEM_ACCOUNT.to_string(),
+            predecessor_id: SYSTEM_ACCOUNT.to_string(),
+            receiver_id: receiver_id.to_string(),
+            actions: vec![Action::Transfer(amount)],
+            dependencies: vec![],
+        }
+    }
+
+    fn account(&self, account_id: &str) -> &Account {
+        self.accounts.get(account_id).unwrap_or_else(|| panic!("account {} does not exist", account_id))
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_receipt_id += 1;
+        self.next_receipt_id
+    }
+}
@@ -0,0 +1,428 @@
+use fractose::{securitize_deposit, securitize_gas, PoolSeed, GAS_FOR_POOL_NEW, GAS_FOR_SECURITIZE, PORTFOLIO_STORAGE_DEPOSIT};
+use fractose_common::{Event, GAS_FOR_NFT_TRANSFER, GAS_FOR_SHARES_CREATE, VAULT_STORAGE_BALANCE};
+use fractose_sim::*;
+use nft_shares::{GAS_FOR_CLAIM, GAS_FOR_CLEANUP, GAS_FOR_REDEEM, GAS_FOR_REDEEM_CUSTODY_CHECK};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde_json::{json, Value};
+use near_sdk::{Balance, Gas};
+
+const FACTORY: &str = "fractose.near";
+const NFT: &str = "nft.near";
+const TOKEN_ID: &str = "0";
+const VAULT: &str = "nft-near-0.fractose.near";
+const POOL: &str = "nft-near-0-pool.fractose.near";
+const ALICE: &str = "alice.near";
+const BOB: &str = "bob.near";
+const CAROL: &str = "carol.near";
+
+const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+const TGAS: Gas = 1_000_000_000_000;
+const SHARES_COUNT: Balance = 100;
+const EXIT_PRICE: Balance = 10 * NEAR;
+const SHARE_PRICE: Balance = EXIT_PRICE / SHARES_COUNT;
+
+/// Factory, NFT contract and three users. Alice owns token 0 and approved the factory
+fn setup() -> Runtime {
+    let mut runtime = runtime();
+    runtime.deploy(FACTORY, FRACTOSE_WASM, 100 * NEAR);
+    runtime.deploy(NFT, MOCK_NFT_WASM, 10 * NEAR);
+    for user in &[ALICE, BOB, CAROL] {
+        runtime.create_account(user, 100 * NEAR);
+    }
+
+    runtime.call(NFT, NFT, "new", json!({ "owner_id": ALICE }), 0, MAX_GAS).assert_success();
+    runtime.call(ALICE, NFT, "nft_mint", json!({
+        "token_id": TOKEN_ID,
+        "receiver_id": ALICE,
+        "token_metadata": { "title": "Mock #0", "media": "https://example.com/0.png" },
+    }), NEAR, MAX_GAS).assert_success();
+    approve_factory(&mut runtime);
+
+    runtime
+}
+
+fn approve_factory(runtime: &mut Runtime) {
+    runtime.call(ALICE, NFT, "nft_approve", json!({ "token_id": TOKEN_ID, "account_id": FACTORY }), NEAR / 100, MAX_GAS)
+        .assert_success();
+}
+
+fn securitize(runtime: &mut Runtime, min_lock_duration: Option<u64>) -> ExecutionResult {
+    securitize_with_gas(runtime, min_lock_duration, MAX_GAS)
+}
+
+fn securitize_with_gas(runtime: &mut Runtime, min_lock_duration: Option<u64>, gas: Gas) -> ExecutionResult {
+    runtime.call(ALICE, FACTORY, "securitize", json!({
+        "nft_contract_address": NFT,
+        "nft_token_id": TOKEN_ID,
+        "shares_count": U128(SHARES_COUNT),
+        "decimals": 0,
+        "exit_price": U128(EXIT_PRICE),
+        "min_lock_duration": min_lock_duration.map(U64),
+    }), securitize_deposit(None), gas)
+}
+
+fn nft_owner(runtime: &Runtime) -> String {
+    let token: Value = runtime.view(NFT, "nft_token", json!({ "token_id": TOKEN_ID }));
+    token["owner_id"].as_str().unwrap().to_string()
+}
+
+fn balance_of(runtime: &Runtime, account_id: &str) -> Balance {
+    runtime.view::<U128>(VAULT, "ft_balance_of", json!({ "account_id": account_id })).0
+}
+
+fn verify_custody(runtime: &mut Runtime) {
+    let result = runtime.call(CAROL, VAULT, "verify_custody", json!({}), 0, MAX_GAS);
+    assert!(result.unwrap_json::<bool>());
+}
+
+/// Register `account_id` with the vault and send it `amount` of Alice's shares
+fn send_shares(runtime: &mut Runtime, account_id: &str, amount: Balance) {
+    let bounds: Value = runtime.view(VAULT, "storage_balance_bounds", json!({}));
+    let min: U128 = near_sdk::serde_json::from_value(bounds["min"].clone()).unwrap();
+    let storage_usage = runtime.storage_usage(VAULT);
+    runtime.call(account_id, VAULT, "storage_deposit", json!({}), min.0, MAX_GAS).assert_success();
+
+    // The registration deposit covers the storage it takes
+    let storage_cost = (runtime.storage_usage(VAULT) - storage_usage) as Balance * STORAGE_PRICE_PER_BYTE;
+    assert!(storage_cost <= min.0, "registration costs {} but the deposit is {}", storage_cost, min.0);
+    runtime.call(ALICE, VAULT, "ft_transfer", json!({ "receiver_id": account_id, "amount": U128(amount) }), 1, MAX_GAS)
+        .assert_success();
+}
+
+#[test]
+fn test_securitize() {
+    let mut runtime = setup();
+    let factory_storage = runtime.storage_usage(FACTORY);
+
+    let result = securitize(&mut runtime, None);
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+
+    // The vault holds the NFT and Alice every share
+    assert_eq!(nft_owner(&runtime), VAULT);
+    assert_eq!(balance_of(&runtime, ALICE), SHARES_COUNT);
+    assert_eq!(runtime.view::<U128>(VAULT, "exit_price", json!({})).0, EXIT_PRICE);
+    assert_eq!(runtime.view::<Option<String>>(FACTORY, "nft_address_of", json!({ "shares_contract": VAULT })), Some(format!("{}/{}", NFT, TOKEN_ID)));
+    assert_eq!(runtime.view::<Vec<String>>(FACTORY, "get_vaults", json!({})), vec![VAULT.to_string()]);
+    let securitize = Event::Securitize { owner_id: ALICE.into(), nft_contract_id: NFT.into(), token_id: TOKEN_ID.into(), vault_id: VAULT.into() };
+    assert!(result.logs().contains(&securitize.to_string().as_str()));
+
+    // Metadata of the NFT was fetched by the vault
+    let info: Value = runtime.view(VAULT, "nft_info", json!({}));
+    assert_eq!(info["metadata"]["title"], "Mock #0");
+
+    // The vault balance covers its code and state, and the factory's index stays small
+    assert_eq!(runtime.balance(VAULT), VAULT_STORAGE_BALANCE);
+    assert!(runtime.storage_cost(VAULT) < VAULT_STORAGE_BALANCE);
+    assert!(runtime.storage_usage(FACTORY) - factory_storage < 1_200);
+}
+
+#[test]
+fn test_securitize_gas() {
+    let mut runtime = setup();
+
+    // Deploying the vault code makes up most of it
+    let result = securitize(&mut runtime, None);
+    assert!(result.gas_burnt() < 100 * TGAS, "burnt {}", result.gas_burnt());
+
+    // Every call stays within its budget. The budget of `create` covers the metadata fetch
+    let gas_of = |receiver_id, method| result.outcome_of(receiver_id, method).unwrap().gas_used;
+    assert!(gas_of(FACTORY, "securitize") - GAS_FOR_SHARES_CREATE - GAS_FOR_NFT_TRANSFER < GAS_FOR_SECURITIZE);
+    assert!(gas_of(VAULT, "create") < GAS_FOR_SHARES_CREATE);
+    assert!(gas_of(NFT, "nft_transfer") < GAS_FOR_NFT_TRANSFER);
+}
+
+#[test]
+fn test_securitize_with_minimum_gas() {
+    let mut runtime = setup();
+
+    let result = securitize_with_gas(&mut runtime, None, securitize_gas(false));
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    assert_eq!(nft_owner(&runtime), VAULT);
+}
+
+#[test]
+fn test_securitize_with_pool_and_minimum_gas() {
+    let mut runtime = setup();
+    let code = Base64VecU8::from(POOL_WASM.to_vec());
+    runtime.call(FACTORY, FACTORY, "set_pool_code", json!({ "code": code }), 0, MAX_GAS).assert_success();
+
+    let pool_seed = PoolSeed { shares: U128(20), near: U128(2 * NEAR) };
+    let result = runtime.call(ALICE, FACTORY, "securitize", json!({
+        "nft_contract_address": NFT,
+        "nft_token_id": TOKEN_ID,
+        "shares_count": U128(SHARES_COUNT),
+        "decimals": 0,
+        "exit_price": U128(EXIT_PRICE),
+        "pool_seed": pool_seed,
+    }), securitize_deposit(Some(&pool_seed)), securitize_gas(true));
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    assert_eq!(nft_owner(&runtime), VAULT);
+    assert_eq!(balance_of(&runtime, POOL), 20);
+    assert!(result.outcome_of(POOL, "new").unwrap().gas_used < GAS_FOR_POOL_NEW);
+}
+
+#[test]
+fn test_securitize_with_too_little_gas() {
+    let mut runtime = setup();
+
+    // Checked before anything is scheduled, so the NFT stays with its owner
+    securitize_with_gas(&mut runtime, None, securitize_gas(false) - 1)
+        .assert_failure(&format!("not enough gas, attach at least {} TGas", securitize_gas(false) / TGAS));
+    securitize_with_gas(&mut runtime, None, TGAS).assert_failure("not enough gas");
+
+    assert!(!runtime.exists(VAULT));
+    assert_eq!(nft_owner(&runtime), ALICE);
+}
+
+#[test]
+fn test_redeem_with_shares() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+    verify_custody(&mut runtime);
+
+    let alice_balance = runtime.balance(ALICE);
+    let vault_balance = runtime.balance(VAULT);
+    let quote: Value = runtime.view(VAULT, "redeem_quote", json!({ "account_id": ALICE }));
+    assert_eq!((quote["near_required"].as_str(), quote["redeemable"].as_bool()), (Some("0"), Some(true)));
+    let result = runtime.call(ALICE, VAULT, "redeem", json!({}), 0, MAX_GAS);
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    let redeem = Event::Redeem { redeemer_id: ALICE.into(), nft_contract_id: NFT.into(), token_id: TOKEN_ID.into(), vault_id: VAULT.into() };
+    assert!(result.logs().contains(&redeem.to_string().as_str()));
+
+    // Alice owns the NFT again, and the empty vault was deleted in her favour. The vault only
+    // kept the yoctoNEAR attached to the NFT transfer
+    assert_eq!(nft_owner(&runtime), ALICE);
+    assert!(!runtime.exists(VAULT));
+    assert_eq!(runtime.balance(ALICE), alice_balance + vault_balance - 1);
+    assert_eq!(quote["refund"].as_str().unwrap().parse::<Balance>().unwrap(), vault_balance - 1);
+}
+
+#[test]
+fn test_redeem_with_near_then_claim() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+    send_shares(&mut runtime, BOB, 30);
+    send_shares(&mut runtime, CAROL, 10);
+    verify_custody(&mut runtime);
+
+    // Alice pays for the shares she doesn't hold, with some change
+    let redeem_amount = runtime.view::<U128>(VAULT, "redeem_amount_of", json!({ "from": ALICE })).0;
+    assert_eq!(redeem_amount, 40 * SHARE_PRICE);
+    let alice_balance = runtime.balance(ALICE);
+    runtime.call(ALICE, VAULT, "redeem", json!({}), redeem_amount + NEAR, MAX_GAS).assert_success();
+
+    assert_eq!(nft_owner(&runtime), ALICE);
+    assert_eq!(runtime.balance(ALICE), alice_balance - redeem_amount);
+    let deed: Value = runtime.view(VAULT, "nft_token", json!({ "token_id": "deed" }));
+    assert_eq!(deed["owner_id"], ALICE);
+
+    // The vault is kept until the remaining holders claimed their NEAR
+    assert_eq!(runtime.view::<U128>(VAULT, "vault_balance", json!({})).0, redeem_amount);
+    assert!(runtime.balance(VAULT) >= runtime.storage_cost(VAULT) + redeem_amount);
+
+    let bob_balance = runtime.balance(BOB);
+    let result = runtime.call(BOB, VAULT, "claim", json!({}), 0, MAX_GAS);
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    assert_eq!(runtime.balance(BOB), bob_balance + 30 * SHARE_PRICE);
+    assert!(runtime.exists(VAULT));
+    assert_eq!(balance_of(&runtime, BOB), 0);
+
+    runtime.call(BOB, VAULT, "claim", json!({}), 0, MAX_GAS).assert_failure("nothing to claim");
+
+    // The last claim burns the remaining supply, but the vault is kept with Alice's deed
+    let result = runtime.call(CAROL, VAULT, "claim", json!({}), 0, MAX_GAS);
+    result.assert_success();
+    assert!(result.outcome_of(VAULT, "cleanup").unwrap().error.is_none());
+    assert!(runtime.exists(VAULT));
+    let deeds: Vec<Value> = runtime.view(VAULT, "nft_tokens_for_owner", json!({ "account_id": ALICE }));
+    assert_eq!(deeds.len(), 1);
+
+    // Burning the deed deletes the vault in favour of Alice
+    let alice_balance = runtime.balance(ALICE);
+    let vault_balance = runtime.balance(VAULT);
+    runtime.call(ALICE, VAULT, "burn_deed", json!({}), 0, MAX_GAS).assert_success();
+    assert!(!runtime.exists(VAULT));
+    assert_eq!(runtime.balance(ALICE), alice_balance + vault_balance);
+}
+
+#[test]
+fn test_redeem_and_claim_with_minimum_gas() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+    send_shares(&mut runtime, BOB, 30);
+    send_shares(&mut runtime, CAROL, 10);
+    verify_custody(&mut runtime);
+
+    let redeem_gas = GAS_FOR_REDEEM + GAS_FOR_NFT_TRANSFER;
+    let payment = runtime.view::<U128>(VAULT, "redeem_amount_of", json!({ "from": ALICE })).0;
+    runtime.call(ALICE, VAULT, "redeem", json!({}), payment, redeem_gas - 1).assert_failure("not enough gas");
+    let result = runtime.call(ALICE, VAULT, "redeem", json!({}), payment, redeem_gas);
+    result.assert_success();
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    assert_eq!(nft_owner(&runtime), ALICE);
+    runtime.call(ALICE, VAULT, "burn_deed", json!({}), 0, MAX_GAS).assert_success();
+
+    // The cleanup callback has enough gas to delete the vault after the last claim
+    let claim_gas = GAS_FOR_CLAIM + GAS_FOR_CLEANUP;
+    runtime.call(BOB, VAULT, "claim", json!({}), 0, claim_gas - 1).assert_failure("not enough gas");
+    for account_id in &[BOB, CAROL] {
+        let result = runtime.call(account_id, VAULT, "claim", json!({}), 0, claim_gas);
+        result.assert_success();
+        assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    }
+    assert!(!runtime.exists(VAULT));
+}
+
+#[test]
+fn test_portfolio() {
+    let mut runtime = setup();
+
+    // Accounts prepay the storage of their index
+    for account_id in &[ALICE, BOB] {
+        runtime.call(account_id, FACTORY, "register_portfolio", json!({}), PORTFOLIO_STORAGE_DEPOSIT, MAX_GAS).assert_success();
+    }
+    assert!(runtime.view::<bool>(FACTORY, "is_portfolio_registered", json!({ "account_id": BOB })));
+    securitize(&mut runtime, None).assert_success();
+    let portfolio_of = |runtime: &Runtime, account_id: &str| -> Vec<(String, Balance, Balance)> {
+        let portfolio: Value = runtime.view(FACTORY, "portfolio_of", json!({ "account_id": account_id }));
+        assert_eq!(portfolio["truncated"], false);
+        portfolio["positions"].as_array().unwrap().iter()
+            .map(|position| (
+                position["vault_id"].as_str().unwrap().to_string(),
+                position["balance"].as_str().unwrap().parse().unwrap(),
+                position["value"].as_str().unwrap().parse().unwrap(),
+            ))
+            .collect()
+    };
+    assert_eq!(portfolio_of(&runtime, ALICE), vec![(VAULT.to_string(), SHARES_COUNT, EXIT_PRICE)]);
+
+    // Vaults report the balances they change, valued at the exit price per share
+    send_shares(&mut runtime, BOB, 30);
+    assert_eq!(portfolio_of(&runtime, ALICE), vec![(VAULT.to_string(), 70, 70 * SHARE_PRICE)]);
+    assert_eq!(portfolio_of(&runtime, BOB), vec![(VAULT.to_string(), 30, 30 * SHARE_PRICE)]);
+
+    // Positions burnt by redeeming and claiming leave the index
+    verify_custody(&mut runtime);
+    let payment = runtime.view::<U128>(VAULT, "redeem_amount_of", json!({ "from": ALICE })).0;
+    runtime.call(ALICE, VAULT, "redeem", json!({}), payment, MAX_GAS).assert_success();
+    assert!(portfolio_of(&runtime, ALICE).is_empty());
+    assert_eq!(portfolio_of(&runtime, BOB), vec![(VAULT.to_string(), 30, 30 * SHARE_PRICE)]);
+    runtime.call(BOB, VAULT, "claim", json!({}), 0, MAX_GAS).assert_success();
+    assert!(portfolio_of(&runtime, BOB).is_empty());
+
+    // Only vaults of the factory can report
+    runtime.call(CAROL, FACTORY, "on_holdings_changed", json!({ "holdings": [[CAROL, "1"]], "share_price": "1" }), 0, MAX_GAS)
+        .assert_failure("not a Fractose vault");
+}
+
+#[test]
+fn test_claim_before_redeem() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+    send_shares(&mut runtime, BOB, 30);
+
+    runtime.call(BOB, VAULT, "claim", json!({}), 0, MAX_GAS).assert_failure("token not redeemed");
+    assert_eq!(balance_of(&runtime, BOB), 30);
+}
+
+#[test]
+fn test_redeem_verifies_custody() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+
+    // The first redemption checks custody with the NFT contract, which needs more gas
+    runtime.call(ALICE, VAULT, "redeem", json!({}), 0, GAS_FOR_REDEEM_CUSTODY_CHECK - 1).assert_failure("not enough gas");
+    let result = runtime.call(ALICE, VAULT, "redeem", json!({}), 0, GAS_FOR_REDEEM_CUSTODY_CHECK);
+    result.assert_success();
+    assert!(result.outcome_of(VAULT, "on_redeem_custody_check").unwrap().error.is_none());
+    assert!(result.promise_errors().is_empty(), "{:?}", result.promise_errors());
+    assert_eq!(nft_owner(&runtime), ALICE);
+}
+
+#[test]
+fn test_redeem_locked_vault() {
+    let mut runtime = setup();
+    let lock: u64 = 3_600_000_000_000;
+    securitize(&mut runtime, Some(lock)).assert_success();
+    send_shares(&mut runtime, BOB, 50);
+    verify_custody(&mut runtime);
+
+    // The deposit of a failed redeem is refunded
+    let bob_balance = runtime.balance(BOB);
+    let payment = 50 * SHARE_PRICE;
+    runtime.call(BOB, VAULT, "redeem", json!({}), payment, MAX_GAS).assert_failure("vault is locked");
+    assert_eq!(runtime.balance(BOB), bob_balance);
+    assert_eq!(nft_owner(&runtime), VAULT);
+
+    runtime.advance_time(lock);
+    runtime.call(BOB, VAULT, "redeem", json!({}), payment, MAX_GAS).assert_success();
+    assert_eq!(nft_owner(&runtime), BOB);
+    assert_eq!(runtime.balance(BOB), bob_balance - payment);
+}
+
+#[test]
+fn test_redeem_underpaid() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+    send_shares(&mut runtime, BOB, 50);
+    verify_custody(&mut runtime);
+
+    runtime.call(BOB, VAULT, "redeem", json!({}), 50 * SHARE_PRICE - 1, MAX_GAS)
+        .assert_failure("insufficient payment amount");
+    assert_eq!(nft_owner(&runtime), VAULT);
+}
+
+#[test]
+fn test_securitize_without_approval() {
+    let mut runtime = setup();
+    runtime.call(ALICE, NFT, "nft_revoke", json!({ "token_id": TOKEN_ID, "account_id": FACTORY }), 1, MAX_GAS)
+        .assert_success();
+
+    // The vault is created but the NFT transfer fails
+    let result = securitize(&mut runtime, None);
+    result.assert_success();
+    assert!(result.outcome_of(NFT, "nft_transfer").unwrap().error.is_some());
+    assert_eq!(nft_owner(&runtime), ALICE);
+
+    // Its custody can't be verified and the factory stops listing it
+    assert!(!runtime.call(CAROL, VAULT, "verify_custody", json!({}), 0, MAX_GAS).unwrap_json::<bool>());
+    let balance = runtime.balance(ALICE);
+    let result = runtime.call(ALICE, VAULT, "redeem", json!({}), SHARE_PRICE, MAX_GAS);
+    let custody_missing = Event::CustodyMissing { owner_id: ALICE.into(), vault_id: VAULT.into() }.to_string();
+    assert!(result.outcome_of(VAULT, "on_redeem_custody_check").unwrap().logs.contains(&custody_missing));
+    assert_eq!(runtime.balance(ALICE), balance);
+    assert_eq!(nft_owner(&runtime), ALICE);
+
+    assert!(!runtime.call(CAROL, FACTORY, "check_custody", json!({ "shares_contract": VAULT }), 0, MAX_GAS).unwrap_json::<bool>());
+    assert!(runtime.view::<bool>(FACTORY, "is_invalid_vault", json!({ "shares_contract": VAULT })));
+    assert_eq!(runtime.view::<Option<String>>(FACTORY, "nft_address_of", json!({ "shares_contract": VAULT })), None);
+}
+
+#[test]
+fn test_securitize_denylisted_contract() {
+    let mut runtime = setup();
+    runtime.call(FACTORY, FACTORY, "deny_contract", json!({ "nft_contract_address": NFT }), 0, MAX_GAS).assert_success();
+    runtime.call(ALICE, FACTORY, "deny_contract", json!({ "nft_contract_address": NFT }), 0, MAX_GAS)
+        .assert_failure("is private");
+
+    securitize(&mut runtime, None).assert_failure("NFT contract is denylisted");
+    assert!(!runtime.exists(VAULT));
+    assert_eq!(nft_owner(&runtime), ALICE);
+}
+
+#[test]
+fn test_securitize_twice() {
+    let mut runtime = setup();
+    securitize(&mut runtime, None).assert_success();
+
+    // The vault account already exists, so the second vault is never created
+    let result = securitize(&mut runtime, None);
+    assert!(result.promise_errors().iter().any(|error| error.contains("AccountAlreadyExists")));
+    assert_eq!(nft_owner(&runtime), VAULT);
+}
+